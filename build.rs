@@ -0,0 +1,27 @@
+use std::{env, fs};
+
+// The kernel and the programs in src/bin are all built for the same target, but they need
+// different linker scripts. The kernel lives in the upper half, while programs are linked as
+// position independent executables that the ELF loader can put anywhere.
+fn main() {
+    let arch = env::var("CARGO_CFG_TARGET_ARCH").unwrap();
+
+    println!("cargo:rustc-link-arg-bin=CappuccinOS=--script=./src/arch/{arch}/linker.ld");
+    println!("cargo:rerun-if-changed=src/arch/{arch}/linker.ld");
+
+    for entry in fs::read_dir("src/bin").unwrap() {
+        let path = entry.unwrap().path();
+
+        if path.extension().map_or(true, |extension| extension != "rs") {
+            continue;
+        }
+
+        let name = path.file_stem().unwrap().to_str().unwrap();
+
+        println!("cargo:rustc-link-arg-bin={name}=--script=./src/bin/linker.ld");
+        println!("cargo:rustc-link-arg-bin={name}=-pie");
+        println!("cargo:rustc-link-arg-bin={name}=--no-dynamic-linker");
+    }
+
+    println!("cargo:rerun-if-changed=src/bin");
+}
@@ -0,0 +1,464 @@
+// A small DEFLATE (RFC 1951) and gzip (RFC 1952) decompressor.
+// The Huffman decoding is modeled after zlib's puff.c, which trades speed for being tiny and
+// easy to follow, that's a good trade for something that runs once at boot.
+// It is its own crate so the tests can run on the host, the kernel uses it as `libs::gzip`.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GzipError {
+    InvalidHeader,
+    UnsupportedCompressionMethod,
+    UnexpectedEof,
+    InvalidBlockType,
+    StoredLengthMismatch,
+    InvalidHuffmanTable,
+    InvalidSymbol,
+    InvalidDistance,
+    CrcMismatch,
+    SizeMismatch,
+}
+
+const GZIP_ID: [u8; 2] = [0x1F, 0x8B];
+const GZIP_METHOD_DEFLATE: u8 = 8;
+
+// gzip header flags
+const FLAG_HCRC: u8 = 0x02;
+const FLAG_EXTRA: u8 = 0x04;
+const FLAG_NAME: u8 = 0x08;
+const FLAG_COMMENT: u8 = 0x10;
+
+const MAX_BITS: usize = 15;
+const MAX_LITERAL_LENGTH_CODES: usize = 288;
+const MAX_DISTANCE_CODES: usize = 30;
+const FIXED_LITERAL_LENGTH_CODES: usize = 288;
+
+// Base lengths and extra bits for length codes 257..285
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+
+// Base offsets and extra bits for distance codes 0..29
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+// The order in which code length code lengths are stored in a dynamic block header
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+const CRC32_TABLE: [u32; 256] = make_crc32_table();
+
+const fn make_crc32_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+
+        while bit < 8 {
+            if crc & 1 != 0 {
+                crc = 0xEDB88320 ^ (crc >> 1);
+            } else {
+                crc >>= 1;
+            }
+            bit += 1;
+        }
+
+        table[i] = crc;
+        i += 1;
+    }
+
+    return table;
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFFFFFFu32;
+
+    for &byte in data {
+        crc = CRC32_TABLE[((crc ^ byte as u32) & 0xFF) as usize] ^ (crc >> 8);
+    }
+
+    return !crc;
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+    bit_buffer: u32,
+    bit_count: u8,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        return Self {
+            data,
+            position: 0,
+            bit_buffer: 0,
+            bit_count: 0,
+        };
+    }
+
+    // DEFLATE packs bits starting at the least significant bit of each byte
+    fn bits(&mut self, count: u8) -> Result<u32, GzipError> {
+        while self.bit_count < count {
+            let byte = *self
+                .data
+                .get(self.position)
+                .ok_or(GzipError::UnexpectedEof)?;
+            self.position += 1;
+
+            self.bit_buffer |= (byte as u32) << self.bit_count;
+            self.bit_count += 8;
+        }
+
+        let value = self.bit_buffer & ((1u64 << count) - 1) as u32;
+
+        self.bit_buffer >>= count;
+        self.bit_count -= count;
+
+        return Ok(value);
+    }
+
+    // Drop any bits left over in the current byte
+    fn align_to_byte(&mut self) {
+        self.bit_buffer = 0;
+        self.bit_count = 0;
+    }
+
+    fn bytes(&mut self, count: usize) -> Result<&'a [u8], GzipError> {
+        let bytes = self
+            .data
+            .get(self.position..self.position + count)
+            .ok_or(GzipError::UnexpectedEof)?;
+
+        self.position += count;
+
+        return Ok(bytes);
+    }
+}
+
+// Canonical Huffman code, stored as the number of codes of every length and the symbols
+// ordered by their code.
+struct Huffman {
+    counts: [u16; MAX_BITS + 1],
+    symbols: [u16; MAX_LITERAL_LENGTH_CODES],
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Result<Self, GzipError> {
+        let mut huffman = Self {
+            counts: [0; MAX_BITS + 1],
+            symbols: [0; MAX_LITERAL_LENGTH_CODES],
+        };
+
+        for &length in lengths {
+            huffman.counts[length as usize] += 1;
+        }
+
+        if huffman.counts[0] as usize == lengths.len() {
+            // No codes at all, this is allowed (for example a distance code that's never used)
+            return Ok(huffman);
+        }
+
+        // Check that the lengths don't describe an over-subscribed code
+        let mut left: i32 = 1;
+        for length in 1..=MAX_BITS {
+            left <<= 1;
+            left -= huffman.counts[length] as i32;
+
+            if left < 0 {
+                return Err(GzipError::InvalidHuffmanTable);
+            }
+        }
+
+        let mut offsets = [0u16; MAX_BITS + 1];
+        for length in 1..MAX_BITS {
+            offsets[length + 1] = offsets[length] + huffman.counts[length];
+        }
+
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length != 0 {
+                huffman.symbols[offsets[length as usize] as usize] = symbol as u16;
+                offsets[length as usize] += 1;
+            }
+        }
+
+        return Ok(huffman);
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> Result<u16, GzipError> {
+        let mut code: i32 = 0;
+        let mut first: i32 = 0;
+        let mut index: i32 = 0;
+
+        for length in 1..=MAX_BITS {
+            code |= reader.bits(1)? as i32;
+
+            let count = self.counts[length] as i32;
+
+            if code - count < first {
+                return Ok(self.symbols[(index + (code - first)) as usize]);
+            }
+
+            index += count;
+            first += count;
+            first <<= 1;
+            code <<= 1;
+        }
+
+        return Err(GzipError::InvalidSymbol);
+    }
+}
+
+fn inflate_stored(reader: &mut BitReader, output: &mut Vec<u8>) -> Result<(), GzipError> {
+    reader.align_to_byte();
+
+    let header = reader.bytes(4)?;
+    let length = u16::from_le_bytes([header[0], header[1]]);
+    let length_complement = u16::from_le_bytes([header[2], header[3]]);
+
+    if length != !length_complement {
+        return Err(GzipError::StoredLengthMismatch);
+    }
+
+    output.extend_from_slice(reader.bytes(length as usize)?);
+
+    return Ok(());
+}
+
+fn inflate_codes(
+    reader: &mut BitReader,
+    output: &mut Vec<u8>,
+    literal_length: &Huffman,
+    distance: &Huffman,
+) -> Result<(), GzipError> {
+    loop {
+        let symbol = literal_length.decode(reader)? as usize;
+
+        if symbol < 256 {
+            output.push(symbol as u8);
+            continue;
+        }
+
+        if symbol == 256 {
+            // End of block
+            return Ok(());
+        }
+
+        let symbol = symbol - 257;
+        if symbol >= LENGTH_BASE.len() {
+            return Err(GzipError::InvalidSymbol);
+        }
+
+        let length = LENGTH_BASE[symbol] as usize + reader.bits(LENGTH_EXTRA[symbol])? as usize;
+
+        let symbol = distance.decode(reader)? as usize;
+        if symbol >= DISTANCE_BASE.len() {
+            return Err(GzipError::InvalidDistance);
+        }
+
+        let distance =
+            DISTANCE_BASE[symbol] as usize + reader.bits(DISTANCE_EXTRA[symbol])? as usize;
+
+        if distance > output.len() {
+            return Err(GzipError::InvalidDistance);
+        }
+
+        // The copy may overlap with itself, so go byte by byte
+        let start = output.len() - distance;
+        for i in 0..length {
+            let byte = output[start + i];
+            output.push(byte);
+        }
+    }
+}
+
+fn inflate_fixed(reader: &mut BitReader, output: &mut Vec<u8>) -> Result<(), GzipError> {
+    let mut lengths = [0u8; FIXED_LITERAL_LENGTH_CODES];
+
+    for (symbol, length) in lengths.iter_mut().enumerate() {
+        *length = match symbol {
+            0..=143 => 8,
+            144..=255 => 9,
+            256..=279 => 7,
+            _ => 8,
+        };
+    }
+
+    let literal_length = Huffman::new(&lengths)?;
+    let distance = Huffman::new(&[5u8; MAX_DISTANCE_CODES])?;
+
+    return inflate_codes(reader, output, &literal_length, &distance);
+}
+
+fn inflate_dynamic(reader: &mut BitReader, output: &mut Vec<u8>) -> Result<(), GzipError> {
+    let literal_length_count = reader.bits(5)? as usize + 257;
+    let distance_count = reader.bits(5)? as usize + 1;
+    let code_length_count = reader.bits(4)? as usize + 4;
+
+    if literal_length_count > 286 || distance_count > MAX_DISTANCE_CODES {
+        return Err(GzipError::InvalidHuffmanTable);
+    }
+
+    let mut code_lengths = [0u8; 19];
+    for &index in CODE_LENGTH_ORDER.iter().take(code_length_count) {
+        code_lengths[index] = reader.bits(3)? as u8;
+    }
+
+    let code_length = Huffman::new(&code_lengths)?;
+
+    let mut lengths = [0u8; 286 + MAX_DISTANCE_CODES];
+    let total = literal_length_count + distance_count;
+    let mut index = 0;
+
+    while index < total {
+        let symbol = code_length.decode(reader)?;
+
+        let (value, repeat) = match symbol {
+            0..=15 => {
+                lengths[index] = symbol as u8;
+                index += 1;
+                continue;
+            }
+            16 => {
+                if index == 0 {
+                    return Err(GzipError::InvalidHuffmanTable);
+                }
+
+                (lengths[index - 1], 3 + reader.bits(2)? as usize)
+            }
+            17 => (0, 3 + reader.bits(3)? as usize),
+            _ => (0, 11 + reader.bits(7)? as usize),
+        };
+
+        if index + repeat > total {
+            return Err(GzipError::InvalidHuffmanTable);
+        }
+
+        lengths[index..index + repeat].fill(value);
+        index += repeat;
+    }
+
+    if lengths[256] == 0 {
+        // Without an end of block code we would never stop
+        return Err(GzipError::InvalidHuffmanTable);
+    }
+
+    let literal_length = Huffman::new(&lengths[..literal_length_count])?;
+    let distance = Huffman::new(&lengths[literal_length_count..total])?;
+
+    return inflate_codes(reader, output, &literal_length, &distance);
+}
+
+fn inflate_into(data: &[u8], output: &mut Vec<u8>) -> Result<usize, GzipError> {
+    let mut reader = BitReader::new(data);
+
+    loop {
+        let is_final = reader.bits(1)? == 1;
+
+        match reader.bits(2)? {
+            0 => inflate_stored(&mut reader, output)?,
+            1 => inflate_fixed(&mut reader, output)?,
+            2 => inflate_dynamic(&mut reader, output)?,
+            _ => return Err(GzipError::InvalidBlockType),
+        }
+
+        if is_final {
+            break;
+        }
+    }
+
+    // Any bits left in the buffer belong to the last, partially used, byte
+    return Ok(reader.position);
+}
+
+/// Decompresses a raw DEFLATE stream.
+pub fn inflate(data: &[u8]) -> Result<Vec<u8>, GzipError> {
+    let mut output = Vec::new();
+
+    inflate_into(data, &mut output)?;
+
+    return Ok(output);
+}
+
+/// Decompresses a gzip file (only the first member), checking the CRC32 and size stored in its trailer.
+pub fn gunzip(data: &[u8]) -> Result<Vec<u8>, GzipError> {
+    if data.len() < 18 || data[0..2] != GZIP_ID {
+        return Err(GzipError::InvalidHeader);
+    }
+
+    if data[2] != GZIP_METHOD_DEFLATE {
+        return Err(GzipError::UnsupportedCompressionMethod);
+    }
+
+    let flags = data[3];
+    // Skip ID, CM, FLG, MTIME, XFL and OS
+    let mut offset = 10;
+
+    if flags & FLAG_EXTRA != 0 {
+        let extra_length = u16::from_le_bytes(
+            data.get(offset..offset + 2)
+                .ok_or(GzipError::UnexpectedEof)?
+                .try_into()
+                .unwrap(),
+        );
+        offset += 2 + extra_length as usize;
+    }
+
+    // The file name and comment are both zero terminated strings
+    for flag in [FLAG_NAME, FLAG_COMMENT] {
+        if flags & flag != 0 {
+            let terminator = data
+                .get(offset..)
+                .and_then(|rest| rest.iter().position(|&byte| byte == 0))
+                .ok_or(GzipError::UnexpectedEof)?;
+
+            offset += terminator + 1;
+        }
+    }
+
+    if flags & FLAG_HCRC != 0 {
+        offset += 2;
+    }
+
+    let compressed = data.get(offset..).ok_or(GzipError::UnexpectedEof)?;
+
+    // ISIZE is the size of the uncompressed data modulo 2^32, we only use it as a hint here.
+    // DEFLATE can't do better than roughly 1032:1, so don't trust anything beyond that.
+    let size_hint = u32::from_le_bytes(data[data.len() - 4..].try_into().unwrap()) as usize;
+    let mut output = Vec::with_capacity(core::cmp::min(size_hint, compressed.len() * 1032));
+
+    let consumed = inflate_into(compressed, &mut output)?;
+
+    let trailer = compressed
+        .get(consumed..consumed + 8)
+        .ok_or(GzipError::UnexpectedEof)?;
+    let expected_crc = u32::from_le_bytes(trailer[0..4].try_into().unwrap());
+    let expected_size = u32::from_le_bytes(trailer[4..8].try_into().unwrap());
+
+    if crc32(&output) != expected_crc {
+        return Err(GzipError::CrcMismatch);
+    }
+
+    if output.len() as u32 != expected_size {
+        return Err(GzipError::SizeMismatch);
+    }
+
+    return Ok(output);
+}
@@ -0,0 +1,97 @@
+// The vectors were made by Python's gzip module, scripts/gzip_vectors.py writes them again
+
+use gzip::{crc32, gunzip, inflate, GzipError};
+
+const STORED: &[u8] = include_bytes!("vectors/stored.bin");
+const STORED_GZ: &[u8] = include_bytes!("vectors/stored.gz");
+const FIXED: &[u8] = include_bytes!("vectors/fixed.bin");
+const FIXED_GZ: &[u8] = include_bytes!("vectors/fixed.gz");
+const DYNAMIC: &[u8] = include_bytes!("vectors/dynamic.bin");
+const DYNAMIC_GZ: &[u8] = include_bytes!("vectors/dynamic.gz");
+
+// Python writes no optional header fields, so the DEFLATE stream is all but the 10 byte header and
+// the 8 byte trailer
+fn deflate_stream(compressed: &[u8]) -> &[u8] {
+    return &compressed[10..compressed.len() - 8];
+}
+
+#[test]
+fn stored_block() {
+    assert_eq!(gunzip(STORED_GZ).unwrap(), STORED);
+}
+
+#[test]
+fn fixed_huffman_block() {
+    assert_eq!(gunzip(FIXED_GZ).unwrap(), FIXED);
+}
+
+#[test]
+fn dynamic_huffman_block() {
+    assert_eq!(gunzip(DYNAMIC_GZ).unwrap(), DYNAMIC);
+}
+
+#[test]
+fn raw_deflate_streams() {
+    assert_eq!(inflate(deflate_stream(STORED_GZ)).unwrap(), STORED);
+    assert_eq!(inflate(deflate_stream(FIXED_GZ)).unwrap(), FIXED);
+    assert_eq!(inflate(deflate_stream(DYNAMIC_GZ)).unwrap(), DYNAMIC);
+}
+
+#[test]
+fn crc32_check_value() {
+    assert_eq!(crc32(b""), 0);
+    assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+}
+
+#[test]
+fn crc32_matches_trailer() {
+    for (data, compressed) in [(STORED, STORED_GZ), (FIXED, FIXED_GZ), (DYNAMIC, DYNAMIC_GZ)] {
+        let trailer = &compressed[compressed.len() - 8..];
+
+        assert_eq!(
+            crc32(data),
+            u32::from_le_bytes(trailer[0..4].try_into().unwrap())
+        );
+    }
+}
+
+#[test]
+fn crc32_mismatch() {
+    let mut compressed = DYNAMIC_GZ.to_vec();
+    let crc_offset = compressed.len() - 8;
+    compressed[crc_offset] ^= 0xFF;
+
+    assert_eq!(gunzip(&compressed), Err(GzipError::CrcMismatch));
+}
+
+#[test]
+fn isize_mismatch() {
+    let mut compressed = FIXED_GZ.to_vec();
+    let size_offset = compressed.len() - 4;
+    compressed[size_offset] = compressed[size_offset].wrapping_add(1);
+
+    assert_eq!(gunzip(&compressed), Err(GzipError::SizeMismatch));
+}
+
+#[test]
+fn truncated_trailer() {
+    let compressed = &STORED_GZ[..STORED_GZ.len() - 4];
+
+    assert_eq!(gunzip(compressed), Err(GzipError::UnexpectedEof));
+}
+
+#[test]
+fn invalid_header() {
+    let mut compressed = FIXED_GZ.to_vec();
+    compressed[0] = 0;
+
+    assert_eq!(gunzip(&compressed), Err(GzipError::InvalidHeader));
+
+    let mut compressed = FIXED_GZ.to_vec();
+    compressed[2] = 0;
+
+    assert_eq!(
+        gunzip(&compressed),
+        Err(GzipError::UnsupportedCompressionMethod)
+    );
+}
@@ -0,0 +1,53 @@
+use alloc::vec::Vec;
+
+use crate::{
+    io,
+    syscall::{self, Errno},
+};
+
+const O_RDONLY: usize = 0;
+
+/// A read-only file, closed when dropped.
+pub struct File {
+    fd: usize,
+}
+
+impl File {
+    pub fn open(path: &str) -> Result<Self, Errno> {
+        // The kernel wants a NUL terminated path
+        let mut c_path = Vec::with_capacity(path.len() + 1);
+        c_path.extend_from_slice(path.as_bytes());
+        c_path.push(0);
+
+        let fd = syscall::to_result(unsafe {
+            syscall::syscall2(syscall::SYS_OPEN, c_path.as_ptr() as usize, O_RDONLY)
+        })?;
+
+        return Ok(Self { fd });
+    }
+
+    pub fn read(&mut self, buf: &mut [u8]) -> Result<usize, Errno> {
+        return io::read(self.fd, buf);
+    }
+
+    pub fn read_to_end(&mut self) -> Result<Vec<u8>, Errno> {
+        let mut data = Vec::new();
+        let mut buf = [0u8; 512];
+
+        loop {
+            let length = self.read(&mut buf)?;
+
+            if length == 0 {
+                return Ok(data);
+            }
+
+            data.extend_from_slice(&buf[..length]);
+        }
+    }
+}
+
+impl Drop for File {
+    fn drop(&mut self) {
+        unsafe { syscall::syscall1(syscall::SYS_CLOSE, self.fd) };
+    }
+}
@@ -0,0 +1,44 @@
+use crate::syscall::{self, Errno};
+
+pub const STDIN: usize = 0;
+pub const STDOUT: usize = 1;
+pub const STDERR: usize = 2;
+
+pub fn read(fd: usize, buf: &mut [u8]) -> Result<usize, Errno> {
+    return syscall::to_result(unsafe {
+        syscall::syscall3(syscall::SYS_READ, fd, buf.as_mut_ptr() as usize, buf.len())
+    });
+}
+
+pub fn write(fd: usize, buf: &[u8]) -> Result<usize, Errno> {
+    return syscall::to_result(unsafe {
+        syscall::syscall3(syscall::SYS_WRITE, fd, buf.as_ptr() as usize, buf.len())
+    });
+}
+
+struct Stdout;
+
+impl core::fmt::Write for Stdout {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        write(STDOUT, s.as_bytes()).map_err(|_| core::fmt::Error)?;
+        return Ok(());
+    }
+}
+
+#[doc(hidden)]
+pub fn _print(args: core::fmt::Arguments) {
+    use core::fmt::Write;
+
+    let _ = Stdout.write_fmt(args);
+}
+
+#[macro_export]
+macro_rules! print {
+    ($($arg:tt)*) => ($crate::io::_print(format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! println {
+    () => ($crate::print!("\n"));
+    ($($arg:tt)*) => ($crate::print!("{}\n", format_args!($($arg)*)));
+}
@@ -0,0 +1,57 @@
+// Userspace runtime for CappuccinOS programs: system call wrappers, an allocator, a panic handler
+// and the code that turns the initial stack into arguments for `main`.
+#![no_std]
+
+extern crate alloc;
+
+pub mod fs;
+pub mod io;
+pub mod memory;
+pub mod process;
+pub mod syscall;
+
+use alloc::vec::Vec;
+
+#[global_allocator]
+static ALLOCATOR: memory::BrkAllocator = memory::BrkAllocator::new();
+
+/// Defines the `_start` symbol of the program, which calls `$main` with the program arguments and
+/// exits with whatever it returns.
+#[macro_export]
+macro_rules! entry {
+    ($main:path) => {
+        extern "C" fn __libcappuccino_start(stack: *const usize) -> ! {
+            $crate::start(stack, $main);
+        }
+
+        // The kernel starts us with rsp pointing at argc, followed by the argv pointers
+        core::arch::global_asm!(
+            ".global _start",
+            "_start:",
+            "mov rdi, rsp",
+            "call {}",
+            sym __libcappuccino_start
+        );
+    };
+}
+
+#[doc(hidden)]
+pub fn start(stack: *const usize, main: fn(&[&str]) -> i32) -> ! {
+    let argc = unsafe { *stack };
+    let argv = unsafe { stack.add(1) as *const *const core::ffi::c_char };
+
+    let mut args: Vec<&str> = Vec::with_capacity(argc);
+
+    for i in 0..argc {
+        let arg = unsafe { core::ffi::CStr::from_ptr(*argv.add(i)) };
+        args.push(arg.to_str().unwrap_or(""));
+    }
+
+    process::exit(main(&args));
+}
+
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    println!("{}", info);
+    process::exit(101);
+}
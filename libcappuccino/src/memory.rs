@@ -0,0 +1,118 @@
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    cell::UnsafeCell,
+};
+
+use crate::syscall::{self, Errno};
+
+const PROT_READ: usize = 0x1;
+const PROT_WRITE: usize = 0x2;
+const MAP_PRIVATE: usize = 0x02;
+const MAP_ANONYMOUS: usize = 0x20;
+
+// Allocations at least this big get their own mapping instead of coming from the break
+const MMAP_THRESHOLD: usize = 0x10000;
+
+/// Moves the program break to `address` and returns where it ended up, `brk(0)` returns the
+/// current break.
+pub fn brk(address: usize) -> usize {
+    return unsafe { syscall::syscall1(syscall::SYS_BRK, address) };
+}
+
+pub fn mmap(length: usize) -> Result<*mut u8, Errno> {
+    let address = syscall::to_result(unsafe {
+        syscall::syscall5(
+            syscall::SYS_MMAP,
+            0,
+            length,
+            PROT_READ | PROT_WRITE,
+            MAP_PRIVATE | MAP_ANONYMOUS,
+            usize::MAX,
+        )
+    })?;
+
+    return Ok(address as *mut u8);
+}
+
+pub fn munmap(address: *mut u8, length: usize) -> Result<(), Errno> {
+    syscall::to_result(unsafe {
+        syscall::syscall2(syscall::SYS_MUNMAP, address as usize, length)
+    })?;
+
+    return Ok(());
+}
+
+struct BumpState {
+    // Start of the free space, and the program break
+    next: usize,
+    end: usize,
+    // Last allocation, which is the only one that can actually be freed
+    last: usize,
+}
+
+/// Hands out memory by moving the program break, large allocations are mapped separately.
+// TODO: reuse freed memory
+pub struct BrkAllocator {
+    state: UnsafeCell<BumpState>,
+}
+
+// Programs only have a single thread
+unsafe impl Sync for BrkAllocator {}
+
+impl BrkAllocator {
+    pub const fn new() -> Self {
+        return Self {
+            state: UnsafeCell::new(BumpState {
+                next: 0,
+                end: 0,
+                last: 0,
+            }),
+        };
+    }
+}
+
+unsafe impl GlobalAlloc for BrkAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if layout.size() >= MMAP_THRESHOLD && layout.align() <= 0x1000 {
+            return mmap(layout.size()).unwrap_or(core::ptr::null_mut());
+        }
+
+        let state = &mut *self.state.get();
+
+        if state.end == 0 {
+            state.next = brk(0);
+            state.end = state.next;
+        }
+
+        let start = (state.next + layout.align() - 1) & !(layout.align() - 1);
+        let new_next = start + layout.size();
+
+        if new_next > state.end {
+            let new_end = brk(new_next);
+
+            if new_end < new_next {
+                return core::ptr::null_mut();
+            }
+
+            state.end = new_end;
+        }
+
+        state.last = start;
+        state.next = new_next;
+
+        return start as *mut u8;
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if layout.size() >= MMAP_THRESHOLD && layout.align() <= 0x1000 {
+            let _ = munmap(ptr, layout.size());
+            return;
+        }
+
+        let state = &mut *self.state.get();
+
+        if ptr as usize == state.last {
+            state.next = state.last;
+        }
+    }
+}
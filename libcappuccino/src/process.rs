@@ -0,0 +1,16 @@
+use crate::syscall;
+
+pub fn exit(code: i32) -> ! {
+    unsafe { syscall::syscall1(syscall::SYS_EXIT, code as usize) };
+
+    // The kernel never comes back from an exit
+    loop {}
+}
+
+pub fn getpid() -> usize {
+    return unsafe { syscall::syscall0(syscall::SYS_GETPID) };
+}
+
+pub fn yield_now() {
+    unsafe { syscall::syscall0(syscall::SYS_YIELD) };
+}
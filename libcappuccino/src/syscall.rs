@@ -0,0 +1,123 @@
+// Raw system calls, the numbers and calling convention match Linux on x86_64.
+// `syscall` clobbers rcx and r11, the kernel leaves everything else except rax alone.
+
+pub const SYS_READ: usize = 0;
+pub const SYS_WRITE: usize = 1;
+pub const SYS_OPEN: usize = 2;
+pub const SYS_CLOSE: usize = 3;
+pub const SYS_MMAP: usize = 9;
+pub const SYS_MUNMAP: usize = 11;
+pub const SYS_BRK: usize = 12;
+pub const SYS_YIELD: usize = 24;
+pub const SYS_GETPID: usize = 39;
+pub const SYS_EXIT: usize = 60;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Errno(pub usize);
+
+impl Errno {
+    pub const EPERM: Errno = Errno(1);
+    pub const ENOENT: Errno = Errno(2);
+    pub const EIO: Errno = Errno(5);
+    pub const EBADF: Errno = Errno(9);
+    pub const ENOMEM: Errno = Errno(12);
+    pub const EFAULT: Errno = Errno(14);
+    pub const ENOTDIR: Errno = Errno(20);
+    pub const EISDIR: Errno = Errno(21);
+    pub const EINVAL: Errno = Errno(22);
+    pub const EROFS: Errno = Errno(30);
+    pub const ENAMETOOLONG: Errno = Errno(36);
+    pub const ENOSYS: Errno = Errno(38);
+}
+
+/// Turns a raw return value into a Result, values between -4095 and -1 are negated errnos.
+pub fn to_result(value: usize) -> Result<usize, Errno> {
+    if value > -4096isize as usize {
+        return Err(Errno(value.wrapping_neg()));
+    }
+
+    return Ok(value);
+}
+
+#[inline(always)]
+pub unsafe fn syscall0(number: usize) -> usize {
+    let ret;
+    core::arch::asm!(
+        "syscall",
+        inlateout("rax") number => ret,
+        lateout("rcx") _,
+        lateout("r11") _,
+        options(nostack)
+    );
+    return ret;
+}
+
+#[inline(always)]
+pub unsafe fn syscall1(number: usize, arg0: usize) -> usize {
+    let ret;
+    core::arch::asm!(
+        "syscall",
+        inlateout("rax") number => ret,
+        lateout("rcx") _,
+        lateout("r11") _,
+        in("rdi") arg0,
+        options(nostack)
+    );
+    return ret;
+}
+
+#[inline(always)]
+pub unsafe fn syscall2(number: usize, arg0: usize, arg1: usize) -> usize {
+    let ret;
+    core::arch::asm!(
+        "syscall",
+        inlateout("rax") number => ret,
+        lateout("rcx") _,
+        lateout("r11") _,
+        in("rdi") arg0,
+        in("rsi") arg1,
+        options(nostack)
+    );
+    return ret;
+}
+
+#[inline(always)]
+pub unsafe fn syscall3(number: usize, arg0: usize, arg1: usize, arg2: usize) -> usize {
+    let ret;
+    core::arch::asm!(
+        "syscall",
+        inlateout("rax") number => ret,
+        lateout("rcx") _,
+        lateout("r11") _,
+        in("rdi") arg0,
+        in("rsi") arg1,
+        in("rdx") arg2,
+        options(nostack)
+    );
+    return ret;
+}
+
+#[inline(always)]
+pub unsafe fn syscall5(
+    number: usize,
+    arg0: usize,
+    arg1: usize,
+    arg2: usize,
+    arg3: usize,
+    arg4: usize,
+) -> usize {
+    let ret;
+    core::arch::asm!(
+        "syscall",
+        inlateout("rax") number => ret,
+        lateout("rcx") _,
+        lateout("r11") _,
+        in("rdi") arg0,
+        in("rsi") arg1,
+        in("rdx") arg2,
+        in("r10") arg3,
+        in("r8") arg4,
+        options(nostack)
+    );
+    return ret;
+}
@@ -0,0 +1,54 @@
+import gzip
+import os
+import sys
+
+# Writes the test vectors for the gzip crate, each one a plain file and the same file compressed by
+# Python's gzip module so that it uses a certain DEFLATE block type
+
+
+def block_type(compressed):
+    # gzip.compress writes no file name, so the DEFLATE stream starts right after the 10 byte
+    # header. BFINAL is the first bit and BTYPE the two after it
+    return (compressed[10] >> 1) & 0b11
+
+
+VECTORS = {
+    # Level 0 doesn't compress at all and only writes stored blocks
+    "stored": (bytes((i * 7 + i // 256) & 0xFF for i in range(1024)), 0, 0),
+    # Too short for the cost of sending a Huffman table to pay off
+    "fixed": (b"Hello from CappuccinOS! Hello from CappuccinOS!\n", 9, 1),
+    "dynamic": (
+        "".join(
+            f"line {i}: the quick brown fox jumps over the lazy dog\n" for i in range(200)
+        ).encode(),
+        9,
+        2,
+    ),
+}
+
+
+def main():
+    if len(sys.argv) != 2:
+        print(f"usage: {sys.argv[0]} OUTPUT_DIRECTORY")
+        sys.exit(1)
+
+    output = sys.argv[1]
+    os.makedirs(output, exist_ok=True)
+
+    for name, (data, level, expected_type) in VECTORS.items():
+        # A fixed mtime keeps the output the same on every run
+        compressed = gzip.compress(data, compresslevel=level, mtime=0)
+
+        if block_type(compressed) != expected_type:
+            print(f"{name}: expected block type {expected_type}, got {block_type(compressed)}")
+            sys.exit(1)
+
+        with open(os.path.join(output, f"{name}.bin"), "wb") as file:
+            file.write(data)
+
+        with open(os.path.join(output, f"{name}.gz"), "wb") as file:
+            file.write(compressed)
+
+
+if __name__ == "__main__":
+    main()
@@ -1,8 +1,73 @@
 import os
 import gzip
-import shutil
+import stat
 import sys
 
+# Writes the source directory as a cpio "newc" archive, see
+# https://www.kernel.org/doc/html/latest/driver-api/early-userspace/buffer-format.html
+CPIO_NEWC_MAGIC = b"070701"
+CPIO_TRAILER = "TRAILER!!!"
+
+
+def pad_to_4(length):
+    return b"\0" * ((4 - (length % 4)) % 4)
+
+
+def cpio_entry(ino, name, mode, mtime, data):
+    encoded_name = name.encode() + b"\0"
+
+    fields = [
+        ino,
+        mode,
+        0,  # uid
+        0,  # gid
+        1,  # nlink
+        mtime,
+        len(data),
+        0,  # devmajor
+        0,  # devminor
+        0,  # rdevmajor
+        0,  # rdevminor
+        len(encoded_name),
+        0,  # check
+    ]
+
+    header = CPIO_NEWC_MAGIC + b"".join(f"{field:08X}".encode() for field in fields)
+
+    entry = header + encoded_name
+    entry += pad_to_4(len(entry))
+    entry += data
+    entry += pad_to_4(len(data))
+
+    return entry
+
+
+def build_archive(source_dir):
+    archive = bytearray()
+    ino = 1
+
+    for foldername, subfolders, filenames in os.walk(source_dir):
+        subfolders.sort()
+
+        for name in sorted(subfolders) + sorted(filenames):
+            path = os.path.join(foldername, name)
+            rel_path = os.path.relpath(path, source_dir)
+            file_stat = os.stat(path)
+
+            if stat.S_ISDIR(file_stat.st_mode):
+                data = b""
+            else:
+                with open(path, "rb") as source_file:
+                    data = source_file.read()
+
+            archive += cpio_entry(ino, rel_path, file_stat.st_mode, int(file_stat.st_mtime), data)
+            ino += 1
+
+    archive += cpio_entry(0, CPIO_TRAILER, 0, 0, b"")
+
+    return bytes(archive)
+
+
 if __name__ == "__main__":
     if len(sys.argv) != 3:
         print(f"Usage: python scripts/initramfs.py /path/to/source/directory /path/to/output/directory/initramfs.gz")
@@ -11,14 +76,10 @@ if __name__ == "__main__":
     source_dir, output_file = sys.argv[1], sys.argv[2]
 
     try:
+        archive = build_archive(source_dir)
+
         with gzip.open(output_file, 'wb') as gz_file:
-            for foldername, subfolders, filenames in os.walk(source_dir):
-                for filename in filenames:
-                    file_path = os.path.join(foldername, filename)
-                    rel_path = os.path.relpath(file_path, source_dir)
-                    gz_file.write(os.path.join(rel_path).encode())
-                    with open(file_path, 'rb') as source_file:
-                        shutil.copyfileobj(source_file, gz_file)
+            gz_file.write(archive)
         print(f"Compression completed. Output file: {output_file}")
     except Exception as e:
         print(f"Error compressing directory: {str(e)}")
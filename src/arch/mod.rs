@@ -9,5 +9,11 @@ mod x86_common;
 #[cfg(target_arch = "x86_64")]
 #[path = "x86_64"]
 mod imp {
+    pub mod apic;
+    pub mod context;
+    pub mod gdt;
     pub mod interrupts;
+    pub mod paging;
+    pub mod smp;
+    pub mod syscall;
 }
@@ -0,0 +1,292 @@
+// Local APIC and IOAPIC driver, taking over from the 8259 PICs once the MADT tells us where
+// everything is. The local APIC is driven through MSRs when the CPU supports x2APIC, and through
+// its memory mapped registers otherwise.
+// ISA IRQs keep the vectors they had on the PIC, so their handlers don't care who delivered them.
+
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use alloc::vec::Vec;
+
+use crate::{
+    arch::{rdmsr, without_interrupts, wrmsr},
+    drivers::acpi::{self, MadtInterruptOverride},
+    libs::mutex::Mutex,
+};
+
+use super::{interrupts, paging};
+
+const IA32_APIC_BASE: u32 = 0x1B;
+const APIC_BASE_X2APIC_ENABLE: u64 = 1 << 10;
+const APIC_BASE_GLOBAL_ENABLE: u64 = 1 << 11;
+
+// In x2APIC mode every register is an MSR, at this base plus its MMIO offset divided by 16
+const X2APIC_MSR_BASE: u32 = 0x800;
+
+// Local APIC registers, as offsets into the MMIO page
+const LAPIC_ID: u32 = 0x20;
+const LAPIC_TASK_PRIORITY: u32 = 0x80;
+const LAPIC_END_OF_INTERRUPT: u32 = 0xB0;
+const LAPIC_SPURIOUS_VECTOR: u32 = 0xF0;
+const LAPIC_LVT_LINT0: u32 = 0x350;
+
+const LAPIC_SOFTWARE_ENABLE: u32 = 1 << 8;
+const LVT_MASKED: u32 = 1 << 16;
+
+// Spurious interrupts must not be acknowledged, so they go to a vector of their own
+pub const SPURIOUS_VECTOR: u8 = 0xFF;
+
+const IOAPIC_REGISTER_SELECT: u64 = 0x00;
+const IOAPIC_WINDOW: u64 = 0x10;
+const IOAPIC_MMIO_SIZE: u64 = 0x20;
+
+const IOAPIC_VERSION: u32 = 0x01;
+// Every redirection entry takes two registers, starting here
+const IOAPIC_REDIRECTION_TABLE: u32 = 0x10;
+
+// Redirection entry bits, everything left at zero means fixed delivery to a physical APIC ID
+const REDIRECTION_ACTIVE_LOW: u64 = 1 << 13;
+const REDIRECTION_LEVEL_TRIGGERED: u64 = 1 << 15;
+const REDIRECTION_MASKED: u64 = 1 << 16;
+
+// MPS INTI flags of interrupt source overrides, "conforming" means ISA's active high and edge
+// triggered
+const POLARITY_MASK: u16 = 0b11;
+const POLARITY_ACTIVE_LOW: u16 = 0b11;
+const TRIGGER_MODE_MASK: u16 = 0b1100;
+const TRIGGER_MODE_LEVEL: u16 = 0b1100;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static X2APIC: AtomicBool = AtomicBool::new(false);
+// Where the local APIC's registers are mapped, unused in x2APIC mode
+static LOCAL_APIC_ADDRESS: AtomicU64 = AtomicU64::new(0);
+
+struct IoApic {
+    // Virtual address of the registers
+    address: u64,
+    // First global system interrupt handled by this IOAPIC
+    gsi_base: u32,
+    redirection_entries: u32,
+}
+
+impl IoApic {
+    fn read(&self, register: u32) -> u32 {
+        unsafe {
+            core::ptr::write_volatile(
+                (self.address + IOAPIC_REGISTER_SELECT) as *mut u32,
+                register,
+            );
+            return core::ptr::read_volatile((self.address + IOAPIC_WINDOW) as *const u32);
+        }
+    }
+
+    fn write(&self, register: u32, value: u32) {
+        unsafe {
+            core::ptr::write_volatile(
+                (self.address + IOAPIC_REGISTER_SELECT) as *mut u32,
+                register,
+            );
+            core::ptr::write_volatile((self.address + IOAPIC_WINDOW) as *mut u32, value);
+        }
+    }
+
+    fn handles(&self, gsi: u32) -> bool {
+        return (self.gsi_base..self.gsi_base + self.redirection_entries).contains(&gsi);
+    }
+
+    fn set_redirection(&self, gsi: u32, entry: u64) {
+        let register = IOAPIC_REDIRECTION_TABLE + (gsi - self.gsi_base) * 2;
+
+        // Mask it while the entry is half written
+        self.write(register, REDIRECTION_MASKED as u32);
+        self.write(register + 1, (entry >> 32) as u32);
+        self.write(register, entry as u32);
+    }
+}
+
+static IO_APICS: Mutex<Vec<IoApic>> = Mutex::new(Vec::new());
+static INTERRUPT_OVERRIDES: Mutex<Vec<MadtInterruptOverride>> = Mutex::new(Vec::new());
+
+fn local_apic_read(register: u32) -> u32 {
+    if X2APIC.load(Ordering::SeqCst) {
+        return rdmsr(X2APIC_MSR_BASE + (register >> 4)) as u32;
+    }
+
+    let address = LOCAL_APIC_ADDRESS.load(Ordering::SeqCst) + register as u64;
+    return unsafe { core::ptr::read_volatile(address as *const u32) };
+}
+
+fn local_apic_write(register: u32, value: u32) {
+    if X2APIC.load(Ordering::SeqCst) {
+        unsafe { wrmsr(X2APIC_MSR_BASE + (register >> 4), value as u64) };
+        return;
+    }
+
+    let address = LOCAL_APIC_ADDRESS.load(Ordering::SeqCst) + register as u64;
+    unsafe { core::ptr::write_volatile(address as *mut u32, value) };
+}
+
+fn x2apic_supported() -> bool {
+    let cpu_id = unsafe { core::arch::x86_64::__cpuid(1) };
+    return cpu_id.ecx & (1 << 21) != 0;
+}
+
+/// Whether interrupts are delivered by the APIC rather than the PIC.
+pub fn is_enabled() -> bool {
+    return ENABLED.load(Ordering::SeqCst);
+}
+
+/// The APIC ID of the CPU we are running on.
+pub fn local_apic_id() -> u32 {
+    if X2APIC.load(Ordering::SeqCst) {
+        return local_apic_read(LAPIC_ID);
+    }
+
+    return local_apic_read(LAPIC_ID) >> 24;
+}
+
+pub fn end_of_interrupt() {
+    local_apic_write(LAPIC_END_OF_INTERRUPT, 0);
+}
+
+/// Enables the local APIC of the CPU we are running on, in the mode `init` picked.
+pub fn init_local_apic() {
+    let mut apic_base = rdmsr(IA32_APIC_BASE) | APIC_BASE_GLOBAL_ENABLE;
+
+    if X2APIC.load(Ordering::SeqCst) {
+        apic_base |= APIC_BASE_X2APIC_ENABLE;
+    }
+
+    unsafe { wrmsr(IA32_APIC_BASE, apic_base) };
+
+    // Accept every priority
+    local_apic_write(LAPIC_TASK_PRIORITY, 0);
+    // LINT0 is wired to the PIC, which has nothing to say anymore
+    local_apic_write(LAPIC_LVT_LINT0, LVT_MASKED);
+    local_apic_write(
+        LAPIC_SPURIOUS_VECTOR,
+        LAPIC_SOFTWARE_ENABLE | SPURIOUS_VECTOR as u32,
+    );
+}
+
+// Finds the global system interrupt an ISA IRQ is wired to and the flags it needs
+fn irq_to_gsi(irq: u8) -> (u32, u16) {
+    return INTERRUPT_OVERRIDES
+        .lock()
+        .read()
+        .iter()
+        .find(|interrupt_override| interrupt_override.bus == 0 && interrupt_override.irq == irq)
+        .map_or((irq as u32, 0), |interrupt_override| {
+            (interrupt_override.gsi, interrupt_override.flags)
+        });
+}
+
+/// Delivers the ISA IRQ `irq` to `vector` on the CPU we are running on.
+pub fn route_irq(irq: u8, vector: u8) {
+    let (gsi, flags) = irq_to_gsi(irq);
+
+    let mut entry = vector as u64 | (local_apic_id() as u64) << 56;
+
+    if flags & POLARITY_MASK == POLARITY_ACTIVE_LOW {
+        entry |= REDIRECTION_ACTIVE_LOW;
+    }
+
+    if flags & TRIGGER_MODE_MASK == TRIGGER_MODE_LEVEL {
+        entry |= REDIRECTION_LEVEL_TRIGGERED;
+    }
+
+    without_interrupts(|| {
+        match IO_APICS
+            .lock()
+            .read()
+            .iter()
+            .find(|io_apic| io_apic.handles(gsi))
+        {
+            Some(io_apic) => io_apic.set_redirection(gsi, entry),
+            None => crate::log_error!("No IOAPIC handles IRQ {irq} (GSI {gsi})"),
+        }
+    });
+}
+
+pub fn init() {
+    let madt = match acpi::madt() {
+        Some(madt) => madt,
+        None => {
+            crate::log_error!("MADT not found, staying on the PIC");
+            return;
+        }
+    };
+
+    if madt.io_apics.is_empty() {
+        crate::log_error!("No IOAPIC found, staying on the PIC");
+        return;
+    }
+
+    if x2apic_supported() {
+        X2APIC.store(true, Ordering::SeqCst);
+    } else {
+        match paging::map_mmio(madt.local_apic_address, paging::PAGE_SIZE) {
+            Ok(address) => LOCAL_APIC_ADDRESS.store(address, Ordering::SeqCst),
+            Err(err) => {
+                crate::log_error!("Failed to map the local APIC: {err:?}");
+                return;
+            }
+        }
+    }
+
+    for madt_io_apic in madt.io_apics.iter() {
+        let address = match paging::map_mmio(madt_io_apic.address as u64, IOAPIC_MMIO_SIZE) {
+            Ok(address) => address,
+            Err(err) => {
+                crate::log_error!("Failed to map IOAPIC {}: {err:?}", madt_io_apic.id);
+                continue;
+            }
+        };
+
+        let mut io_apic = IoApic {
+            address,
+            gsi_base: madt_io_apic.gsi_base,
+            redirection_entries: 0,
+        };
+
+        io_apic.redirection_entries = ((io_apic.read(IOAPIC_VERSION) >> 16) & 0xFF) + 1;
+
+        // Nothing gets through until a handler is installed for it
+        for gsi in io_apic.gsi_base..io_apic.gsi_base + io_apic.redirection_entries {
+            io_apic.set_redirection(gsi, REDIRECTION_MASKED);
+        }
+
+        IO_APICS.lock().write().push(io_apic);
+    }
+
+    *INTERRUPT_OVERRIDES.lock().write() = madt.interrupt_overrides;
+
+    without_interrupts(|| {
+        interrupts::PICS.lock().write().disable();
+
+        init_local_apic();
+
+        ENABLED.store(true, Ordering::SeqCst);
+
+        // Handlers installed while the PIC was in charge
+        for irq in interrupts::enabled_irqs() {
+            route_irq(irq, interrupts::PIC_1_OFFSET + irq);
+        }
+    });
+
+    // `_PRT` describes the IOAPIC's wiring from now on
+    acpi::aml::set_interrupt_model(true);
+
+    crate::log_ok!(
+        "APIC initialized in {} mode with {} IOAPIC(s), {} CPU(s) found",
+        if X2APIC.load(Ordering::SeqCst) {
+            "x2APIC"
+        } else {
+            "xAPIC"
+        },
+        IO_APICS.lock().read().len(),
+        madt.local_apics
+            .iter()
+            .filter(|local_apic| local_apic.usable)
+            .count()
+    );
+}
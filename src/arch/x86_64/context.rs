@@ -0,0 +1,45 @@
+// Switching between the kernel stacks of tasks. Only the callee saved registers and the flags
+// are kept on the stack, everything else has already been saved by whoever called us.
+
+/// Saves the current context on the stack, stores the stack pointer in `old_stack_pointer` and
+/// resumes the context saved at `new_stack_pointer`. Returns once something switches back.
+#[naked]
+pub unsafe extern "C" fn switch_context(old_stack_pointer: *mut u64, new_stack_pointer: u64) {
+    core::arch::asm!(
+        "pushfq",
+        "push rbp",
+        "push rbx",
+        "push r12",
+        "push r13",
+        "push r14",
+        "push r15",
+        "mov [rdi], rsp",
+        "mov rsp, rsi",
+        "pop r15",
+        "pop r14",
+        "pop r13",
+        "pop r12",
+        "pop rbx",
+        "pop rbp",
+        "popfq",
+        "ret",
+        options(noreturn)
+    );
+}
+
+/// Lays out a fresh stack so that switching to it calls `entry`, with interrupts disabled.
+pub fn initial_stack_pointer(stack_top: u64, entry: extern "C" fn() -> !) -> u64 {
+    let top = stack_top & !0xF;
+
+    // r15, r14, r13, r12, rbx and rbp, the flags, the return address and padding that leaves
+    // the stack aligned the way a function call would
+    let frame = [0, 0, 0, 0, 0, 0, 0x2, entry as u64, 0];
+
+    let stack_pointer = top - (frame.len() * 8) as u64;
+
+    for (i, value) in frame.iter().enumerate() {
+        unsafe { *((stack_pointer + i as u64 * 8) as *mut u64) = *value };
+    }
+
+    return stack_pointer;
+}
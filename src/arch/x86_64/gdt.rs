@@ -0,0 +1,195 @@
+// Our own GDT and TSS, replacing the one Limine left us with.
+// The layout is also what SYSCALL/SYSRET expect: kernel code followed by kernel data, and user
+// data followed by user code.
+
+use crate::libs::mutex::Mutex;
+
+pub const KERNEL_CODE_SELECTOR: u16 = 0x08;
+pub const KERNEL_DATA_SELECTOR: u16 = 0x10;
+pub const USER_DATA_SELECTOR: u16 = 0x18 | 3;
+pub const USER_CODE_SELECTOR: u16 = 0x20 | 3;
+pub const TSS_SELECTOR: u16 = 0x28;
+
+// IST index (1 based, 0 means "don't switch stacks") used by the double fault handler
+pub const DOUBLE_FAULT_IST_INDEX: u8 = 1;
+
+const KERNEL_STACK_SIZE: usize = 0x4000;
+const IST_STACK_SIZE: usize = 0x4000;
+const BOOT_STACK_SIZE: usize = 0x10000;
+
+// Access byte bits
+const PRESENT: u64 = 1 << 47;
+const USER: u64 = 3 << 45;
+const CODE_OR_DATA: u64 = 1 << 44;
+const EXECUTABLE: u64 = 1 << 43;
+const READ_WRITE: u64 = 1 << 41;
+// Flag bits
+const LONG_MODE: u64 = 1 << 53;
+
+const KERNEL_CODE: u64 = PRESENT | CODE_OR_DATA | EXECUTABLE | READ_WRITE | LONG_MODE;
+const KERNEL_DATA: u64 = PRESENT | CODE_OR_DATA | READ_WRITE;
+const USER_CODE: u64 = KERNEL_CODE | USER;
+const USER_DATA: u64 = KERNEL_DATA | USER;
+
+// Available 64-bit TSS
+const TSS_TYPE: u64 = 0x9 << 40;
+
+#[repr(C, packed)]
+#[derive(Clone, Copy)]
+pub struct TaskStateSegment {
+    reserved_1: u32,
+    // Stack pointers loaded when switching to ring 0, 1 and 2
+    pub privilege_stack_table: [u64; 3],
+    reserved_2: u64,
+    pub interrupt_stack_table: [u64; 7],
+    reserved_3: u64,
+    reserved_4: u16,
+    iomap_base: u16,
+}
+
+impl TaskStateSegment {
+    const fn new() -> Self {
+        return Self {
+            reserved_1: 0,
+            privilege_stack_table: [0; 3],
+            reserved_2: 0,
+            interrupt_stack_table: [0; 7],
+            reserved_3: 0,
+            reserved_4: 0,
+            // No I/O permission bitmap, ring 3 can't touch any ports
+            iomap_base: core::mem::size_of::<TaskStateSegment>() as u16,
+        };
+    }
+}
+
+#[repr(C, align(16))]
+struct Stack<const SIZE: usize>([u8; SIZE]);
+
+impl<const SIZE: usize> Stack<SIZE> {
+    fn top(&self) -> u64 {
+        return self.0.as_ptr() as u64 + SIZE as u64;
+    }
+}
+
+// Stack used when an interrupt or a syscall comes in from ring 3
+static KERNEL_STACK: Stack<KERNEL_STACK_SIZE> = Stack([0; KERNEL_STACK_SIZE]);
+static DOUBLE_FAULT_STACK: Stack<IST_STACK_SIZE> = Stack([0; IST_STACK_SIZE]);
+// Replaces the stack Limine booted us with, which is in bootloader reclaimable memory
+static BOOT_STACK: Stack<BOOT_STACK_SIZE> = Stack([0; BOOT_STACK_SIZE]);
+
+/// A CPU's GDT and TSS. Every CPU needs a TSS of its own since it holds the stacks the CPU
+/// switches to, and so a GDT of its own to point at it.
+pub struct Descriptors {
+    // null, kernel code, kernel data, user data, user code and the two halves of the TSS
+    // descriptor
+    gdt: [u64; 7],
+    tss: TaskStateSegment,
+}
+
+impl Descriptors {
+    pub const fn new() -> Self {
+        return Self {
+            gdt: [0, KERNEL_CODE, KERNEL_DATA, USER_DATA, USER_CODE, 0, 0],
+            tss: TaskStateSegment::new(),
+        };
+    }
+}
+
+static BSP_DESCRIPTORS: Mutex<Descriptors> = Mutex::new(Descriptors::new());
+
+#[repr(C, packed)]
+struct GdtPtr {
+    limit: u16,
+    base: u64,
+}
+
+fn tss_descriptor(tss: &TaskStateSegment) -> (u64, u64) {
+    let base = tss as *const TaskStateSegment as u64;
+    let limit = (core::mem::size_of::<TaskStateSegment>() - 1) as u64;
+
+    let low = PRESENT
+        | TSS_TYPE
+        | (limit & 0xFFFF)
+        | ((base & 0xFFFFFF) << 16)
+        | (((limit >> 16) & 0xF) << 48)
+        | (((base >> 24) & 0xFF) << 56);
+    let high = base >> 32;
+
+    return (low, high);
+}
+
+/// Sets the stack the CPU we are running on switches to when an interrupt or a syscall comes
+/// from ring 3.
+pub fn set_kernel_stack(stack_top: u64) {
+    super::syscall::set_kernel_stack(stack_top);
+}
+
+/// Moves onto the kernel's own boot stack and calls `entry`, everything on the old stack is lost.
+pub fn switch_to_boot_stack(entry: extern "C" fn() -> !) -> ! {
+    unsafe {
+        core::arch::asm!(
+            "mov rsp, {stack}",
+            "xor rbp, rbp",
+            "call {entry}",
+            stack = in(reg) BOOT_STACK.top(),
+            entry = in(reg) entry,
+            options(noreturn)
+        );
+    }
+}
+
+/// Loads `descriptors` on the CPU we are running on and returns its TSS.
+pub fn load(
+    descriptors: &'static mut Descriptors,
+    kernel_stack_top: u64,
+    double_fault_stack_top: u64,
+) -> &'static mut TaskStateSegment {
+    let tss = &mut descriptors.tss;
+    tss.privilege_stack_table[0] = kernel_stack_top;
+    tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize - 1] = double_fault_stack_top;
+
+    let (tss_low, tss_high) = tss_descriptor(tss);
+
+    let gdt = &mut descriptors.gdt;
+    gdt[5] = tss_low;
+    gdt[6] = tss_high;
+
+    let gdt_ptr = GdtPtr {
+        limit: (core::mem::size_of::<[u64; 7]>() - 1) as u16,
+        base: gdt.as_ptr() as u64,
+    };
+
+    unsafe {
+        core::arch::asm!(
+            "lgdt [{gdt_ptr}]",
+            // Reload cs with a far return
+            "push {code}",
+            "lea {tmp}, [rip + 2f]",
+            "push {tmp}",
+            "retfq",
+            "2:",
+            "mov ds, {data:x}",
+            "mov es, {data:x}",
+            "mov fs, {data:x}",
+            "mov gs, {data:x}",
+            "mov ss, {data:x}",
+            "ltr {tss:x}",
+            gdt_ptr = in(reg) &gdt_ptr,
+            code = in(reg) KERNEL_CODE_SELECTOR as u64,
+            data = in(reg) KERNEL_DATA_SELECTOR as u64,
+            tss = in(reg) TSS_SELECTOR as u64,
+            tmp = out(reg) _,
+        );
+    }
+
+    return &mut descriptors.tss;
+}
+
+/// Loads the bootstrap processor's GDT and TSS, returns the TSS.
+pub fn init() -> &'static mut TaskStateSegment {
+    return load(
+        BSP_DESCRIPTORS.lock().write(),
+        KERNEL_STACK.top(),
+        DOUBLE_FAULT_STACK.top(),
+    );
+}
@@ -1,4 +1,5 @@
-use super::idt_set_gate;
+use super::{idt_set_gate, idt_set_gate_with_ist};
+use crate::arch::gdt;
 use crate::libs::util::hcf;
 use crate::{log_error, log_info};
 
@@ -38,6 +39,16 @@ pub extern "C" fn exception_handler(int: u64, eip: u64, cs: u64, eflags: u64) ->
         eflags
     );
 
+    // A user program did something it shouldn't have, kill it instead of the whole kernel
+    if cs & 3 == 3 {
+        log_error!("Program killed");
+
+        // The kernel expects its own GS, see arch::syscall
+        unsafe { core::arch::asm!("swapgs") };
+
+        crate::sys::scheduler::exit(128 + int as usize);
+    }
+
     hcf();
 }
 
@@ -47,6 +58,9 @@ pub extern "C" fn div_error() {
         core::arch::asm!(
             // WHY DOESN'T PUSH DO THIS CORRECTLY
             "mov rdi, 0x00",
+            "mov rsi, [rsp + 0]",
+            "mov rdx, [rsp + 8]",
+            "mov rcx, [rsp + 16]",
             "call exception_handler",
             "add esp, 4",
             "iretq",
@@ -60,6 +74,9 @@ pub extern "C" fn invalid_opcode() {
     unsafe {
         core::arch::asm!(
             "mov rdi, 0x06",
+            "mov rsi, [rsp + 0]",
+            "mov rdx, [rsp + 8]",
+            "mov rcx, [rsp + 16]",
             "call exception_handler",
             "add esp, 4",
             "iretq",
@@ -73,6 +90,9 @@ pub extern "C" fn double_fault() {
     unsafe {
         core::arch::asm!(
             "mov rdi, 0x08",
+            "mov rsi, [rsp + 8]",
+            "mov rdx, [rsp + 16]",
+            "mov rcx, [rsp + 24]",
             "call exception_handler",
             "add esp, 4",
             "iretq",
@@ -86,6 +106,9 @@ pub extern "C" fn general_protection_fault() {
     unsafe {
         core::arch::asm!(
             "mov rdi, 0x0D",
+            "mov rsi, [rsp + 8]",
+            "mov rdx, [rsp + 16]",
+            "mov rcx, [rsp + 24]",
             "call exception_handler",
             "add esp, 4",
             "iretq",
@@ -99,6 +122,9 @@ pub extern "C" fn page_fault() {
     unsafe {
         core::arch::asm!(
             "mov rdi, 0x0E",
+            "mov rsi, [rsp + 8]",
+            "mov rdx, [rsp + 16]",
+            "mov rcx, [rsp + 24]",
             "call exception_handler",
             "add esp, 4",
             "iretq",
@@ -127,7 +153,7 @@ pub fn set_exceptions() {
 
     idt_set_gate(0x00, div_error as u64);
     idt_set_gate(0x06, invalid_opcode as u64);
-    idt_set_gate(0x08, double_fault as u64);
+    idt_set_gate_with_ist(0x08, double_fault as u64, gdt::DOUBLE_FAULT_IST_INDEX);
     idt_set_gate(0x0D, general_protection_fault as u64);
     idt_set_gate(0x0E, page_fault as u64);
 }
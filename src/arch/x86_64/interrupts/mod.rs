@@ -1,7 +1,11 @@
 mod exceptions;
 
+use core::sync::atomic::{AtomicU16, AtomicU8, Ordering};
+
 use crate::{arch::x86_common::pic::ChainedPics, libs::mutex::Mutex};
 
+use super::{apic, gdt};
+
 #[repr(C, packed)]
 #[derive(Clone, Copy)]
 struct IdtEntry {
@@ -37,16 +41,23 @@ struct IdtPtr {
 static IDT: Mutex<[IdtEntry; 256]> = Mutex::new([IdtEntry::new(); 256]);
 
 #[repr(u8)]
+#[derive(Clone, Copy)]
 pub enum InterruptIndex {
     Timer = PIC_1_OFFSET,
     Keyboard,
-    Ide = PIC_1_OFFSET + 14,
+    PrimaryIde = PIC_1_OFFSET + 14,
+    SecondaryIde,
 }
 
 impl InterruptIndex {
     pub fn as_u8(self) -> u8 {
         self as u8
     }
+
+    // The ISA IRQ line behind this interrupt
+    pub fn irq(self) -> u8 {
+        return self as u8 - PIC_1_OFFSET;
+    }
 }
 
 pub const PIC_1_OFFSET: u8 = 32;
@@ -54,36 +65,166 @@ pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
 
 pub static PICS: Mutex<ChainedPics> = Mutex::new(ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET));
 
+// Vectors for interrupts that don't come in on an IRQ line, like MSIs, right after the ISA ones
+// and up to the syscall gate
+const DYNAMIC_VECTORS_START: u8 = PIC_2_OFFSET + 8;
+const DYNAMIC_VECTORS_END: u8 = 0x80;
+
+static NEXT_DYNAMIC_VECTOR: AtomicU8 = AtomicU8::new(DYNAMIC_VECTORS_START);
+
+// Bitmap of the ISA IRQs with a handler, so they can be routed again when the APIC takes over
+static ENABLED_IRQS: AtomicU16 = AtomicU16::new(0);
+
 static mut IDT_PTR: IdtPtr = IdtPtr {
     limit: (core::mem::size_of::<IdtEntry>() * 256) as u16 - 1,
     base: 0,
 };
 
+// Present 64-bit interrupt gate, only reachable from ring 0 with the `int` instruction
+const GATE_KERNEL: u8 = 0x8E;
+// Same as above, but ring 3 is allowed to use `int` on it too
+const GATE_USER: u8 = 0xEE;
+
 pub fn idt_set_gate(num: u8, function_ptr: u64) {
+    set_gate(num, function_ptr, GATE_KERNEL, 0);
+}
+
+/// Like `idt_set_gate`, but the interrupt can be raised from user mode.
+pub fn idt_set_user_gate(num: u8, function_ptr: u64) {
+    set_gate(num, function_ptr, GATE_USER, 0);
+}
+
+/// Like `idt_set_gate`, but the CPU switches to the stack at `ist` in the TSS first.
+pub fn idt_set_gate_with_ist(num: u8, function_ptr: u64, ist: u8) {
+    set_gate(num, function_ptr, GATE_KERNEL, ist);
+}
+
+fn set_gate(num: u8, function_ptr: u64, flags: u8, ist: u8) {
     let base = function_ptr;
     IDT.lock().write()[num as usize] = IdtEntry {
         base_lo: (base & 0xFFFF) as u16,
         base_mid: ((base >> 16) & 0xFFFF) as u16,
         base_hi: ((base >> 32) & 0xFFFFFFFF) as u32,
-        sel: 0x28,
-        ist: 0,
+        sel: gdt::KERNEL_CODE_SELECTOR,
+        ist,
         always0: 0,
-        flags: 0xEE,
+        flags,
     };
 
     // If the interrupt with this number occurred with the "null" interrupt handler
     // We will need to tell the PIC that interrupt is over, this stops new interrupts
     // From never firing because "it was never finished"
-    PICS.lock().write().notify_end_of_interrupt(num);
+    // The APIC only lets through interrupts that have a handler, so it never needs this
+    if !apic::is_enabled() {
+        PICS.lock().write().notify_end_of_interrupt(num);
+    }
+}
+
+/// Installs `function_ptr` as the handler of a hardware interrupt and lets the interrupt through
+/// whichever interrupt controller is in use.
+pub fn set_irq_handler(index: InterruptIndex, function_ptr: u64) {
+    idt_set_gate(index.as_u8(), function_ptr);
+
+    ENABLED_IRQS.fetch_or(1 << index.irq(), Ordering::SeqCst);
+
+    if apic::is_enabled() {
+        apic::route_irq(index.irq(), index.as_u8());
+    }
+}
+
+/// Installs `function_ptr` on a free vector and returns it, None once they've run out. These
+/// interrupts only exist with the APIC, so their handlers acknowledge them with
+/// `apic::end_of_interrupt`.
+pub fn allocate_vector(function_ptr: u64) -> Option<u8> {
+    let mut vector = NEXT_DYNAMIC_VECTOR.load(Ordering::SeqCst);
+
+    loop {
+        if vector >= DYNAMIC_VECTORS_END {
+            return None;
+        }
+
+        match NEXT_DYNAMIC_VECTOR.compare_exchange(
+            vector,
+            vector + 1,
+            Ordering::SeqCst,
+            Ordering::SeqCst,
+        ) {
+            Ok(_) => break,
+            Err(current) => vector = current,
+        }
+    }
+
+    idt_set_gate(vector, function_ptr);
+
+    return Some(vector);
+}
+
+/// The ISA IRQs that have a handler installed.
+pub fn enabled_irqs() -> impl Iterator<Item = u8> {
+    let enabled_irqs = ENABLED_IRQS.load(Ordering::SeqCst);
+
+    return (0..16).filter(move |irq| enabled_irqs & (1 << irq) != 0);
+}
+
+/// Tells the interrupt controller in use that the interrupt `index` has been handled.
+pub fn end_of_interrupt(index: InterruptIndex) {
+    if apic::is_enabled() {
+        apic::end_of_interrupt();
+        return;
+    }
+
+    PICS.lock().write().notify_end_of_interrupt(index.as_u8());
 }
 
 extern "x86-interrupt" fn null_interrupt_handler() {}
 
-extern "x86-interrupt" fn timer_handler() {
-    // crate::usr::tty::puts(".");
-    PICS.lock()
-        .write()
-        .notify_end_of_interrupt(InterruptIndex::Timer.as_u8());
+// The scheduler can switch tasks from inside the timer interrupt, so the kernel GS has to be in
+// place (see arch::syscall) and the switched to task may leave through a different path
+#[naked]
+extern "C" fn timer_interrupt() {
+    unsafe {
+        core::arch::asm!(
+            "test qword ptr [rsp + 8], 3",
+            "jz 2f",
+            "swapgs",
+            "2:",
+            // Everything the called function is allowed to clobber
+            "push rax",
+            "push rcx",
+            "push rdx",
+            "push rsi",
+            "push rdi",
+            "push r8",
+            "push r9",
+            "push r10",
+            "push r11",
+            "call {}",
+            "pop r11",
+            "pop r10",
+            "pop r9",
+            "pop r8",
+            "pop rdi",
+            "pop rsi",
+            "pop rdx",
+            "pop rcx",
+            "pop rax",
+            "test qword ptr [rsp + 8], 3",
+            "jz 3f",
+            "swapgs",
+            "3:",
+            "iretq",
+            sym timer_handler,
+            options(noreturn)
+        );
+    }
+}
+
+extern "C" fn timer_handler() {
+    // Has to happen before switching away, or the next tick never comes
+    end_of_interrupt(InterruptIndex::Timer);
+
+    crate::sys::time::tick();
+    crate::sys::scheduler::tick();
 }
 
 fn idt_init() {
@@ -104,12 +245,33 @@ fn idt_init() {
 
         exceptions::set_exceptions();
 
-        idt_set_gate(InterruptIndex::Timer.as_u8(), timer_handler as u64);
-        idt_set_gate(0x80, syscall as u64);
+        set_irq_handler(InterruptIndex::Timer, timer_interrupt as u64);
+        idt_set_user_gate(0x80, syscall as u64);
+    }
 
+    load_idt();
+}
+
+/// Loads the IDT on the CPU we are running on, every CPU shares the same one.
+pub fn load_idt() {
+    unsafe {
+        core::arch::asm!(
+            "lidt [{}]",
+            in(reg) core::ptr::addr_of!(IDT_PTR)
+        );
+    }
+}
+
+/// Resets the CPU by loading an empty IDT and raising an exception, which has nowhere to go.
+pub fn triple_fault() -> ! {
+    let empty_idt = IdtPtr { limit: 0, base: 0 };
+
+    unsafe {
         core::arch::asm!(
             "lidt [{}]",
-            in(reg) &IDT_PTR
+            "int3",
+            in(reg) core::ptr::addr_of!(empty_idt),
+            options(noreturn)
         );
     }
 }
@@ -118,15 +280,45 @@ fn idt_init() {
 pub extern "C" fn syscall() {
     unsafe {
         core::arch::asm!(
-            "push rdi",
-            "push rsi",
-            "push rdx",
+            // Switch to the kernel GS if we came from ring 3, see arch::syscall
+            "test qword ptr [rsp + 8], 3",
+            "jz 2f",
+            "swapgs",
+            "2:",
+            // Everything the called function is allowed to clobber, user code only expects rax to
+            // change
             "push rcx",
+            "push rdx",
+            "push rsi",
+            "push rdi",
+            "push r8",
+            "push r9",
+            "push r10",
+            "push r11",
+            // Keep the stack 16 byte aligned for the call
+            "sub rsp, 8",
+            // Number in rax, arguments in rdi, rsi, rdx, r10 and r8, shuffle them into the C
+            // calling convention
+            "mov r9, r8",
+            "mov r8, r10",
+            "mov rcx, rdx",
+            "mov rdx, rsi",
+            "mov rsi, rdi",
+            "mov rdi, rax",
             "call {}",
+            "add rsp, 8",
+            "pop r11",
+            "pop r10",
+            "pop r9",
+            "pop r8",
             "pop rdi",
             "pop rsi",
             "pop rdx",
             "pop rcx",
+            "test qword ptr [rsp + 8], 3",
+            "jz 3f",
+            "swapgs",
+            "3:",
             "iretq",
             options(noreturn),
             sym syscall_handler
@@ -134,13 +326,15 @@ pub extern "C" fn syscall() {
     }
 }
 
-pub extern "C" fn syscall_handler(rdi: u64, rsi: u64, rdx: u64, rcx: u64) {
-    let buf = rdx as *const u8; // Treat as pointer to u8 (byte array)
-    let count = rcx as usize;
-
-    let slice = unsafe { core::slice::from_raw_parts(buf, count) };
-    let message = core::str::from_utf8(slice).unwrap();
-    crate::print!("{}", message);
+pub extern "C" fn syscall_handler(
+    number: u64,
+    arg0: u64,
+    arg1: u64,
+    arg2: u64,
+    arg3: u64,
+    arg4: u64,
+) -> i64 {
+    return crate::sys::syscall::dispatch(number, arg0, arg1, arg2, arg3, arg4);
 }
 
 pub fn init() {
@@ -1,4 +1,10 @@
+pub mod apic;
+pub mod context;
+pub mod gdt;
 pub mod interrupts;
+pub mod paging;
+pub mod smp;
+pub mod syscall;
 
 #[path = "../x86_common/mod.rs"]
 pub mod common;
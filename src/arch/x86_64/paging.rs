@@ -0,0 +1,490 @@
+// Four level page table management on top of the page tables Limine left us with.
+// Page tables are always accessed through the HHDM, so an address space doesn't need to be active
+// to be changed.
+
+use core::{
+    ops::BitOr,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use limine::HhdmRequest;
+
+use crate::{
+    arch::{rdmsr, without_interrupts},
+    sys::frame_allocator::FRAME_ALLOCATOR,
+};
+
+pub static HHDM_REQUEST: HhdmRequest = HhdmRequest::new(0);
+
+pub const PAGE_SIZE: u64 = 0x1000;
+pub const LARGE_PAGE_SIZE: u64 = 0x20_0000;
+
+// PML4 entry 0 holds Limine's identity mapping of the low 4GiB. It is shared with every address
+// space, so user mappings start at the next entry and end where the kernel's upper half begins.
+pub const USER_SPACE_START: u64 = 0x0000_0080_0000_0000;
+pub const USER_SPACE_END: u64 = 0x0000_8000_0000_0000;
+
+const USER_PML4_ENTRIES: core::ops::Range<usize> = 1..256;
+const KERNEL_PML4_ENTRIES: core::ops::Range<usize> = 256..512;
+
+const ADDRESS_MASK: u64 = 0x000F_FFFF_FFFF_F000;
+
+// Device memory gets a PML4 entry of its own, right after the kernel heap's
+const MMIO_START: u64 = 0xFFFF_B000_0000_0000;
+const MMIO_END: u64 = MMIO_START + 0x80_0000_0000;
+
+const IA32_EFER: u32 = 0xC000_0080;
+const EFER_NO_EXECUTE_ENABLE: u64 = 1 << 11;
+
+static mut KERNEL_PML4: u64 = 0;
+// Limine's response lives in bootloader reclaimable memory, so it is read once in `init`
+static mut HHDM_OFFSET: u64 = 0;
+
+static NEXT_MMIO_ADDRESS: AtomicU64 = AtomicU64::new(MMIO_START);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PageFlags(u64);
+
+impl PageFlags {
+    pub const PRESENT: Self = Self(1 << 0);
+    pub const WRITABLE: Self = Self(1 << 1);
+    pub const USER: Self = Self(1 << 2);
+    pub const WRITE_THROUGH: Self = Self(1 << 3);
+    pub const NO_CACHE: Self = Self(1 << 4);
+    const HUGE_PAGE: Self = Self(1 << 7);
+    pub const GLOBAL: Self = Self(1 << 8);
+    pub const NO_EXECUTE: Self = Self(1 << 63);
+
+    pub const fn empty() -> Self {
+        return Self(0);
+    }
+
+    pub const fn bits(self) -> u64 {
+        return self.0;
+    }
+
+    pub const fn contains(self, other: Self) -> bool {
+        return self.0 & other.0 == other.0;
+    }
+
+    pub const fn without(self, other: Self) -> Self {
+        return Self(self.0 & !other.0);
+    }
+
+    fn from_entry(entry: u64) -> Self {
+        return Self(entry & !ADDRESS_MASK & !Self::HUGE_PAGE.0);
+    }
+}
+
+impl BitOr for PageFlags {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        return Self(self.0 | rhs.0);
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PageSize {
+    Small,
+    Large,
+}
+
+impl PageSize {
+    pub const fn bytes(self) -> u64 {
+        return match self {
+            PageSize::Small => PAGE_SIZE,
+            PageSize::Large => LARGE_PAGE_SIZE,
+        };
+    }
+
+    // Level of the table the page's entry lives in, 0 being the PT
+    const fn level(self) -> usize {
+        return match self {
+            PageSize::Small => 0,
+            PageSize::Large => 1,
+        };
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PagingError {
+    NotMapped,
+    AlreadyMapped,
+    Misaligned,
+    // The page is mapped, but with a different page size than asked for
+    SizeMismatch,
+    // User pages outside of the user half, they would end up in tables shared with the kernel
+    InvalidAddress,
+    OutOfMemory,
+}
+
+pub fn hhdm_offset() -> u64 {
+    return unsafe { HHDM_OFFSET };
+}
+
+fn read_cr3() -> u64 {
+    let cr3: u64;
+    unsafe { core::arch::asm!("mov {}, cr3", out(reg) cr3) };
+    return cr3 & ADDRESS_MASK;
+}
+
+fn table_at(physical_address: u64) -> &'static mut [u64; 512] {
+    return unsafe { &mut *((physical_address + hhdm_offset()) as *mut [u64; 512]) };
+}
+
+// Page tables get a frame of their own, the kernel heap can't be used since it grows by mapping
+// pages, which may need new tables
+fn allocate_table() -> Result<u64, PagingError> {
+    let table = without_interrupts(|| FRAME_ALLOCATOR.lock().write().allocate())
+        .ok_or(PagingError::OutOfMemory)?;
+
+    table_at(table).fill(0);
+
+    return Ok(table);
+}
+
+fn free_table(physical_address: u64) {
+    without_interrupts(|| FRAME_ALLOCATOR.lock().write().free(physical_address));
+}
+
+fn index_at(virtual_address: u64, level: usize) -> usize {
+    return ((virtual_address >> (12 + level * 9)) & 0x1FF) as usize;
+}
+
+fn no_execute_supported() -> bool {
+    return rdmsr(IA32_EFER) & EFER_NO_EXECUTE_ENABLE != 0;
+}
+
+pub struct AddressSpace {
+    // Physical address of the PML4
+    pml4: u64,
+    // The kernel's address space is never freed
+    owned: bool,
+}
+
+impl AddressSpace {
+    /// The address space the kernel was booted with.
+    pub fn kernel() -> Self {
+        return Self {
+            pml4: unsafe { KERNEL_PML4 },
+            owned: false,
+        };
+    }
+
+    /// Creates an empty address space for a user program, sharing the kernel's mappings.
+    pub fn new_user() -> Result<Self, PagingError> {
+        let pml4 = allocate_table()?;
+
+        let kernel_table = table_at(unsafe { KERNEL_PML4 });
+        let table = table_at(pml4);
+
+        table[0] = kernel_table[0];
+        for i in KERNEL_PML4_ENTRIES {
+            table[i] = kernel_table[i];
+        }
+
+        return Ok(Self { pml4, owned: true });
+    }
+
+    pub fn is_active(&self) -> bool {
+        return read_cr3() == self.pml4;
+    }
+
+    pub fn activate(&self) {
+        unsafe { core::arch::asm!("mov cr3, {}", in(reg) self.pml4) };
+    }
+
+    fn flush(&self, virtual_address: u64) {
+        if self.is_active() {
+            unsafe { core::arch::asm!("invlpg [{}]", in(reg) virtual_address) };
+        }
+    }
+
+    // Walks down to the table holding the entry for `virtual_address` at `level`, creating the
+    // tables on the way if `create` is set
+    fn find_entry(
+        &mut self,
+        virtual_address: u64,
+        level: usize,
+        create: Option<PageFlags>,
+    ) -> Result<&'static mut u64, PagingError> {
+        let mut table = table_at(self.pml4);
+
+        for current_level in ((level + 1)..4).rev() {
+            let entry = &mut table[index_at(virtual_address, current_level)];
+
+            if *entry & PageFlags::PRESENT.bits() == 0 {
+                let parent_flags = match create {
+                    Some(parent_flags) => parent_flags,
+                    None => return Err(PagingError::NotMapped),
+                };
+
+                *entry = allocate_table()? | parent_flags.bits();
+            } else if *entry & PageFlags::HUGE_PAGE.bits() != 0 {
+                return match create {
+                    Some(_) => Err(PagingError::AlreadyMapped),
+                    None => Err(PagingError::SizeMismatch),
+                };
+            } else if let Some(parent_flags) = create {
+                // Permissions are the intersection of every level, so parents need to allow
+                // anything their children do
+                *entry |= parent_flags.bits();
+            }
+
+            table = table_at(*entry & ADDRESS_MASK);
+        }
+
+        return Ok(&mut table[index_at(virtual_address, level)]);
+    }
+
+    fn leaf_entry(&self, flags: PageFlags, size: PageSize) -> u64 {
+        let mut flags = flags | PageFlags::PRESENT;
+
+        if !no_execute_supported() {
+            flags = flags.without(PageFlags::NO_EXECUTE);
+        }
+
+        if size == PageSize::Large {
+            flags = flags | PageFlags::HUGE_PAGE;
+        }
+
+        return flags.bits();
+    }
+
+    pub fn map(
+        &mut self,
+        virtual_address: u64,
+        physical_address: u64,
+        size: PageSize,
+        flags: PageFlags,
+    ) -> Result<(), PagingError> {
+        if virtual_address % size.bytes() != 0 || physical_address % size.bytes() != 0 {
+            return Err(PagingError::Misaligned);
+        }
+
+        if flags.contains(PageFlags::USER)
+            && !(USER_SPACE_START..USER_SPACE_END).contains(&virtual_address)
+        {
+            return Err(PagingError::InvalidAddress);
+        }
+
+        let parent_flags = PageFlags::PRESENT
+            | PageFlags::WRITABLE
+            | if flags.contains(PageFlags::USER) {
+                PageFlags::USER
+            } else {
+                PageFlags::empty()
+            };
+
+        let entry = self.find_entry(virtual_address, size.level(), Some(parent_flags))?;
+
+        if *entry & PageFlags::PRESENT.bits() != 0 {
+            return Err(PagingError::AlreadyMapped);
+        }
+
+        *entry = physical_address | self.leaf_entry(flags, size);
+
+        self.flush(virtual_address);
+
+        return Ok(());
+    }
+
+    /// Removes the mapping at `virtual_address` and returns the physical address it pointed to.
+    pub fn unmap(&mut self, virtual_address: u64, size: PageSize) -> Result<u64, PagingError> {
+        let entry = self.mapped_entry(virtual_address, size)?;
+        let physical_address = *entry & ADDRESS_MASK;
+
+        *entry = 0;
+
+        self.flush(virtual_address);
+
+        return Ok(physical_address);
+    }
+
+    /// Replaces the flags of an existing mapping.
+    pub fn protect(
+        &mut self,
+        virtual_address: u64,
+        size: PageSize,
+        flags: PageFlags,
+    ) -> Result<(), PagingError> {
+        let leaf_flags = self.leaf_entry(flags, size);
+        let entry = self.mapped_entry(virtual_address, size)?;
+
+        *entry = (*entry & ADDRESS_MASK) | leaf_flags;
+
+        self.flush(virtual_address);
+
+        return Ok(());
+    }
+
+    fn mapped_entry(
+        &mut self,
+        virtual_address: u64,
+        size: PageSize,
+    ) -> Result<&'static mut u64, PagingError> {
+        if virtual_address % size.bytes() != 0 {
+            return Err(PagingError::Misaligned);
+        }
+
+        let entry = self.find_entry(virtual_address, size.level(), None)?;
+
+        if *entry & PageFlags::PRESENT.bits() == 0 {
+            return Err(PagingError::NotMapped);
+        }
+
+        let is_huge = *entry & PageFlags::HUGE_PAGE.bits() != 0;
+        if is_huge != (size == PageSize::Large) {
+            return Err(PagingError::SizeMismatch);
+        }
+
+        return Ok(entry);
+    }
+
+    /// Returns the physical address and flags `virtual_address` is mapped to.
+    pub fn translate(&self, virtual_address: u64) -> Option<(u64, PageFlags)> {
+        let mut table = table_at(self.pml4);
+
+        for level in (0..4).rev() {
+            let entry = table[index_at(virtual_address, level)];
+
+            if entry & PageFlags::PRESENT.bits() == 0 {
+                return None;
+            }
+
+            if level == 0 || entry & PageFlags::HUGE_PAGE.bits() != 0 {
+                let page_mask = (1u64 << (12 + level * 9)) - 1;
+                let physical_address =
+                    (entry & ADDRESS_MASK & !page_mask) | (virtual_address & page_mask);
+
+                return Some((physical_address, PageFlags::from_entry(entry)));
+            }
+
+            table = table_at(entry & ADDRESS_MASK);
+        }
+
+        return None;
+    }
+}
+
+fn free_tables(table_address: u64, level: usize) {
+    if level > 0 {
+        for entry in table_at(table_address).iter() {
+            if entry & PageFlags::PRESENT.bits() != 0 && entry & PageFlags::HUGE_PAGE.bits() == 0 {
+                free_tables(entry & ADDRESS_MASK, level - 1);
+            }
+        }
+    }
+
+    free_table(table_address);
+}
+
+impl Drop for AddressSpace {
+    fn drop(&mut self) {
+        if !self.owned {
+            return;
+        }
+
+        if self.is_active() {
+            AddressSpace::kernel().activate();
+        }
+
+        // Only the user half belongs to us, the rest is shared with the kernel. The pages
+        // themselves are owned by whoever mapped them
+        let table = table_at(self.pml4);
+        for i in USER_PML4_ENTRIES {
+            if table[i] & PageFlags::PRESENT.bits() != 0 {
+                free_tables(table[i] & ADDRESS_MASK, 2);
+            }
+        }
+
+        free_table(self.pml4);
+    }
+}
+
+// Copies the table and every table below it that `should_move` picks into frames of our own,
+// returns where the table ended up
+fn relocate_table(
+    table_address: u64,
+    level: usize,
+    should_move: &impl Fn(u64) -> bool,
+) -> Result<u64, PagingError> {
+    if level > 0 {
+        for entry in table_at(table_address).iter_mut() {
+            if *entry & PageFlags::PRESENT.bits() == 0 || *entry & PageFlags::HUGE_PAGE.bits() != 0
+            {
+                continue;
+            }
+
+            let child = relocate_table(*entry & ADDRESS_MASK, level - 1, should_move)?;
+            *entry = (*entry & !ADDRESS_MASK) | child;
+        }
+    }
+
+    if !should_move(table_address) {
+        return Ok(table_address);
+    }
+
+    let new_table = allocate_table()?;
+    table_at(new_table).copy_from_slice(table_at(table_address));
+
+    return Ok(new_table);
+}
+
+/// Moves the kernel's page tables that live in physical memory `should_move` returns true for
+/// into frames from the frame allocator. Must be done before that memory is given away, since
+/// Limine builds its page tables in bootloader reclaimable memory.
+pub fn relocate_kernel_tables(should_move: impl Fn(u64) -> bool) -> Result<(), PagingError> {
+    let pml4 = relocate_table(unsafe { KERNEL_PML4 }, 3, &should_move)?;
+
+    unsafe { KERNEL_PML4 = pml4 };
+
+    // Also gets rid of any cached pointers to the old tables
+    AddressSpace::kernel().activate();
+
+    return Ok(());
+}
+
+/// Maps `size` bytes of device registers at `physical_address` into the kernel's half, uncached,
+/// and returns the virtual address of `physical_address`. User address spaces only share the
+/// kernel's PML4 entries that existed when they were made, so the first call has to happen before
+/// any program is started.
+pub fn map_mmio(physical_address: u64, size: u64) -> Result<u64, PagingError> {
+    let offset = physical_address % PAGE_SIZE;
+    let base = physical_address - offset;
+    let length = (offset + size).div_ceil(PAGE_SIZE) * PAGE_SIZE;
+
+    let virtual_address = NEXT_MMIO_ADDRESS.fetch_add(length, Ordering::SeqCst);
+
+    if virtual_address + length > MMIO_END {
+        return Err(PagingError::OutOfMemory);
+    }
+
+    let mut kernel_space = AddressSpace::kernel();
+
+    for page in (0..length).step_by(PAGE_SIZE as usize) {
+        kernel_space.map(
+            virtual_address + page,
+            base + page,
+            PageSize::Small,
+            PageFlags::WRITABLE
+                | PageFlags::NO_CACHE
+                | PageFlags::WRITE_THROUGH
+                | PageFlags::NO_EXECUTE,
+        )?;
+    }
+
+    return Ok(virtual_address + offset);
+}
+
+pub fn init() {
+    unsafe {
+        KERNEL_PML4 = read_cr3();
+        HHDM_OFFSET = HHDM_REQUEST
+            .get_response()
+            .get()
+            .expect("Limine did not give us a HHDM!")
+            .offset;
+    }
+}
@@ -0,0 +1,306 @@
+// Brings up the application processors Limine parked for us. Each one gets its own GDT, TSS,
+// per-CPU data and local APIC, then goes to the scheduler's idle loop for APs.
+// Tasks only run on the bootstrap processor for now, the scheduler's state isn't safe to share
+// between CPUs yet. For the same reason the APs never touch the heap, everything they need is
+// allocated for them up front.
+// An AP starts out on Limine's stack and page tables, which are gone once bootloader memory is
+// reclaimed. So an AP has to claim its start before it uses them, and one that shows up after we
+// stopped waiting for it halts instead.
+
+use core::{
+    fmt,
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+use alloc::{boxed::Box, vec, vec::Vec};
+use limine::{SmpInfo, SmpRequest};
+
+use crate::{
+    arch,
+    libs::mutex::Mutex,
+    sys::{scheduler, time::Deadline},
+};
+
+use super::{
+    apic,
+    gdt::{self, Descriptors},
+    interrupts,
+    paging::AddressSpace,
+    syscall::{self, PerCpu},
+};
+
+static SMP_REQUEST: SmpRequest = SmpRequest::new(0);
+
+const AP_STACK_SIZE: usize = 0x10000;
+const AP_IST_STACK_SIZE: usize = 0x4000;
+
+// How long the APs get to check in before we stop waiting for them
+const AP_STARTUP_TIMEOUT_MS: u64 = 1000;
+// How long an AP that claimed its start gets to finish it after that
+const AP_CLAIMED_TIMEOUT_MS: u64 = 100;
+
+// CPUs past this many are left parked
+const MAX_CPUS: usize = 256;
+
+// Where an AP is in starting up
+const AP_STARTING: u8 = 0;
+// It is still on Limine's stack and page tables, but they won't go away under it
+const AP_CLAIMED: u8 = 1;
+const AP_ONLINE: u8 = 2;
+// We stopped waiting for it, it halts if it still shows up
+const AP_ABANDONED: u8 = 3;
+
+// Indexed by CPU ID. A static rather than part of CPUS, an AP checks its state before it can see
+// the heap
+static AP_STATES: [AtomicU8; MAX_CPUS] = [const { AtomicU8::new(AP_STARTING) }; MAX_CPUS];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CpuState {
+    Running,
+    Idle,
+    // Never checked in after being started
+    Offline,
+}
+
+impl fmt::Display for CpuState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            CpuState::Running => write!(f, "running"),
+            CpuState::Idle => write!(f, "idle"),
+            CpuState::Offline => write!(f, "offline"),
+        };
+    }
+}
+
+/// A snapshot of a CPU, for listing them.
+pub struct CpuInfo {
+    pub id: usize,
+    pub apic_id: u32,
+    pub bootstrap: bool,
+    pub state: CpuState,
+}
+
+struct Cpu {
+    apic_id: u32,
+    bootstrap: bool,
+    // The AP's ApStart, zero for the BSP
+    start: u64,
+}
+
+// Indexed by CPU ID, never grows once the APs are started
+static CPUS: Mutex<Vec<Cpu>> = Mutex::new(Vec::new());
+
+// Everything an AP needs to get going, handed to it through Limine's extra argument
+struct ApStart {
+    id: usize,
+    stack_top: u64,
+    double_fault_stack_top: u64,
+    descriptors: *mut Descriptors,
+    per_cpu: *mut PerCpu,
+}
+
+fn leak_stack(size: usize) -> u64 {
+    let stack = Box::leak(vec![0u8; size].into_boxed_slice());
+    return (stack.as_ptr() as u64 + size as u64) & !0xF;
+}
+
+// Limine's info structure and the stack the AP starts on are in bootloader reclaimable memory,
+// so this gets off of both before doing anything else
+extern "C" fn ap_entry(info: *const SmpInfo) -> ! {
+    let id = unsafe { (*info).extra_argument } as usize;
+
+    if AP_STATES[id]
+        .compare_exchange(AP_STARTING, AP_CLAIMED, Ordering::SeqCst, Ordering::SeqCst)
+        .is_err()
+    {
+        // Too late, the stack we are on may already belong to something else, so nothing may
+        // touch it anymore
+        unsafe { core::arch::asm!("cli", "2:", "hlt", "jmp 2b", options(noreturn)) };
+    }
+
+    // Limine gives the APs the page tables it booted us with, which we have moved since
+    AddressSpace::kernel().activate();
+
+    let start = CPUS.lock().read()[id].start as *mut ApStart;
+
+    unsafe {
+        core::arch::asm!(
+            "mov rsp, {stack}",
+            "xor rbp, rbp",
+            "call {entry}",
+            stack = in(reg) (*start).stack_top,
+            entry = sym ap_main,
+            in("rdi") start,
+            options(noreturn)
+        );
+    }
+}
+
+extern "C" fn ap_main(start: *mut ApStart) -> ! {
+    let start = unsafe { &mut *start };
+
+    let tss = gdt::load(
+        unsafe { &mut *start.descriptors },
+        start.stack_top,
+        start.double_fault_stack_top,
+    );
+    syscall::init_cpu(unsafe { &mut *start.per_cpu }, tss);
+
+    interrupts::load_idt();
+    apic::init_local_apic();
+
+    AP_STATES[start.id].store(AP_ONLINE, Ordering::SeqCst);
+
+    scheduler::ap_idle();
+}
+
+/// Starts every application processor, has to happen before bootloader memory is reclaimed.
+pub fn init() {
+    let bsp = Cpu {
+        apic_id: apic::local_apic_id(),
+        bootstrap: true,
+        start: 0,
+    };
+
+    AP_STATES[0].store(AP_ONLINE, Ordering::SeqCst);
+
+    let smp_response = match SMP_REQUEST.get_response().get_mut() {
+        Some(smp_response) if apic::is_enabled() => smp_response,
+        _ => {
+            CPUS.lock().write().push(bsp);
+            crate::log_info!("Running on the bootstrap processor only");
+            return;
+        }
+    };
+
+    let bsp_apic_id = smp_response.bsp_lapic_id;
+    let limine_cpus = smp_response.cpus();
+
+    // Every CPU is in the list before any AP starts, so they can look themselves up without it
+    // moving
+    let cpus = CPUS.lock().write();
+    cpus.reserve(limine_cpus.len());
+    cpus.push(bsp);
+
+    for limine_cpu in limine_cpus.iter() {
+        if limine_cpu.lapic_id == bsp_apic_id {
+            continue;
+        }
+
+        if cpus.len() == MAX_CPUS {
+            crate::log_info!("Only starting the first {} CPUs", MAX_CPUS);
+            break;
+        }
+
+        let id = cpus.len();
+
+        let start = Box::leak(Box::new(ApStart {
+            id,
+            stack_top: leak_stack(AP_STACK_SIZE),
+            double_fault_stack_top: leak_stack(AP_IST_STACK_SIZE),
+            descriptors: Box::leak(Box::new(Descriptors::new())),
+            per_cpu: Box::leak(Box::new(PerCpu::new())),
+        }));
+
+        cpus.push(Cpu {
+            apic_id: limine_cpu.lapic_id,
+            bootstrap: false,
+            start: start as *mut ApStart as u64,
+        });
+    }
+
+    let ap_count = cpus.len() - 1;
+
+    // Both lists are in the same order, minus the BSP
+    let limine_aps = limine_cpus
+        .iter_mut()
+        .filter(|limine_cpu| limine_cpu.lapic_id != bsp_apic_id)
+        .take(ap_count);
+
+    for (id, limine_cpu) in (1..).zip(limine_aps) {
+        // The write to goto_address is what wakes the AP up, so it has to come last
+        unsafe {
+            core::ptr::write_volatile(&mut limine_cpu.extra_argument, id as u64);
+            core::ptr::write_volatile(
+                &mut limine_cpu.goto_address,
+                ap_entry as extern "C" fn(*const SmpInfo) -> !,
+            );
+        }
+    }
+
+    let mut deadline = Deadline::after_ms(AP_STARTUP_TIMEOUT_MS);
+
+    while count_in_state(AP_ONLINE) < cpus.len() && !deadline.has_passed() {
+        arch::pause();
+    }
+
+    let limine_aps = limine_cpus
+        .iter_mut()
+        .filter(|limine_cpu| limine_cpu.lapic_id != bsp_apic_id)
+        .take(ap_count);
+
+    // Whatever hasn't claimed its start by now never gets to
+    for (id, limine_cpu) in (1..).zip(limine_aps) {
+        if AP_STATES[id]
+            .compare_exchange(
+                AP_STARTING,
+                AP_ABANDONED,
+                Ordering::SeqCst,
+                Ordering::SeqCst,
+            )
+            .is_ok()
+        {
+            // The limine crate types goto_address as a fn pointer, but the AP only jumps to it
+            // once it is nonzero
+            let goto_address = &mut limine_cpu.goto_address as *mut _ as *mut u64;
+
+            unsafe { core::ptr::write_volatile(goto_address, 0) };
+        }
+    }
+
+    // And those that did have to be off of bootloader memory before it can be reclaimed
+    let mut deadline = Deadline::after_ms(AP_CLAIMED_TIMEOUT_MS);
+
+    while count_in_state(AP_CLAIMED) > 0 {
+        if deadline.has_passed() {
+            crate::log_error!("An application processor got stuck starting up");
+            break;
+        }
+
+        arch::pause();
+    }
+
+    crate::log_ok!(
+        "Started {} of {} application processor(s)",
+        count_in_state(AP_ONLINE) - 1,
+        ap_count
+    );
+}
+
+fn count_in_state(state: u8) -> usize {
+    let cpu_count = CPUS.lock().read().len();
+
+    return AP_STATES[..cpu_count]
+        .iter()
+        .filter(|ap_state| ap_state.load(Ordering::SeqCst) == state)
+        .count();
+}
+
+pub fn cpus() -> Vec<CpuInfo> {
+    return CPUS
+        .lock()
+        .read()
+        .iter()
+        .enumerate()
+        .map(|(id, cpu)| CpuInfo {
+            id,
+            apic_id: cpu.apic_id,
+            bootstrap: cpu.bootstrap,
+            state: match (cpu.bootstrap, AP_STATES[id].load(Ordering::SeqCst)) {
+                (true, _) => CpuState::Running,
+                (false, AP_ONLINE) => CpuState::Idle,
+                (false, _) => CpuState::Offline,
+            },
+        })
+        .collect();
+}
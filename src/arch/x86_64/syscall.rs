@@ -0,0 +1,135 @@
+// Fast system calls with the `syscall` instruction. `int 0x80` still works and ends up in the same
+// dispatcher.
+//
+// The kernel always runs with GS pointing at the per-CPU data below, user mode gets whatever is
+// in KERNEL_GS_BASE. Every way into the kernel from ring 3 that cares about GS does a `swapgs`
+// on the way in, and another one on the way out.
+
+use crate::arch::{rdmsr, wrmsr};
+
+use super::gdt::{self, TaskStateSegment};
+
+const IA32_EFER: u32 = 0xC000_0080;
+const IA32_STAR: u32 = 0xC000_0081;
+const IA32_LSTAR: u32 = 0xC000_0082;
+const IA32_FMASK: u32 = 0xC000_0084;
+const IA32_GS_BASE: u32 = 0xC000_0101;
+const IA32_KERNEL_GS_BASE: u32 = 0xC000_0102;
+
+const EFER_SYSCALL_ENABLE: u64 = 1 << 0;
+
+// RFLAGS bits cleared on entry: TF, IF, DF, IOPL, NT and AC
+const SYSCALL_FLAGS_MASK: u64 = (1 << 8) | (1 << 9) | (1 << 10) | (3 << 12) | (1 << 14) | (1 << 18);
+
+/// Data that every CPU has its own copy of, found through GS while in the kernel.
+#[repr(C)]
+pub struct PerCpu {
+    // Loaded into rsp when a syscall comes in, the offsets are used by `syscall_entry`
+    kernel_stack: u64,
+    // Where the user stack pointer is kept until the syscall returns
+    user_stack: u64,
+    // Address of this block, GS relative loads are the only way to find it
+    this: *mut PerCpu,
+    tss: *mut TaskStateSegment,
+}
+
+impl PerCpu {
+    pub const fn new() -> Self {
+        return Self {
+            kernel_stack: 0,
+            user_stack: 0,
+            this: core::ptr::null_mut(),
+            tss: core::ptr::null_mut(),
+        };
+    }
+}
+
+static mut BSP_PER_CPU: PerCpu = PerCpu::new();
+
+fn current() -> &'static mut PerCpu {
+    let this: *mut PerCpu;
+    unsafe { core::arch::asm!("mov {}, gs:[16]", out(reg) this, options(nostack, readonly)) };
+    return unsafe { &mut *this };
+}
+
+pub fn set_kernel_stack(stack_top: u64) {
+    let per_cpu = current();
+
+    per_cpu.kernel_stack = stack_top;
+    unsafe { (*per_cpu.tss).privilege_stack_table[0] = stack_top };
+}
+
+#[naked]
+extern "C" fn syscall_entry() {
+    unsafe {
+        core::arch::asm!(
+            // rcx holds the user rip and r11 the user rflags, interrupts are already off thanks
+            // to the flags mask
+            "swapgs",
+            "mov gs:[8], rsp",
+            "mov rsp, gs:[0]",
+            "push qword ptr gs:[8]",
+            "push r11",
+            "push rcx",
+            // The rest of the registers the called function is allowed to clobber
+            "push rdx",
+            "push rsi",
+            "push rdi",
+            "push r8",
+            "push r9",
+            "push r10",
+            // Keep the stack 16 byte aligned for the call
+            "sub rsp, 8",
+            "mov r9, r8",
+            "mov r8, r10",
+            "mov rcx, rdx",
+            "mov rdx, rsi",
+            "mov rsi, rdi",
+            "mov rdi, rax",
+            "call {}",
+            "add rsp, 8",
+            "pop r10",
+            "pop r9",
+            "pop r8",
+            "pop rdi",
+            "pop rsi",
+            "pop rdx",
+            "pop rcx",
+            "pop r11",
+            "pop rsp",
+            "swapgs",
+            "sysretq",
+            sym super::interrupts::syscall_handler,
+            options(noreturn)
+        );
+    }
+}
+
+/// Points GS at `per_cpu` and enables syscalls on the CPU we are running on.
+pub fn init_cpu(per_cpu: &'static mut PerCpu, tss: &'static mut TaskStateSegment) {
+    per_cpu.kernel_stack = tss.privilege_stack_table[0];
+    per_cpu.tss = tss;
+
+    let this = per_cpu as *mut PerCpu;
+    per_cpu.this = this;
+
+    unsafe {
+        wrmsr(IA32_GS_BASE, this as u64);
+        wrmsr(IA32_KERNEL_GS_BASE, 0);
+
+        wrmsr(IA32_EFER, rdmsr(IA32_EFER) | EFER_SYSCALL_ENABLE);
+
+        // syscall loads cs from bits 32..48 and ss from the next descriptor. sysret loads ss
+        // from 8 past bits 48..64 and cs from 16 past them, which are the user data and code
+        // selectors
+        let star = ((gdt::KERNEL_CODE_SELECTOR as u64) << 32)
+            | (((gdt::KERNEL_DATA_SELECTOR | 3) as u64) << 48);
+        wrmsr(IA32_STAR, star);
+        wrmsr(IA32_LSTAR, syscall_entry as u64);
+        wrmsr(IA32_FMASK, SYSCALL_FLAGS_MASK);
+    }
+}
+
+pub fn init(tss: &'static mut TaskStateSegment) {
+    init_cpu(unsafe { &mut *core::ptr::addr_of_mut!(BSP_PER_CPU) }, tss);
+}
@@ -1,5 +1,6 @@
 pub mod io;
 pub mod pic;
+pub mod pit;
 
 #[repr(u8)]
 pub enum MTRRMode {
@@ -54,6 +55,36 @@ pub unsafe fn set_mtrr(base: u64, size: u64, mode: MTRRMode) {
     }
 }
 
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[inline(always)]
+pub fn rdmsr(msr: u32) -> u64 {
+    let (low, high): (u32, u32);
+
+    unsafe {
+        core::arch::asm!(
+            "rdmsr",
+            in("ecx") msr,
+            out("eax") low,
+            out("edx") high,
+            options(nomem, nostack, preserves_flags)
+        );
+    }
+
+    return ((high as u64) << 32) | low as u64;
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[inline(always)]
+pub unsafe fn wrmsr(msr: u32, value: u64) {
+    core::arch::asm!(
+        "wrmsr",
+        in("ecx") msr,
+        in("eax") value as u32,
+        in("edx") (value >> 32) as u32,
+        options(nostack, preserves_flags)
+    );
+}
+
 #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
 #[inline(always)]
 pub fn pause() {
@@ -61,3 +92,53 @@ pub fn pause() {
         core::arch::asm!("pause");
     };
 }
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[inline(always)]
+pub fn interrupts_enabled() -> bool {
+    let flags: usize;
+
+    unsafe {
+        core::arch::asm!("pushf", "pop {}", out(reg) flags, options(preserves_flags));
+    }
+
+    // IF
+    return flags & (1 << 9) != 0;
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[inline(always)]
+pub fn enable_interrupts() {
+    unsafe { core::arch::asm!("sti", options(nostack)) };
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[inline(always)]
+pub fn disable_interrupts() {
+    unsafe { core::arch::asm!("cli", options(nostack)) };
+}
+
+/// Runs `f` with interrupts disabled, then puts them back the way they were.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+pub fn without_interrupts<T>(f: impl FnOnce() -> T) -> T {
+    let enabled = interrupts_enabled();
+
+    if enabled {
+        disable_interrupts();
+    }
+
+    let result = f();
+
+    if enabled {
+        enable_interrupts();
+    }
+
+    return result;
+}
+
+/// Waits for the next interrupt.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[inline(always)]
+pub fn halt() {
+    unsafe { core::arch::asm!("hlt", options(nomem, nostack)) };
+}
@@ -1,7 +1,7 @@
 // Originally from pic8259 (https://docs.rs/pic8259/0.10.1/pic8259/)
 // But this one feeds my addiction of not adding unnecessary crates
 // And I can read and learn about the PIC too ig
-// Driver for the 8086 PIC, we might switch to the APIC later on.
+// Driver for the 8086 PIC, only in charge until the APIC takes over.
 
 use super::io::{inb, io_wait, outb};
 
@@ -100,8 +100,7 @@ impl ChainedPics {
         self.pics[1].write_mask(mask2);
     }
 
-    // Keep for later if we want to use APIC later in the future
-    #[allow(dead_code)]
+    // Masks every interrupt, for when the APIC takes over
     pub fn disable(&mut self) {
         self.write_masks(0xFF, 0xFF);
     }
@@ -0,0 +1,57 @@
+// Driver for the 8253/8254 programmable interval timer. Only channel 0 is used, it is wired to
+// IRQ 0 and drives the system timer.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use super::io::{inb, outb};
+
+// The PIT's input clock, in Hz
+pub const BASE_FREQUENCY: u32 = 1_193_182;
+
+const CHANNEL_0_DATA_PORT: u16 = 0x40;
+const COMMAND_PORT: u16 = 0x43;
+
+// Channel 0, low byte then high byte of the reload value, mode 2 (rate generator), binary
+const CHANNEL_0_RATE_GENERATOR: u8 = 0b0011_0100;
+// Channel 0, copy the current count so both of its bytes can be read
+const CHANNEL_0_LATCH: u8 = 0b0000_0000;
+
+// What channel 0 counts down from. The firmware leaves it at 65536 in square wave mode, which
+// counts down twice as fast, so waits before the timer is set up only take half as long
+static RELOAD_VALUE: AtomicU32 = AtomicU32::new(65536);
+
+/// Makes channel 0 fire `frequency` times a second, returns the frequency it actually ended up
+/// with since it has to be a whole division of the base clock.
+pub fn set_frequency(frequency: u32) -> u32 {
+    // A reload value of 0 means 65536, which we never ask for
+    let divisor = (BASE_FREQUENCY / frequency.max(1)).clamp(1, u16::MAX as u32);
+
+    outb(COMMAND_PORT, CHANNEL_0_RATE_GENERATOR);
+    outb(CHANNEL_0_DATA_PORT, (divisor & 0xFF) as u8);
+    outb(CHANNEL_0_DATA_PORT, (divisor >> 8) as u8);
+
+    RELOAD_VALUE.store(divisor, Ordering::SeqCst);
+
+    return BASE_FREQUENCY / divisor;
+}
+
+/// Where channel 0 is in counting down. It keeps counting with interrupts disabled, unlike the
+/// ticks its interrupt drives.
+pub fn count() -> u16 {
+    outb(COMMAND_PORT, CHANNEL_0_LATCH);
+
+    let low = inb(CHANNEL_0_DATA_PORT);
+    let high = inb(CHANNEL_0_DATA_PORT);
+
+    return u16::from_le_bytes([low, high]);
+}
+
+/// How many clocks of the base frequency went by between two reads of `count`. The counter can
+/// only have gone around once, so it has to be read at least once a timer period.
+pub fn clocks_between(previous: u16, current: u16) -> u32 {
+    if current <= previous {
+        return (previous - current) as u32;
+    }
+
+    return previous as u32 + RELOAD_VALUE.load(Ordering::SeqCst) - current as u32;
+}
@@ -0,0 +1,1541 @@
+// Runs AML bytecode straight out of the table copies. Loading a table and running a method are
+// the same thing, loading just happens in the root scope and keeps whatever it creates.
+
+use alloc::{boxed::Box, string::String, vec::Vec};
+use core::cmp::Ordering;
+
+use crate::arch;
+
+use super::{
+    exists, get_object, insert,
+    name::{is_valid_segment, AmlName, NameSeg, NameString},
+    region::{self, Field, FieldKind, OperationRegion},
+    remove, resolve, truncate,
+    value::{AmlValue, Method, MethodBody},
+    AmlError,
+};
+
+const ZERO_OP: u8 = 0x00;
+const ONE_OP: u8 = 0x01;
+const ALIAS_OP: u8 = 0x06;
+const NAME_OP: u8 = 0x08;
+const BYTE_PREFIX: u8 = 0x0A;
+const WORD_PREFIX: u8 = 0x0B;
+const DWORD_PREFIX: u8 = 0x0C;
+const STRING_PREFIX: u8 = 0x0D;
+const QWORD_PREFIX: u8 = 0x0E;
+const SCOPE_OP: u8 = 0x10;
+const BUFFER_OP: u8 = 0x11;
+const PACKAGE_OP: u8 = 0x12;
+const VAR_PACKAGE_OP: u8 = 0x13;
+const METHOD_OP: u8 = 0x14;
+const EXTERNAL_OP: u8 = 0x15;
+const DUAL_NAME_PREFIX: u8 = 0x2E;
+const MULTI_NAME_PREFIX: u8 = 0x2F;
+const EXT_OP_PREFIX: u8 = 0x5B;
+const ROOT_CHAR: u8 = b'\\';
+const PARENT_PREFIX_CHAR: u8 = b'^';
+const LOCAL0_OP: u8 = 0x60;
+const LOCAL7_OP: u8 = 0x67;
+const ARG0_OP: u8 = 0x68;
+const ARG6_OP: u8 = 0x6E;
+const STORE_OP: u8 = 0x70;
+const REF_OF_OP: u8 = 0x71;
+const ADD_OP: u8 = 0x72;
+const CONCAT_OP: u8 = 0x73;
+const SUBTRACT_OP: u8 = 0x74;
+const INCREMENT_OP: u8 = 0x75;
+const DECREMENT_OP: u8 = 0x76;
+const MULTIPLY_OP: u8 = 0x77;
+const DIVIDE_OP: u8 = 0x78;
+const SHIFT_LEFT_OP: u8 = 0x79;
+const SHIFT_RIGHT_OP: u8 = 0x7A;
+const AND_OP: u8 = 0x7B;
+const NAND_OP: u8 = 0x7C;
+const OR_OP: u8 = 0x7D;
+const NOR_OP: u8 = 0x7E;
+const XOR_OP: u8 = 0x7F;
+const NOT_OP: u8 = 0x80;
+const FIND_SET_LEFT_BIT_OP: u8 = 0x81;
+const FIND_SET_RIGHT_BIT_OP: u8 = 0x82;
+const DEREF_OF_OP: u8 = 0x83;
+const CONCAT_RES_OP: u8 = 0x84;
+const MOD_OP: u8 = 0x85;
+const NOTIFY_OP: u8 = 0x86;
+const SIZE_OF_OP: u8 = 0x87;
+const INDEX_OP: u8 = 0x88;
+const MATCH_OP: u8 = 0x89;
+const CREATE_DWORD_FIELD_OP: u8 = 0x8A;
+const CREATE_WORD_FIELD_OP: u8 = 0x8B;
+const CREATE_BYTE_FIELD_OP: u8 = 0x8C;
+const CREATE_BIT_FIELD_OP: u8 = 0x8D;
+const OBJECT_TYPE_OP: u8 = 0x8E;
+const CREATE_QWORD_FIELD_OP: u8 = 0x8F;
+const LAND_OP: u8 = 0x90;
+const LOR_OP: u8 = 0x91;
+const LNOT_OP: u8 = 0x92;
+const LEQUAL_OP: u8 = 0x93;
+const LGREATER_OP: u8 = 0x94;
+const LLESS_OP: u8 = 0x95;
+const TO_BUFFER_OP: u8 = 0x96;
+const TO_DECIMAL_STRING_OP: u8 = 0x97;
+const TO_HEX_STRING_OP: u8 = 0x98;
+const TO_INTEGER_OP: u8 = 0x99;
+const TO_STRING_OP: u8 = 0x9C;
+const COPY_OBJECT_OP: u8 = 0x9D;
+const MID_OP: u8 = 0x9E;
+const CONTINUE_OP: u8 = 0x9F;
+const IF_OP: u8 = 0xA0;
+const ELSE_OP: u8 = 0xA1;
+const WHILE_OP: u8 = 0xA2;
+const NOOP_OP: u8 = 0xA3;
+const RETURN_OP: u8 = 0xA4;
+const BREAK_OP: u8 = 0xA5;
+const BREAKPOINT_OP: u8 = 0xCC;
+const ONES_OP: u8 = 0xFF;
+
+// Opcodes after EXT_OP_PREFIX
+const MUTEX_OP: u8 = 0x01;
+const EVENT_OP: u8 = 0x02;
+const COND_REF_OF_OP: u8 = 0x12;
+const CREATE_FIELD_OP: u8 = 0x13;
+const LOAD_TABLE_OP: u8 = 0x1F;
+const LOAD_OP: u8 = 0x20;
+const STALL_OP: u8 = 0x21;
+const SLEEP_OP: u8 = 0x22;
+const ACQUIRE_OP: u8 = 0x23;
+const SIGNAL_OP: u8 = 0x24;
+const WAIT_OP: u8 = 0x25;
+const RESET_OP: u8 = 0x26;
+const RELEASE_OP: u8 = 0x27;
+const FROM_BCD_OP: u8 = 0x28;
+const TO_BCD_OP: u8 = 0x29;
+const UNLOAD_OP: u8 = 0x2A;
+const REVISION_OP: u8 = 0x30;
+const DEBUG_OP: u8 = 0x31;
+const FATAL_OP: u8 = 0x32;
+const TIMER_OP: u8 = 0x33;
+const OP_REGION_OP: u8 = 0x80;
+const FIELD_OP: u8 = 0x81;
+const DEVICE_OP: u8 = 0x82;
+const PROCESSOR_OP: u8 = 0x83;
+const POWER_RES_OP: u8 = 0x84;
+const THERMAL_ZONE_OP: u8 = 0x85;
+const INDEX_FIELD_OP: u8 = 0x86;
+const BANK_FIELD_OP: u8 = 0x87;
+const DATA_REGION_OP: u8 = 0x88;
+
+// Entries of a field list that aren't named fields
+const RESERVED_FIELD: u8 = 0x00;
+const ACCESS_FIELD: u8 = 0x01;
+const CONNECT_FIELD: u8 = 0x02;
+const EXTENDED_ACCESS_FIELD: u8 = 0x03;
+
+// Match operators
+const MATCH_TRUE: u8 = 0;
+const MATCH_EQUAL: u8 = 1;
+const MATCH_LESS_EQUAL: u8 = 2;
+const MATCH_LESS: u8 = 3;
+const MATCH_GREATER_EQUAL: u8 = 4;
+const MATCH_GREATER: u8 = 5;
+
+// Resource templates end with this tag and a checksum byte
+const END_TAG: u8 = 0x79;
+
+// The version of the interpreter, for the Revision opcode
+const INTERPRETER_REVISION: u64 = 2;
+
+const MAX_CALL_DEPTH: usize = 64;
+const MAX_LOOP_ITERATIONS: usize = 0x10_0000;
+// Buffers get their size from the AML, firmware never needs anywhere near this much
+const MAX_BUFFER_SIZE: u64 = 0x10_0000;
+
+enum Flow {
+    Next,
+    Return(AmlValue),
+    Break,
+    Continue,
+}
+
+// Where a value can be stored
+enum Target {
+    Null,
+    Debug,
+    Local(usize),
+    Arg(usize),
+    Name(AmlName),
+    Index(Box<Target>, usize),
+}
+
+struct Context {
+    code: &'static [u8],
+    offset: usize,
+    scope: AmlName,
+    locals: [AmlValue; 8],
+    args: [AmlValue; 7],
+    // Objects a method creates are gone once it returns, the ones a table creates stay
+    temporary: bool,
+    created: Vec<AmlName>,
+    depth: usize,
+}
+
+fn is_name_lead(byte: u8) -> bool {
+    return matches!(
+        byte,
+        b'A'..=b'Z' | b'_' | ROOT_CHAR | PARENT_PREFIX_CHAR | DUAL_NAME_PREFIX | MULTI_NAME_PREFIX
+    );
+}
+
+fn logical(value: bool) -> AmlValue {
+    return AmlValue::Integer(if value { truncate(u64::MAX) } else { 0 });
+}
+
+/// Runs a table's AML in the root scope, adding everything it declares to the namespace.
+pub fn load(aml: &'static [u8]) -> Result<(), AmlError> {
+    let mut context = Context::new(aml, AmlName::root(), false, 0);
+    context.execute_until(aml.len())?;
+
+    return Ok(());
+}
+
+pub fn invoke(
+    path: &AmlName,
+    method: &Method,
+    args: Vec<AmlValue>,
+    depth: usize,
+) -> Result<AmlValue, AmlError> {
+    if depth > MAX_CALL_DEPTH {
+        return Err(AmlError::RecursionLimit);
+    }
+
+    let code = match method.body {
+        MethodBody::Aml(code) => code,
+        MethodBody::Native(native) => return native(&args),
+    };
+
+    // The method's name is the scope of anything it declares
+    let mut context = Context::new(code, path.clone(), true, depth);
+
+    for (slot, arg) in context.args.iter_mut().zip(args) {
+        *slot = arg;
+    }
+
+    let result = context.execute_until(code.len());
+
+    for name in context.created.iter() {
+        remove(name);
+    }
+
+    return match result? {
+        Flow::Return(value) => Ok(value),
+        _ => Ok(AmlValue::Uninitialized),
+    };
+}
+
+impl Context {
+    fn new(code: &'static [u8], scope: AmlName, temporary: bool, depth: usize) -> Self {
+        return Self {
+            code,
+            offset: 0,
+            scope,
+            locals: Default::default(),
+            args: Default::default(),
+            temporary,
+            created: Vec::new(),
+            depth,
+        };
+    }
+
+    fn peek(&self) -> Result<u8, AmlError> {
+        return self
+            .code
+            .get(self.offset)
+            .copied()
+            .ok_or(AmlError::UnexpectedEnd);
+    }
+
+    fn next_byte(&mut self) -> Result<u8, AmlError> {
+        let byte = self.peek()?;
+        self.offset += 1;
+
+        return Ok(byte);
+    }
+
+    fn next_bytes(&mut self, count: usize) -> Result<&'static [u8], AmlError> {
+        let code = self.code;
+        let bytes = code
+            .get(self.offset..self.offset + count)
+            .ok_or(AmlError::UnexpectedEnd)?;
+        self.offset += count;
+
+        return Ok(bytes);
+    }
+
+    fn next_integer(&mut self, size: usize) -> Result<u64, AmlError> {
+        return Ok(self
+            .next_bytes(size)?
+            .iter()
+            .rev()
+            .fold(0, |value, &byte| value << 8 | byte as u64));
+    }
+
+    // The raw value of a PkgLength, which is a length in bytes or a bit count in field lists
+    fn pkg_length_value(&mut self) -> Result<usize, AmlError> {
+        let lead = self.next_byte()?;
+        let extra_bytes = (lead >> 6) as usize;
+
+        if extra_bytes == 0 {
+            return Ok((lead & 0x3F) as usize);
+        }
+
+        let mut length = (lead & 0x0F) as usize;
+
+        for i in 0..extra_bytes {
+            length |= (self.next_byte()? as usize) << (4 + i * 8);
+        }
+
+        return Ok(length);
+    }
+
+    // Where the object a PkgLength is in front of ends, the length counts itself
+    fn pkg_end(&mut self) -> Result<usize, AmlError> {
+        let start = self.offset;
+        let end = start + self.pkg_length_value()?;
+
+        if end > self.code.len() {
+            return Err(AmlError::UnexpectedEnd);
+        }
+
+        return Ok(end);
+    }
+
+    fn name_seg(&mut self) -> Result<NameSeg, AmlError> {
+        let mut segment = [0u8; 4];
+        segment.copy_from_slice(self.next_bytes(4)?);
+
+        if !is_valid_segment(&segment) {
+            return Err(AmlError::InvalidName);
+        }
+
+        return Ok(segment);
+    }
+
+    fn name_string(&mut self) -> Result<NameString, AmlError> {
+        let mut name = NameString::default();
+
+        if self.peek()? == ROOT_CHAR {
+            name.root = true;
+            self.offset += 1;
+        } else {
+            while self.peek()? == PARENT_PREFIX_CHAR {
+                name.parent_prefixes += 1;
+                self.offset += 1;
+            }
+        }
+
+        let count = match self.peek()? {
+            ZERO_OP => {
+                self.offset += 1;
+                0
+            }
+            DUAL_NAME_PREFIX => {
+                self.offset += 1;
+                2
+            }
+            MULTI_NAME_PREFIX => {
+                self.offset += 1;
+                self.next_byte()? as usize
+            }
+            _ => 1,
+        };
+
+        for _ in 0..count {
+            name.segments.push(self.name_seg()?);
+        }
+
+        return Ok(name);
+    }
+
+    // Reads the name of something being declared, and where in the namespace it goes
+    fn declared_name(&mut self) -> Result<AmlName, AmlError> {
+        let name = self.name_string()?;
+        return name.resolve(&self.scope).ok_or(AmlError::InvalidName);
+    }
+
+    fn declare(&mut self, path: AmlName, object: AmlValue) {
+        if self.temporary && !exists(&path) {
+            self.created.push(path.clone());
+        }
+
+        insert(path, object);
+    }
+
+    fn resolve(&self, name: &NameString) -> Result<AmlName, AmlError> {
+        return resolve(&self.scope, name)
+            .ok_or_else(|| AmlError::NameNotFound(name.resolve(&self.scope).unwrap_or_default()));
+    }
+
+    fn execute_until(&mut self, end: usize) -> Result<Flow, AmlError> {
+        while self.offset < end {
+            match self.execute_term()? {
+                Flow::Next => {}
+                flow => return Ok(flow),
+            }
+        }
+
+        return Ok(Flow::Next);
+    }
+
+    // Runs the body of a Scope, Device and the like with `path` as the scope
+    fn execute_in_scope(&mut self, path: AmlName, end: usize) -> Result<Flow, AmlError> {
+        let scope = core::mem::replace(&mut self.scope, path);
+        let flow = self.execute_until(end);
+        self.scope = scope;
+        self.offset = end;
+
+        return flow;
+    }
+
+    fn execute_term(&mut self) -> Result<Flow, AmlError> {
+        match self.peek()? {
+            NAME_OP => {
+                self.offset += 1;
+                let path = self.declared_name()?;
+                let value = self.evaluate_term()?;
+                self.declare(path, value);
+            }
+            ALIAS_OP => {
+                self.offset += 1;
+                let source = self.name_string()?;
+                let source = self.resolve(&source)?;
+                let path = self.declared_name()?;
+                self.declare(path, AmlValue::Alias(source));
+            }
+            SCOPE_OP => {
+                self.offset += 1;
+                let end = self.pkg_end()?;
+                let path = self.declared_name()?;
+
+                if !exists(&path) {
+                    self.declare(path.clone(), AmlValue::Scope);
+                }
+
+                return self.execute_in_scope(path, end);
+            }
+            METHOD_OP => {
+                self.offset += 1;
+                let end = self.pkg_end()?;
+                let path = self.declared_name()?;
+                let flags = self.next_byte()?;
+                let code = self.code;
+
+                self.declare(
+                    path,
+                    AmlValue::Method(Method {
+                        flags,
+                        body: MethodBody::Aml(&code[self.offset..end]),
+                    }),
+                );
+                self.offset = end;
+            }
+            EXTERNAL_OP => {
+                self.offset += 1;
+                self.name_string()?;
+                // Object type and argument count
+                self.next_bytes(2)?;
+            }
+            CREATE_BIT_FIELD_OP => {
+                self.offset += 1;
+                self.create_buffer_field(1, true)?;
+            }
+            CREATE_BYTE_FIELD_OP => {
+                self.offset += 1;
+                self.create_buffer_field(8, false)?;
+            }
+            CREATE_WORD_FIELD_OP => {
+                self.offset += 1;
+                self.create_buffer_field(16, false)?;
+            }
+            CREATE_DWORD_FIELD_OP => {
+                self.offset += 1;
+                self.create_buffer_field(32, false)?;
+            }
+            CREATE_QWORD_FIELD_OP => {
+                self.offset += 1;
+                self.create_buffer_field(64, false)?;
+            }
+            IF_OP => {
+                self.offset += 1;
+                return self.execute_if();
+            }
+            ELSE_OP => {
+                // Only reached when the If before it was taken
+                self.offset += 1;
+                self.offset = self.pkg_end()?;
+            }
+            WHILE_OP => {
+                self.offset += 1;
+                return self.execute_while();
+            }
+            NOOP_OP | BREAKPOINT_OP => self.offset += 1,
+            RETURN_OP => {
+                self.offset += 1;
+                return Ok(Flow::Return(self.evaluate_term()?));
+            }
+            BREAK_OP => {
+                self.offset += 1;
+                return Ok(Flow::Break);
+            }
+            CONTINUE_OP => {
+                self.offset += 1;
+                return Ok(Flow::Continue);
+            }
+            EXT_OP_PREFIX => return self.execute_ext_term(),
+            _ => {
+                self.evaluate_term()?;
+            }
+        }
+
+        return Ok(Flow::Next);
+    }
+
+    fn execute_ext_term(&mut self) -> Result<Flow, AmlError> {
+        let opcode = *self
+            .code
+            .get(self.offset + 1)
+            .ok_or(AmlError::UnexpectedEnd)?;
+
+        match opcode {
+            MUTEX_OP => {
+                self.offset += 2;
+                let path = self.declared_name()?;
+                let sync_level = self.next_byte()? & 0x0F;
+                self.declare(path, AmlValue::Mutex { sync_level });
+            }
+            EVENT_OP => {
+                self.offset += 2;
+                let path = self.declared_name()?;
+                self.declare(path, AmlValue::Event);
+            }
+            CREATE_FIELD_OP => {
+                self.offset += 2;
+                let buffer = self.buffer_field_source()?;
+                let offset = self.evaluate_integer()?;
+                let length = self.evaluate_integer()?;
+                let path = self.declared_name()?;
+
+                self.declare(
+                    path,
+                    AmlValue::BufferField {
+                        buffer,
+                        offset,
+                        length,
+                    },
+                );
+            }
+            OP_REGION_OP => {
+                self.offset += 2;
+                let path = self.declared_name()?;
+                let space = self.next_byte()?;
+                let offset = self.evaluate_integer()?;
+                let length = self.evaluate_integer()?;
+
+                self.declare(
+                    path,
+                    AmlValue::OperationRegion(OperationRegion {
+                        space,
+                        offset,
+                        length,
+                        parent: self.scope.clone(),
+                    }),
+                );
+            }
+            FIELD_OP => {
+                self.offset += 2;
+                let end = self.pkg_end()?;
+                let region = self.name_string()?;
+                let region = self.resolve(&region)?;
+                let flags = self.next_byte()?;
+
+                self.field_list(end, FieldKind::Region(region), flags)?;
+            }
+            INDEX_FIELD_OP => {
+                self.offset += 2;
+                let end = self.pkg_end()?;
+                let index = self.name_string()?;
+                let index = self.resolve(&index)?;
+                let data = self.name_string()?;
+                let data = self.resolve(&data)?;
+                let flags = self.next_byte()?;
+
+                self.field_list(end, FieldKind::Index { index, data }, flags)?;
+            }
+            BANK_FIELD_OP => {
+                self.offset += 2;
+                let end = self.pkg_end()?;
+                let region = self.name_string()?;
+                let region = self.resolve(&region)?;
+                let bank = self.name_string()?;
+                let bank = self.resolve(&bank)?;
+                let value = self.evaluate_integer()?;
+                let flags = self.next_byte()?;
+
+                self.field_list(
+                    end,
+                    FieldKind::Bank {
+                        region,
+                        bank,
+                        value,
+                    },
+                    flags,
+                )?;
+            }
+            DEVICE_OP => {
+                self.offset += 2;
+                let end = self.pkg_end()?;
+                let path = self.declared_name()?;
+                self.declare(path.clone(), AmlValue::Device);
+
+                return self.execute_in_scope(path, end);
+            }
+            PROCESSOR_OP => {
+                self.offset += 2;
+                let end = self.pkg_end()?;
+                let path = self.declared_name()?;
+                let id = self.next_byte()?;
+                let block_address = self.next_integer(4)? as u32;
+                let block_length = self.next_byte()?;
+
+                self.declare(
+                    path.clone(),
+                    AmlValue::Processor {
+                        id,
+                        block_address,
+                        block_length,
+                    },
+                );
+
+                return self.execute_in_scope(path, end);
+            }
+            POWER_RES_OP => {
+                self.offset += 2;
+                let end = self.pkg_end()?;
+                let path = self.declared_name()?;
+                let system_level = self.next_byte()?;
+                let resource_order = self.next_integer(2)? as u16;
+
+                self.declare(
+                    path.clone(),
+                    AmlValue::PowerResource {
+                        system_level,
+                        resource_order,
+                    },
+                );
+
+                return self.execute_in_scope(path, end);
+            }
+            THERMAL_ZONE_OP => {
+                self.offset += 2;
+                let end = self.pkg_end()?;
+                let path = self.declared_name()?;
+                self.declare(path.clone(), AmlValue::ThermalZone);
+
+                return self.execute_in_scope(path, end);
+            }
+            // Regions over other tables would need the firmware's copies, which are gone by now
+            DATA_REGION_OP => return Err(AmlError::Unsupported),
+            _ => {
+                self.evaluate_term()?;
+            }
+        }
+
+        return Ok(Flow::Next);
+    }
+
+    fn execute_if(&mut self) -> Result<Flow, AmlError> {
+        let end = self.pkg_end()?;
+        let predicate = self.evaluate_integer()? != 0;
+
+        if predicate {
+            let flow = self.execute_until(end)?;
+            self.offset = end;
+
+            // The Else, if there is one, is skipped by execute_term
+            return Ok(flow);
+        }
+
+        self.offset = end;
+
+        if self.code.get(self.offset) == Some(&ELSE_OP) {
+            self.offset += 1;
+            let else_end = self.pkg_end()?;
+            let flow = self.execute_until(else_end)?;
+            self.offset = else_end;
+
+            return Ok(flow);
+        }
+
+        return Ok(Flow::Next);
+    }
+
+    fn execute_while(&mut self) -> Result<Flow, AmlError> {
+        let end = self.pkg_end()?;
+        let start = self.offset;
+
+        for _ in 0..MAX_LOOP_ITERATIONS {
+            self.offset = start;
+
+            if self.evaluate_integer()? == 0 {
+                self.offset = end;
+                return Ok(Flow::Next);
+            }
+
+            match self.execute_until(end)? {
+                Flow::Next | Flow::Continue => {}
+                Flow::Break => {
+                    self.offset = end;
+                    return Ok(Flow::Next);
+                }
+                flow => return Ok(flow),
+            }
+        }
+
+        return Err(AmlError::LoopLimit);
+    }
+
+    fn field_list(&mut self, end: usize, kind: FieldKind, mut flags: u8) -> Result<(), AmlError> {
+        let mut offset = 0;
+
+        while self.offset < end {
+            match self.peek()? {
+                RESERVED_FIELD => {
+                    self.offset += 1;
+                    offset += self.pkg_length_value()? as u64;
+                }
+                ACCESS_FIELD => {
+                    self.offset += 1;
+                    let access_type = self.next_byte()?;
+                    // Access attributes only matter for SMBus and friends
+                    self.next_byte()?;
+                    flags = flags & !0x0F | access_type & 0x0F;
+                }
+                EXTENDED_ACCESS_FIELD => {
+                    self.offset += 1;
+                    let access_type = self.next_byte()?;
+                    self.next_bytes(2)?;
+                    flags = flags & !0x0F | access_type & 0x0F;
+                }
+                CONNECT_FIELD => return Err(AmlError::Unsupported),
+                _ => {
+                    let segment = self.name_seg()?;
+                    let length = self.pkg_length_value()? as u64;
+                    let path = self.scope.child(segment);
+
+                    self.declare(
+                        path,
+                        AmlValue::Field(Field {
+                            kind: kind.clone(),
+                            flags,
+                            offset,
+                            length,
+                        }),
+                    );
+
+                    offset += length;
+                }
+            }
+        }
+
+        return Ok(());
+    }
+
+    // Buffer fields can only be made over named buffers
+    fn buffer_field_source(&mut self) -> Result<AmlName, AmlError> {
+        return match self.target()? {
+            Target::Name(path) => Ok(path),
+            _ => Err(AmlError::Unsupported),
+        };
+    }
+
+    fn create_buffer_field(&mut self, length: u64, bit_index: bool) -> Result<(), AmlError> {
+        let buffer = self.buffer_field_source()?;
+        let index = self.evaluate_integer()?;
+        let path = self.declared_name()?;
+
+        self.declare(
+            path,
+            AmlValue::BufferField {
+                buffer,
+                offset: if bit_index { index } else { index * 8 },
+                length,
+            },
+        );
+
+        return Ok(());
+    }
+
+    fn evaluate_integer(&mut self) -> Result<u64, AmlError> {
+        return self.evaluate_term()?.as_integer();
+    }
+
+    // Reads whatever is at `path`, running methods without arguments
+    fn read_object(&mut self, path: &AmlName) -> Result<AmlValue, AmlError> {
+        return match get_object(path) {
+            Some(AmlValue::Method(method)) => invoke(path, &method, Vec::new(), self.depth + 1),
+            Some(AmlValue::Field(field)) => region::read_field(&field),
+            Some(AmlValue::BufferField {
+                buffer,
+                offset,
+                length,
+            }) => match get_object(&buffer) {
+                Some(AmlValue::Buffer(bytes)) => {
+                    Ok(region::read_buffer_field(&bytes, offset, length))
+                }
+                _ => Err(AmlError::TypeMismatch),
+            },
+            Some(
+                AmlValue::Device
+                | AmlValue::Scope
+                | AmlValue::Processor { .. }
+                | AmlValue::PowerResource { .. }
+                | AmlValue::ThermalZone
+                | AmlValue::Mutex { .. }
+                | AmlValue::Event
+                | AmlValue::OperationRegion(_),
+            ) => Ok(AmlValue::Reference(path.clone())),
+            Some(object) => Ok(object),
+            None => Err(AmlError::NameNotFound(path.clone())),
+        };
+    }
+
+    // A name in an expression, which is either an object or a method call
+    fn evaluate_name(&mut self) -> Result<AmlValue, AmlError> {
+        let name = self.name_string()?;
+        let path = self.resolve(&name)?;
+
+        if let Some(AmlValue::Method(method)) = get_object(&path) {
+            let mut args = Vec::with_capacity(method.arg_count());
+
+            for _ in 0..method.arg_count() {
+                args.push(self.evaluate_term()?);
+            }
+
+            return invoke(&path, &method, args, self.depth + 1);
+        }
+
+        return self.read_object(&path);
+    }
+
+    fn evaluate_term(&mut self) -> Result<AmlValue, AmlError> {
+        let opcode = self.peek()?;
+
+        if is_name_lead(opcode) {
+            return self.evaluate_name();
+        }
+
+        self.offset += 1;
+
+        return match opcode {
+            ZERO_OP => Ok(AmlValue::Integer(0)),
+            ONE_OP => Ok(AmlValue::Integer(1)),
+            ONES_OP => Ok(AmlValue::Integer(truncate(u64::MAX))),
+            BYTE_PREFIX => Ok(AmlValue::Integer(self.next_integer(1)?)),
+            WORD_PREFIX => Ok(AmlValue::Integer(self.next_integer(2)?)),
+            DWORD_PREFIX => Ok(AmlValue::Integer(self.next_integer(4)?)),
+            QWORD_PREFIX => Ok(AmlValue::Integer(truncate(self.next_integer(8)?))),
+            STRING_PREFIX => {
+                let start = self.offset;
+
+                while self.next_byte()? != 0 {}
+
+                Ok(AmlValue::String(
+                    String::from_utf8_lossy(&self.code[start..self.offset - 1]).into_owned(),
+                ))
+            }
+            BUFFER_OP => {
+                let end = self.pkg_end()?;
+                let size = self.evaluate_integer()?;
+
+                if size > MAX_BUFFER_SIZE {
+                    return Err(AmlError::Unsupported);
+                }
+
+                let mut bytes = self
+                    .code
+                    .get(self.offset..end)
+                    .ok_or(AmlError::UnexpectedEnd)?
+                    .to_vec();
+                bytes.resize((size as usize).max(bytes.len()), 0);
+                self.offset = end;
+
+                Ok(AmlValue::Buffer(bytes))
+            }
+            PACKAGE_OP => {
+                let end = self.pkg_end()?;
+                let count = self.next_byte()? as usize;
+                self.package(end, count)
+            }
+            VAR_PACKAGE_OP => {
+                let end = self.pkg_end()?;
+                let count = self.evaluate_integer()? as usize;
+                self.package(end, count)
+            }
+            LOCAL0_OP..=LOCAL7_OP => Ok(self.locals[(opcode - LOCAL0_OP) as usize].clone()),
+            ARG0_OP..=ARG6_OP => Ok(self.args[(opcode - ARG0_OP) as usize].clone()),
+            STORE_OP => {
+                let value = self.evaluate_term()?;
+                let target = self.target()?;
+                self.store(&target, value.clone())?;
+                Ok(value)
+            }
+            COPY_OBJECT_OP => {
+                let value = self.evaluate_term()?;
+                let target = self.target()?;
+                self.write_raw(&target, value.clone())?;
+                Ok(value)
+            }
+            REF_OF_OP => match self.target()? {
+                Target::Name(path) => Ok(AmlValue::Reference(path)),
+                target => self.read_target(&target),
+            },
+            DEREF_OF_OP => match self.evaluate_term()? {
+                AmlValue::Reference(path) => self.read_object(&path),
+                AmlValue::String(path) => {
+                    let path = AmlName::parse(&path).ok_or(AmlError::InvalidName)?;
+                    self.read_object(&path)
+                }
+                value => Ok(value),
+            },
+            ADD_OP => self.binary_op(|a, b| Ok(a.wrapping_add(b))),
+            SUBTRACT_OP => self.binary_op(|a, b| Ok(a.wrapping_sub(b))),
+            MULTIPLY_OP => self.binary_op(|a, b| Ok(a.wrapping_mul(b))),
+            SHIFT_LEFT_OP => self.binary_op(|a, b| Ok(a.checked_shl(b as u32).unwrap_or(0))),
+            SHIFT_RIGHT_OP => self.binary_op(|a, b| Ok(a.checked_shr(b as u32).unwrap_or(0))),
+            AND_OP => self.binary_op(|a, b| Ok(a & b)),
+            NAND_OP => self.binary_op(|a, b| Ok(!(a & b))),
+            OR_OP => self.binary_op(|a, b| Ok(a | b)),
+            NOR_OP => self.binary_op(|a, b| Ok(!(a | b))),
+            XOR_OP => self.binary_op(|a, b| Ok(a ^ b)),
+            MOD_OP => self.binary_op(|a, b| a.checked_rem(b).ok_or(AmlError::DivideByZero)),
+            DIVIDE_OP => {
+                let dividend = self.evaluate_integer()?;
+                let divisor = self.evaluate_integer()?;
+
+                if divisor == 0 {
+                    return Err(AmlError::DivideByZero);
+                }
+
+                let remainder = self.target()?;
+                self.store(&remainder, AmlValue::Integer(dividend % divisor))?;
+                let quotient = self.target()?;
+                self.store(&quotient, AmlValue::Integer(dividend / divisor))?;
+
+                Ok(AmlValue::Integer(dividend / divisor))
+            }
+            NOT_OP => self.unary_op(|value| !value),
+            FIND_SET_LEFT_BIT_OP => self.unary_op(|value| match value {
+                0 => 0,
+                value => 64 - value.leading_zeros() as u64,
+            }),
+            FIND_SET_RIGHT_BIT_OP => self.unary_op(|value| match value {
+                0 => 0,
+                value => value.trailing_zeros() as u64 + 1,
+            }),
+            INCREMENT_OP | DECREMENT_OP => {
+                let target = self.target()?;
+                let value = self.read_target(&target)?.as_integer()?;
+                let value = AmlValue::Integer(truncate(if opcode == INCREMENT_OP {
+                    value.wrapping_add(1)
+                } else {
+                    value.wrapping_sub(1)
+                }));
+
+                self.store(&target, value.clone())?;
+                Ok(value)
+            }
+            CONCAT_OP => {
+                let first = self.evaluate_term()?;
+                let second = self.evaluate_term()?;
+
+                let result = match first {
+                    AmlValue::String(mut string) => {
+                        string.push_str(&second.as_string()?);
+                        AmlValue::String(string)
+                    }
+                    AmlValue::Integer(value) => {
+                        let mut bytes = value.to_le_bytes()[..self.integer_bytes()].to_vec();
+                        bytes.extend(&second.as_integer()?.to_le_bytes()[..self.integer_bytes()]);
+                        AmlValue::Buffer(bytes)
+                    }
+                    first => {
+                        let mut bytes = first.as_buffer()?;
+                        bytes.extend(second.as_buffer()?);
+                        AmlValue::Buffer(bytes)
+                    }
+                };
+
+                let target = self.target()?;
+                self.store(&target, result.clone())?;
+                Ok(result)
+            }
+            CONCAT_RES_OP => {
+                let first = self.evaluate_term()?.as_buffer()?;
+                let second = self.evaluate_term()?.as_buffer()?;
+
+                let mut bytes = strip_end_tag(&first).to_vec();
+                bytes.extend_from_slice(strip_end_tag(&second));
+                bytes.extend_from_slice(&[END_TAG, 0]);
+
+                let result = AmlValue::Buffer(bytes);
+                let target = self.target()?;
+                self.store(&target, result.clone())?;
+                Ok(result)
+            }
+            SIZE_OF_OP => {
+                let target = self.target()?;
+
+                match self.read_target(&target)? {
+                    AmlValue::String(string) => Ok(AmlValue::Integer(string.len() as u64)),
+                    AmlValue::Buffer(bytes) => Ok(AmlValue::Integer(bytes.len() as u64)),
+                    AmlValue::Package(elements) => Ok(AmlValue::Integer(elements.len() as u64)),
+                    _ => Err(AmlError::TypeMismatch),
+                }
+            }
+            INDEX_OP => {
+                let source = self.evaluate_term()?;
+                let index = self.evaluate_integer()? as usize;
+                let element = index_of(&source, index)?;
+
+                let target = self.target()?;
+                self.store(&target, element.clone())?;
+                Ok(element)
+            }
+            MATCH_OP => {
+                let package = self.evaluate_term()?;
+                let first_op = self.next_byte()?;
+                let first = self.evaluate_term()?;
+                let second_op = self.next_byte()?;
+                let second = self.evaluate_term()?;
+                let start = self.evaluate_integer()? as usize;
+
+                let mut found = truncate(u64::MAX);
+
+                for (i, element) in package.as_package()?.iter().enumerate().skip(start) {
+                    if match_element(element, first_op, &first)?
+                        && match_element(element, second_op, &second)?
+                    {
+                        found = i as u64;
+                        break;
+                    }
+                }
+
+                Ok(AmlValue::Integer(found))
+            }
+            OBJECT_TYPE_OP => {
+                let target = self.target()?;
+
+                let object_type = match &target {
+                    Target::Name(path) => get_object(path).map_or(0, |object| object.object_type()),
+                    target => self.read_target(target)?.object_type(),
+                };
+
+                Ok(AmlValue::Integer(object_type))
+            }
+            LAND_OP => {
+                let first = self.evaluate_integer()?;
+                let second = self.evaluate_integer()?;
+                Ok(logical(first != 0 && second != 0))
+            }
+            LOR_OP => {
+                let first = self.evaluate_integer()?;
+                let second = self.evaluate_integer()?;
+                Ok(logical(first != 0 || second != 0))
+            }
+            LNOT_OP => Ok(logical(self.evaluate_integer()? == 0)),
+            LEQUAL_OP => Ok(logical(self.compare()? == Ordering::Equal)),
+            LGREATER_OP => Ok(logical(self.compare()? == Ordering::Greater)),
+            LLESS_OP => Ok(logical(self.compare()? == Ordering::Less)),
+            TO_BUFFER_OP => {
+                let value = AmlValue::Buffer(match self.evaluate_term()? {
+                    AmlValue::Integer(value) => {
+                        value.to_le_bytes()[..self.integer_bytes()].to_vec()
+                    }
+                    value => value.as_buffer()?,
+                });
+
+                let target = self.target()?;
+                self.store(&target, value.clone())?;
+                Ok(value)
+            }
+            TO_INTEGER_OP => {
+                let value = match self.evaluate_term()? {
+                    // Explicit conversions understand decimal too
+                    AmlValue::String(string) if !string.starts_with("0x") => AmlValue::Integer(
+                        string
+                            .chars()
+                            .map_while(|character| character.to_digit(10))
+                            .fold(0u64, |value, digit| {
+                                value.wrapping_mul(10).wrapping_add(digit as u64)
+                            }),
+                    ),
+                    value => AmlValue::Integer(value.as_integer()?),
+                };
+
+                let target = self.target()?;
+                self.store(&target, value.clone())?;
+                Ok(value)
+            }
+            TO_DECIMAL_STRING_OP | TO_HEX_STRING_OP => {
+                let source = self.evaluate_term()?;
+                let decimal = opcode == TO_DECIMAL_STRING_OP;
+
+                let string = match source {
+                    AmlValue::String(string) => string,
+                    AmlValue::Integer(value) if decimal => alloc::format!("{value}"),
+                    AmlValue::Integer(value) => alloc::format!("0x{value:X}"),
+                    AmlValue::Buffer(bytes) => bytes
+                        .iter()
+                        .map(|byte| {
+                            if decimal {
+                                alloc::format!("{byte}")
+                            } else {
+                                alloc::format!("0x{byte:02X}")
+                            }
+                        })
+                        .collect::<Vec<_>>()
+                        .join(","),
+                    _ => return Err(AmlError::TypeMismatch),
+                };
+
+                let value = AmlValue::String(string);
+                let target = self.target()?;
+                self.store(&target, value.clone())?;
+                Ok(value)
+            }
+            TO_STRING_OP => {
+                let bytes = self.evaluate_term()?.as_buffer()?;
+                let length = self.evaluate_integer()? as usize;
+
+                let string = bytes
+                    .iter()
+                    .take(length)
+                    .take_while(|&&byte| byte != 0)
+                    .map(|&byte| byte as char)
+                    .collect();
+
+                let value = AmlValue::String(string);
+                let target = self.target()?;
+                self.store(&target, value.clone())?;
+                Ok(value)
+            }
+            MID_OP => {
+                let source = self.evaluate_term()?;
+                let index = self.evaluate_integer()? as usize;
+                let length = self.evaluate_integer()? as usize;
+
+                let value = match source {
+                    AmlValue::String(string) => {
+                        AmlValue::String(string.chars().skip(index).take(length).collect())
+                    }
+                    source => AmlValue::Buffer(
+                        source
+                            .as_buffer()?
+                            .into_iter()
+                            .skip(index)
+                            .take(length)
+                            .collect(),
+                    ),
+                };
+
+                let target = self.target()?;
+                self.store(&target, value.clone())?;
+                Ok(value)
+            }
+            NOTIFY_OP => {
+                let target = self.target()?;
+                let value = self.evaluate_integer()?;
+
+                if let Target::Name(path) = target {
+                    crate::log_info!("AML: Notify({path}, {value:#X}) ignored");
+                }
+
+                Ok(AmlValue::Uninitialized)
+            }
+            EXT_OP_PREFIX => self.evaluate_ext_term(),
+            _ => Err(AmlError::InvalidOpcode(opcode as u16)),
+        };
+    }
+
+    fn evaluate_ext_term(&mut self) -> Result<AmlValue, AmlError> {
+        let opcode = self.next_byte()?;
+
+        return match opcode {
+            COND_REF_OF_OP => {
+                // Unlike every other use of a name, this one is allowed to not exist
+                let path = if is_name_lead(self.peek()?) {
+                    let name = self.name_string()?;
+                    resolve(&self.scope, &name)
+                } else {
+                    match self.target()? {
+                        Target::Name(path) => Some(path),
+                        _ => None,
+                    }
+                };
+
+                let target = self.target()?;
+
+                match path {
+                    Some(path) => {
+                        self.store(&target, AmlValue::Reference(path))?;
+                        Ok(logical(true))
+                    }
+                    None => Ok(logical(false)),
+                }
+            }
+            STALL_OP => {
+                // Port 0x80 takes about a microsecond to write
+                for _ in 0..self.evaluate_integer()? {
+                    arch::io::io_wait();
+                }
+
+                Ok(AmlValue::Uninitialized)
+            }
+            SLEEP_OP => {
+                let ms = self.evaluate_integer()?;
+
+                if arch::interrupts_enabled() {
+                    crate::sys::time::sleep_ms(ms);
+                }
+
+                Ok(AmlValue::Uninitialized)
+            }
+            ACQUIRE_OP => {
+                self.target()?;
+                // Timeout
+                self.next_bytes(2)?;
+
+                // Zero means it was acquired
+                Ok(AmlValue::Integer(0))
+            }
+            WAIT_OP => {
+                self.target()?;
+                self.evaluate_integer()?;
+
+                Ok(AmlValue::Integer(0))
+            }
+            SIGNAL_OP | RESET_OP | RELEASE_OP => {
+                self.target()?;
+                Ok(AmlValue::Uninitialized)
+            }
+            FROM_BCD_OP => {
+                let mut bcd = self.evaluate_integer()?;
+                let mut value = 0;
+                let mut multiplier = 1;
+
+                while bcd != 0 {
+                    value += (bcd & 0xF) * multiplier;
+                    multiplier *= 10;
+                    bcd >>= 4;
+                }
+
+                let value = AmlValue::Integer(value);
+                let target = self.target()?;
+                self.store(&target, value.clone())?;
+                Ok(value)
+            }
+            TO_BCD_OP => {
+                let mut decimal = self.evaluate_integer()?;
+                let mut value = 0;
+                let mut shift = 0;
+
+                while decimal != 0 && shift < 64 {
+                    value |= (decimal % 10) << shift;
+                    decimal /= 10;
+                    shift += 4;
+                }
+
+                let value = AmlValue::Integer(value);
+                let target = self.target()?;
+                self.store(&target, value.clone())?;
+                Ok(value)
+            }
+            REVISION_OP => Ok(AmlValue::Integer(INTERPRETER_REVISION)),
+            // In units of 100 nanoseconds
+            TIMER_OP => Ok(AmlValue::Integer(
+                crate::sys::time::uptime().as_nanos() as u64 / 100,
+            )),
+            FATAL_OP => {
+                let fatal_type = self.next_byte()?;
+                let code = self.next_integer(4)? as u32;
+                let argument = self.evaluate_integer()?;
+
+                Err(AmlError::Fatal {
+                    fatal_type,
+                    code,
+                    argument,
+                })
+            }
+            LOAD_OP | LOAD_TABLE_OP | UNLOAD_OP => Err(AmlError::Unsupported),
+            _ => Err(AmlError::InvalidOpcode(
+                (EXT_OP_PREFIX as u16) << 8 | opcode as u16,
+            )),
+        };
+    }
+
+    fn integer_bytes(&self) -> usize {
+        return (super::integer_width() / 8) as usize;
+    }
+
+    fn package(&mut self, end: usize, count: usize) -> Result<AmlValue, AmlError> {
+        let mut elements = Vec::with_capacity(count);
+
+        while self.offset < end {
+            // Names in packages refer to objects, they aren't evaluated
+            if is_name_lead(self.peek()?) {
+                let name = self.name_string()?;
+                let path = resolve(&self.scope, &name)
+                    .or_else(|| name.resolve(&self.scope))
+                    .ok_or(AmlError::InvalidName)?;
+
+                elements.push(AmlValue::Reference(path));
+            } else {
+                elements.push(self.evaluate_term()?);
+            }
+        }
+
+        if elements.len() < count {
+            elements.resize(count, AmlValue::Uninitialized);
+        }
+
+        self.offset = end;
+
+        return Ok(AmlValue::Package(elements));
+    }
+
+    fn binary_op(
+        &mut self,
+        op: impl FnOnce(u64, u64) -> Result<u64, AmlError>,
+    ) -> Result<AmlValue, AmlError> {
+        let first = self.evaluate_integer()?;
+        let second = self.evaluate_integer()?;
+        let value = AmlValue::Integer(truncate(op(first, second)?));
+
+        let target = self.target()?;
+        self.store(&target, value.clone())?;
+
+        return Ok(value);
+    }
+
+    fn unary_op(&mut self, op: impl FnOnce(u64) -> u64) -> Result<AmlValue, AmlError> {
+        let value = AmlValue::Integer(truncate(op(self.evaluate_integer()?)));
+
+        let target = self.target()?;
+        self.store(&target, value.clone())?;
+
+        return Ok(value);
+    }
+
+    // Strings and buffers compare byte by byte, everything else as integers
+    fn compare(&mut self) -> Result<Ordering, AmlError> {
+        let first = self.evaluate_term()?;
+        let second = self.evaluate_term()?;
+
+        return Ok(match first {
+            AmlValue::String(string) => string.as_bytes().cmp(second.as_string()?.as_bytes()),
+            AmlValue::Buffer(bytes) => bytes.as_slice().cmp(second.as_buffer()?.as_slice()),
+            first => first.as_integer()?.cmp(&second.as_integer()?),
+        });
+    }
+
+    fn target(&mut self) -> Result<Target, AmlError> {
+        let opcode = self.peek()?;
+
+        if is_name_lead(opcode) {
+            let name = self.name_string()?;
+            return Ok(Target::Name(self.resolve(&name)?));
+        }
+
+        match opcode {
+            ZERO_OP => {
+                self.offset += 1;
+                return Ok(Target::Null);
+            }
+            LOCAL0_OP..=LOCAL7_OP => {
+                self.offset += 1;
+                return Ok(Target::Local((opcode - LOCAL0_OP) as usize));
+            }
+            ARG0_OP..=ARG6_OP => {
+                self.offset += 1;
+                return Ok(Target::Arg((opcode - ARG0_OP) as usize));
+            }
+            EXT_OP_PREFIX if self.code.get(self.offset + 1) == Some(&DEBUG_OP) => {
+                self.offset += 2;
+                return Ok(Target::Debug);
+            }
+            INDEX_OP => {
+                self.offset += 1;
+                let source = self.target()?;
+                let index = self.evaluate_integer()? as usize;
+
+                let element = index_of(&self.read_target(&source)?, index)?;
+                let target = self.target()?;
+                self.store(&target, element)?;
+
+                return Ok(Target::Index(Box::new(source), index));
+            }
+            _ => {}
+        }
+
+        // Anything else has to evaluate to a reference, like DerefOf or a method returning one
+        return match self.evaluate_term()? {
+            AmlValue::Reference(path) => Ok(Target::Name(path)),
+            _ => Err(AmlError::TypeMismatch),
+        };
+    }
+
+    // The value of a target, without reading fields behind it
+    fn read_raw(&mut self, target: &Target) -> Result<AmlValue, AmlError> {
+        return match target {
+            Target::Null | Target::Debug => Ok(AmlValue::Uninitialized),
+            Target::Local(local) => Ok(self.locals[*local].clone()),
+            Target::Arg(arg) => Ok(self.args[*arg].clone()),
+            Target::Name(path) => get_object(path).ok_or(AmlError::NameNotFound(path.clone())),
+            Target::Index(source, index) => index_of(&self.read_raw(source)?, *index),
+        };
+    }
+
+    fn read_target(&mut self, target: &Target) -> Result<AmlValue, AmlError> {
+        return match target {
+            Target::Name(path) => self.read_object(path),
+            target => self.read_raw(target),
+        };
+    }
+
+    // Replaces whatever is in `target`, with no conversions
+    fn write_raw(&mut self, target: &Target, value: AmlValue) -> Result<(), AmlError> {
+        match target {
+            Target::Null => {}
+            Target::Debug => crate::log_info!("AML: {value}"),
+            Target::Local(local) => self.locals[*local] = value,
+            Target::Arg(arg) => self.args[*arg] = value,
+            Target::Name(path) => insert(path.clone(), value),
+            Target::Index(source, index) => {
+                let mut container = self.read_raw(source)?;
+
+                match &mut container {
+                    AmlValue::Package(elements) => {
+                        *elements.get_mut(*index).ok_or(AmlError::IndexOutOfBounds)? = value;
+                    }
+                    AmlValue::Buffer(bytes) => {
+                        *bytes.get_mut(*index).ok_or(AmlError::IndexOutOfBounds)? =
+                            value.as_integer()? as u8;
+                    }
+                    _ => return Err(AmlError::TypeMismatch),
+                }
+
+                self.write_raw(source, container)?;
+            }
+        }
+
+        return Ok(());
+    }
+
+    // Stores `value` the way Store does, converting it to the type of named data objects
+    fn store(&mut self, target: &Target, value: AmlValue) -> Result<(), AmlError> {
+        let path = match target {
+            // An argument holding a reference is stored through
+            Target::Arg(arg) => match self.args[*arg].clone() {
+                AmlValue::Reference(path) => path,
+                _ => return self.write_raw(target, value),
+            },
+            Target::Name(path) => path.clone(),
+            target => return self.write_raw(target, value),
+        };
+
+        let converted = match get_object(&path) {
+            Some(AmlValue::Field(field)) => return region::write_field(&field, &value),
+            Some(AmlValue::BufferField {
+                buffer,
+                offset,
+                length,
+            }) => {
+                let mut bytes = match get_object(&buffer) {
+                    Some(AmlValue::Buffer(bytes)) => bytes,
+                    _ => return Err(AmlError::TypeMismatch),
+                };
+
+                region::write_buffer_field(&mut bytes, offset, length, &value);
+                insert(buffer, AmlValue::Buffer(bytes));
+
+                return Ok(());
+            }
+            Some(AmlValue::Integer(_)) => AmlValue::Integer(truncate(value.as_integer()?)),
+            Some(AmlValue::String(_)) => AmlValue::String(value.as_string()?),
+            // Buffers keep their size
+            Some(AmlValue::Buffer(old)) => {
+                let mut bytes = value.as_buffer()?;
+                bytes.resize(old.len(), 0);
+                AmlValue::Buffer(bytes)
+            }
+            Some(AmlValue::Method(_) | AmlValue::Device | AmlValue::Scope) => {
+                return Err(AmlError::TypeMismatch)
+            }
+            _ => value,
+        };
+
+        insert(path, converted);
+
+        return Ok(());
+    }
+}
+
+fn index_of(source: &AmlValue, index: usize) -> Result<AmlValue, AmlError> {
+    return match source {
+        AmlValue::Package(elements) => elements
+            .get(index)
+            .cloned()
+            .ok_or(AmlError::IndexOutOfBounds),
+        AmlValue::Buffer(bytes) => bytes
+            .get(index)
+            .map(|&byte| AmlValue::Integer(byte as u64))
+            .ok_or(AmlError::IndexOutOfBounds),
+        AmlValue::String(string) => string
+            .as_bytes()
+            .get(index)
+            .map(|&byte| AmlValue::Integer(byte as u64))
+            .ok_or(AmlError::IndexOutOfBounds),
+        _ => Err(AmlError::TypeMismatch),
+    };
+}
+
+fn match_element(element: &AmlValue, op: u8, value: &AmlValue) -> Result<bool, AmlError> {
+    if op == MATCH_TRUE {
+        return Ok(true);
+    }
+
+    // Elements that can't be compared never match
+    let ordering = match element {
+        AmlValue::Integer(element) => element.cmp(&value.as_integer()?),
+        AmlValue::String(element) => element.as_bytes().cmp(value.as_string()?.as_bytes()),
+        AmlValue::Buffer(element) => element.as_slice().cmp(value.as_buffer()?.as_slice()),
+        _ => return Ok(false),
+    };
+
+    return Ok(match op {
+        MATCH_EQUAL => ordering == Ordering::Equal,
+        MATCH_LESS_EQUAL => ordering != Ordering::Greater,
+        MATCH_LESS => ordering == Ordering::Less,
+        MATCH_GREATER_EQUAL => ordering != Ordering::Less,
+        MATCH_GREATER => ordering == Ordering::Greater,
+        _ => false,
+    });
+}
+
+// A resource template without its end tag
+fn strip_end_tag(bytes: &[u8]) -> &[u8] {
+    if bytes.len() >= 2 && bytes[bytes.len() - 2] == END_TAG {
+        return &bytes[..bytes.len() - 2];
+    }
+
+    return bytes;
+}
@@ -0,0 +1,265 @@
+// AML interpreter. The DSDT and SSDTs are run once to build the namespace, after which methods and
+// named objects like `\_S5`, `_PRT`, `_HID` and `_CRS` can be evaluated.
+// The namespace is only ever touched by one CPU at a time, so AML Mutexes and Events always
+// succeed right away.
+
+pub mod name;
+pub mod region;
+pub mod value;
+
+mod interpreter;
+
+use alloc::{collections::BTreeMap, vec::Vec};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::libs::mutex::Mutex;
+
+pub use self::{
+    name::{AmlName, NameSeg, NameString},
+    value::AmlValue,
+};
+
+use self::value::{Method, MethodBody};
+
+#[derive(Debug)]
+pub enum AmlError {
+    UnexpectedEnd,
+    InvalidOpcode(u16),
+    InvalidName,
+    NameNotFound(AmlName),
+    TypeMismatch,
+    IndexOutOfBounds,
+    DivideByZero,
+    UnsupportedRegion(u8),
+    // Valid AML we don't handle, like loading tables at runtime
+    Unsupported,
+    // A While loop ran for too long
+    LoopLimit,
+    RecursionLimit,
+    Fatal {
+        fatal_type: u8,
+        code: u32,
+        argument: u64,
+    },
+}
+
+// Ordered by path, so every object comes right before the ones in its scope
+static NAMESPACE: Mutex<BTreeMap<AmlName, AmlValue>> = Mutex::new(BTreeMap::new());
+
+// Tables before revision 2 only have 32 bit integers
+static INTEGERS_32_BIT: AtomicBool = AtomicBool::new(false);
+
+// What we claim to be when the firmware asks, `_REV` is the ACPI revision we support
+const OS_NAME: &str = "Microsoft Windows NT";
+const OS_REVISION: u64 = 2;
+
+pub(super) fn integer_width() -> u64 {
+    if INTEGERS_32_BIT.load(Ordering::SeqCst) {
+        return 32;
+    }
+
+    return 64;
+}
+
+// Truncates `value` to the integer width of the tables
+pub(super) fn truncate(value: u64) -> u64 {
+    if INTEGERS_32_BIT.load(Ordering::SeqCst) {
+        return value & 0xFFFF_FFFF;
+    }
+
+    return value;
+}
+
+/// The object at `path`, with aliases followed.
+pub fn get_object(path: &AmlName) -> Option<AmlValue> {
+    let object = NAMESPACE.lock().read().get(path).cloned();
+
+    return match object {
+        Some(AmlValue::Alias(target)) => get_object(&target),
+        object => object,
+    };
+}
+
+fn exists(path: &AmlName) -> bool {
+    return NAMESPACE.lock().read().contains_key(path);
+}
+
+fn insert(path: AmlName, object: AmlValue) {
+    NAMESPACE.lock().write().insert(path, object);
+}
+
+// Removes `path` and everything in its scope
+fn remove(path: &AmlName) {
+    NAMESPACE
+        .lock()
+        .write()
+        .retain(|name, _| !name.starts_with(path));
+}
+
+/// Finds a lone name segment the way AML does, in `scope` first and then in every scope above it.
+pub fn lookup(scope: &AmlName, segment: NameSeg) -> Option<AmlName> {
+    let mut scope = Some(scope.clone());
+
+    while let Some(current) = scope {
+        let path = current.child(segment);
+
+        if exists(&path) {
+            return Some(resolve_alias(path));
+        }
+
+        scope = current.parent();
+    }
+
+    return None;
+}
+
+/// The path `name` refers to when used in `scope`, if there is something there.
+pub fn resolve(scope: &AmlName, name: &NameString) -> Option<AmlName> {
+    if name.uses_search_rules() {
+        return lookup(scope, name.segments[0]);
+    }
+
+    let path = name.resolve(scope)?;
+
+    if !exists(&path) {
+        return None;
+    }
+
+    return Some(resolve_alias(path));
+}
+
+fn resolve_alias(path: AmlName) -> AmlName {
+    let object = NAMESPACE.lock().read().get(&path).cloned();
+
+    return match object {
+        Some(AmlValue::Alias(target)) => resolve_alias(target),
+        _ => path,
+    };
+}
+
+/// Evaluates the object at `path`. Methods are run with `args`, fields are read and anything else
+/// is returned as is.
+pub fn evaluate(path: &AmlName, args: Vec<AmlValue>) -> Result<AmlValue, AmlError> {
+    let path = resolve_alias(path.clone());
+
+    return match get_object(&path) {
+        Some(AmlValue::Method(method)) => interpreter::invoke(&path, &method, args, 0),
+        Some(AmlValue::Field(field)) => region::read_field(&field),
+        Some(AmlValue::BufferField {
+            buffer,
+            offset,
+            length,
+        }) => match get_object(&buffer) {
+            Some(AmlValue::Buffer(bytes)) => Ok(region::read_buffer_field(&bytes, offset, length)),
+            _ => Err(AmlError::TypeMismatch),
+        },
+        Some(object) => Ok(object),
+        None => Err(AmlError::NameNotFound(path)),
+    };
+}
+
+/// Like `evaluate`, but without arguments and from a path string.
+pub fn evaluate_path(path: &str) -> Result<AmlValue, AmlError> {
+    let name = AmlName::parse(path).ok_or(AmlError::InvalidName)?;
+    return evaluate(&name, Vec::new());
+}
+
+/// Every object in the namespace, parents before their children.
+pub fn namespace() -> Vec<(AmlName, AmlValue)> {
+    return NAMESPACE
+        .lock()
+        .read()
+        .iter()
+        .map(|(name, object)| (name.clone(), object.clone()))
+        .collect();
+}
+
+// `\_OSI`, the firmware asks whether we support a feature or are a certain version of Windows
+fn os_interface(args: &[AmlValue]) -> Result<AmlValue, AmlError> {
+    let interface = args.first().ok_or(AmlError::TypeMismatch)?.as_string()?;
+
+    let supported = interface.starts_with("Windows")
+        || interface == "Module Device"
+        || interface == "Processor Device"
+        || interface == "3.0 Thermal Model";
+
+    return Ok(AmlValue::Integer(if supported {
+        truncate(u64::MAX)
+    } else {
+        0
+    }));
+}
+
+fn add_predefined_objects() {
+    for scope in ["\\_GPE", "\\_PR", "\\_SB", "\\_SI", "\\_TZ"] {
+        insert(AmlName::parse(scope).unwrap(), AmlValue::Scope);
+    }
+
+    insert(AmlName::parse("\\_OS").unwrap(), AmlValue::from(OS_NAME));
+    insert(
+        AmlName::parse("\\_REV").unwrap(),
+        AmlValue::Integer(OS_REVISION),
+    );
+    insert(
+        AmlName::parse("\\_OSI").unwrap(),
+        AmlValue::Method(Method {
+            flags: 1,
+            body: MethodBody::Native(os_interface),
+        }),
+    );
+    insert(
+        AmlName::parse("\\_GL").unwrap(),
+        AmlValue::Mutex { sync_level: 0 },
+    );
+}
+
+/// Builds the namespace from the DSDT and every SSDT.
+pub fn init() {
+    let dsdt = match super::find_table(b"DSDT") {
+        Some(dsdt) => dsdt,
+        None => {
+            crate::log_error!("No DSDT found, not loading any AML");
+            return;
+        }
+    };
+
+    let dsdt_revision = super::tables()
+        .iter()
+        .find(|table| &table.signature == b"DSDT")
+        .map_or(2, |table| table.revision);
+
+    INTEGERS_32_BIT.store(dsdt_revision < 2, Ordering::SeqCst);
+
+    add_predefined_objects();
+
+    for (i, aml) in core::iter::once(dsdt)
+        .chain(super::find_tables(b"SSDT"))
+        .enumerate()
+    {
+        if let Err(err) = interpreter::load(aml) {
+            crate::log_error!(
+                "Failed to load {}: {:?}",
+                if i == 0 { "the DSDT" } else { "an SSDT" },
+                err
+            );
+        }
+    }
+
+    crate::log_ok!(
+        "Loaded the AML namespace with {} objects",
+        NAMESPACE.lock().read().len()
+    );
+}
+
+/// Tells the firmware which interrupt controller we use, which changes what `_PRT` returns.
+pub fn set_interrupt_model(apic: bool) {
+    let path = AmlName::parse("\\_PIC").unwrap();
+
+    if !exists(&path) {
+        return;
+    }
+
+    if let Err(err) = evaluate(&path, alloc::vec![AmlValue::Integer(apic as u64)]) {
+        crate::log_error!("Failed to run \\_PIC: {err:?}");
+    }
+}
@@ -0,0 +1,171 @@
+// Paths in the ACPI namespace. Every path is made of four character segments, shorter names are
+// padded with underscores, so `\_SB.PCI0` and `\_SB_.PCI0` are the same thing.
+
+use core::fmt;
+
+use alloc::vec::Vec;
+
+pub type NameSeg = [u8; 4];
+
+/// An absolute path in the namespace, the root has no segments.
+#[derive(Clone, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct AmlName {
+    segments: Vec<NameSeg>,
+}
+
+impl AmlName {
+    pub const fn root() -> Self {
+        return Self {
+            segments: Vec::new(),
+        };
+    }
+
+    /// Parses a path like `\_SB.PCI0._PRT`. Relative paths are taken from the root.
+    pub fn parse(path: &str) -> Option<Self> {
+        let path = path.strip_prefix('\\').unwrap_or(path);
+
+        if path.is_empty() {
+            return Some(Self::root());
+        }
+
+        let mut segments = Vec::new();
+
+        for part in path.split('.') {
+            if part.is_empty() || part.len() > 4 {
+                return None;
+            }
+
+            let mut segment = [b'_'; 4];
+            segment[..part.len()].copy_from_slice(part.as_bytes());
+
+            if !is_valid_segment(&segment) {
+                return None;
+            }
+
+            segments.push(segment);
+        }
+
+        return Some(Self { segments });
+    }
+
+    pub fn segments(&self) -> &[NameSeg] {
+        return &self.segments;
+    }
+
+    pub fn depth(&self) -> usize {
+        return self.segments.len();
+    }
+
+    pub fn last(&self) -> Option<NameSeg> {
+        return self.segments.last().copied();
+    }
+
+    pub fn parent(&self) -> Option<Self> {
+        if self.segments.is_empty() {
+            return None;
+        }
+
+        return Some(Self {
+            segments: self.segments[..self.segments.len() - 1].to_vec(),
+        });
+    }
+
+    pub fn child(&self, segment: NameSeg) -> Self {
+        let mut segments = self.segments.clone();
+        segments.push(segment);
+
+        return Self { segments };
+    }
+
+    /// Whether `self` is `other` or somewhere below it.
+    pub fn starts_with(&self, other: &AmlName) -> bool {
+        return self.segments.starts_with(&other.segments);
+    }
+}
+
+impl fmt::Display for AmlName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "\\")?;
+
+        for (i, segment) in self.segments.iter().enumerate() {
+            if i != 0 {
+                write!(f, ".")?;
+            }
+
+            write!(f, "{}", segment_str(segment))?;
+        }
+
+        return Ok(());
+    }
+}
+
+// Errors print the path rather than a list of byte arrays
+impl fmt::Debug for AmlName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(f, "{self}");
+    }
+}
+
+pub fn segment_str(segment: &NameSeg) -> &str {
+    return core::str::from_utf8(segment).unwrap_or("????");
+}
+
+pub fn is_valid_segment(segment: &NameSeg) -> bool {
+    return segment.iter().enumerate().all(|(i, &byte)| match byte {
+        b'A'..=b'Z' | b'_' => true,
+        b'0'..=b'9' => i != 0,
+        _ => false,
+    });
+}
+
+/// A name as it appears in AML, which can be relative to the scope it's used in.
+#[derive(Clone, Debug, Default)]
+pub struct NameString {
+    pub root: bool,
+    // How many `^` it starts with, each one goes up a scope
+    pub parent_prefixes: usize,
+    pub segments: Vec<NameSeg>,
+}
+
+impl NameString {
+    /// Only lone segments are looked up in the enclosing scopes when they aren't found.
+    pub fn uses_search_rules(&self) -> bool {
+        return !self.root && self.parent_prefixes == 0 && self.segments.len() == 1;
+    }
+
+    /// The path this names when used in `scope`, None if it goes above the root.
+    pub fn resolve(&self, scope: &AmlName) -> Option<AmlName> {
+        let mut segments = if self.root {
+            Vec::new()
+        } else {
+            let depth = scope.depth().checked_sub(self.parent_prefixes)?;
+            scope.segments()[..depth].to_vec()
+        };
+
+        segments.extend_from_slice(&self.segments);
+
+        return Some(AmlName { segments });
+    }
+}
+
+impl fmt::Display for NameString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.root {
+            write!(f, "\\")?;
+        }
+
+        for _ in 0..self.parent_prefixes {
+            write!(f, "^")?;
+        }
+
+        for (i, segment) in self.segments.iter().enumerate() {
+            if i != 0 {
+                write!(f, ".")?;
+            }
+
+            write!(f, "{}", segment_str(segment))?;
+        }
+
+        return Ok(());
+    }
+}
@@ -0,0 +1,408 @@
+// Operation regions and the fields declared in them, which is how AML gets at the hardware.
+// Only system memory, I/O ports and PCI configuration space are supported, the embedded
+// controller and friends need drivers of their own.
+
+use alloc::{collections::BTreeMap, vec, vec::Vec};
+
+use crate::{
+    arch::{
+        io::{inb, inl, inw, outb, outl, outw},
+        paging,
+    },
+    drivers::{
+        acpi::{ADDRESS_SPACE_PCI_CONFIG, ADDRESS_SPACE_SYSTEM_IO, ADDRESS_SPACE_SYSTEM_MEMORY},
+        pci,
+    },
+    libs::mutex::Mutex,
+};
+
+use super::{evaluate, get_object, lookup, name::AmlName, value::AmlValue, AmlError};
+
+// Field flags
+const ACCESS_TYPE_MASK: u8 = 0x0F;
+const UPDATE_RULE_SHIFT: u8 = 5;
+const UPDATE_RULE_MASK: u8 = 0b11;
+
+const UPDATE_PRESERVE: u8 = 0;
+const UPDATE_WRITE_AS_ONES: u8 = 1;
+
+#[derive(Clone, Debug)]
+pub struct OperationRegion {
+    pub space: u8,
+    pub offset: u64,
+    pub length: u64,
+    // The scope it was declared in, a PCI configuration region belongs to the device there
+    pub parent: AmlName,
+}
+
+impl OperationRegion {
+    pub fn space_name(&self) -> &'static str {
+        return match self.space {
+            ADDRESS_SPACE_SYSTEM_MEMORY => "SystemMemory",
+            ADDRESS_SPACE_SYSTEM_IO => "SystemIO",
+            ADDRESS_SPACE_PCI_CONFIG => "PCI_Config",
+            3 => "EmbeddedControl",
+            4 => "SMBus",
+            5 => "SystemCMOS",
+            6 => "PciBarTarget",
+            7 => "IPMI",
+            8 => "GeneralPurposeIO",
+            9 => "GenericSerialBus",
+            10 => "PCC",
+            _ => "OEM",
+        };
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum FieldKind {
+    Region(AmlName),
+    // The offset is written to the index field, then the data field is accessed
+    Index {
+        index: AmlName,
+        data: AmlName,
+    },
+    // `value` is written to the bank field before every access to the region
+    Bank {
+        region: AmlName,
+        bank: AmlName,
+        value: u64,
+    },
+}
+
+#[derive(Clone, Debug)]
+pub struct Field {
+    pub kind: FieldKind,
+    pub flags: u8,
+    // In bits
+    pub offset: u64,
+    pub length: u64,
+}
+
+impl Field {
+    // How many bits to access the region with at a time
+    fn access_width(&self) -> u64 {
+        return match self.flags & ACCESS_TYPE_MASK {
+            2 => 16,
+            3 => 32,
+            4 => 64,
+            // Any, byte and buffer access
+            _ => 8,
+        };
+    }
+
+    fn update_rule(&self) -> u8 {
+        return (self.flags >> UPDATE_RULE_SHIFT) & UPDATE_RULE_MASK;
+    }
+}
+
+// Physical pages of memory regions and where we mapped them
+static MAPPED_PAGES: Mutex<BTreeMap<u64, u64>> = Mutex::new(BTreeMap::new());
+
+fn map_page(physical_address: u64) -> Result<u64, AmlError> {
+    let page = physical_address & !(paging::PAGE_SIZE - 1);
+
+    let mapped = MAPPED_PAGES.lock().read().get(&page).copied();
+
+    let virtual_page = match mapped {
+        Some(virtual_page) => virtual_page,
+        None => {
+            let virtual_page = paging::map_mmio(page, paging::PAGE_SIZE)
+                .map_err(|_| AmlError::UnsupportedRegion(ADDRESS_SPACE_SYSTEM_MEMORY))?;
+            MAPPED_PAGES.lock().write().insert(page, virtual_page);
+            virtual_page
+        }
+    };
+
+    return Ok(virtual_page + physical_address - page);
+}
+
+// Bus, device and function of the device a PCI configuration region belongs to
+fn pci_location(region: &OperationRegion) -> Result<(u8, u8, u8), AmlError> {
+    let address = match evaluate(&region.parent.child(*b"_ADR"), Vec::new()) {
+        Ok(address) => address.as_integer()?,
+        Err(AmlError::NameNotFound(_)) => 0,
+        Err(err) => return Err(err),
+    };
+
+    // The bus number belongs to the host bridge the device is under
+    let bus = match lookup(&region.parent, *b"_BBN") {
+        Some(path) => evaluate(&path, Vec::new())?.as_integer()?,
+        None => 0,
+    };
+
+    return Ok((bus as u8, (address >> 16) as u8, address as u8));
+}
+
+fn region_read(region: &OperationRegion, offset: u64, width: u64) -> Result<u64, AmlError> {
+    let address = region.offset + offset;
+
+    match region.space {
+        ADDRESS_SPACE_SYSTEM_MEMORY => {
+            let address = map_page(address)?;
+
+            return unsafe {
+                Ok(match width {
+                    8 => core::ptr::read_volatile(address as *const u8) as u64,
+                    16 => core::ptr::read_volatile(address as *const u16) as u64,
+                    32 => core::ptr::read_volatile(address as *const u32) as u64,
+                    _ => core::ptr::read_volatile(address as *const u64),
+                })
+            };
+        }
+        ADDRESS_SPACE_SYSTEM_IO => {
+            let port = address as u16;
+
+            return match width {
+                8 => Ok(inb(port) as u64),
+                16 => Ok(inw(port) as u64),
+                32 => Ok(inl(port) as u64),
+                _ => Err(AmlError::UnsupportedRegion(region.space)),
+            };
+        }
+        ADDRESS_SPACE_PCI_CONFIG => {
+            let (bus, device, function) = pci_location(region)?;
+            let dword = pci::read_pci_config(bus, device, function, address as u16 & 0xFFC);
+            let value = dword as u64 >> ((address & 3) * 8);
+
+            return Ok(match width {
+                64 => {
+                    value
+                        | (pci::read_pci_config(bus, device, function, (address as u16 & 0xFFC) + 4)
+                            as u64)
+                            << 32
+                }
+                _ => value & ((1 << width) - 1),
+            });
+        }
+        space => return Err(AmlError::UnsupportedRegion(space)),
+    }
+}
+
+fn region_write(
+    region: &OperationRegion,
+    offset: u64,
+    width: u64,
+    value: u64,
+) -> Result<(), AmlError> {
+    let address = region.offset + offset;
+
+    match region.space {
+        ADDRESS_SPACE_SYSTEM_MEMORY => {
+            let address = map_page(address)?;
+
+            unsafe {
+                match width {
+                    8 => core::ptr::write_volatile(address as *mut u8, value as u8),
+                    16 => core::ptr::write_volatile(address as *mut u16, value as u16),
+                    32 => core::ptr::write_volatile(address as *mut u32, value as u32),
+                    _ => core::ptr::write_volatile(address as *mut u64, value),
+                }
+            }
+        }
+        ADDRESS_SPACE_SYSTEM_IO => {
+            let port = address as u16;
+
+            match width {
+                8 => outb(port, value as u8),
+                16 => outw(port, value as u16),
+                32 => outl(port, value as u32),
+                _ => return Err(AmlError::UnsupportedRegion(region.space)),
+            }
+        }
+        ADDRESS_SPACE_PCI_CONFIG => {
+            let (bus, device, function) = pci_location(region)?;
+            let register = address as u16 & 0xFFC;
+
+            if width == 64 {
+                pci::write_pci_config(bus, device, function, register, value as u32);
+                pci::write_pci_config(bus, device, function, register + 4, (value >> 32) as u32);
+                return Ok(());
+            }
+
+            // Configuration space is accessed a dword at a time, so smaller writes have to keep
+            // the rest of it
+            let shift = (address & 3) * 8;
+            let mask = ((1u64 << width) - 1) << shift;
+            let dword = pci::read_pci_config(bus, device, function, register) as u64;
+
+            pci::write_pci_config(
+                bus,
+                device,
+                function,
+                register,
+                (dword & !mask | (value << shift) & mask) as u32,
+            );
+        }
+        space => return Err(AmlError::UnsupportedRegion(space)),
+    }
+
+    return Ok(());
+}
+
+fn get_region(path: &AmlName) -> Result<OperationRegion, AmlError> {
+    return match get_object(path) {
+        Some(AmlValue::OperationRegion(region)) => Ok(region),
+        Some(_) => Err(AmlError::TypeMismatch),
+        None => Err(AmlError::NameNotFound(path.clone())),
+    };
+}
+
+fn get_field(path: &AmlName) -> Result<Field, AmlError> {
+    return match get_object(path) {
+        Some(AmlValue::Field(field)) => Ok(field),
+        Some(_) => Err(AmlError::TypeMismatch),
+        None => Err(AmlError::NameNotFound(path.clone())),
+    };
+}
+
+// Reads `width` bits at byte `offset` of whatever the field is in
+fn access_read(kind: &FieldKind, offset: u64, width: u64) -> Result<u64, AmlError> {
+    match kind {
+        FieldKind::Region(region) => return region_read(&get_region(region)?, offset, width),
+        FieldKind::Index { index, data } => {
+            write_field(&get_field(index)?, &AmlValue::Integer(offset))?;
+            return read_field(&get_field(data)?)?.as_integer();
+        }
+        FieldKind::Bank {
+            region,
+            bank,
+            value,
+        } => {
+            write_field(&get_field(bank)?, &AmlValue::Integer(*value))?;
+            return region_read(&get_region(region)?, offset, width);
+        }
+    }
+}
+
+fn access_write(kind: &FieldKind, offset: u64, width: u64, value: u64) -> Result<(), AmlError> {
+    match kind {
+        FieldKind::Region(region) => {
+            return region_write(&get_region(region)?, offset, width, value)
+        }
+        FieldKind::Index { index, data } => {
+            write_field(&get_field(index)?, &AmlValue::Integer(offset))?;
+            return write_field(&get_field(data)?, &AmlValue::Integer(value));
+        }
+        FieldKind::Bank {
+            region,
+            bank,
+            value: bank_value,
+        } => {
+            write_field(&get_field(bank)?, &AmlValue::Integer(*bank_value))?;
+            return region_write(&get_region(region)?, offset, width, value);
+        }
+    }
+}
+
+fn get_bit(bytes: &[u8], bit: u64) -> bool {
+    return bytes
+        .get((bit / 8) as usize)
+        .is_some_and(|byte| byte & (1 << (bit % 8)) != 0);
+}
+
+fn set_bit(bytes: &mut [u8], bit: u64, set: bool) {
+    let byte = &mut bytes[(bit / 8) as usize];
+
+    if set {
+        *byte |= 1 << (bit % 8);
+    } else {
+        *byte &= !(1 << (bit % 8));
+    }
+}
+
+// Small enough fields read as integers, bigger ones as buffers
+fn bits_to_value(bytes: Vec<u8>, length: u64) -> AmlValue {
+    if length <= super::integer_width() {
+        return AmlValue::Integer(AmlValue::Buffer(bytes).as_integer().unwrap_or(0));
+    }
+
+    return AmlValue::Buffer(bytes);
+}
+
+pub fn read_field(field: &Field) -> Result<AmlValue, AmlError> {
+    let width = field.access_width();
+    let end = field.offset + field.length;
+    let mut bytes = vec![0u8; field.length.div_ceil(8) as usize];
+
+    let mut access = field.offset / width * width;
+
+    while access < end {
+        let value = access_read(&field.kind, access / 8, width)?;
+
+        for bit in access.max(field.offset)..(access + width).min(end) {
+            if value & (1 << (bit - access)) != 0 {
+                set_bit(&mut bytes, bit - field.offset, true);
+            }
+        }
+
+        access += width;
+    }
+
+    return Ok(bits_to_value(bytes, field.length));
+}
+
+pub fn write_field(field: &Field, value: &AmlValue) -> Result<(), AmlError> {
+    let source = value.as_buffer()?;
+    let width = field.access_width();
+    let end = field.offset + field.length;
+    let width_mask = if width == 64 {
+        u64::MAX
+    } else {
+        (1 << width) - 1
+    };
+
+    let mut access = field.offset / width * width;
+
+    while access < end {
+        let covered = access >= field.offset && access + width <= end;
+
+        // Bits of the access that aren't part of the field get what the update rule says
+        let mut chunk = match field.update_rule() {
+            _ if covered => 0,
+            UPDATE_PRESERVE => access_read(&field.kind, access / 8, width)?,
+            UPDATE_WRITE_AS_ONES => width_mask,
+            _ => 0,
+        };
+
+        for bit in access.max(field.offset)..(access + width).min(end) {
+            if get_bit(&source, bit - field.offset) {
+                chunk |= 1 << (bit - access);
+            } else {
+                chunk &= !(1 << (bit - access));
+            }
+        }
+
+        access_write(&field.kind, access / 8, width, chunk)?;
+
+        access += width;
+    }
+
+    return Ok(());
+}
+
+/// Reads `length` bits at bit `offset` of a buffer.
+pub fn read_buffer_field(buffer: &[u8], offset: u64, length: u64) -> AmlValue {
+    let mut bytes = vec![0u8; length.div_ceil(8) as usize];
+
+    for bit in 0..length {
+        if get_bit(buffer, offset + bit) {
+            set_bit(&mut bytes, bit, true);
+        }
+    }
+
+    return bits_to_value(bytes, length);
+}
+
+/// Writes `length` bits at bit `offset` of a buffer, bits past the end of it are dropped.
+pub fn write_buffer_field(buffer: &mut [u8], offset: u64, length: u64, value: &AmlValue) {
+    let source = value.as_buffer().unwrap_or_default();
+
+    for bit in 0..length {
+        if offset + bit >= buffer.len() as u64 * 8 {
+            break;
+        }
+
+        set_bit(buffer, offset + bit, get_bit(&source, bit));
+    }
+}
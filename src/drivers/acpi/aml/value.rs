@@ -0,0 +1,214 @@
+// The objects AML works with, from plain data to the devices and methods in the namespace.
+
+use core::fmt;
+
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+
+use super::{
+    name::AmlName,
+    region::{Field, OperationRegion},
+    AmlError,
+};
+
+/// Built in methods, like `\_OSI`, that the OS provides instead of the firmware.
+pub type NativeMethod = fn(&[AmlValue]) -> Result<AmlValue, AmlError>;
+
+#[derive(Clone, Copy)]
+pub enum MethodBody {
+    Aml(&'static [u8]),
+    Native(NativeMethod),
+}
+
+#[derive(Clone, Copy)]
+pub struct Method {
+    pub flags: u8,
+    pub body: MethodBody,
+}
+
+impl Method {
+    pub fn arg_count(&self) -> usize {
+        return (self.flags & 0b111) as usize;
+    }
+}
+
+impl fmt::Debug for Method {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self.body {
+            MethodBody::Aml(code) => {
+                write!(f, "Method({} args, {} bytes)", self.arg_count(), code.len())
+            }
+            MethodBody::Native(_) => write!(f, "Method({} args, native)", self.arg_count()),
+        };
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub enum AmlValue {
+    #[default]
+    Uninitialized,
+    Integer(u64),
+    String(String),
+    Buffer(Vec<u8>),
+    Package(Vec<AmlValue>),
+    // A named object, from RefOf or a name inside of a package
+    Reference(AmlName),
+    Method(Method),
+    Device,
+    // Predefined scopes like \_SB, which hold objects without being one
+    Scope,
+    Processor {
+        id: u8,
+        block_address: u32,
+        block_length: u8,
+    },
+    PowerResource {
+        system_level: u8,
+        resource_order: u16,
+    },
+    ThermalZone,
+    Mutex {
+        sync_level: u8,
+    },
+    Event,
+    OperationRegion(OperationRegion),
+    Field(Field),
+    // Bits of a named buffer, in bits
+    BufferField {
+        buffer: AmlName,
+        offset: u64,
+        length: u64,
+    },
+    Alias(AmlName),
+}
+
+impl AmlValue {
+    /// The number ObjectType returns for this.
+    pub fn object_type(&self) -> u64 {
+        return match self {
+            AmlValue::Uninitialized => 0,
+            AmlValue::Integer(_) => 1,
+            AmlValue::String(_) => 2,
+            AmlValue::Buffer(_) => 3,
+            AmlValue::Package(_) => 4,
+            AmlValue::Field(_) => 5,
+            AmlValue::Device => 6,
+            AmlValue::Event => 7,
+            AmlValue::Method(_) => 8,
+            AmlValue::Mutex { .. } => 9,
+            AmlValue::OperationRegion(_) => 10,
+            AmlValue::PowerResource { .. } => 11,
+            AmlValue::Processor { .. } => 12,
+            AmlValue::ThermalZone => 13,
+            AmlValue::BufferField { .. } => 14,
+            AmlValue::Reference(_) | AmlValue::Scope | AmlValue::Alias(_) => 0,
+        };
+    }
+
+    pub fn as_integer(&self) -> Result<u64, AmlError> {
+        return match self {
+            AmlValue::Integer(value) => Ok(*value),
+            // Strings are read as hex, up to the first character that isn't a digit
+            AmlValue::String(string) => Ok(string
+                .trim_start_matches("0x")
+                .chars()
+                .map_while(|character| character.to_digit(16))
+                .take(16)
+                .fold(0, |value, digit| value << 4 | digit as u64)),
+            AmlValue::Buffer(bytes) => Ok(bytes
+                .iter()
+                .take(8)
+                .rev()
+                .fold(0, |value, &byte| value << 8 | byte as u64)),
+            _ => Err(AmlError::TypeMismatch),
+        };
+    }
+
+    pub fn as_buffer(&self) -> Result<Vec<u8>, AmlError> {
+        return match self {
+            AmlValue::Integer(value) => Ok(value.to_le_bytes().to_vec()),
+            AmlValue::String(string) => {
+                let mut bytes = string.as_bytes().to_vec();
+                bytes.push(0);
+                Ok(bytes)
+            }
+            AmlValue::Buffer(bytes) => Ok(bytes.clone()),
+            _ => Err(AmlError::TypeMismatch),
+        };
+    }
+
+    pub fn as_string(&self) -> Result<String, AmlError> {
+        return match self {
+            AmlValue::Integer(value) => Ok(format!("{value:016X}")),
+            AmlValue::String(string) => Ok(string.clone()),
+            AmlValue::Buffer(bytes) => Ok(bytes
+                .iter()
+                .map(|byte| format!("{byte:02X}"))
+                .collect::<Vec<_>>()
+                .join(" ")),
+            _ => Err(AmlError::TypeMismatch),
+        };
+    }
+
+    pub fn as_package(&self) -> Result<&[AmlValue], AmlError> {
+        return match self {
+            AmlValue::Package(elements) => Ok(elements),
+            _ => Err(AmlError::TypeMismatch),
+        };
+    }
+}
+
+impl fmt::Display for AmlValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            AmlValue::Uninitialized => write!(f, "Uninitialized"),
+            AmlValue::Integer(value) => write!(f, "Integer {value:#X}"),
+            AmlValue::String(string) => write!(f, "String {:?}", string),
+            AmlValue::Buffer(bytes) => write!(f, "Buffer ({} bytes)", bytes.len()),
+            AmlValue::Package(elements) => write!(f, "Package ({} elements)", elements.len()),
+            AmlValue::Reference(name) => write!(f, "Reference {name}"),
+            AmlValue::Method(method) => match method.body {
+                MethodBody::Aml(_) => write!(f, "Method ({} args)", method.arg_count()),
+                MethodBody::Native(_) => {
+                    write!(f, "Method ({} args, built in)", method.arg_count())
+                }
+            },
+            AmlValue::Device => write!(f, "Device"),
+            AmlValue::Scope => write!(f, "Scope"),
+            AmlValue::Processor { id, .. } => write!(f, "Processor {id}"),
+            AmlValue::PowerResource { system_level, .. } => {
+                write!(f, "PowerResource (S{system_level})")
+            }
+            AmlValue::ThermalZone => write!(f, "ThermalZone"),
+            AmlValue::Mutex { .. } => write!(f, "Mutex"),
+            AmlValue::Event => write!(f, "Event"),
+            AmlValue::OperationRegion(region) => write!(
+                f,
+                "OperationRegion {} {:#X} ({:#X} bytes)",
+                region.space_name(),
+                region.offset,
+                region.length
+            ),
+            AmlValue::Field(field) => {
+                write!(f, "Field ({} bits at bit {})", field.length, field.offset)
+            }
+            AmlValue::BufferField {
+                buffer,
+                offset,
+                length,
+            } => {
+                write!(f, "BufferField ({length} bits at bit {offset} of {buffer})")
+            }
+            AmlValue::Alias(target) => write!(f, "Alias {target}"),
+        };
+    }
+}
+
+impl From<&str> for AmlValue {
+    fn from(string: &str) -> Self {
+        return AmlValue::String(string.to_string());
+    }
+}
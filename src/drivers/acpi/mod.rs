@@ -0,0 +1,265 @@
+// Finds the ACPI tables through the RSDP Limine gives us. Every table is checksummed and copied
+// onto the heap, so the firmware's copies can be reclaimed once we are done with them.
+
+pub mod aml;
+pub mod power;
+pub mod tables;
+
+pub use self::tables::*;
+
+use alloc::{boxed::Box, vec::Vec};
+use limine::RsdpRequest;
+
+use crate::{arch::paging, libs::mutex::Mutex, log_error, log_ok};
+
+static RSDP_REQUEST: RsdpRequest = RsdpRequest::new(0);
+
+#[repr(C, packed)]
+struct RSDP {
+    signature: [u8; 8],
+    checksum: u8,
+    oem_id: [u8; 6],
+    revision: u8,
+    rsdt_address: u32,
+
+    // Only on Revision > 0
+    length: u32,
+    xsdt_address: u64,
+    extended_checksum: u8,
+    reserved: [u8; 3],
+}
+
+const RSDP_V1_LENGTH: usize = 20;
+const RSDP_SIG: [u8; 8] = *b"RSD PTR ";
+
+// ACPI 2.0 is where the XSDT was added
+const XSDT_REVISION: u8 = 2;
+
+impl RSDP {
+    pub fn is_valid(&self) -> bool {
+        if self.signature != RSDP_SIG {
+            return false;
+        }
+
+        if core::str::from_utf8(&self.oem_id).is_err() {
+            return false;
+        }
+
+        let length = if self.revision > 0 {
+            self.length as usize
+        } else {
+            RSDP_V1_LENGTH
+        };
+
+        let bytes =
+            unsafe { core::slice::from_raw_parts(self as *const RSDP as *const u8, length) };
+
+        return checksum(bytes) == 0;
+    }
+}
+
+#[repr(C, packed)]
+struct ACPISDTHeader {
+    signature: [u8; 4],
+    length: u32,
+    revision: u8,
+    checksum: u8,
+    oem_id: [u8; 6],
+    oem_table_id: [u8; 8],
+    oem_revision: u32,
+    creator_id: u32,
+    creator_revision: u32,
+}
+
+const HEADER_LENGTH: usize = core::mem::size_of::<ACPISDTHeader>();
+
+/// The header of a table, for listing them.
+#[derive(Clone, Copy, Debug)]
+pub struct TableInfo {
+    pub signature: [u8; 4],
+    pub oem_id: [u8; 6],
+    pub oem_table_id: [u8; 8],
+    pub revision: u8,
+    pub length: usize,
+    // Where the firmware put it
+    pub physical_address: u64,
+}
+
+struct Table {
+    info: TableInfo,
+    // The whole table, header included
+    bytes: Box<[u8]>,
+}
+
+static TABLES: Mutex<Vec<Table>> = Mutex::new(Vec::new());
+
+fn checksum(bytes: &[u8]) -> u8 {
+    return bytes.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+}
+
+fn header_at(physical_address: u64) -> &'static ACPISDTHeader {
+    return unsafe { &*((physical_address + paging::hhdm_offset()) as *const ACPISDTHeader) };
+}
+
+// Checks the table at `physical_address` and copies it, None if it's broken
+fn copy_table(physical_address: u64) -> Option<Table> {
+    if physical_address == 0 {
+        return None;
+    }
+
+    let header = header_at(physical_address);
+    let length = header.length as usize;
+
+    if length < HEADER_LENGTH {
+        return None;
+    }
+
+    let bytes =
+        unsafe { core::slice::from_raw_parts(header as *const ACPISDTHeader as *const u8, length) };
+
+    if checksum(bytes) != 0 {
+        log_error!(
+            "ACPI table {} at {:#X} has a bad checksum",
+            signature_str(&header.signature),
+            physical_address
+        );
+        return None;
+    }
+
+    return Some(Table {
+        info: TableInfo {
+            signature: header.signature,
+            oem_id: header.oem_id,
+            oem_table_id: header.oem_table_id,
+            revision: header.revision,
+            length,
+            physical_address,
+        },
+        bytes: Box::from(bytes),
+    });
+}
+
+pub fn signature_str(signature: &[u8]) -> &str {
+    return core::str::from_utf8(signature).unwrap_or("????");
+}
+
+pub fn init_acpi() {
+    let rsdp_response = RSDP_REQUEST.get_response().get();
+
+    if rsdp_response.is_none() {
+        log_error!("Failed to initialize ACPI: RSDP not found!");
+        return;
+    }
+
+    let rsdp_address = &rsdp_response.unwrap().address;
+
+    let rsdp_table: &RSDP = unsafe { &*(rsdp_address.as_ptr().unwrap() as *const RSDP) };
+
+    if !rsdp_table.is_valid() {
+        log_error!("Failed to initialize ACPI: RSDP was not valid!");
+        return;
+    }
+
+    // The XSDT holds 64 bit pointers, the RSDT 32 bit ones
+    let (root_address, entry_size) =
+        if rsdp_table.revision >= XSDT_REVISION && rsdp_table.xsdt_address != 0 {
+            (rsdp_table.xsdt_address, 8)
+        } else {
+            (rsdp_table.rsdt_address as u64, 4)
+        };
+
+    let root = match copy_table(root_address) {
+        Some(root) => root,
+        None => {
+            log_error!("Failed to initialize ACPI: root table was not valid!");
+            return;
+        }
+    };
+
+    let mut tables = Vec::new();
+
+    for entry in root.bytes[HEADER_LENGTH..].chunks_exact(entry_size) {
+        let mut address = [0u8; 8];
+        address[..entry_size].copy_from_slice(entry);
+
+        if let Some(table) = copy_table(u64::from_le_bytes(address)) {
+            tables.push(table);
+        }
+    }
+
+    tables.insert(0, root);
+
+    // The DSDT is only pointed to by the FADT
+    let dsdt_address = tables
+        .iter()
+        .find(|table| &table.info.signature == b"FACP")
+        .and_then(|fadt| Fadt::parse(&fadt.bytes[HEADER_LENGTH..]))
+        .map(|fadt| fadt.dsdt_address);
+
+    if let Some(dsdt) = dsdt_address.and_then(copy_table) {
+        tables.push(dsdt);
+    }
+
+    let table_count = tables.len();
+
+    *TABLES.lock().write() = tables;
+
+    log_ok!(
+        "Successfully initialized ACPI revision {}, found {} tables",
+        rsdp_table.revision,
+        table_count
+    );
+
+    aml::init();
+
+    // Everything we need has been copied out
+    crate::sys::mem::reclaim_acpi_memory();
+}
+
+/// The contents of the first table with `signature`, without its header.
+pub fn find_table(signature: &[u8; 4]) -> Option<&'static [u8]> {
+    return TABLES
+        .lock()
+        .read()
+        .iter()
+        .find(|table| &table.info.signature == signature)
+        .map(|table| &table.bytes[HEADER_LENGTH..]);
+}
+
+/// The contents of every table with `signature`, without their headers. There can be any number
+/// of SSDTs for example.
+pub fn find_tables(signature: &[u8; 4]) -> Vec<&'static [u8]> {
+    return TABLES
+        .lock()
+        .read()
+        .iter()
+        .filter(|table| &table.info.signature == signature)
+        .map(|table| &table.bytes[HEADER_LENGTH..])
+        .collect();
+}
+
+pub fn tables() -> Vec<TableInfo> {
+    return TABLES
+        .lock()
+        .read()
+        .iter()
+        .map(|table| table.info)
+        .collect();
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    return u16::from_le_bytes([bytes[offset], bytes[offset + 1]]);
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    return u32::from_le_bytes([
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ]);
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    return read_u32(bytes, offset) as u64 | (read_u32(bytes, offset + 4) as u64) << 32;
+}
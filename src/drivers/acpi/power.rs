@@ -0,0 +1,228 @@
+// Shutting down and rebooting through the FADT's fixed hardware registers.
+// The sleep type of S5 comes from the `\_S5` package in the AML namespace.
+
+use crate::{
+    arch::{
+        self, interrupts,
+        io::{inb, inl, inw, outb, outl, outw},
+        paging,
+    },
+    drivers::keyboard,
+};
+
+use super::{
+    aml, fadt, Fadt, GenericAddress, ADDRESS_SPACE_SYSTEM_IO, ADDRESS_SPACE_SYSTEM_MEMORY,
+};
+
+// PM1 control register bits
+const SCI_ENABLE: u64 = 1 << 0;
+const SLEEP_TYPE_SHIFT: u64 = 10;
+const SLEEP_TYPE_MASK: u64 = 0b111 << SLEEP_TYPE_SHIFT;
+const SLEEP_ENABLE: u64 = 1 << 13;
+
+// How long each way of rebooting or powering off gets before we try the next one
+const RESET_TIMEOUT_MS: u64 = 500;
+const ACPI_ENABLE_TIMEOUT_MS: u64 = 3000;
+
+#[derive(Debug)]
+pub enum PowerError {
+    NoFadt,
+    // There is no `\_S5` package we can read
+    NoSleepState,
+    NoControlBlock,
+    UnsupportedRegister,
+    AcpiEnableTimeout,
+    // Everything was written and the machine is still on
+    StillRunning,
+}
+
+// How many bits to access a register with, older tables leave the access size undefined
+fn access_width(register: &GenericAddress) -> u8 {
+    return match register.access_size {
+        1 => 8,
+        2 => 16,
+        3 => 32,
+        4 => 64,
+        _ => register.bit_width,
+    };
+}
+
+fn read_register(register: &GenericAddress) -> Result<u64, PowerError> {
+    let width = access_width(register);
+
+    match register.address_space {
+        ADDRESS_SPACE_SYSTEM_IO => {
+            let port = register.address as u16;
+
+            return match width {
+                8 => Ok(inb(port) as u64),
+                16 => Ok(inw(port) as u64),
+                32 => Ok(inl(port) as u64),
+                _ => Err(PowerError::UnsupportedRegister),
+            };
+        }
+        ADDRESS_SPACE_SYSTEM_MEMORY => {
+            let address = paging::map_mmio(register.address, width as u64 / 8)
+                .map_err(|_| PowerError::UnsupportedRegister)?;
+
+            return unsafe {
+                match width {
+                    8 => Ok(core::ptr::read_volatile(address as *const u8) as u64),
+                    16 => Ok(core::ptr::read_volatile(address as *const u16) as u64),
+                    32 => Ok(core::ptr::read_volatile(address as *const u32) as u64),
+                    64 => Ok(core::ptr::read_volatile(address as *const u64)),
+                    _ => Err(PowerError::UnsupportedRegister),
+                }
+            };
+        }
+        _ => return Err(PowerError::UnsupportedRegister),
+    }
+}
+
+fn write_register(register: &GenericAddress, value: u64) -> Result<(), PowerError> {
+    let width = access_width(register);
+
+    match register.address_space {
+        ADDRESS_SPACE_SYSTEM_IO => {
+            let port = register.address as u16;
+
+            match width {
+                8 => outb(port, value as u8),
+                16 => outw(port, value as u16),
+                32 => outl(port, value as u32),
+                _ => return Err(PowerError::UnsupportedRegister),
+            }
+        }
+        ADDRESS_SPACE_SYSTEM_MEMORY => {
+            let address = paging::map_mmio(register.address, width as u64 / 8)
+                .map_err(|_| PowerError::UnsupportedRegister)?;
+
+            unsafe {
+                match width {
+                    8 => core::ptr::write_volatile(address as *mut u8, value as u8),
+                    16 => core::ptr::write_volatile(address as *mut u16, value as u16),
+                    32 => core::ptr::write_volatile(address as *mut u32, value as u32),
+                    64 => core::ptr::write_volatile(address as *mut u64, value),
+                    _ => return Err(PowerError::UnsupportedRegister),
+                }
+            }
+        }
+        _ => return Err(PowerError::UnsupportedRegister),
+    }
+
+    return Ok(());
+}
+
+/// The values to write to SLP_TYP of PM1a and PM1b to enter S5.
+pub fn s5_sleep_type() -> Option<(u8, u8)> {
+    let s5 = aml::evaluate_path("\\_S5").ok()?;
+    let package = s5.as_package().ok()?;
+
+    let sleep_type_a = package.first()?.as_integer().ok()?;
+    let sleep_type_b = match package.get(1) {
+        Some(sleep_type_b) => sleep_type_b.as_integer().ok()?,
+        None => 0,
+    };
+
+    return Some((sleep_type_a as u8, sleep_type_b as u8));
+}
+
+// Asks the firmware to give us the power management registers, if it still owns them
+fn enable_acpi(fadt: &Fadt, pm1a_control: &GenericAddress) -> Result<(), PowerError> {
+    if read_register(pm1a_control)? & SCI_ENABLE != 0
+        || fadt.smi_command_port == 0
+        || fadt.acpi_enable == 0
+    {
+        return Ok(());
+    }
+
+    outb(fadt.smi_command_port as u16, fadt.acpi_enable);
+
+    let mut deadline = crate::sys::time::Deadline::after_ms(ACPI_ENABLE_TIMEOUT_MS);
+
+    while read_register(pm1a_control)? & SCI_ENABLE == 0 {
+        if deadline.has_passed() {
+            return Err(PowerError::AcpiEnableTimeout);
+        }
+
+        arch::pause();
+    }
+
+    return Ok(());
+}
+
+fn set_sleep_type(
+    register: &GenericAddress,
+    sleep_type: u8,
+    enable: bool,
+) -> Result<(), PowerError> {
+    let mut value = read_register(register)? & !(SLEEP_TYPE_MASK | SLEEP_ENABLE);
+    value |= (sleep_type as u64) << SLEEP_TYPE_SHIFT & SLEEP_TYPE_MASK;
+
+    if enable {
+        value |= SLEEP_ENABLE;
+    }
+
+    return write_register(register, value);
+}
+
+/// Powers the machine off by entering S5. Only returns if that didn't work.
+pub fn shutdown() -> PowerError {
+    let fadt = match fadt() {
+        Some(fadt) => fadt,
+        None => return PowerError::NoFadt,
+    };
+
+    let (sleep_type_a, sleep_type_b) = match s5_sleep_type() {
+        Some(sleep_type) => sleep_type,
+        None => return PowerError::NoSleepState,
+    };
+
+    let pm1a_control = match fadt.pm1a_control_block {
+        Some(pm1a_control) => pm1a_control,
+        None => return PowerError::NoControlBlock,
+    };
+
+    if let Err(err) = enable_acpi(&fadt, &pm1a_control) {
+        return err;
+    }
+
+    // SLP_TYP goes into both blocks before either of them gets SLP_EN
+    let result = arch::without_interrupts(|| {
+        set_sleep_type(&pm1a_control, sleep_type_a, false)?;
+
+        if let Some(pm1b_control) = fadt.pm1b_control_block {
+            set_sleep_type(&pm1b_control, sleep_type_b, false)?;
+            set_sleep_type(&pm1b_control, sleep_type_b, true)?;
+        }
+
+        return set_sleep_type(&pm1a_control, sleep_type_a, true);
+    });
+
+    if let Err(err) = result {
+        return err;
+    }
+
+    crate::sys::time::busy_sleep_ms(RESET_TIMEOUT_MS);
+
+    return PowerError::StillRunning;
+}
+
+/// Resets the machine, through the FADT's reset register if there is one, then the keyboard
+/// controller, and as a last resort a triple fault.
+pub fn reboot() -> ! {
+    if let Some(fadt) = fadt() {
+        if let Some(reset_register) = fadt.reset_register {
+            match write_register(&reset_register, fadt.reset_value as u64) {
+                Ok(()) => crate::sys::time::busy_sleep_ms(RESET_TIMEOUT_MS),
+                Err(err) => crate::log_error!("Failed to use the ACPI reset register: {err:?}"),
+            }
+        }
+    }
+
+    if keyboard::pulse_reset_line().is_ok() {
+        crate::sys::time::busy_sleep_ms(RESET_TIMEOUT_MS);
+    }
+
+    interrupts::triple_fault();
+}
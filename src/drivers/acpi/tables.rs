@@ -0,0 +1,405 @@
+// Typed versions of the fixed ACPI tables we use. Everything is decoded from the copies made in
+// `init_acpi`, with offsets relative to the end of the table's header.
+
+use alloc::vec::Vec;
+
+use super::{find_table, read_u16, read_u32, read_u64};
+
+// Address spaces of a generic address
+pub const ADDRESS_SPACE_SYSTEM_MEMORY: u8 = 0;
+pub const ADDRESS_SPACE_SYSTEM_IO: u8 = 1;
+pub const ADDRESS_SPACE_PCI_CONFIG: u8 = 2;
+
+/// Where a register lives, in memory, I/O ports or PCI configuration space.
+#[derive(Clone, Copy, Debug)]
+pub struct GenericAddress {
+    pub address_space: u8,
+    pub bit_width: u8,
+    pub bit_offset: u8,
+    pub access_size: u8,
+    pub address: u64,
+}
+
+impl GenericAddress {
+    fn parse(bytes: &[u8], offset: usize) -> Option<Self> {
+        if bytes.len() < offset + 12 {
+            return None;
+        }
+
+        let address = Self {
+            address_space: bytes[offset],
+            bit_width: bytes[offset + 1],
+            bit_offset: bytes[offset + 2],
+            access_size: bytes[offset + 3],
+            address: read_u64(bytes, offset + 4),
+        };
+
+        if address.address == 0 {
+            return None;
+        }
+
+        return Some(address);
+    }
+
+    fn io_port(port: u32, length: u8) -> Option<Self> {
+        if port == 0 {
+            return None;
+        }
+
+        return Some(Self {
+            address_space: ADDRESS_SPACE_SYSTEM_IO,
+            bit_width: length * 8,
+            bit_offset: 0,
+            access_size: 0,
+            address: port as u64,
+        });
+    }
+}
+
+// MADT entry types
+const MADT_LOCAL_APIC: u8 = 0;
+const MADT_IO_APIC: u8 = 1;
+const MADT_INTERRUPT_OVERRIDE: u8 = 2;
+const MADT_LOCAL_APIC_ADDRESS: u8 = 5;
+const MADT_LOCAL_X2APIC: u8 = 9;
+
+// Local APIC flags
+const MADT_PROCESSOR_ENABLED: u32 = 1 << 0;
+const MADT_PROCESSOR_ONLINE_CAPABLE: u32 = 1 << 1;
+
+#[derive(Clone, Copy, Debug)]
+pub struct MadtLocalApic {
+    pub processor_id: u32,
+    pub apic_id: u32,
+    // Disabled processors can still be brought online when the online capable bit is set
+    pub usable: bool,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct MadtIoApic {
+    pub id: u8,
+    pub address: u32,
+    // The first global system interrupt this IOAPIC handles
+    pub gsi_base: u32,
+}
+
+/// An ISA IRQ that isn't wired to the global system interrupt of the same number.
+#[derive(Clone, Copy, Debug)]
+pub struct MadtInterruptOverride {
+    pub bus: u8,
+    pub irq: u8,
+    pub gsi: u32,
+    // MPS INTI flags, bits 0-1 are the polarity and bits 2-3 the trigger mode
+    pub flags: u16,
+}
+
+/// The Multiple APIC Description Table, describing the interrupt controllers of the system.
+#[derive(Clone, Debug)]
+pub struct Madt {
+    pub local_apic_address: u64,
+    pub flags: u32,
+    pub local_apics: Vec<MadtLocalApic>,
+    pub io_apics: Vec<MadtIoApic>,
+    pub interrupt_overrides: Vec<MadtInterruptOverride>,
+}
+
+impl Madt {
+    fn parse(data: &[u8]) -> Option<Self> {
+        if data.len() < 8 {
+            return None;
+        }
+
+        let mut madt = Self {
+            local_apic_address: read_u32(data, 0) as u64,
+            flags: read_u32(data, 4),
+            local_apics: Vec::new(),
+            io_apics: Vec::new(),
+            interrupt_overrides: Vec::new(),
+        };
+
+        let mut offset = 8;
+
+        // Every entry starts with its type and length
+        while offset + 2 <= data.len() {
+            let entry_type = data[offset];
+            let length = data[offset + 1] as usize;
+
+            if length < 2 || offset + length > data.len() {
+                break;
+            }
+
+            let entry = &data[offset..offset + length];
+
+            match entry_type {
+                MADT_LOCAL_APIC if length >= 8 => {
+                    let flags = read_u32(entry, 4);
+
+                    madt.local_apics.push(MadtLocalApic {
+                        processor_id: entry[2] as u32,
+                        apic_id: entry[3] as u32,
+                        usable: flags & (MADT_PROCESSOR_ENABLED | MADT_PROCESSOR_ONLINE_CAPABLE)
+                            != 0,
+                    });
+                }
+                MADT_IO_APIC if length >= 12 => madt.io_apics.push(MadtIoApic {
+                    id: entry[2],
+                    address: read_u32(entry, 4),
+                    gsi_base: read_u32(entry, 8),
+                }),
+                MADT_INTERRUPT_OVERRIDE if length >= 10 => {
+                    madt.interrupt_overrides.push(MadtInterruptOverride {
+                        bus: entry[2],
+                        irq: entry[3],
+                        gsi: read_u32(entry, 4),
+                        flags: read_u16(entry, 8),
+                    })
+                }
+                MADT_LOCAL_APIC_ADDRESS if length >= 12 => {
+                    madt.local_apic_address = read_u64(entry, 4);
+                }
+                MADT_LOCAL_X2APIC if length >= 16 => {
+                    let flags = read_u32(entry, 8);
+
+                    madt.local_apics.push(MadtLocalApic {
+                        processor_id: read_u32(entry, 12),
+                        apic_id: read_u32(entry, 4),
+                        usable: flags & (MADT_PROCESSOR_ENABLED | MADT_PROCESSOR_ONLINE_CAPABLE)
+                            != 0,
+                    });
+                }
+                _ => {}
+            }
+
+            offset += length;
+        }
+
+        return Some(madt);
+    }
+}
+
+pub fn madt() -> Option<Madt> {
+    return Madt::parse(find_table(b"APIC")?);
+}
+
+// FADT flags
+const FADT_RESET_REGISTER_SUPPORTED: u32 = 1 << 10;
+
+// IA-PC boot architecture flags
+const BOOT_ARCH_8042: u16 = 1 << 1;
+
+// Offsets of the fields we read, the extended ones were added in ACPI 2.0
+const FADT_DSDT: usize = 4;
+const FADT_SCI_INTERRUPT: usize = 10;
+const FADT_SMI_COMMAND: usize = 12;
+const FADT_ACPI_ENABLE: usize = 16;
+const FADT_ACPI_DISABLE: usize = 17;
+const FADT_PM1A_EVENT_BLOCK: usize = 20;
+const FADT_PM1B_EVENT_BLOCK: usize = 24;
+const FADT_PM1A_CONTROL_BLOCK: usize = 28;
+const FADT_PM1B_CONTROL_BLOCK: usize = 32;
+const FADT_PM_TIMER_BLOCK: usize = 40;
+const FADT_PM1_EVENT_LENGTH: usize = 52;
+const FADT_PM1_CONTROL_LENGTH: usize = 53;
+const FADT_PM_TIMER_LENGTH: usize = 55;
+const FADT_CENTURY: usize = 72;
+const FADT_BOOT_ARCHITECTURE_FLAGS: usize = 73;
+const FADT_FLAGS: usize = 76;
+const FADT_RESET_REGISTER: usize = 80;
+const FADT_RESET_VALUE: usize = 92;
+const FADT_X_DSDT: usize = 104;
+const FADT_X_PM1A_EVENT_BLOCK: usize = 112;
+const FADT_X_PM1B_EVENT_BLOCK: usize = 124;
+const FADT_X_PM1A_CONTROL_BLOCK: usize = 136;
+const FADT_X_PM1B_CONTROL_BLOCK: usize = 148;
+const FADT_X_PM_TIMER_BLOCK: usize = 172;
+
+/// The Fixed ACPI Description Table, with the power management registers and the DSDT.
+#[derive(Clone, Copy, Debug)]
+pub struct Fadt {
+    pub dsdt_address: u64,
+    pub sci_interrupt: u16,
+    // Writing `acpi_enable` to this port hands power management over to us, zero if the
+    // system only does ACPI mode
+    pub smi_command_port: u32,
+    pub acpi_enable: u8,
+    pub acpi_disable: u8,
+    pub pm1a_event_block: Option<GenericAddress>,
+    pub pm1b_event_block: Option<GenericAddress>,
+    pub pm1a_control_block: Option<GenericAddress>,
+    pub pm1b_control_block: Option<GenericAddress>,
+    pub pm_timer_block: Option<GenericAddress>,
+    // CMOS register holding the century, zero if there is none
+    pub century_register: u8,
+    pub boot_architecture_flags: u16,
+    pub flags: u32,
+    pub reset_register: Option<GenericAddress>,
+    pub reset_value: u8,
+}
+
+impl Fadt {
+    // Prefers the 64 bit version of a register block, if there is one
+    fn register_block(
+        data: &[u8],
+        extended_offset: usize,
+        legacy_offset: usize,
+        length: u8,
+    ) -> Option<GenericAddress> {
+        return GenericAddress::parse(data, extended_offset)
+            .or_else(|| GenericAddress::io_port(read_u32(data, legacy_offset), length));
+    }
+
+    pub(super) fn parse(data: &[u8]) -> Option<Self> {
+        // Everything up to the flags has been there since ACPI 1.0
+        if data.len() < FADT_FLAGS + 4 {
+            return None;
+        }
+
+        let flags = read_u32(data, FADT_FLAGS);
+
+        let dsdt_address = match data.len() >= FADT_X_DSDT + 8 {
+            true if read_u64(data, FADT_X_DSDT) != 0 => read_u64(data, FADT_X_DSDT),
+            _ => read_u32(data, FADT_DSDT) as u64,
+        };
+
+        let reset_register = if flags & FADT_RESET_REGISTER_SUPPORTED != 0 {
+            GenericAddress::parse(data, FADT_RESET_REGISTER)
+        } else {
+            None
+        };
+
+        let event_length = data[FADT_PM1_EVENT_LENGTH];
+        let control_length = data[FADT_PM1_CONTROL_LENGTH];
+
+        return Some(Self {
+            dsdt_address,
+            sci_interrupt: read_u16(data, FADT_SCI_INTERRUPT),
+            smi_command_port: read_u32(data, FADT_SMI_COMMAND),
+            acpi_enable: data[FADT_ACPI_ENABLE],
+            acpi_disable: data[FADT_ACPI_DISABLE],
+            pm1a_event_block: Self::register_block(
+                data,
+                FADT_X_PM1A_EVENT_BLOCK,
+                FADT_PM1A_EVENT_BLOCK,
+                event_length,
+            ),
+            pm1b_event_block: Self::register_block(
+                data,
+                FADT_X_PM1B_EVENT_BLOCK,
+                FADT_PM1B_EVENT_BLOCK,
+                event_length,
+            ),
+            pm1a_control_block: Self::register_block(
+                data,
+                FADT_X_PM1A_CONTROL_BLOCK,
+                FADT_PM1A_CONTROL_BLOCK,
+                control_length,
+            ),
+            pm1b_control_block: Self::register_block(
+                data,
+                FADT_X_PM1B_CONTROL_BLOCK,
+                FADT_PM1B_CONTROL_BLOCK,
+                control_length,
+            ),
+            pm_timer_block: Self::register_block(
+                data,
+                FADT_X_PM_TIMER_BLOCK,
+                FADT_PM_TIMER_BLOCK,
+                data[FADT_PM_TIMER_LENGTH],
+            ),
+            century_register: data[FADT_CENTURY],
+            boot_architecture_flags: read_u16(data, FADT_BOOT_ARCHITECTURE_FLAGS),
+            flags,
+            reset_register,
+            reset_value: data.get(FADT_RESET_VALUE).copied().unwrap_or(0),
+        });
+    }
+
+    /// Whether there is a PS/2 controller. Only meaningful on ACPI 2.0 and later, older tables
+    /// always say there isn't.
+    pub fn has_8042(&self) -> bool {
+        return self.boot_architecture_flags & BOOT_ARCH_8042 != 0;
+    }
+}
+
+pub fn fadt() -> Option<Fadt> {
+    return Fadt::parse(find_table(b"FACP")?);
+}
+
+/// The High Precision Event Timer Description Table.
+#[derive(Clone, Copy, Debug)]
+pub struct Hpet {
+    pub hardware_revision: u8,
+    pub comparator_count: u8,
+    pub counter_is_64_bit: bool,
+    pub legacy_replacement_capable: bool,
+    pub pci_vendor_id: u16,
+    pub address: GenericAddress,
+    pub hpet_number: u8,
+    // In periodic mode, in main counter ticks
+    pub minimum_tick: u16,
+    pub page_protection: u8,
+}
+
+impl Hpet {
+    fn parse(data: &[u8]) -> Option<Self> {
+        if data.len() < 20 {
+            return None;
+        }
+
+        let block_id = read_u32(data, 0);
+
+        return Some(Self {
+            hardware_revision: block_id as u8,
+            // The field holds the number of the last comparator
+            comparator_count: ((block_id >> 8) & 0x1F) as u8 + 1,
+            counter_is_64_bit: block_id & (1 << 13) != 0,
+            legacy_replacement_capable: block_id & (1 << 15) != 0,
+            pci_vendor_id: (block_id >> 16) as u16,
+            address: GenericAddress::parse(data, 4)?,
+            hpet_number: data[16],
+            minimum_tick: read_u16(data, 17),
+            page_protection: data[19],
+        });
+    }
+}
+
+pub fn hpet() -> Option<Hpet> {
+    return Hpet::parse(find_table(b"HPET")?);
+}
+
+/// A range of PCI buses whose configuration space is memory mapped.
+#[derive(Clone, Copy, Debug)]
+pub struct McfgEntry {
+    pub base_address: u64,
+    pub segment_group: u16,
+    pub start_bus: u8,
+    pub end_bus: u8,
+}
+
+/// The PCI Express memory mapped configuration space table.
+#[derive(Clone, Debug)]
+pub struct Mcfg {
+    pub entries: Vec<McfgEntry>,
+}
+
+impl Mcfg {
+    fn parse(data: &[u8]) -> Option<Self> {
+        // Eight reserved bytes, then 16 bytes per entry
+        let entries = data
+            .get(8..)?
+            .chunks_exact(16)
+            .map(|entry| McfgEntry {
+                base_address: read_u64(entry, 0),
+                segment_group: read_u16(entry, 8),
+                start_bus: entry[10],
+                end_bus: entry[11],
+            })
+            .collect();
+
+        return Some(Self { entries });
+    }
+}
+
+pub fn mcfg() -> Option<Mcfg> {
+    return Mcfg::parse(find_table(b"MCFG")?);
+}
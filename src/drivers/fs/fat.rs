@@ -1,14 +1,17 @@
-use core::alloc::Layout;
-
 use alloc::{
-    alloc::alloc,
     boxed::Box,
+    format,
     string::{String, ToString},
     sync::Arc,
     vec::Vec,
 };
 
-use crate::drivers::storage::drive::{BlockDevice, GPTPartitionEntry};
+use crate::drivers::{
+    rtc::DateTime,
+    storage::drive::{BlockDevice, GPTPartitionEntry},
+};
+
+use super::vfs::{VfsDirectoryEntry, VfsError, VfsFile, VfsFileSystem, VfsNodeType, VfsStat};
 
 // The first Cluster (perhaps 0xF0FFFF0F) is the FAT ID
 // The second cluster stores the end-of-cluster-chain marker
@@ -127,7 +130,7 @@ struct LongFileName {
 }
 
 #[repr(packed)]
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug)]
 pub struct FileEntry {
     file_name: [u8; 8],
     extension: [u8; 3],
@@ -144,9 +147,74 @@ pub struct FileEntry {
     file_size: u32,
 }
 
-pub struct FATFS<'a> {
+impl FileEntry {
+    fn cluster(&self) -> usize {
+        return (((self.high_first_cluster_number as u32) << 16)
+            | self.low_first_cluster_number as u32) as usize;
+    }
+
+    fn is_directory(&self) -> bool {
+        return self.attributes & FileEntryAttributes::Directory as u8 != 0;
+    }
+
+    // FAT dates are packed as year since 1980 (7 bits), month (4 bits) and day (5 bits), times
+    // as hours (5 bits), minutes (6 bits) and seconds divided by two (5 bits)
+    fn modified_timestamp(&self) -> Option<u64> {
+        let date = self.modified_date;
+        let time = self.modified_time;
+
+        // Some tools leave the date zeroed, which isn't a valid date
+        if date == 0 {
+            return None;
+        }
+
+        let date_time = DateTime {
+            year: 1980 + (date >> 9),
+            month: ((date >> 5) & 0x0F) as u8,
+            day: (date & 0x1F) as u8,
+            hour: (time >> 11) as u8,
+            minute: ((time >> 5) & 0x3F) as u8,
+            second: ((time & 0x1F) * 2) as u8,
+        };
+
+        return Some(date_time.to_unix_timestamp());
+    }
+
+    fn stat(&self) -> VfsStat {
+        if self.is_directory() {
+            return VfsStat {
+                modified: self.modified_timestamp(),
+                ..VfsStat::directory()
+            };
+        }
+
+        return VfsStat {
+            node_type: VfsNodeType::File,
+            size: self.file_size as usize,
+            modified: self.modified_timestamp(),
+        };
+    }
+
+    // Formats the 8.3 name as "NAME.EXT"
+    fn short_name(&self) -> String {
+        let file_name = core::str::from_utf8(&self.file_name)
+            .unwrap_or("")
+            .trim_end();
+        let extension = core::str::from_utf8(&self.extension)
+            .unwrap_or("")
+            .trim_end();
+
+        if extension.is_empty() {
+            return file_name.to_string();
+        }
+
+        return format!("{}.{}", file_name, extension);
+    }
+}
+
+pub struct FATFS {
     // Block device Info
-    drive: Box<&'a dyn BlockDevice>,
+    drive: Arc<dyn BlockDevice>,
     partition: GPTPartitionEntry,
     // FAT info
     fs_info: FSInfo,
@@ -156,8 +224,8 @@ pub struct FATFS<'a> {
     fat_type: FatType,
 }
 
-impl<'a> FATFS<'a> {
-    pub fn new(drive: &'a dyn BlockDevice, partition: GPTPartitionEntry) -> Result<Self, ()> {
+impl FATFS {
+    pub fn new(drive: Arc<dyn BlockDevice>, partition: GPTPartitionEntry) -> Result<Self, ()> {
         let bpb_bytes = drive
             .read(partition.start_sector, 1)
             .expect("Failed to read FAT32 BIOS Parameter Block!");
@@ -227,7 +295,7 @@ impl<'a> FATFS<'a> {
         };
 
         return Ok(Self {
-            drive: Box::new(drive),
+            drive,
             partition,
             fs_info,
             fat: Arc::from(fat),
@@ -237,12 +305,15 @@ impl<'a> FATFS<'a> {
         });
     }
 
-    fn read_file(&self, file_entry: FileEntry) -> Arc<[u8]> {
+    fn read_file(&self, file_entry: &FileEntry) -> Arc<[u8]> {
         let mut file: Vec<u8> = Vec::with_capacity(file_entry.file_size as usize);
         let mut file_ptr_index = 0;
 
-        let mut cluster = ((file_entry.high_first_cluster_number as u32) << 16)
-            | file_entry.low_first_cluster_number as u32;
+        let mut cluster = file_entry.cluster() as u32;
+
+        if file_entry.file_size == 0 {
+            return Arc::from(file);
+        }
 
         let mut copied_bytes = 0;
         let cluster_size = self.bpb.sectors_per_cluster as usize * 512;
@@ -288,131 +359,145 @@ impl<'a> FATFS<'a> {
     }
 
     pub fn read(&self, path: &str) -> Result<Arc<[u8]>, ()> {
-        let path_componenets: Vec<&str> = path.trim_start_matches('/').split('/').collect();
+        match self.find_entry(path)? {
+            Some(file_entry) if !file_entry.is_directory() => {
+                return Ok(self.read_file(&file_entry));
+            }
+            _ => return Err(()),
+        }
+    }
+
+    // Walks `path` from the root directory, `None` is the root directory itself
+    fn find_entry(&self, path: &str) -> Result<Option<FileEntry>, ()> {
         let mut current_cluster = self.bpb.root_dir_cluster as usize;
+        let mut entry: Option<FileEntry> = None;
 
-        for path in path_componenets {
-            let dir_entry: FileEntry = self.find_entry_in_directory(current_cluster, path)?;
+        for path_component in path.split('/').filter(|component| !component.is_empty()) {
+            if let Some(parent) = entry {
+                if !parent.is_directory() {
+                    return Err(());
+                }
 
-            if dir_entry.attributes == FileEntryAttributes::Directory as u8 {
-                current_cluster = (((dir_entry.high_first_cluster_number as u32) << 16)
-                    | dir_entry.low_first_cluster_number as u32)
-                    as usize;
-            } else {
-                return Ok(self.read_file(dir_entry));
+                current_cluster = parent.cluster();
             }
+
+            entry = Some(self.find_entry_in_directory(current_cluster, path_component)?);
         }
 
-        return Err(());
+        return Ok(entry);
     }
 
     fn find_entry_in_directory(&self, cluster: usize, name: &str) -> Result<FileEntry, ()> {
-        let mut i: usize = 0;
+        for (entry_name, file_entry) in self.read_directory(cluster)? {
+            if entry_name.eq_ignore_ascii_case(name) {
+                return Ok(file_entry);
+            }
+        }
+
+        return Err(());
+    }
+
+    fn read_directory(&self, mut cluster: usize) -> Result<Vec<(String, FileEntry)>, ()> {
+        let mut entries: Vec<(String, FileEntry)> = Vec::new();
         // Long file name is stored outsize because long filename and the real entry on separate entries
         let mut long_filename: Vec<LongFileName> = Vec::new();
-        let mut long_filename_string: Option<String> = None;
-
-        let data_sector = self.drive.read(
-            self.partition.start_sector + self.cluster_to_sector(cluster) as u64,
-            self.bpb.sectors_per_cluster as usize,
-        )?;
 
         loop {
-            let bytes: [u8; core::mem::size_of::<FileEntry>()] =
-                data_sector[(i * 32)..((i + 1) * 32)].try_into().unwrap();
-            let first_byte = bytes[0];
-
-            let file_entry: FileEntry;
-
-            i += 1;
+            let data_sector = self.drive.read(
+                self.partition.start_sector + self.cluster_to_sector(cluster) as u64,
+                self.bpb.sectors_per_cluster as usize,
+            )?;
+
+            for i in 0..(data_sector.len() / 32) {
+                let bytes: [u8; core::mem::size_of::<FileEntry>()] =
+                    data_sector[(i * 32)..((i + 1) * 32)].try_into().unwrap();
+                let first_byte = bytes[0];
+
+                // Step 1
+                if first_byte == 0x00 {
+                    return Ok(entries); // End of directory listing
+                }
 
-            // Step 1
-            if first_byte == 0x00 {
-                break; // End of directory listing
-            }
+                // Step 2
+                if first_byte == 0xE5 {
+                    long_filename.clear();
+                    continue; // Directory is unused, ignore it
+                }
 
-            // Step 2
-            if first_byte == 0xE5 {
-                continue; // Directory is unused, ignore it
-            } else if bytes[11] == FileEntryAttributes::LongFileName as u8 {
-                // Entry is LFN (step 3)
-                // read long filename somehow (step 4)
-                let long_filename_part: LongFileName;
+                if bytes[11] == FileEntryAttributes::LongFileName as u8 {
+                    // Entry is LFN (step 3)
+                    // read long filename somehow (step 4)
+                    let long_filename_part: LongFileName;
 
-                unsafe {
-                    long_filename_part = core::mem::transmute(bytes);
+                    unsafe {
+                        long_filename_part = core::mem::transmute(bytes);
+                    }
+                    long_filename.push(long_filename_part);
+                    continue;
                 }
-                long_filename.push(long_filename_part);
-                continue;
-            } else {
+
                 // step 5
-                unsafe {
-                    file_entry = core::mem::transmute(bytes);
+                let file_entry: FileEntry = unsafe { core::mem::transmute(bytes) };
+
+                if file_entry.attributes & FileEntryAttributes::VolumeId as u8 != 0 {
+                    long_filename.clear();
+                    continue;
                 }
 
                 // step 6
-                if !long_filename.is_empty() {
+                let name = if !long_filename.is_empty() {
                     // Make fileEntry with LFN (step 7)
-                    let mut string: Vec<u16> = Vec::with_capacity(long_filename.len() * 13);
+                    let name = Self::assemble_long_filename(&long_filename);
+                    long_filename.clear();
+                    name
+                } else {
+                    file_entry.short_name()
+                };
 
-                    for i in 0..long_filename.len() {
-                        let i = (long_filename.len() - 1) - i;
-                        let long_filename = &long_filename[i];
+                if name == "." || name == ".." {
+                    continue;
+                }
 
-                        let mut character_bytes = Vec::new();
-                        let characters = long_filename.first_characters;
+                entries.push((name, file_entry));
+            }
 
-                        character_bytes.extend_from_slice(&characters);
-                        let characters = long_filename.second_characters;
+            cluster = self.get_next_cluster(cluster) as usize;
 
-                        character_bytes.extend_from_slice(&characters);
-                        let characters = long_filename.final_characters;
+            if cluster >= EOC as usize {
+                break;
+            }
+        }
 
-                        character_bytes.extend_from_slice(&characters);
+        return Ok(entries);
+    }
 
-                        // remove 0x0000 characters and 0xFFFF characters
-                        character_bytes.retain(|&x| x != 0xFFFF && x != 0x0000);
+    fn assemble_long_filename(long_filename: &[LongFileName]) -> String {
+        let mut string: Vec<u16> = Vec::with_capacity(long_filename.len() * 13);
 
-                        for &le_character in character_bytes.iter() {
-                            // Convert little-endian u16 to native-endian u16
-                            let native_endian_value = u16::from_le(le_character);
-                            string.push(native_endian_value);
-                        }
-                    }
-                    long_filename_string = Some(String::from_utf16(&string).unwrap());
-                    long_filename.clear();
-                }
-            }
+        // LFN entries are stored in reverse order
+        for long_filename in long_filename.iter().rev() {
+            let mut character_bytes = Vec::new();
+            let characters = long_filename.first_characters;
 
-            if name.replacen(".", "", 1).len() <= 11 {
-                let search_parts: Vec<&str> = name.split(".").collect();
+            character_bytes.extend_from_slice(&characters);
+            let characters = long_filename.second_characters;
 
-                let filename = core::str::from_utf8(&file_entry.file_name).unwrap();
-                let extension = core::str::from_utf8(&file_entry.extension).unwrap();
+            character_bytes.extend_from_slice(&characters);
+            let characters = long_filename.final_characters;
 
-                if search_parts.len() == 1
-                    && !filename.contains(&search_parts[0].to_ascii_uppercase())
-                {
-                    continue;
-                } else if search_parts.len() > 1
-                    && (!filename.contains(&search_parts[0].to_ascii_uppercase())
-                        || !extension.contains(&search_parts[1].to_ascii_uppercase()))
-                {
-                    continue;
-                }
+            character_bytes.extend_from_slice(&characters);
 
-                return Ok(file_entry);
-            } else {
-                // Long file name
-                if long_filename_string != Some(name.to_string()) {
-                    continue;
-                }
+            // remove 0x0000 characters and 0xFFFF characters
+            character_bytes.retain(|&x| x != 0xFFFF && x != 0x0000);
 
-                return Ok(file_entry);
+            for &le_character in character_bytes.iter() {
+                // Convert little-endian u16 to native-endian u16
+                let native_endian_value = u16::from_le(le_character);
+                string.push(native_endian_value);
             }
         }
 
-        return Err(());
+        return String::from_utf16_lossy(&string);
     }
 
     fn cluster_to_sector(&self, cluster: usize) -> usize {
@@ -433,3 +518,63 @@ impl<'a> FATFS<'a> {
         return self.fat[cluster] & 0x0FFFFFFF;
     }
 }
+
+struct FatFile<'a> {
+    fs: &'a FATFS,
+    file_entry: FileEntry,
+}
+
+impl<'a> VfsFile for FatFile<'a> {
+    fn read(&self) -> Result<Arc<[u8]>, VfsError> {
+        return Ok(self.fs.read_file(&self.file_entry));
+    }
+
+    fn stat(&self) -> VfsStat {
+        return self.file_entry.stat();
+    }
+}
+
+impl VfsFileSystem for FATFS {
+    fn open<'a>(&'a self, path: &str) -> Result<Box<dyn VfsFile + 'a>, VfsError> {
+        let file_entry = self
+            .find_entry(path)
+            .map_err(|_| VfsError::NotFound)?
+            .ok_or(VfsError::IsADirectory)?;
+
+        if file_entry.is_directory() {
+            return Err(VfsError::IsADirectory);
+        }
+
+        return Ok(Box::new(FatFile {
+            fs: self,
+            file_entry,
+        }));
+    }
+
+    fn list_directory(&self, path: &str) -> Result<Vec<VfsDirectoryEntry>, VfsError> {
+        let cluster = match self.find_entry(path).map_err(|_| VfsError::NotFound)? {
+            Some(file_entry) if !file_entry.is_directory() => return Err(VfsError::NotADirectory),
+            Some(file_entry) => file_entry.cluster(),
+            None => self.bpb.root_dir_cluster as usize,
+        };
+
+        let entries = self
+            .read_directory(cluster)
+            .map_err(|_| VfsError::IoError)?;
+
+        return Ok(entries
+            .iter()
+            .map(|(name, file_entry)| VfsDirectoryEntry {
+                name: name.clone(),
+                stat: file_entry.stat(),
+            })
+            .collect());
+    }
+
+    fn stat(&self, path: &str) -> Result<VfsStat, VfsError> {
+        return match self.find_entry(path).map_err(|_| VfsError::NotFound)? {
+            Some(file_entry) => Ok(file_entry.stat()),
+            None => Ok(VfsStat::directory()),
+        };
+    }
+}
@@ -0,0 +1,318 @@
+// Read-only file system backed by the initramfs Limine module.
+// The archive is in the cpio "newc" format, as written by scripts/initramfs.py
+
+use alloc::{
+    boxed::Box,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use limine::ModuleRequest;
+
+use crate::{libs::gzip, log_error, log_info, log_ok};
+
+use super::vfs::{self, VfsDirectoryEntry, VfsError, VfsFile, VfsFileSystem, VfsNodeType, VfsStat};
+
+pub static MODULE_REQUEST: ModuleRequest = ModuleRequest::new(0);
+
+const INITRAMFS_MODULE_NAME: &str = "initramfs.gz";
+
+const GZIP_MAGIC: [u8; 2] = [0x1F, 0x8B];
+
+const CPIO_NEWC_MAGIC: &[u8; 6] = b"070701";
+const CPIO_HEADER_SIZE: usize = 110;
+const CPIO_TRAILER: &str = "TRAILER!!!";
+
+// File type bits of the cpio mode field
+const CPIO_MODE_TYPE_MASK: usize = 0o170000;
+const CPIO_MODE_DIRECTORY: usize = 0o040000;
+const CPIO_MODE_REGULAR_FILE: usize = 0o100000;
+
+#[derive(Debug)]
+pub enum CpioError {
+    InvalidMagic,
+    InvalidHeader,
+    Truncated,
+}
+
+#[derive(Debug)]
+struct CpioHeader {
+    mode: usize,
+    file_size: usize,
+    name_size: usize,
+}
+
+impl CpioHeader {
+    fn from_bytes(bytes: &[u8]) -> Result<Self, CpioError> {
+        if bytes.len() < CPIO_HEADER_SIZE {
+            return Err(CpioError::Truncated);
+        }
+
+        if &bytes[0..6] != CPIO_NEWC_MAGIC {
+            return Err(CpioError::InvalidMagic);
+        }
+
+        // Every field after the magic is 8 ASCII hex digits:
+        // ino, mode, uid, gid, nlink, mtime, filesize, devmajor, devminor,
+        // rdevmajor, rdevminor, namesize, check
+        let field = |index: usize| -> Result<usize, CpioError> {
+            let start = 6 + index * 8;
+            let digits = core::str::from_utf8(&bytes[start..start + 8])
+                .map_err(|_| CpioError::InvalidHeader)?;
+
+            return usize::from_str_radix(digits, 16).map_err(|_| CpioError::InvalidHeader);
+        };
+
+        return Ok(Self {
+            mode: field(1)?,
+            file_size: field(6)?,
+            name_size: field(11)?,
+        });
+    }
+}
+
+#[inline]
+const fn align_up_4(value: usize) -> usize {
+    return (value + 3) & !3;
+}
+
+struct InitramfsNode {
+    // Absolute path inside of the initramfs, always starts with a '/'
+    path: String,
+    node_type: VfsNodeType,
+    data: Arc<[u8]>,
+}
+
+impl InitramfsNode {
+    fn stat(&self) -> VfsStat {
+        return VfsStat {
+            node_type: self.node_type,
+            size: self.data.len(),
+            modified: None,
+        };
+    }
+}
+
+pub struct Initramfs {
+    nodes: Vec<InitramfsNode>,
+}
+
+impl Initramfs {
+    pub fn from_cpio(archive: &[u8]) -> Result<Self, CpioError> {
+        let mut nodes = Vec::new();
+        let mut offset = 0;
+
+        loop {
+            let header =
+                CpioHeader::from_bytes(archive.get(offset..).ok_or(CpioError::Truncated)?)?;
+
+            let name_start = offset + CPIO_HEADER_SIZE;
+            let data_start = align_up_4(name_start + header.name_size);
+            let data_end = data_start + header.file_size;
+
+            if data_end > archive.len() || header.name_size == 0 {
+                return Err(CpioError::Truncated);
+            }
+
+            // name_size includes the NUL terminator
+            let name =
+                core::str::from_utf8(&archive[name_start..name_start + header.name_size - 1])
+                    .map_err(|_| CpioError::InvalidHeader)?;
+
+            if name == CPIO_TRAILER {
+                break;
+            }
+
+            let node_type = match header.mode & CPIO_MODE_TYPE_MASK {
+                CPIO_MODE_DIRECTORY => Some(VfsNodeType::Directory),
+                CPIO_MODE_REGULAR_FILE => Some(VfsNodeType::File),
+                // Symlinks, device nodes and friends are not supported
+                _ => None,
+            };
+
+            let path = vfs::normalize_path(name);
+
+            if let Some(node_type) = node_type {
+                if path != "/" {
+                    nodes.push(InitramfsNode {
+                        path,
+                        node_type,
+                        data: Arc::from(&archive[data_start..data_end]),
+                    });
+                }
+            }
+
+            offset = align_up_4(data_end);
+        }
+
+        return Ok(Self { nodes });
+    }
+
+    fn find_node(&self, path: &str) -> Option<&InitramfsNode> {
+        return self.nodes.iter().find(|node| node.path == path);
+    }
+
+    // Returns the part of `node_path` after the directory `path`, if it is inside of it
+    fn relative_to<'a>(node_path: &'a str, path: &str) -> Option<&'a str> {
+        if path == "/" {
+            return Some(node_path.trim_start_matches('/'));
+        }
+
+        return node_path.strip_prefix(path)?.strip_prefix('/');
+    }
+}
+
+struct InitramfsFile {
+    data: Arc<[u8]>,
+}
+
+impl VfsFile for InitramfsFile {
+    fn read(&self) -> Result<Arc<[u8]>, VfsError> {
+        return Ok(self.data.clone());
+    }
+
+    fn stat(&self) -> VfsStat {
+        return VfsStat {
+            node_type: VfsNodeType::File,
+            size: self.data.len(),
+            modified: None,
+        };
+    }
+}
+
+impl VfsFileSystem for Initramfs {
+    fn open<'a>(&'a self, path: &str) -> Result<Box<dyn VfsFile + 'a>, VfsError> {
+        let node = self.find_node(path);
+
+        match node {
+            Some(node) if node.node_type == VfsNodeType::File => {
+                return Ok(Box::new(InitramfsFile {
+                    data: node.data.clone(),
+                }));
+            }
+            Some(_) => return Err(VfsError::IsADirectory),
+            None => {
+                if self.stat(path).is_ok() {
+                    return Err(VfsError::IsADirectory);
+                }
+
+                return Err(VfsError::NotFound);
+            }
+        }
+    }
+
+    fn list_directory(&self, path: &str) -> Result<Vec<VfsDirectoryEntry>, VfsError> {
+        if self.stat(path)?.node_type != VfsNodeType::Directory {
+            return Err(VfsError::NotADirectory);
+        }
+
+        let mut entries: Vec<VfsDirectoryEntry> = Vec::new();
+
+        for node in self.nodes.iter() {
+            let relative_path = match Self::relative_to(&node.path, path) {
+                Some(relative_path) if !relative_path.is_empty() => relative_path,
+                _ => continue,
+            };
+
+            // Directories that only exist implicitly through the paths of their children
+            let (name, stat) = match relative_path.split_once('/') {
+                Some((directory, _)) => (directory, VfsStat::directory()),
+                None => (relative_path, node.stat()),
+            };
+
+            if entries.iter().any(|entry| entry.name == name) {
+                continue;
+            }
+
+            entries.push(VfsDirectoryEntry {
+                name: name.to_string(),
+                stat,
+            });
+        }
+
+        return Ok(entries);
+    }
+
+    fn stat(&self, path: &str) -> Result<VfsStat, VfsError> {
+        if path == "/" {
+            return Ok(VfsStat::directory());
+        }
+
+        if let Some(node) = self.find_node(path) {
+            return Ok(node.stat());
+        }
+
+        if self
+            .nodes
+            .iter()
+            .any(|node| Self::relative_to(&node.path, path).is_some())
+        {
+            return Ok(VfsStat::directory());
+        }
+
+        return Err(VfsError::NotFound);
+    }
+}
+
+fn find_initramfs_module() -> Option<&'static [u8]> {
+    let module_response = MODULE_REQUEST.get_response().get()?;
+
+    for module in module_response.modules() {
+        let c_path = module.path.to_str();
+        if c_path.is_none() {
+            continue;
+        }
+
+        if c_path
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .contains(INITRAMFS_MODULE_NAME)
+        {
+            let base = module.base.as_ptr()?;
+
+            return Some(unsafe { core::slice::from_raw_parts(base, module.length as usize) });
+        }
+    }
+
+    return None;
+}
+
+pub fn init() {
+    let module = find_initramfs_module();
+
+    if module.is_none() {
+        log_error!("Initramfs: {} module not found!", INITRAMFS_MODULE_NAME);
+        return;
+    }
+
+    let module = module.unwrap();
+
+    log_info!("Initramfs is located at: {:#018X?}", module.as_ptr_range());
+
+    let archive = if module.starts_with(&GZIP_MAGIC) {
+        match gzip::gunzip(module) {
+            Ok(archive) => archive,
+            Err(err) => {
+                log_error!("Initramfs: failed to decompress archive: {:?}", err);
+                return;
+            }
+        }
+    } else {
+        module.to_vec()
+    };
+
+    let initramfs = match Initramfs::from_cpio(&archive) {
+        Ok(initramfs) => initramfs,
+        Err(err) => {
+            log_error!("Initramfs: failed to parse cpio archive: {:?}", err);
+            return;
+        }
+    };
+
+    log_ok!("Initramfs: Loaded {} entries", initramfs.nodes.len());
+
+    if vfs::mount("/", Box::new(initramfs)).is_err() {
+        log_error!("Initramfs: failed to mount initramfs at /");
+    }
+}
@@ -1,2 +1,3 @@
 pub mod fat;
+pub mod initramfs;
 pub mod vfs;
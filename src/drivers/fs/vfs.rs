@@ -1,74 +1,263 @@
-// use alloc::{boxed::Box, string::String, vec::Vec};
+use alloc::{
+    boxed::Box,
+    format,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
 
-// use crate::drivers::storage::drive::BlockDevice;
+use crate::libs::mutex::Mutex;
 
-// // Define a custom error enum for file-related errors
-// enum VfsError {
-//     NotFound,
-//     FileExists,
-// }
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VfsError {
+    NotFound,
+    FileExists,
+    NotADirectory,
+    IsADirectory,
+    ReadOnly,
+    InvalidPath,
+    IoError,
+}
 
-// trait VfsDevice {
-//     fn mount(&self, mount_point: &str) -> Result<(), ()>;
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VfsNodeType {
+    File,
+    Directory,
+}
 
-//     fn open(&self, path: &str) -> Result<Box<dyn VfsFile>, VfsError>;
+#[derive(Clone, Copy, Debug)]
+pub struct VfsStat {
+    pub node_type: VfsNodeType,
+    pub size: usize,
+    // Unix timestamp of the last modification, if the file system keeps track of it
+    pub modified: Option<u64>,
+}
 
-//     fn create(&self, path: &str) -> Result<Box<dyn VfsFile>, VfsError>;
+impl VfsStat {
+    pub const fn directory() -> Self {
+        return Self {
+            node_type: VfsNodeType::Directory,
+            size: 0,
+            modified: None,
+        };
+    }
+}
 
-//     fn remove(&self, path: &str) -> Result<(), VfsError>;
+#[derive(Clone, Debug)]
+pub struct VfsDirectoryEntry {
+    pub name: String,
+    pub stat: VfsStat,
+}
 
-//     fn list_directory(&self, path: &str) -> Result<Vec<String>, VfsError>;
-// }
+pub trait VfsFile {
+    // Read the entire contents of the file
+    fn read(&self) -> Result<Arc<[u8]>, VfsError>;
 
-// trait VfsFileSystem {
-//     // Mount the file system at the specified mount point
-//     fn mount(&mut self, mount_point: &str) -> Result<(), VfsError>;
+    fn stat(&self) -> VfsStat;
+}
 
-//     // Open a file by its path and return a file handle
-//     fn open_file(&self, path: &str) -> Result<Box<dyn VfsFile>, VfsError>;
+// Every path handed to a file system is relative to its mount point, but still starts with a '/'
+pub trait VfsFileSystem {
+    // Open a file by its path and return a file handle
+    fn open<'a>(&'a self, path: &str) -> Result<Box<dyn VfsFile + 'a>, VfsError>;
 
-//     // Create a new file at the specified path
-//     fn create_file(&self, path: &str) -> Result<Box<dyn VfsFile>, VfsError>;
+    // List the contents of a directory
+    fn list_directory(&self, path: &str) -> Result<Vec<VfsDirectoryEntry>, VfsError>;
 
-//     // Remove a file or directory at the specified path
-//     fn remove(&self, path: &str) -> Result<(), VfsError>;
+    fn stat(&self, path: &str) -> Result<VfsStat, VfsError>;
+}
 
-//     // List the contents of a directory
-//     fn list_directory(&self, path: &str) -> Result<Vec<String>, VfsError>;
-// }
+struct Mount {
+    mount_point: String,
+    // The directory of the file system that shows up at the mount point
+    root: String,
+    fs: Box<dyn VfsFileSystem>,
+}
 
-// struct VfsBlockDevice {
-//     inner: Box<dyn BlockDevice>,
-// }
+static MOUNTS: Mutex<Vec<Mount>> = Mutex::new(Vec::new());
 
-// impl VfsBlockDevice {
-//     pub fn new(inner: Box<dyn BlockDevice>) -> Self {
-//         Self { inner }
-//     }
-// }
+/// Collapses duplicate slashes, `.` and `..` so that every path in the mount table
+/// (and every path we resolve against it) has exactly one spelling.
+pub fn normalize_path(path: &str) -> String {
+    let mut components: Vec<&str> = Vec::new();
 
-// impl BlockDevice for VfsBlockDevice {
-//     fn read(&self, sector: u64, sector_count: usize) -> Result<alloc::sync::Arc<[u8]>, ()> {
-//         return self.inner.read(sector, sector_count);
-//     }
+    for component in path.split('/') {
+        match component {
+            "" | "." => continue,
+            ".." => {
+                components.pop();
+            }
+            _ => components.push(component),
+        }
+    }
 
-//     fn sector_count(&self) -> u64 {
-//         return self.inner.sector_count();
-//     }
+    return format!("/{}", components.join("/"));
+}
 
-//     fn write(&self, sector: u64, data: &[u8]) -> Result<(), ()> {
-//         return self.inner.write(sector, data);
-//     }
-// }
+pub fn mount(mount_point: &str, fs: Box<dyn VfsFileSystem>) -> Result<(), VfsError> {
+    return mount_subtree(mount_point, fs, "/");
+}
 
-// // Implement the VfsDevice trait for VfsBlockDevice with the required VFS-specific methods
-// impl VfsDevice for VfsBlockDevice {
-//     fn mount(&self, mount_point: &str) -> Result<(), VfsError> {
-//         // Implement the mounting logic here
-//         // You can use the inner BlockDevice for low-level access
-//         // and provide VFS-specific functionality
-//         // ...
-//     }
+/// Mounts only the `root` directory of `fs` at `mount_point`, the rest of it stays out of sight.
+pub fn mount_subtree(
+    mount_point: &str,
+    fs: Box<dyn VfsFileSystem>,
+    root: &str,
+) -> Result<(), VfsError> {
+    if !mount_point.starts_with('/') || !root.starts_with('/') {
+        return Err(VfsError::InvalidPath);
+    }
 
-//     // ... implement other VfsDevice methods ...
-// }
+    let mount_point = normalize_path(mount_point);
+    let root = normalize_path(root);
+
+    if root != "/" && fs.stat(&root)?.node_type != VfsNodeType::Directory {
+        return Err(VfsError::NotADirectory);
+    }
+
+    let mut mounts_lock = MOUNTS.lock();
+    let mounts = mounts_lock.write();
+
+    if mounts.iter().any(|mount| mount.mount_point == mount_point) {
+        return Err(VfsError::FileExists);
+    }
+
+    if root == "/" {
+        crate::log_ok!("VFS: Mounted file system at {}", mount_point);
+    } else {
+        crate::log_ok!("VFS: Mounted {} of a file system at {}", root, mount_point);
+    }
+
+    mounts.push(Mount {
+        mount_point,
+        root,
+        fs,
+    });
+
+    return Ok(());
+}
+
+// Returns the remainder of `path` if it lives under `mount_point`
+fn strip_mount_point<'a>(path: &'a str, mount_point: &str) -> Option<&'a str> {
+    if mount_point == "/" {
+        return Some(path);
+    }
+
+    let rest = path.strip_prefix(mount_point)?;
+
+    if rest.is_empty() {
+        return Some("/");
+    }
+
+    if rest.starts_with('/') {
+        return Some(rest);
+    }
+
+    return None;
+}
+
+// Find the file system with the longest mount point that contains `path`
+fn resolve(path: &str) -> Option<(&'static dyn VfsFileSystem, String)> {
+    let mounts = MOUNTS.lock().read();
+
+    let mut best: Option<(&Mount, &str)> = None;
+
+    for mount in mounts.iter() {
+        if let Some(rest) = strip_mount_point(path, &mount.mount_point) {
+            if best.is_none() || mount.mount_point.len() > best.unwrap().0.mount_point.len() {
+                best = Some((mount, rest));
+            }
+        }
+    }
+
+    return best.map(|(mount, rest)| {
+        let path = if mount.root == "/" {
+            rest.to_string()
+        } else {
+            normalize_path(&format!("{}{}", mount.root, rest))
+        };
+
+        (&*mount.fs, path)
+    });
+}
+
+// Mount points that are direct children of `path`, these show up as directories even if
+// the parent file system has no such directory.
+fn child_mount_points(path: &str) -> Vec<String> {
+    let mut children = Vec::new();
+
+    for mount in MOUNTS.lock().read().iter() {
+        if mount.mount_point == path {
+            continue;
+        }
+
+        if let Some(rest) = strip_mount_point(&mount.mount_point, path) {
+            let rest = rest.trim_start_matches('/');
+
+            if !rest.is_empty() && !rest.contains('/') {
+                children.push(rest.to_string());
+            }
+        }
+    }
+
+    return children;
+}
+
+pub fn open(path: &str) -> Result<Box<dyn VfsFile>, VfsError> {
+    let path = normalize_path(path);
+
+    let (fs, relative_path) = resolve(&path).ok_or(VfsError::NotFound)?;
+
+    return fs.open(&relative_path);
+}
+
+pub fn read(path: &str) -> Result<Arc<[u8]>, VfsError> {
+    return open(path)?.read();
+}
+
+pub fn stat(path: &str) -> Result<VfsStat, VfsError> {
+    let path = normalize_path(path);
+
+    if MOUNTS
+        .lock()
+        .read()
+        .iter()
+        .any(|mount| mount.mount_point == path)
+        || !child_mount_points(&path).is_empty()
+    {
+        return Ok(VfsStat::directory());
+    }
+
+    let (fs, relative_path) = resolve(&path).ok_or(VfsError::NotFound)?;
+
+    return fs.stat(&relative_path);
+}
+
+pub fn list_directory(path: &str) -> Result<Vec<VfsDirectoryEntry>, VfsError> {
+    let path = normalize_path(path);
+
+    let mount_points = child_mount_points(&path);
+
+    let mut entries = match resolve(&path) {
+        Some((fs, relative_path)) => match fs.list_directory(&relative_path) {
+            Ok(entries) => entries,
+            Err(VfsError::NotFound) if !mount_points.is_empty() => Vec::new(),
+            Err(err) => return Err(err),
+        },
+        None if !mount_points.is_empty() => Vec::new(),
+        None => return Err(VfsError::NotFound),
+    };
+
+    for mount_point in mount_points {
+        if entries.iter().any(|entry| entry.name == mount_point) {
+            continue;
+        }
+
+        entries.push(VfsDirectoryEntry {
+            name: mount_point,
+            stat: VfsStat::directory(),
+        });
+    }
+
+    return Ok(entries);
+}
@@ -1,15 +1,24 @@
-use crate::arch::interrupts::{idt_set_gate, InterruptIndex};
+use crate::arch::interrupts::{set_irq_handler, InterruptIndex};
 // Shitty keyboard driver
 #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
 use crate::arch::{
     interrupts,
     io::{inb, outb},
 };
-use core::sync::atomic::{AtomicBool, Ordering};
+use core::sync::atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering};
 
 const KBD_DATA_PORT: u16 = 0x60;
 const KBD_COMMAND_AND_STATUS_PORT: u16 = 0x64;
 
+const SCANCODE_BUFFER_SIZE: usize = 64;
+
+// How long the controller gets to answer before we give up on it
+const CONTROLLER_TIMEOUT_MS: u64 = 100;
+
+// Status register bits
+const OUTPUT_BUFFER_FULL: u8 = 1 << 0;
+const INPUT_BUFFER_FULL: u8 = 1 << 1;
+
 pub struct Key<'a> {
     pub pressed: bool,
     pub name: &'a str,
@@ -18,19 +27,46 @@ pub struct Key<'a> {
 
 static EXTENDED_KEY: AtomicBool = AtomicBool::new(false);
 
+// Scancodes waiting for the shell, only the interrupt handler writes and only the shell reads
+const EMPTY_SCANCODE: AtomicU8 = AtomicU8::new(0);
+static SCANCODE_BUFFER: [AtomicU8; SCANCODE_BUFFER_SIZE] = [EMPTY_SCANCODE; SCANCODE_BUFFER_SIZE];
+static SCANCODE_READ_INDEX: AtomicUsize = AtomicUsize::new(0);
+static SCANCODE_WRITE_INDEX: AtomicUsize = AtomicUsize::new(0);
+
 #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
 pub extern "x86-interrupt" fn keyboard_interrupt_handler() {
-    interrupts::PICS
-        .lock()
-        .write()
-        .notify_end_of_interrupt(interrupts::InterruptIndex::Keyboard.as_u8());
+    interrupts::end_of_interrupt(InterruptIndex::Keyboard);
 
     let scancode = inb(KBD_DATA_PORT);
 
-    let key = parse_key(scancode);
+    let write_index = SCANCODE_WRITE_INDEX.load(Ordering::SeqCst);
+    let next_index = (write_index + 1) % SCANCODE_BUFFER_SIZE;
+
+    // Drop the key if the shell has fallen that far behind
+    if next_index == SCANCODE_READ_INDEX.load(Ordering::SeqCst) {
+        return;
+    }
+
+    SCANCODE_BUFFER[write_index].store(scancode, Ordering::SeqCst);
+    SCANCODE_WRITE_INDEX.store(next_index, Ordering::SeqCst);
+}
+
+/// Takes the next buffered key event, if there is one.
+pub fn read_key() -> Option<Key<'static>> {
+    loop {
+        let read_index = SCANCODE_READ_INDEX.load(Ordering::SeqCst);
+
+        if read_index == SCANCODE_WRITE_INDEX.load(Ordering::SeqCst) {
+            return None;
+        }
 
-    if let Some(key) = key {
-        crate::usr::shell::handle_key(key)
+        let scancode = SCANCODE_BUFFER[read_index].load(Ordering::SeqCst);
+        SCANCODE_READ_INDEX.store((read_index + 1) % SCANCODE_BUFFER_SIZE, Ordering::SeqCst);
+
+        // Prefixes and unknown scancodes don't make a key on their own
+        if let Some(key) = parse_key(scancode) {
+            return Some(key);
+        }
     }
 }
 
@@ -41,15 +77,42 @@ pub enum KBDError {
     TestFailed,
 }
 
+// Waits until the status register has `mask` set, or clear if `set` is false
+fn wait_for_status(mask: u8, set: bool) -> Result<(), KBDError> {
+    let mut deadline = crate::sys::time::Deadline::after_ms(CONTROLLER_TIMEOUT_MS);
+
+    while (inb(KBD_COMMAND_AND_STATUS_PORT) & mask != 0) != set {
+        if deadline.has_passed() {
+            return Err(KBDError::TimeoutError);
+        }
+
+        crate::arch::pause();
+    }
+
+    return Ok(());
+}
+
+fn send_command(command: u8) -> Result<(), KBDError> {
+    wait_for_status(INPUT_BUFFER_FULL, false)?;
+    outb(KBD_COMMAND_AND_STATUS_PORT, command);
+
+    return Ok(());
+}
+
+/// Pulses the CPU reset line through the controller's output port, the oldest way to reboot a PC.
+pub fn pulse_reset_line() -> Result<(), KBDError> {
+    return send_command(0xFE);
+}
+
 pub fn init() -> Result<(), KBDError> {
     // flush output buffer
-    while (inb(KBD_COMMAND_AND_STATUS_PORT) & 1) != 0 {
+    while (inb(KBD_COMMAND_AND_STATUS_PORT) & OUTPUT_BUFFER_FULL) != 0 {
         inb(KBD_DATA_PORT);
     }
 
     // Disable PS/2 Devices (second then first)
-    outb(KBD_COMMAND_AND_STATUS_PORT, 0xA7);
-    outb(KBD_COMMAND_AND_STATUS_PORT, 0xAD);
+    send_command(0xA7)?;
+    send_command(0xAD)?;
 
     outb(KBD_COMMAND_AND_STATUS_PORT, 0xFF);
     let status = inb(KBD_COMMAND_AND_STATUS_PORT);
@@ -63,21 +126,22 @@ pub fn init() -> Result<(), KBDError> {
     }
 
     // Test the controller
-    outb(KBD_COMMAND_AND_STATUS_PORT, 0xAA);
+    send_command(0xAA)?;
+    wait_for_status(OUTPUT_BUFFER_FULL, true)?;
     let result = inb(KBD_DATA_PORT);
 
     if result != 0x55 {
         return Err(KBDError::TestFailed);
     }
 
-    idt_set_gate(
-        InterruptIndex::Keyboard.as_u8(),
+    set_irq_handler(
+        InterruptIndex::Keyboard,
         crate::drivers::keyboard::keyboard_interrupt_handler as u64,
     );
 
     // Enable PS/2 Devices (second then first)
-    outb(KBD_COMMAND_AND_STATUS_PORT, 0xA8);
-    outb(KBD_COMMAND_AND_STATUS_PORT, 0xAE);
+    send_command(0xA8)?;
+    send_command(0xAE)?;
 
     // Reset Devices
     inb(KBD_COMMAND_AND_STATUS_PORT);
@@ -92,11 +156,14 @@ pub fn consume_scancode() {
 
 #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
 pub fn set_leds(led_byte: u8) {
-    // Command bytes
-    outb(KBD_DATA_PORT, 0xED);
-    while !(inb(KBD_DATA_PORT) == 0xfa) {}
-    // Data byte
-    outb(KBD_DATA_PORT, led_byte);
+    // The acknowledgement is polled for, so it can't be picked up by the interrupt handler
+    crate::arch::without_interrupts(|| {
+        // Command bytes
+        outb(KBD_DATA_PORT, 0xED);
+        while !(inb(KBD_DATA_PORT) == 0xfa) {}
+        // Data byte
+        outb(KBD_DATA_PORT, led_byte);
+    });
 }
 
 fn parse_key(mut scancode: u8) -> Option<Key<'static>> {
@@ -2,6 +2,7 @@ pub mod acpi;
 pub mod fs;
 pub mod keyboard;
 pub mod pci;
+pub mod rtc;
 pub mod serial;
 pub mod storage;
 pub mod video;
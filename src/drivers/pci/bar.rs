@@ -0,0 +1,160 @@
+// Base address registers, where a function tells us which I/O ports and memory it decodes. Their
+// size is found by writing all ones and seeing which address bits stick.
+
+use core::fmt;
+
+use crate::arch::paging::{self, PagingError};
+
+use super::{read_pci_config, write_pci_config, COMMAND_IO_SPACE, COMMAND_MEMORY_SPACE};
+
+const BAR_IO_SPACE: u32 = 1 << 0;
+const BAR_MEMORY_TYPE_MASK: u32 = 0b110;
+const BAR_MEMORY_TYPE_64_BIT: u32 = 0b100;
+const BAR_PREFETCHABLE: u32 = 1 << 3;
+
+const BAR_IO_ADDRESS_MASK: u32 = !0b11;
+const BAR_MEMORY_ADDRESS_MASK: u32 = !0b1111;
+
+#[derive(Clone, Copy, Debug)]
+pub enum Bar {
+    Io {
+        port: u16,
+        size: u16,
+    },
+    Memory {
+        address: u64,
+        size: u64,
+        prefetchable: bool,
+        is_64_bit: bool,
+    },
+}
+
+impl Bar {
+    /// Maps a memory BAR into the kernel's half and returns where, I/O BARs have nothing to map.
+    pub fn map(&self) -> Result<u64, PagingError> {
+        return match *self {
+            Bar::Memory { address, size, .. } => paging::map_mmio(address, size),
+            Bar::Io { .. } => Err(PagingError::InvalidAddress),
+        };
+    }
+
+    pub fn io_port(&self) -> Option<u16> {
+        return match *self {
+            Bar::Io { port, .. } => Some(port),
+            Bar::Memory { .. } => None,
+        };
+    }
+}
+
+impl fmt::Display for Bar {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match *self {
+            Bar::Io { port, size } => write!(f, "I/O ports at {:#X} [size={}]", port, size),
+            Bar::Memory {
+                address,
+                size,
+                prefetchable,
+                is_64_bit,
+            } => write!(
+                f,
+                "Memory at {:#X} ({}-bit, {}) [size={:#X}]",
+                address,
+                if is_64_bit { 64 } else { 32 },
+                if prefetchable {
+                    "prefetchable"
+                } else {
+                    "non-prefetchable"
+                },
+                size
+            ),
+        };
+    }
+}
+
+// Writes all ones to the BAR at `offset` and returns what it reads back, the BAR itself is left
+// as it was
+fn probe_register(bus: u8, device: u8, func: u8, offset: u16) -> u32 {
+    let value = read_pci_config(bus, device, func, offset);
+
+    write_pci_config(bus, device, func, offset, 0xFFFF_FFFF);
+    let mask = read_pci_config(bus, device, func, offset);
+    write_pci_config(bus, device, func, offset, value);
+
+    return mask;
+}
+
+/// Decodes and sizes the BARs of a function. A 64-bit BAR takes up two slots, the second of which
+/// is left as None like unimplemented ones.
+pub(super) fn read_bars(bus: u8, device: u8, func: u8, header_type: u8) -> [Option<Bar>; 6] {
+    let mut bars = [None; 6];
+
+    // Bridges only have two BARs before their bus numbers, CardBus bridges have none we care about
+    let bar_count = match header_type & 0x7F {
+        0x00 => 6,
+        0x01 => 2,
+        _ => return bars,
+    };
+
+    // The device mustn't decode the all ones address while it's being sized
+    let command = read_pci_config(bus, device, func, 0x04) & 0xFFFF;
+    write_pci_config(
+        bus,
+        device,
+        func,
+        0x04,
+        command & !(COMMAND_IO_SPACE | COMMAND_MEMORY_SPACE) as u32,
+    );
+
+    let mut index = 0;
+
+    while index < bar_count {
+        let offset = 0x10 + index as u16 * 4;
+        let value = read_pci_config(bus, device, func, offset);
+        let mask = probe_register(bus, device, func, offset);
+
+        if value & BAR_IO_SPACE != 0 {
+            // Some devices leave the upper 16 bits of I/O BARs at zero
+            let size_mask = (mask & BAR_IO_ADDRESS_MASK) as u16;
+
+            if size_mask != 0 {
+                bars[index] = Some(Bar::Io {
+                    port: (value & BAR_IO_ADDRESS_MASK) as u16,
+                    size: (!size_mask).wrapping_add(1),
+                });
+            }
+
+            index += 1;
+            continue;
+        }
+
+        let is_64_bit = value & BAR_MEMORY_TYPE_MASK == BAR_MEMORY_TYPE_64_BIT;
+
+        let mut address = (value & BAR_MEMORY_ADDRESS_MASK) as u64;
+        let mut size_mask = (mask & BAR_MEMORY_ADDRESS_MASK) as u64;
+
+        if is_64_bit && index + 1 < bar_count {
+            let high_offset = offset + 4;
+
+            address |= (read_pci_config(bus, device, func, high_offset) as u64) << 32;
+            size_mask |= (probe_register(bus, device, func, high_offset) as u64) << 32;
+        } else {
+            // Makes a 32-bit BAR's size come out right below
+            size_mask |= 0xFFFF_FFFF_0000_0000;
+        }
+
+        if size_mask != 0xFFFF_FFFF_0000_0000 && size_mask != 0 {
+            bars[index] = Some(Bar::Memory {
+                address,
+                size: (!size_mask).wrapping_add(1),
+                prefetchable: value & BAR_PREFETCHABLE != 0,
+                is_64_bit,
+            });
+        }
+
+        index += if is_64_bit { 2 } else { 1 };
+    }
+
+    write_pci_config(bus, device, func, 0x04, command);
+
+    return bars;
+}
@@ -0,0 +1,202 @@
+// Access to the configuration space of PCI functions. The legacy 0xCF8/0xCFC ports can only reach
+// the first 256 bytes of it, PCI Express ECAM maps all 4096 bytes of every function into memory,
+// which is where the extended capabilities live. ECAM is used when the MCFG table describes it.
+
+use alloc::{boxed::Box, collections::BTreeMap, vec::Vec};
+
+use crate::{
+    arch::{
+        io::{inl, outl},
+        paging,
+    },
+    drivers::acpi,
+    libs::mutex::Mutex,
+};
+
+const PCI_CONFIG_PORT: u16 = 0xCF8; // The base I/O port for PCI configuration access
+const PCI_DATA_PORT: u16 = 0xCFC; // The data port for reading/writing configuration data
+
+pub const LEGACY_CONFIG_SPACE_SIZE: u16 = 256;
+pub const EXTENDED_CONFIG_SPACE_SIZE: u16 = 4096;
+
+// Each bus gets 1 MiB of ECAM space, 4 KiB for each of its 32 devices with 8 functions
+const ECAM_BUS_SIZE: u64 = 1 << 20;
+
+/// A way of reading and writing configuration space. Offsets are rounded down to a dword, and
+/// reads past the end of what the mechanism can reach return all ones.
+pub trait ConfigAccess: Send + Sync {
+    fn read(&self, bus: u8, device: u8, func: u8, offset: u16) -> u32;
+    fn write(&self, bus: u8, device: u8, func: u8, offset: u16, value: u32);
+
+    /// How many bytes of configuration space every function has through this mechanism.
+    fn config_space_size(&self) -> u16;
+}
+
+/// Configuration mechanism #1, through the 0xCF8 and 0xCFC I/O ports.
+pub struct PortIo;
+
+impl PortIo {
+    fn address(bus: u8, device: u8, func: u8, offset: u16) -> u32 {
+        let mut address: u32 = 0;
+        address |= 1 << 31; // Enable bit
+        address |= (bus as u32) << 16; // Set Bus Number
+        address |= (device as u32) << 11; // Set Device Number
+        address |= (func as u32) << 8; // Set Function number
+        address |= (offset & 0xFC) as u32; // Set Register offset
+
+        return address;
+    }
+}
+
+impl ConfigAccess for PortIo {
+    fn read(&self, bus: u8, device: u8, func: u8, offset: u16) -> u32 {
+        if offset >= LEGACY_CONFIG_SPACE_SIZE {
+            return 0xFFFF_FFFF;
+        }
+
+        // Write the address to the PCI_CONFIG_PORT
+        outl(PCI_CONFIG_PORT, Self::address(bus, device, func, offset));
+
+        // Read the data from the PCI_DATA_PORT
+        return inl(PCI_DATA_PORT);
+    }
+
+    fn write(&self, bus: u8, device: u8, func: u8, offset: u16, value: u32) {
+        if offset >= LEGACY_CONFIG_SPACE_SIZE {
+            return;
+        }
+
+        outl(PCI_CONFIG_PORT, Self::address(bus, device, func, offset));
+        outl(PCI_DATA_PORT, value);
+    }
+
+    fn config_space_size(&self) -> u16 {
+        return LEGACY_CONFIG_SPACE_SIZE;
+    }
+}
+
+/// The PCI Express Enhanced Configuration Access Mechanism, where configuration space is memory
+/// mapped. Only segment group 0 is used, since that's the only one the rest of the PCI code knows
+/// about.
+pub struct Ecam {
+    regions: Vec<acpi::McfgEntry>,
+    // Buses are mapped the first time they're touched, mapping all 256 MiB up front would waste a
+    // lot of page tables on buses that don't exist
+    mapped_buses: Mutex<BTreeMap<u8, u64>>,
+}
+
+impl Ecam {
+    pub fn new(mcfg: &acpi::Mcfg) -> Option<Self> {
+        let regions: Vec<acpi::McfgEntry> = mcfg
+            .entries
+            .iter()
+            .filter(|entry| entry.segment_group == 0 && entry.start_bus <= entry.end_bus)
+            .copied()
+            .collect();
+
+        if regions.is_empty() {
+            return None;
+        }
+
+        return Some(Self {
+            regions,
+            mapped_buses: Mutex::new(BTreeMap::new()),
+        });
+    }
+
+    // The virtual address of the start of the bus' ECAM space
+    fn bus_address(&self, bus: u8) -> Option<u64> {
+        if let Some(&address) = self.mapped_buses.lock().read().get(&bus) {
+            return Some(address);
+        }
+
+        let region = self
+            .regions
+            .iter()
+            .find(|region| (region.start_bus..=region.end_bus).contains(&bus))?;
+
+        let physical_address =
+            region.base_address + (bus - region.start_bus) as u64 * ECAM_BUS_SIZE;
+        let address = paging::map_mmio(physical_address, ECAM_BUS_SIZE).ok()?;
+
+        self.mapped_buses.lock().write().insert(bus, address);
+
+        return Some(address);
+    }
+
+    fn register(&self, bus: u8, device: u8, func: u8, offset: u16) -> Option<*mut u32> {
+        if device >= 32 || func >= 8 || offset >= EXTENDED_CONFIG_SPACE_SIZE {
+            return None;
+        }
+
+        let address = self.bus_address(bus)?
+            + ((device as u64) << 15 | (func as u64) << 12 | (offset & 0xFFC) as u64);
+
+        return Some(address as *mut u32);
+    }
+}
+
+impl ConfigAccess for Ecam {
+    fn read(&self, bus: u8, device: u8, func: u8, offset: u16) -> u32 {
+        return match self.register(bus, device, func, offset) {
+            Some(register) => unsafe { core::ptr::read_volatile(register) },
+            None => 0xFFFF_FFFF,
+        };
+    }
+
+    fn write(&self, bus: u8, device: u8, func: u8, offset: u16, value: u32) {
+        if let Some(register) = self.register(bus, device, func, offset) {
+            unsafe { core::ptr::write_volatile(register, value) };
+        }
+    }
+
+    fn config_space_size(&self) -> u16 {
+        return EXTENDED_CONFIG_SPACE_SIZE;
+    }
+}
+
+// None until `init` finds something better than the I/O ports
+static CONFIG_ACCESS: Mutex<Option<Box<dyn ConfigAccess>>> = Mutex::new(None);
+
+/// The mechanism configuration space is currently accessed through.
+pub fn access() -> &'static dyn ConfigAccess {
+    return match CONFIG_ACCESS.lock().read() {
+        Some(access) => access.as_ref(),
+        None => &PortIo,
+    };
+}
+
+/// Switches to ECAM if the firmware describes it in the MCFG table.
+pub fn init() {
+    let ecam = match acpi::mcfg() {
+        Some(mcfg) => Ecam::new(&mcfg),
+        None => None,
+    };
+
+    let ecam = match ecam {
+        Some(ecam) => ecam,
+        None => {
+            crate::log_info!(
+                "No usable MCFG, PCI configuration space is accessed through I/O ports"
+            );
+            return;
+        }
+    };
+
+    // Make sure the mapping actually works before relying on it, bus 0 device 0 always exists
+    if ecam.read(0, 0, 0, 0) & 0xFFFF != PortIo.read(0, 0, 0, 0) & 0xFFFF {
+        crate::log_error!("ECAM doesn't agree with the I/O ports, not using it");
+        return;
+    }
+
+    for region in ecam.regions.iter() {
+        crate::log_ok!(
+            "PCI Express ECAM at {:#X} for buses {}-{}",
+            region.base_address,
+            region.start_bus,
+            region.end_bus
+        );
+    }
+
+    *CONFIG_ACCESS.lock().write() = Some(Box::new(ecam));
+}
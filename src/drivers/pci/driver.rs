@@ -0,0 +1,124 @@
+// Binding drivers to PCI devices. A driver says which devices it handles with a match table, and
+// its probe function is called for every device that matches and isn't taken yet. A driver that
+// fails to probe a device leaves it for the next one that matches.
+
+use alloc::vec::Vec;
+
+use crate::libs::mutex::Mutex;
+
+use super::{PciDevice, PCI_DEVICES};
+
+/// One entry in a driver's match table.
+#[derive(Clone, Copy, Debug)]
+pub enum PciMatch {
+    /// A specific device from a specific vendor.
+    Id { vendor_id: u16, device_id: u16 },
+    /// A kind of device, with `None` matching any programming interface.
+    Class {
+        class_code: u8,
+        subclass_code: u8,
+        prog_if: Option<u8>,
+    },
+}
+
+impl PciMatch {
+    pub fn matches(&self, device: &PciDevice) -> bool {
+        return match *self {
+            PciMatch::Id {
+                vendor_id,
+                device_id,
+            } => device.vendor_id == vendor_id && device.device_id == device_id,
+            PciMatch::Class {
+                class_code,
+                subclass_code,
+                prog_if,
+            } => {
+                device.class_code == class_code
+                    && device.subclass_code == subclass_code
+                    && prog_if.map_or(true, |prog_if| device.prog_if == prog_if)
+            }
+        };
+    }
+}
+
+pub struct PciDriver {
+    pub name: &'static str,
+    pub matches: &'static [PciMatch],
+    /// Sets the device up, an error means the driver couldn't use it after all.
+    pub probe: fn(&PciDevice) -> Result<(), ()>,
+}
+
+impl PciDriver {
+    fn matches(&self, device: &PciDevice) -> bool {
+        return self.matches.iter().any(|entry| entry.matches(device));
+    }
+}
+
+// Drivers that are part of the kernel, `register_driver` adds more
+static BUILTIN_DRIVERS: &[&PciDriver] = &[
+    &crate::drivers::storage::ide::PCI_DRIVER,
+    &crate::drivers::storage::ahci::PCI_DRIVER,
+];
+
+static DRIVERS: Mutex<Vec<&'static PciDriver>> = Mutex::new(Vec::new());
+
+/// Adds a driver and probes it against every device that doesn't have one yet.
+pub fn register_driver(driver: &'static PciDriver) {
+    DRIVERS.lock().write().push(driver);
+
+    let device_count = PCI_DEVICES.lock().read().len();
+
+    for index in 0..device_count {
+        bind_device(index, &[driver]);
+    }
+}
+
+/// Gives every device that was found to the first driver that matches it and accepts it.
+pub fn bind_drivers() {
+    if DRIVERS.lock().read().is_empty() {
+        DRIVERS.lock().write().extend_from_slice(BUILTIN_DRIVERS);
+    }
+
+    // Probing can take a while and drivers may look at the device list themselves, so neither
+    // list is held locked while probing
+    let drivers = DRIVERS.lock().read().clone();
+    let device_count = PCI_DEVICES.lock().read().len();
+
+    for index in 0..device_count {
+        bind_device(index, &drivers);
+    }
+}
+
+fn bind_device(index: usize, drivers: &[&'static PciDriver]) {
+    let device = PCI_DEVICES.lock().read()[index].clone();
+
+    if device.driver.is_some() {
+        return;
+    }
+
+    for driver in drivers.iter().filter(|driver| driver.matches(&device)) {
+        if (driver.probe)(&device).is_err() {
+            crate::log_error!(
+                "PCI: {} failed to probe {:02X}:{:02X}.{}",
+                driver.name,
+                device.bus,
+                device.device,
+                device.func
+            );
+            continue;
+        }
+
+        PCI_DEVICES.lock().write()[index].driver = Some(driver.name);
+
+        crate::log_ok!(
+            "PCI: {} bound to {:02X}:{:02X}.{} ({})",
+            driver.name,
+            device.bus,
+            device.device,
+            device.func,
+            device.class_name()
+        );
+
+        return;
+    }
+}
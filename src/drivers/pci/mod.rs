@@ -0,0 +1,433 @@
+pub mod bar;
+pub mod config;
+pub mod driver;
+pub mod msi;
+
+use alloc::vec::Vec;
+
+use crate::libs::mutex::Mutex;
+
+use self::bar::Bar;
+
+/// Reads the dword at `offset` in a function's configuration space.
+pub fn read_pci_config(bus: u8, device: u8, func: u8, offset: u16) -> u32 {
+    return config::access().read(bus, device, func, offset);
+}
+
+pub fn write_pci_config(bus: u8, device: u8, func: u8, offset: u16, value: u32) {
+    config::access().write(bus, device, func, offset, value);
+}
+
+#[inline]
+fn read_pci_vendor_id(bus: u8, device: u8, func: u8) -> u16 {
+    return (read_pci_config(bus, device, func, 0x00) & 0xFFFF) as u16;
+}
+
+#[inline]
+fn read_pci_device_id(bus: u8, device: u8, func: u8) -> u16 {
+    return ((read_pci_config(bus, device, func, 0x00) >> 16) & 0xFFFF) as u16;
+}
+
+#[inline]
+fn read_pci_class_code(bus: u8, device: u8, func: u8) -> u8 {
+    return ((read_pci_config(bus, device, func, 0x08) >> 24) & 0xFF) as u8;
+}
+
+#[inline]
+fn read_pci_subclass_code(bus: u8, device: u8, func: u8) -> u8 {
+    return ((read_pci_config(bus, device, func, 0x08) >> 16) & 0xFF) as u8;
+}
+
+#[inline]
+fn read_pci_prog_if(bus: u8, device: u8, func: u8) -> u8 {
+    // Read the Prog IF (Programming Interface) from the PCI configuration space
+    return ((read_pci_config(bus, device, func, 0x08) >> 8) & 0xFF) as u8;
+}
+
+#[inline]
+fn read_pci_revision_id(bus: u8, device: u8, func: u8) -> u8 {
+    return (read_pci_config(bus, device, func, 0x08) & 0xFF) as u8;
+}
+
+#[inline]
+fn read_pci_header_type(bus: u8, device: u8, func: u8) -> u8 {
+    return ((read_pci_config(bus, device, func, 0x0C) >> 16) & 0xFF) as u8;
+}
+
+#[inline]
+fn read_pci_to_pci_secondary_bus(bus: u8, device: u8, func: u8) -> u8 {
+    return ((read_pci_config(bus, device, func, 0x18) >> 8) & 0xFF) as u8;
+}
+
+// Command register bits
+pub const COMMAND_IO_SPACE: u16 = 1 << 0;
+pub const COMMAND_MEMORY_SPACE: u16 = 1 << 1;
+pub const COMMAND_BUS_MASTER: u16 = 1 << 2;
+pub const COMMAND_INTERRUPT_DISABLE: u16 = 1 << 10;
+
+// Standard capability IDs
+pub const CAPABILITY_POWER_MANAGEMENT: u16 = 0x01;
+pub const CAPABILITY_MSI: u16 = 0x05;
+pub const CAPABILITY_VENDOR_SPECIFIC: u16 = 0x09;
+pub const CAPABILITY_PCI_EXPRESS: u16 = 0x10;
+pub const CAPABILITY_MSI_X: u16 = 0x11;
+pub const CAPABILITY_SATA: u16 = 0x12;
+
+// Extended capability IDs, only PCI Express functions have these
+pub const EXTENDED_CAPABILITY_AER: u16 = 0x0001;
+pub const EXTENDED_CAPABILITY_VIRTUAL_CHANNEL: u16 = 0x0002;
+pub const EXTENDED_CAPABILITY_SERIAL_NUMBER: u16 = 0x0003;
+pub const EXTENDED_CAPABILITY_VENDOR_SPECIFIC: u16 = 0x000B;
+pub const EXTENDED_CAPABILITY_ARI: u16 = 0x000E;
+pub const EXTENDED_CAPABILITY_SR_IOV: u16 = 0x0010;
+
+/// An entry in one of a function's capability lists.
+#[derive(Clone, Copy, Debug)]
+pub struct Capability {
+    pub id: u16,
+    // Where its registers start in configuration space
+    pub offset: u16,
+    // Whether it's in the PCI Express extended list, past the first 256 bytes
+    pub extended: bool,
+}
+
+impl Capability {
+    pub fn name(&self) -> Option<&'static str> {
+        if self.extended {
+            return match self.id {
+                EXTENDED_CAPABILITY_AER => Some("AER"),
+                EXTENDED_CAPABILITY_VIRTUAL_CHANNEL => Some("VC"),
+                EXTENDED_CAPABILITY_SERIAL_NUMBER => Some("Serial Number"),
+                EXTENDED_CAPABILITY_VENDOR_SPECIFIC => Some("Vendor Specific"),
+                EXTENDED_CAPABILITY_ARI => Some("ARI"),
+                EXTENDED_CAPABILITY_SR_IOV => Some("SR-IOV"),
+                _ => None,
+            };
+        }
+
+        return match self.id {
+            CAPABILITY_POWER_MANAGEMENT => Some("Power Management"),
+            CAPABILITY_MSI => Some("MSI"),
+            CAPABILITY_VENDOR_SPECIFIC => Some("Vendor Specific"),
+            CAPABILITY_PCI_EXPRESS => Some("PCI Express"),
+            CAPABILITY_MSI_X => Some("MSI-X"),
+            CAPABILITY_SATA => Some("SATA"),
+            _ => None,
+        };
+    }
+}
+
+impl core::fmt::Display for Capability {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        return match self.name() {
+            Some(name) => write!(f, "{}", name),
+            None if self.extended => write!(f, "Extended {:#06X}", self.id),
+            None => write!(f, "{:#04X}", self.id),
+        };
+    }
+}
+
+fn read_capabilities(bus: u8, device: u8, func: u8) -> Vec<Capability> {
+    let mut capabilities = Vec::new();
+
+    // Bit 4 of the status register says whether there's a capability list at all
+    if (read_pci_config(bus, device, func, 0x04) >> 16) & (1 << 4) != 0 {
+        // CardBus bridges keep the pointer somewhere else
+        let pointer_register = if read_pci_header_type(bus, device, func) & 0x7F == 0x02 {
+            0x14
+        } else {
+            0x34
+        };
+
+        let mut offset = (read_pci_config(bus, device, func, pointer_register) & 0xFC) as u16;
+
+        // Bounded, in case a broken device makes the list go in a circle
+        for _ in 0..48 {
+            // The first 64 bytes are the header, so a pointer into them ends the list
+            if offset < 0x40 {
+                break;
+            }
+
+            let header = read_pci_config(bus, device, func, offset);
+
+            capabilities.push(Capability {
+                id: (header & 0xFF) as u16,
+                offset,
+                extended: false,
+            });
+
+            offset = ((header >> 8) & 0xFC) as u16;
+        }
+    }
+
+    // The extended list always starts right after the legacy configuration space, but it can only
+    // be reached through ECAM
+    let is_pci_express = capabilities
+        .iter()
+        .any(|capability| capability.id == CAPABILITY_PCI_EXPRESS);
+
+    if is_pci_express && config::access().config_space_size() > config::LEGACY_CONFIG_SPACE_SIZE {
+        let mut offset = config::LEGACY_CONFIG_SPACE_SIZE;
+
+        for _ in 0..(config::EXTENDED_CONFIG_SPACE_SIZE - config::LEGACY_CONFIG_SPACE_SIZE) / 4 {
+            let header = read_pci_config(bus, device, func, offset);
+
+            if header == 0 || header == 0xFFFF_FFFF {
+                break;
+            }
+
+            capabilities.push(Capability {
+                id: (header & 0xFFFF) as u16,
+                offset,
+                extended: true,
+            });
+
+            offset = ((header >> 20) & 0xFFC) as u16;
+
+            if offset < config::LEGACY_CONFIG_SPACE_SIZE {
+                break;
+            }
+        }
+    }
+
+    return capabilities;
+}
+
+#[derive(Clone)]
+pub struct PciDevice {
+    pub bus: u8,
+    pub device: u8,
+    pub func: u8,
+    // __reserved: u8
+    pub device_id: u16,
+    pub vendor_id: u16,
+    pub class_code: u8,
+    pub subclass_code: u8,
+    pub prog_if: u8,
+    pub revision_id: u8,
+    pub bars: [Option<Bar>; 6],
+    pub capabilities: Vec<Capability>,
+    // The name of the driver that took the device, if any did
+    pub driver: Option<&'static str>,
+}
+
+impl PciDevice {
+    fn new(bus: u8, device: u8, func: u8) -> Self {
+        // Read the Vendor ID and Device ID registers for each func
+        let vendor_id = read_pci_vendor_id(bus, device, func);
+
+        let device_id = read_pci_device_id(bus, device, func);
+        let class_code = read_pci_class_code(bus, device, func);
+        let subclass_code = read_pci_subclass_code(bus, device, func);
+        let prog_if = read_pci_prog_if(bus, device, func);
+        let revision_id = read_pci_revision_id(bus, device, func);
+        let bars = bar::read_bars(bus, device, func, read_pci_header_type(bus, device, func));
+        let capabilities = read_capabilities(bus, device, func);
+
+        return Self {
+            bus,
+            device,
+            func,
+            device_id,
+            vendor_id,
+            class_code,
+            subclass_code,
+            prog_if,
+            revision_id,
+            bars,
+            capabilities,
+            driver: None,
+        };
+    }
+
+    pub fn read_config(&self, offset: u16) -> u32 {
+        return read_pci_config(self.bus, self.device, self.func, offset);
+    }
+
+    pub fn write_config(&self, offset: u16, value: u32) {
+        write_pci_config(self.bus, self.device, self.func, offset, value);
+    }
+
+    pub fn command(&self) -> u16 {
+        return (self.read_config(0x04) & 0xFFFF) as u16;
+    }
+
+    // The status register shares the dword, but its bits are cleared by writing ones to them, so
+    // the upper half is written as zeros
+    fn set_command(&self, command: u16) {
+        self.write_config(0x04, command as u32);
+    }
+
+    /// Lets the device read and write memory on its own, which DMA and MSI need.
+    pub fn enable_bus_mastering(&self) {
+        self.set_command(self.command() | COMMAND_BUS_MASTER);
+    }
+
+    /// Makes the device respond to accesses to its memory and I/O BARs.
+    pub fn enable_decoding(&self) {
+        let mut command = self.command();
+
+        if self
+            .bars
+            .iter()
+            .flatten()
+            .any(|bar| matches!(bar, Bar::Memory { .. }))
+        {
+            command |= COMMAND_MEMORY_SPACE;
+        }
+
+        if self
+            .bars
+            .iter()
+            .flatten()
+            .any(|bar| matches!(bar, Bar::Io { .. }))
+        {
+            command |= COMMAND_IO_SPACE;
+        }
+
+        self.set_command(command);
+    }
+
+    /// Stops the device from raising its INTx line, for when it uses MSI instead.
+    pub fn disable_legacy_interrupts(&self) {
+        self.set_command(self.command() | COMMAND_INTERRUPT_DISABLE);
+    }
+
+    /// Where the registers of the standard capability `id` start, if the function has it.
+    pub fn find_capability(&self, id: u16) -> Option<u16> {
+        return self
+            .capabilities
+            .iter()
+            .find(|capability| !capability.extended && capability.id == id)
+            .map(|capability| capability.offset);
+    }
+
+    /// Like `find_capability`, but for the PCI Express extended capabilities.
+    pub fn find_extended_capability(&self, id: u16) -> Option<u16> {
+        return self
+            .capabilities
+            .iter()
+            .find(|capability| capability.extended && capability.id == id)
+            .map(|capability| capability.offset);
+    }
+
+    /// What kind of device the class codes say this is.
+    pub fn class_name(&self) -> &'static str {
+        return match (self.class_code, self.subclass_code) {
+            (0x00, _) => "Unclassified device",
+            (0x01, 0x00) => "SCSI controller",
+            (0x01, 0x01) => "IDE controller",
+            (0x01, 0x05) => "ATA controller",
+            (0x01, 0x06) => "SATA controller",
+            (0x01, 0x08) => "NVMe controller",
+            (0x01, _) => "Storage controller",
+            (0x02, 0x00) => "Ethernet controller",
+            (0x02, _) => "Network controller",
+            (0x03, 0x00) => "VGA controller",
+            (0x03, _) => "Display controller",
+            (0x04, 0x01 | 0x03) => "Audio device",
+            (0x04, _) => "Multimedia controller",
+            (0x05, _) => "Memory controller",
+            (0x06, 0x00) => "Host bridge",
+            (0x06, 0x01) => "ISA bridge",
+            (0x06, 0x04) => "PCI bridge",
+            (0x06, _) => "Bridge",
+            (0x07, _) => "Communication controller",
+            (0x08, _) => "System peripheral",
+            (0x09, _) => "Input device controller",
+            (0x0C, 0x03) => "USB controller",
+            (0x0C, 0x05) => "SMBus controller",
+            (0x0C, _) => "Serial bus controller",
+            (0x0D, _) => "Wireless controller",
+            _ => "Unknown device",
+        };
+    }
+}
+
+impl core::fmt::Display for PciDevice {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "Bus: {} Device: {} Function: {} VendorID: {:#X} DeviceID: {:#X} ClassCode: {:#04X} SubclassCode: {:#04X} ProgIF: {:#04X}",
+        self.bus, self.device, self.func, self.vendor_id, self.device_id, self.class_code, self.subclass_code, self.prog_if)
+    }
+}
+
+// Every function found on the buses, in the order they were found
+pub static PCI_DEVICES: Mutex<Vec<PciDevice>> = Mutex::new(Vec::new());
+
+/// Every PCI function that was found, with the driver bound to it.
+pub fn devices() -> Vec<PciDevice> {
+    return PCI_DEVICES.lock().read().clone();
+}
+
+pub fn enumerate_pci_bus() {
+    config::init();
+
+    let header_type = read_pci_header_type(0, 0, 0);
+    if (header_type & 0x80) == 0 {
+        // Single PCI host controller
+        check_bus(0);
+    } else {
+        // Multiple PCI host controllers, each function of the host bridge is responsible for
+        // the bus with the same number
+        for function in 0..8 {
+            if read_pci_vendor_id(0, 0, function) == 0xFFFF {
+                continue;
+            }
+            let bus = function;
+            check_bus(bus);
+        }
+    }
+
+    crate::log_info!("PCI: Found {} devices", PCI_DEVICES.lock().read().len());
+}
+
+fn check_bus(bus: u8) {
+    for device in 0..32 {
+        check_device(bus, device);
+    }
+}
+
+fn check_device(bus: u8, device: u8) {
+    let mut func: u8 = 0;
+
+    let vendor_id = read_pci_vendor_id(bus, device, func);
+
+    if vendor_id == 0xFFFF {
+        return;
+    }
+
+    check_function(bus, device, func);
+    let header_type = read_pci_header_type(bus, device, func);
+
+    if header_type & 0x80 != 0 {
+        // It's a multi-function device
+        func += 1;
+
+        while func < 8 {
+            if read_pci_vendor_id(bus, device, func) != 0xFFFF {
+                check_function(bus, device, func);
+            }
+
+            func += 1;
+        }
+    }
+}
+
+fn check_function(bus: u8, device: u8, func: u8) {
+    PCI_DEVICES
+        .lock()
+        .write()
+        .push(PciDevice::new(bus, device, func));
+
+    let class_code: u8;
+    let subclass_code: u8;
+    let secondary_bus: u8;
+
+    class_code = read_pci_class_code(bus, device, func);
+    subclass_code = read_pci_subclass_code(bus, device, func);
+
+    if class_code == 0x06 && subclass_code == 0x04 {
+        secondary_bus = read_pci_to_pci_secondary_bus(bus, device, func);
+        check_bus(secondary_bus);
+    }
+}
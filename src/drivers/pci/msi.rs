@@ -0,0 +1,183 @@
+// Message signalled interrupts. Instead of pulling an IRQ line the device writes its vector to the
+// local APIC's address range, so the interrupt goes straight to a CPU without the IOAPIC or any
+// sharing. MSI-X is used when a device has both, with every entry of its table on the one vector.
+
+use crate::arch::{apic, interrupts, paging};
+
+use super::{bar::Bar, PciDevice, CAPABILITY_MSI, CAPABILITY_MSI_X};
+
+// Where messages have to be written, with the destination APIC ID in bits 12 to 19
+const MSI_ADDRESS_BASE: u32 = 0xFEE0_0000;
+
+// MSI message control
+const MSI_ENABLE: u16 = 1 << 0;
+const MSI_MULTIPLE_MESSAGE_ENABLE_MASK: u16 = 0b111 << 4;
+const MSI_64_BIT: u16 = 1 << 7;
+const MSI_PER_VECTOR_MASKING: u16 = 1 << 8;
+
+// MSI-X message control
+const MSI_X_TABLE_SIZE_MASK: u16 = 0x7FF;
+const MSI_X_FUNCTION_MASK: u16 = 1 << 14;
+const MSI_X_ENABLE: u16 = 1 << 15;
+
+// The low three bits of the table register say which BAR the table is in
+const MSI_X_BIR_MASK: u32 = 0b111;
+
+// Every MSI-X table entry is the message address, its upper half, the data and a vector control
+const MSI_X_ENTRY_SIZE: u64 = 16;
+const MSI_X_VECTOR_MASKED: u32 = 1 << 0;
+
+#[derive(Debug)]
+pub enum MsiError {
+    // The device has neither capability
+    Unsupported,
+    NoApic,
+    NoFreeVector,
+    // The MSI-X table isn't in a memory BAR, or it couldn't be mapped
+    InvalidTable,
+    // Only 8 bit APIC IDs fit in a message without interrupt remapping
+    ApicIdTooLarge,
+}
+
+fn message_address() -> Result<u32, MsiError> {
+    let apic_id = apic::local_apic_id();
+
+    if apic_id > 0xFF {
+        return Err(MsiError::ApicIdTooLarge);
+    }
+
+    return Ok(MSI_ADDRESS_BASE | apic_id << 12);
+}
+
+fn message_control(device: &PciDevice, capability: u16) -> u16 {
+    return (device.read_config(capability) >> 16) as u16;
+}
+
+// The capability ID and next pointer in the lower half are read only
+fn set_message_control(device: &PciDevice, capability: u16, control: u16) {
+    let header = device.read_config(capability) & 0xFFFF;
+    device.write_config(capability, header | (control as u32) << 16);
+}
+
+/// Installs `handler` on a new vector and has the device deliver its interrupts there, on the CPU
+/// we are running on. Returns the vector, the handler has to acknowledge it with
+/// `apic::end_of_interrupt`.
+pub fn enable(device: &PciDevice, handler: u64) -> Result<u8, MsiError> {
+    if !apic::is_enabled() {
+        return Err(MsiError::NoApic);
+    }
+
+    let address = message_address()?;
+
+    let vector = if let Some(capability) = device.find_capability(CAPABILITY_MSI_X) {
+        let (table, entries) = map_msi_x_table(device, capability)?;
+        let vector = interrupts::allocate_vector(handler).ok_or(MsiError::NoFreeVector)?;
+
+        enable_msi_x(device, capability, table, entries, address, vector);
+
+        vector
+    } else if let Some(capability) = device.find_capability(CAPABILITY_MSI) {
+        let vector = interrupts::allocate_vector(handler).ok_or(MsiError::NoFreeVector)?;
+
+        enable_msi(device, capability, address, vector);
+
+        vector
+    } else {
+        return Err(MsiError::Unsupported);
+    };
+
+    // Messages are memory writes the device makes on its own
+    device.disable_legacy_interrupts();
+    device.enable_bus_mastering();
+
+    return Ok(vector);
+}
+
+fn enable_msi(device: &PciDevice, capability: u16, address: u32, vector: u8) {
+    let control = message_control(device, capability);
+
+    device.write_config(capability + 0x04, address);
+
+    let data_register = if control & MSI_64_BIT != 0 {
+        device.write_config(capability + 0x08, 0);
+        capability + 0x0C
+    } else {
+        capability + 0x08
+    };
+
+    // Fixed delivery and edge triggered, so the data is nothing but the vector
+    device.write_config(data_register, vector as u32);
+
+    if control & MSI_PER_VECTOR_MASKING != 0 {
+        let mask_register = data_register + 0x04;
+        let mask = device.read_config(mask_register);
+
+        device.write_config(mask_register, mask & !1);
+    }
+
+    // We only ever give out one vector, so only one message is enabled
+    set_message_control(
+        device,
+        capability,
+        control & !MSI_MULTIPLE_MESSAGE_ENABLE_MASK | MSI_ENABLE,
+    );
+}
+
+// Returns where the table was mapped and how many entries it has
+fn map_msi_x_table(device: &PciDevice, capability: u16) -> Result<(u64, u64), MsiError> {
+    let entries = (message_control(device, capability) & MSI_X_TABLE_SIZE_MASK) as u64 + 1;
+
+    let table_register = device.read_config(capability + 0x04);
+    let bir = (table_register & MSI_X_BIR_MASK) as usize;
+    let table_offset = (table_register & !MSI_X_BIR_MASK) as u64;
+
+    let bar_address = match device.bars.get(bir).copied().flatten() {
+        Some(Bar::Memory { address, .. }) => address,
+        _ => return Err(MsiError::InvalidTable),
+    };
+
+    // The table is only reachable while the device decodes its memory BARs
+    device.enable_decoding();
+
+    let table = paging::map_mmio(bar_address + table_offset, entries * MSI_X_ENTRY_SIZE)
+        .map_err(|_| MsiError::InvalidTable)?;
+
+    return Ok((table, entries));
+}
+
+fn enable_msi_x(
+    device: &PciDevice,
+    capability: u16,
+    table: u64,
+    entries: u64,
+    address: u32,
+    vector: u8,
+) {
+    let control = message_control(device, capability);
+
+    // Nothing gets sent while the function is masked, so the entries can be changed safely
+    set_message_control(
+        device,
+        capability,
+        control | MSI_X_ENABLE | MSI_X_FUNCTION_MASK,
+    );
+
+    for entry in 0..entries {
+        let entry = (table + entry * MSI_X_ENTRY_SIZE) as *mut u32;
+
+        unsafe {
+            core::ptr::write_volatile(entry, address);
+            core::ptr::write_volatile(entry.add(1), 0);
+            core::ptr::write_volatile(entry.add(2), vector as u32);
+
+            let vector_control = core::ptr::read_volatile(entry.add(3));
+            core::ptr::write_volatile(entry.add(3), vector_control & !MSI_X_VECTOR_MASKED);
+        }
+    }
+
+    set_message_control(
+        device,
+        capability,
+        (control | MSI_X_ENABLE) & !MSI_X_FUNCTION_MASK,
+    );
+}
@@ -0,0 +1,265 @@
+// CMOS real-time clock, the only source of wall-clock time we have.
+// The RTC is only read once at boot, after that the time is kept by adding the uptime to it.
+
+use core::{
+    fmt,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use crate::{
+    arch::{
+        self,
+        io::{inb, outb},
+    },
+    sys::time::Deadline,
+};
+
+const CMOS_ADDRESS: u16 = 0x70;
+const CMOS_DATA: u16 = 0x71;
+
+const REGISTER_SECONDS: u8 = 0x00;
+const REGISTER_MINUTES: u8 = 0x02;
+const REGISTER_HOURS: u8 = 0x04;
+const REGISTER_DAY: u8 = 0x07;
+const REGISTER_MONTH: u8 = 0x08;
+const REGISTER_YEAR: u8 = 0x09;
+const REGISTER_STATUS_A: u8 = 0x0A;
+const REGISTER_STATUS_B: u8 = 0x0B;
+
+// Status A
+const UPDATE_IN_PROGRESS: u8 = 1 << 7;
+
+// Status B
+const HOURS_24: u8 = 1 << 1;
+const BINARY_MODE: u8 = 1 << 2;
+
+// Set in the hours register for PM times when the clock is in 12 hour mode
+const HOUR_PM: u8 = 1 << 7;
+
+// The RTC only stores two digits of the year. Not every system has a century register, in which
+// case we assume it's the 21st century
+const DEFAULT_CENTURY: u16 = 20;
+
+const SECONDS_PER_DAY: u64 = 86400;
+
+// An update takes about 2 ms, a clock that's still busy after this is broken or not there at all
+const READ_TIMEOUT_MS: u64 = 10;
+
+// Unix time of the moment the system timer started ticking
+static BOOT_TIMESTAMP: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    /// Seconds since 1970-01-01 00:00:00 UTC, dates before that are clamped to it.
+    pub fn to_unix_timestamp(&self) -> u64 {
+        let days = days_from_civil(self.year as i64, self.month as i64, self.day as i64);
+
+        if days < 0 {
+            return 0;
+        }
+
+        return days as u64 * SECONDS_PER_DAY
+            + self.hour as u64 * 3600
+            + self.minute as u64 * 60
+            + self.second as u64;
+    }
+
+    pub fn from_unix_timestamp(timestamp: u64) -> Self {
+        let (year, month, day) = civil_from_days((timestamp / SECONDS_PER_DAY) as i64);
+        let seconds = timestamp % SECONDS_PER_DAY;
+
+        return Self {
+            year: year as u16,
+            month: month as u8,
+            day: day as u8,
+            hour: (seconds / 3600) as u8,
+            minute: (seconds / 60 % 60) as u8,
+            second: (seconds % 60) as u8,
+        };
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        );
+    }
+}
+
+// Days since 1970-01-01 of a date in the proleptic Gregorian calendar.
+// From http://howardhinnant.github.io/date_algorithms.html
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    return era * 146097 + day_of_era - 719468;
+}
+
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    return (year, month, day);
+}
+
+fn read_register(register: u8) -> u8 {
+    // Bit 7 of the address port disables NMIs, we leave it clear
+    outb(CMOS_ADDRESS, register & 0x7F);
+    return inb(CMOS_DATA);
+}
+
+fn update_in_progress() -> bool {
+    return read_register(REGISTER_STATUS_A) & UPDATE_IN_PROGRESS != 0;
+}
+
+// The raw register values, still in whatever format the RTC is set to
+fn read_raw(deadline: &mut Deadline) -> Result<[u8; 6], ()> {
+    while update_in_progress() {
+        if deadline.has_passed() {
+            return Err(());
+        }
+
+        arch::pause();
+    }
+
+    return Ok([
+        read_register(REGISTER_SECONDS),
+        read_register(REGISTER_MINUTES),
+        read_register(REGISTER_HOURS),
+        read_register(REGISTER_DAY),
+        read_register(REGISTER_MONTH),
+        read_register(REGISTER_YEAR),
+    ]);
+}
+
+fn bcd_to_binary(value: u8) -> u8 {
+    return (value & 0x0F) + (value >> 4) * 10;
+}
+
+/// Reads the current date and time straight from the RTC, fails if it doesn't settle on one in
+/// time.
+pub fn read() -> Result<DateTime, ()> {
+    // The FADT says where the century register is, if there is one
+    let century_register = crate::drivers::acpi::fadt()
+        .map(|fadt| fadt.century_register)
+        .filter(|&register| register != 0);
+
+    let (raw, mut century, status_b) = arch::without_interrupts(|| {
+        let mut deadline = Deadline::after_ms(READ_TIMEOUT_MS);
+
+        // An update can still start between the check and the reads, so we read until we get
+        // the same values twice in a row
+        let mut raw = read_raw(&mut deadline)?;
+
+        loop {
+            let again = read_raw(&mut deadline)?;
+
+            if again == raw {
+                break;
+            }
+
+            if deadline.has_passed() {
+                return Err(());
+            }
+
+            raw = again;
+        }
+
+        let century = century_register.map(read_register);
+
+        return Ok((raw, century, read_register(REGISTER_STATUS_B)));
+    })?;
+
+    let [mut second, mut minute, mut hour, mut day, mut month, mut year] = raw;
+
+    let is_pm = hour & HOUR_PM != 0;
+    hour &= !HOUR_PM;
+
+    if status_b & BINARY_MODE == 0 {
+        second = bcd_to_binary(second);
+        minute = bcd_to_binary(minute);
+        hour = bcd_to_binary(hour);
+        day = bcd_to_binary(day);
+        month = bcd_to_binary(month);
+        year = bcd_to_binary(year);
+        century = century.map(bcd_to_binary);
+    }
+
+    // 12 AM is 0 and 12 PM is 12 on a 24 hour clock
+    if status_b & HOURS_24 == 0 {
+        hour %= 12;
+
+        if is_pm {
+            hour += 12;
+        }
+    }
+
+    return Ok(DateTime {
+        year: century.map_or(DEFAULT_CENTURY, |century| century as u16) * 100 + year as u16,
+        month,
+        day,
+        hour,
+        minute,
+        second,
+    });
+}
+
+pub fn init() {
+    let date_time = match read() {
+        Ok(date_time) => date_time,
+        Err(()) => {
+            crate::log_error!("RTC: The clock never finished updating, the time is unknown");
+            return;
+        }
+    };
+
+    BOOT_TIMESTAMP.store(
+        date_time
+            .to_unix_timestamp()
+            .saturating_sub(crate::sys::time::uptime().as_secs()),
+        Ordering::SeqCst,
+    );
+
+    crate::log_ok!("RTC initialized, the time is {date_time}");
+}
+
+/// The current Unix time, in seconds.
+pub fn now() -> u64 {
+    let boot_timestamp = BOOT_TIMESTAMP.load(Ordering::SeqCst);
+
+    if boot_timestamp == 0 {
+        // Without a clock all we can say is how long we've been up
+        return read()
+            .map(|date_time| date_time.to_unix_timestamp())
+            .unwrap_or_else(|_| crate::sys::time::uptime().as_secs());
+    }
+
+    return boot_timestamp + crate::sys::time::uptime().as_secs();
+}
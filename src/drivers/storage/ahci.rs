@@ -0,0 +1,828 @@
+// AHCI, the interface SATA controllers have. Every port has a list of command slots in memory that
+// point to command tables, each of which holds a FIS to send to the drive and a list of the memory
+// regions the data goes to or comes from. We only ever use the first slot of a port. A finished
+// command interrupts us through MSI when the controller has it, otherwise, or with interrupts
+// disabled, we poll the port for it.
+
+use core::{
+    mem::size_of,
+    sync::atomic::{AtomicU32, AtomicU64, Ordering},
+};
+
+use alloc::{sync::Arc, vec::Vec};
+
+use crate::{
+    arch::{apic, paging},
+    drivers::pci::{
+        driver::{PciDriver, PciMatch},
+        msi, PciDevice,
+    },
+    libs::mutex::{Mutex, MutexGuard},
+    sys::{
+        frame_allocator::{FRAME_ALLOCATOR, FRAME_SIZE},
+        scheduler,
+        time::Deadline,
+    },
+};
+
+use super::drive::BlockDevice;
+
+const AHCI_SECTOR_SIZE: usize = 512;
+// How long a drive gets to finish a command before we give up on it
+const DRIVE_TIMEOUT_MS: u64 = 5000;
+
+// Generic host control registers, relative to ABAR
+const HBA_CAPABILITIES: u64 = 0x00;
+const HBA_GLOBAL_CONTROL: u64 = 0x04;
+const HBA_INTERRUPT_STATUS: u64 = 0x08;
+const HBA_PORTS_IMPLEMENTED: u64 = 0x0C;
+const HBA_VERSION: u64 = 0x10;
+const HBA_CAPABILITIES_EXTENDED: u64 = 0x24;
+const HBA_HANDOFF_CONTROL: u64 = 0x28;
+
+const CAPABILITIES_64_BIT: u32 = 1 << 31;
+const CAPABILITIES_STAGGERED_SPIN_UP: u32 = 1 << 27;
+const CAPABILITIES_EXTENDED_HANDOFF: u32 = 1 << 0;
+
+const GLOBAL_CONTROL_INTERRUPT_ENABLE: u32 = 1 << 1;
+const GLOBAL_CONTROL_AHCI_ENABLE: u32 = 1 << 31;
+
+// The firmware owns the controller until we ask for it
+const HANDOFF_BIOS_OWNED: u32 = 1 << 0;
+const HANDOFF_OS_OWNED: u32 = 1 << 1;
+
+// Every port has its own registers, one after the other
+const PORT_REGISTERS_BASE: u64 = 0x100;
+const PORT_REGISTERS_SIZE: u64 = 0x80;
+const MAX_PORTS: usize = 32;
+
+const PORT_COMMAND_LIST_BASE: u64 = 0x00;
+const PORT_COMMAND_LIST_BASE_UPPER: u64 = 0x04;
+const PORT_FIS_BASE: u64 = 0x08;
+const PORT_FIS_BASE_UPPER: u64 = 0x0C;
+const PORT_INTERRUPT_STATUS: u64 = 0x10;
+const PORT_INTERRUPT_ENABLE: u64 = 0x14;
+const PORT_COMMAND: u64 = 0x18;
+const PORT_TASK_FILE_DATA: u64 = 0x20;
+const PORT_SIGNATURE: u64 = 0x24;
+const PORT_SATA_STATUS: u64 = 0x28;
+const PORT_SATA_ERROR: u64 = 0x30;
+const PORT_COMMAND_ISSUE: u64 = 0x38;
+
+const PORT_COMMAND_START: u32 = 1 << 0;
+const PORT_COMMAND_SPIN_UP: u32 = 1 << 1;
+const PORT_COMMAND_POWER_ON: u32 = 1 << 2;
+const PORT_COMMAND_FIS_RECEIVE_ENABLE: u32 = 1 << 4;
+const PORT_COMMAND_FIS_RECEIVE_RUNNING: u32 = 1 << 14;
+const PORT_COMMAND_LIST_RUNNING: u32 = 1 << 15;
+
+const PORT_INTERRUPT_DEVICE_TO_HOST: u32 = 1 << 0;
+const PORT_INTERRUPT_PIO_SETUP: u32 = 1 << 1;
+const PORT_INTERRUPT_TASK_FILE_ERROR: u32 = 1 << 30;
+// What a command raises when it's done, the PIO setup FIS is how PIO reads like identify finish
+const PORT_INTERRUPTS_COMPLETION: u32 =
+    PORT_INTERRUPT_DEVICE_TO_HOST | PORT_INTERRUPT_PIO_SETUP | PORT_INTERRUPT_TASK_FILE_ERROR;
+
+const TASK_FILE_ERROR: u32 = 1 << 0;
+const TASK_FILE_DATA_REQUEST: u32 = 1 << 3;
+const TASK_FILE_BUSY: u32 = 1 << 7;
+
+// The device detection and interface power management fields of the SATA status
+const SATA_STATUS_DETECTION_MASK: u32 = 0x0F;
+const SATA_STATUS_DEVICE_PRESENT: u32 = 0x03;
+const SATA_STATUS_POWER_MASK: u32 = 0x0F00;
+const SATA_STATUS_POWER_ACTIVE: u32 = 0x0100;
+
+const SIGNATURE_ATA: u32 = 0x0000_0101;
+const SIGNATURE_ATAPI: u32 = 0xEB14_0101;
+
+// Where things are in the frame every port gets. The command list has 32 headers of 32 bytes and
+// has to be 1 KiB aligned, the received FIS area is 256 bytes and the command table 128 byte
+// aligned
+const COMMAND_LIST_OFFSET: u64 = 0x000;
+const RECEIVED_FIS_OFFSET: u64 = 0x400;
+const COMMAND_TABLE_OFFSET: u64 = 0x500;
+// The command FIS, an ATAPI command and some reserved space come before the PRDT
+const COMMAND_TABLE_PRDT_OFFSET: u64 = 0x80;
+
+// Every port bounces DMA through a buffer this big, which is also the most one command moves
+const DMA_BUFFER_FRAMES: usize = 16;
+const DMA_BUFFER_SIZE: usize = DMA_BUFFER_FRAMES * FRAME_SIZE as usize;
+const SECTORS_PER_COMMAND: usize = DMA_BUFFER_SIZE / AHCI_SECTOR_SIZE;
+
+const FIS_TYPE_REGISTER_HOST_TO_DEVICE: u8 = 0x27;
+// Set when the FIS carries a command rather than a device control update
+const FIS_COMMAND: u8 = 1 << 7;
+const FIS_DEVICE_LBA: u8 = 1 << 6;
+
+const COMMAND_HEADER_WRITE: u16 = 1 << 6;
+
+// The registers of the controller whose interrupts come in through MSI, zero if none do. The
+// handler can only tell one controller's interrupts apart, any others poll
+static MSI_HBA: AtomicU64 = AtomicU64::new(0);
+// The port interrupt status bits the handler took off the controller, for `issue` to pick up
+static PORT_INTERRUPTS: [AtomicU32; MAX_PORTS] = [const { AtomicU32::new(0) }; MAX_PORTS];
+
+pub static PCI_DRIVER: PciDriver = PciDriver {
+    name: "ahci",
+    matches: &[PciMatch::Class {
+        class_code: 0x01,
+        subclass_code: 0x06,
+        prog_if: Some(0x01),
+    }],
+    probe,
+};
+
+#[repr(u8)]
+#[derive(Clone, Copy, Debug)]
+enum AHCICommand {
+    ReadDMAExt = 0x25,
+    WriteDMAExt = 0x35,
+    CacheFlushExt = 0xEA,
+    Identify = 0xEC,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct CommandHeader {
+    // The command FIS length in dwords, and whether data goes to the drive
+    flags: u16,
+    prdt_length: u16,
+    // Filled in by the controller with how much was transferred
+    prd_byte_count: u32,
+    table_address: u32,
+    table_address_upper: u32,
+    reserved: [u32; 4],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct PhysicalRegionDescriptor {
+    address: u32,
+    address_upper: u32,
+    reserved: u32,
+    // One less than the number of bytes, which has to be even
+    byte_count: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+struct RegisterHostToDeviceFIS {
+    fis_type: u8,
+    flags: u8,
+    command: u8,
+    features_low: u8,
+    lba0: u8,
+    lba1: u8,
+    lba2: u8,
+    device: u8,
+    lba3: u8,
+    lba4: u8,
+    lba5: u8,
+    features_high: u8,
+    count_low: u8,
+    count_high: u8,
+    icc: u8,
+    control: u8,
+    reserved: [u8; 4],
+}
+
+impl RegisterHostToDeviceFIS {
+    fn new(command: AHCICommand, sector: u64, sector_count: u16) -> Self {
+        return Self {
+            fis_type: FIS_TYPE_REGISTER_HOST_TO_DEVICE,
+            flags: FIS_COMMAND,
+            command: command as u8,
+            lba0: sector as u8,
+            lba1: (sector >> 8) as u8,
+            lba2: (sector >> 16) as u8,
+            device: FIS_DEVICE_LBA,
+            lba3: (sector >> 24) as u8,
+            lba4: (sector >> 32) as u8,
+            lba5: (sector >> 40) as u8,
+            count_low: sector_count as u8,
+            count_high: (sector_count >> 8) as u8,
+            ..Default::default()
+        };
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum AHCIDirection {
+    Read,
+    Write,
+}
+
+fn read_register(address: u64) -> u32 {
+    return unsafe { core::ptr::read_volatile(address as *const u32) };
+}
+
+fn write_register(address: u64, value: u32) {
+    unsafe { core::ptr::write_volatile(address as *mut u32, value) };
+}
+
+// Polls until `done` returns true, gives up after the drive timeout
+fn wait_until(mut done: impl FnMut() -> bool) -> Result<(), ()> {
+    let mut deadline = Deadline::after_ms(DRIVE_TIMEOUT_MS);
+
+    while !done() {
+        if deadline.has_passed() {
+            return Err(());
+        }
+
+        // Other tasks can have the CPU until the drive is done
+        if scheduler::is_running() {
+            scheduler::yield_now();
+        } else {
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            crate::arch::pause();
+        }
+    }
+
+    return Ok(());
+}
+
+fn port_registers(hba: u64, index: usize) -> u64 {
+    return hba + PORT_REGISTERS_BASE + index as u64 * PORT_REGISTERS_SIZE;
+}
+
+// Every port that raised an interrupt has its status taken off it, so the next command's
+// interrupt can come through
+extern "x86-interrupt" fn interrupt_handler() {
+    let hba = MSI_HBA.load(Ordering::SeqCst);
+    let pending = read_register(hba + HBA_INTERRUPT_STATUS);
+
+    for (index, interrupts) in PORT_INTERRUPTS.iter().enumerate() {
+        if pending & (1 << index) == 0 {
+            continue;
+        }
+
+        // The port's status has to be cleared before the controller's
+        let registers = port_registers(hba, index);
+        let status = read_register(registers + PORT_INTERRUPT_STATUS);
+        write_register(registers + PORT_INTERRUPT_STATUS, status);
+
+        interrupts.fetch_or(status, Ordering::SeqCst);
+    }
+
+    write_register(hba + HBA_INTERRUPT_STATUS, pending);
+
+    apic::end_of_interrupt();
+}
+
+// Returns whether the controller's interrupts come to `interrupt_handler` now
+fn enable_interrupts(device: &PciDevice, hba: u64) -> bool {
+    if MSI_HBA.load(Ordering::SeqCst) != 0 {
+        return false;
+    }
+
+    // The handler needs it as soon as the first interrupt can come in
+    MSI_HBA.store(hba, Ordering::SeqCst);
+
+    match msi::enable(device, interrupt_handler as u64) {
+        Ok(vector) => {
+            crate::log_info!("AHCI: Commands complete through MSI vector {}", vector);
+            return true;
+        }
+        Err(err) => {
+            crate::log_info!("AHCI: No MSI ({:?}), polling for commands instead", err);
+            MSI_HBA.store(0, Ordering::SeqCst);
+            return false;
+        }
+    }
+}
+
+fn probe(device: &PciDevice) -> Result<(), ()> {
+    // ABAR, where all of the controller's registers are
+    let abar = device.bars[5].ok_or(())?;
+
+    device.enable_decoding();
+    device.enable_bus_mastering();
+
+    let hba = abar.map().map_err(|_| ())?;
+
+    take_ownership(hba);
+
+    let uses_interrupts = enable_interrupts(device, hba);
+
+    // Anything the firmware left pending is cleared by writing ones to it
+    write_register(hba + HBA_INTERRUPT_STATUS, u32::MAX);
+
+    let global_control = read_register(hba + HBA_GLOBAL_CONTROL) | GLOBAL_CONTROL_AHCI_ENABLE;
+    write_register(
+        hba + HBA_GLOBAL_CONTROL,
+        if uses_interrupts {
+            global_control | GLOBAL_CONTROL_INTERRUPT_ENABLE
+        } else {
+            global_control & !GLOBAL_CONTROL_INTERRUPT_ENABLE
+        },
+    );
+
+    let capabilities = read_register(hba + HBA_CAPABILITIES);
+    let version = read_register(hba + HBA_VERSION);
+    let ports_implemented = read_register(hba + HBA_PORTS_IMPLEMENTED);
+
+    crate::log_info!(
+        "AHCI: Controller version {}.{}, ports {:#010b}",
+        version >> 16,
+        (version >> 8) & 0xFF,
+        ports_implemented
+    );
+
+    let mut drives = Vec::new();
+
+    for index in 0..MAX_PORTS {
+        if ports_implemented & (1 << index) == 0 {
+            continue;
+        }
+
+        let registers = port_registers(hba, index);
+
+        if capabilities & CAPABILITIES_STAGGERED_SPIN_UP != 0 {
+            let command = read_register(registers + PORT_COMMAND);
+            write_register(
+                registers + PORT_COMMAND,
+                command | PORT_COMMAND_SPIN_UP | PORT_COMMAND_POWER_ON,
+            );
+        }
+
+        let sata_status = read_register(registers + PORT_SATA_STATUS);
+
+        if sata_status & SATA_STATUS_DETECTION_MASK != SATA_STATUS_DEVICE_PRESENT
+            || sata_status & SATA_STATUS_POWER_MASK != SATA_STATUS_POWER_ACTIVE
+        {
+            continue;
+        }
+
+        match read_register(registers + PORT_SIGNATURE) {
+            SIGNATURE_ATA => {}
+            SIGNATURE_ATAPI => {
+                crate::log_info!(
+                    "AHCI: Port {} has an ATAPI drive, which isn't supported",
+                    index
+                );
+                continue;
+            }
+            signature => {
+                crate::log_info!(
+                    "AHCI: Port {} has an unknown device {:#X}",
+                    index,
+                    signature
+                );
+                continue;
+            }
+        }
+
+        let port = match AHCIPort::new(
+            registers,
+            index,
+            capabilities & CAPABILITIES_64_BIT != 0,
+            uses_interrupts,
+        ) {
+            Some(port) => port,
+            None => {
+                crate::log_error!("AHCI: Failed to set up port {}", index);
+                continue;
+            }
+        };
+
+        match AHCIDrive::new(port) {
+            Ok(drive) => {
+                crate::log_info!(
+                    "AHCI: Drive on port {} has {} sectors ({} MB)",
+                    index,
+                    drive.sector_count,
+                    (drive.sector_count * AHCI_SECTOR_SIZE as u64) / 1024 / 1024
+                );
+
+                drives.push(Arc::new(drive));
+            }
+            Err(()) => crate::log_error!("AHCI: Failed to identify the drive on port {}", index),
+        }
+    }
+
+    crate::log_info!(
+        "AHCI: Detected {} drive{}",
+        drives.len(),
+        match drives.len() {
+            1 => "",
+            _ => "s",
+        }
+    );
+
+    for drive in drives {
+        super::register_block_device(drive.clone());
+        super::mount_partitions(drive);
+    }
+
+    return Ok(());
+}
+
+// Firmware that uses the controller itself has to be asked to let go of it
+fn take_ownership(hba: u64) {
+    if read_register(hba + HBA_CAPABILITIES_EXTENDED) & CAPABILITIES_EXTENDED_HANDOFF == 0 {
+        return;
+    }
+
+    let handoff = read_register(hba + HBA_HANDOFF_CONTROL);
+    write_register(hba + HBA_HANDOFF_CONTROL, handoff | HANDOFF_OS_OWNED);
+
+    if wait_until(|| read_register(hba + HBA_HANDOFF_CONTROL) & HANDOFF_BIOS_OWNED == 0).is_err() {
+        crate::log_error!("AHCI: The firmware didn't hand over the controller");
+    }
+}
+
+// Returns the frame for a port's command structures and its bounce buffer
+fn allocate_port_memory(supports_64_bit: bool) -> Option<(u64, u64)> {
+    let mut frame_allocator = FRAME_ALLOCATOR.lock();
+    let frame_allocator = frame_allocator.write();
+
+    let memory = frame_allocator.allocate()?;
+
+    let buffer = match frame_allocator.allocate_contiguous(DMA_BUFFER_FRAMES) {
+        Some(buffer) => buffer,
+        None => {
+            frame_allocator.free(memory);
+            return None;
+        }
+    };
+
+    // Controllers without 64 bit addressing ignore the upper halves
+    if !supports_64_bit
+        && (memory + FRAME_SIZE > 1 << 32 || buffer + DMA_BUFFER_SIZE as u64 > 1 << 32)
+    {
+        frame_allocator.free(memory);
+        frame_allocator.free_contiguous(buffer, DMA_BUFFER_FRAMES);
+        return None;
+    }
+
+    return Some((memory, buffer));
+}
+
+struct AHCIPort {
+    // Virtual address of the port's registers
+    registers: u64,
+    index: usize,
+    // Whether the port's commands interrupt us when they finish
+    uses_interrupts: bool,
+    // Physical addresses of the frame holding the command list, received FISes and command
+    // table, and of the bounce buffer
+    memory: u64,
+    buffer: u64,
+}
+
+impl AHCIPort {
+    fn new(
+        registers: u64,
+        index: usize,
+        supports_64_bit: bool,
+        uses_interrupts: bool,
+    ) -> Option<Self> {
+        let (memory, buffer) = allocate_port_memory(supports_64_bit)?;
+
+        let port = Self {
+            registers,
+            index,
+            uses_interrupts,
+            memory,
+            buffer,
+        };
+
+        unsafe { core::ptr::write_bytes(port.memory_ptr(0), 0, FRAME_SIZE as usize) };
+
+        if port.stop().is_err() {
+            let mut frame_allocator = FRAME_ALLOCATOR.lock();
+            let frame_allocator = frame_allocator.write();
+
+            frame_allocator.free(memory);
+            frame_allocator.free_contiguous(buffer, DMA_BUFFER_FRAMES);
+            return None;
+        }
+
+        let command_list = memory + COMMAND_LIST_OFFSET;
+        let received_fis = memory + RECEIVED_FIS_OFFSET;
+
+        port.write(PORT_COMMAND_LIST_BASE, command_list as u32);
+        port.write(PORT_COMMAND_LIST_BASE_UPPER, (command_list >> 32) as u32);
+        port.write(PORT_FIS_BASE, received_fis as u32);
+        port.write(PORT_FIS_BASE_UPPER, (received_fis >> 32) as u32);
+        port.write(
+            PORT_INTERRUPT_ENABLE,
+            if uses_interrupts {
+                PORT_INTERRUPTS_COMPLETION
+            } else {
+                0
+            },
+        );
+
+        port.start();
+
+        return Some(port);
+    }
+
+    fn read(&self, register: u64) -> u32 {
+        return read_register(self.registers + register);
+    }
+
+    fn write(&self, register: u64, value: u32) {
+        write_register(self.registers + register, value);
+    }
+
+    fn memory_ptr(&self, offset: u64) -> *mut u8 {
+        return (self.memory + offset + paging::hhdm_offset()) as *mut u8;
+    }
+
+    fn buffer_ptr(&self) -> *mut u8 {
+        return (self.buffer + paging::hhdm_offset()) as *mut u8;
+    }
+
+    // The command list and FIS area may only be changed while the port isn't running them
+    fn stop(&self) -> Result<(), ()> {
+        let command = self.read(PORT_COMMAND);
+        self.write(PORT_COMMAND, command & !PORT_COMMAND_START);
+
+        wait_until(|| self.read(PORT_COMMAND) & PORT_COMMAND_LIST_RUNNING == 0)?;
+
+        let command = self.read(PORT_COMMAND);
+        self.write(PORT_COMMAND, command & !PORT_COMMAND_FIS_RECEIVE_ENABLE);
+
+        return wait_until(|| self.read(PORT_COMMAND) & PORT_COMMAND_FIS_RECEIVE_RUNNING == 0);
+    }
+
+    fn start(&self) {
+        // Both are cleared by writing ones to them
+        self.write(PORT_SATA_ERROR, u32::MAX);
+        self.write(PORT_INTERRUPT_STATUS, u32::MAX);
+
+        let command = self.read(PORT_COMMAND);
+        self.write(PORT_COMMAND, command | PORT_COMMAND_FIS_RECEIVE_ENABLE);
+
+        let command = self.read(PORT_COMMAND);
+        self.write(PORT_COMMAND, command | PORT_COMMAND_START);
+    }
+
+    // A failed command stops the port, it has to be restarted before it takes another
+    fn recover(&self) {
+        if self.stop().is_err() {
+            crate::log_error!("AHCI: Failed to stop a port after an error");
+            return;
+        }
+
+        self.start();
+    }
+
+    /// Sends `fis` to the drive through the first command slot and waits for it to finish. The
+    /// first `length` bytes of the bounce buffer are where the data goes to or comes from.
+    fn issue(
+        &self,
+        fis: RegisterHostToDeviceFIS,
+        length: usize,
+        direction: AHCIDirection,
+    ) -> Result<(), ()> {
+        if length > DMA_BUFFER_SIZE {
+            return Err(());
+        }
+
+        wait_until(|| {
+            self.read(PORT_TASK_FILE_DATA) & (TASK_FILE_BUSY | TASK_FILE_DATA_REQUEST) == 0
+        })
+        .map_err(|_| crate::log_error!("AHCI: Timed out waiting for the drive"))?;
+
+        let table = self.memory + COMMAND_TABLE_OFFSET;
+        let prdt_length = if length > 0 { 1 } else { 0 };
+
+        unsafe {
+            (self.memory_ptr(COMMAND_TABLE_OFFSET) as *mut RegisterHostToDeviceFIS)
+                .write_volatile(fis);
+
+            // One entry is enough, the bounce buffer is contiguous
+            (self.memory_ptr(COMMAND_TABLE_OFFSET + COMMAND_TABLE_PRDT_OFFSET)
+                as *mut PhysicalRegionDescriptor)
+                .write_volatile(PhysicalRegionDescriptor {
+                    address: self.buffer as u32,
+                    address_upper: (self.buffer >> 32) as u32,
+                    reserved: 0,
+                    byte_count: length.saturating_sub(1) as u32,
+                });
+
+            (self.memory_ptr(COMMAND_LIST_OFFSET) as *mut CommandHeader).write_volatile(
+                CommandHeader {
+                    flags: (size_of::<RegisterHostToDeviceFIS>() / size_of::<u32>()) as u16
+                        | match direction {
+                            AHCIDirection::Read => 0,
+                            AHCIDirection::Write => COMMAND_HEADER_WRITE,
+                        },
+                    prdt_length,
+                    prd_byte_count: 0,
+                    table_address: table as u32,
+                    table_address_upper: (table >> 32) as u32,
+                    reserved: [0; 4],
+                },
+            );
+        }
+
+        let interrupts = &PORT_INTERRUPTS[self.index];
+
+        interrupts.store(0, Ordering::SeqCst);
+        self.write(PORT_INTERRUPT_STATUS, u32::MAX);
+        self.write(PORT_COMMAND_ISSUE, 1);
+
+        // The interrupt can't get to us with interrupts disabled, like in a syscall, so then the
+        // port is polled. The handler can still run on another CPU and take the status first
+        let use_interrupts = self.uses_interrupts && crate::arch::interrupts_enabled();
+        let mut failed = false;
+
+        let completed = wait_until(|| {
+            let status = if use_interrupts {
+                interrupts.load(Ordering::SeqCst)
+            } else {
+                self.read(PORT_INTERRUPT_STATUS) | interrupts.load(Ordering::SeqCst)
+            };
+
+            if use_interrupts && status & PORT_INTERRUPTS_COMPLETION == 0 {
+                return false;
+            }
+
+            failed = status & PORT_INTERRUPT_TASK_FILE_ERROR != 0;
+
+            return failed || self.read(PORT_COMMAND_ISSUE) & 1 == 0;
+        });
+
+        if completed.is_err() {
+            crate::log_error!("AHCI: Timed out waiting for a command");
+            self.recover();
+            return Err(());
+        }
+
+        let task_file = self.read(PORT_TASK_FILE_DATA);
+
+        if failed || task_file & TASK_FILE_ERROR != 0 {
+            crate::log_error!(
+                "AHCI: Command {:#X} failed, status: {:#X}, error: {:#X}",
+                fis.command,
+                task_file & 0xFF,
+                (task_file >> 8) & 0xFF
+            );
+            self.recover();
+            return Err(());
+        }
+
+        return Ok(());
+    }
+}
+
+struct AHCIDrive {
+    // Commands go through one slot and one bounce buffer, so only one can run at a time
+    port: Mutex<AHCIPort>,
+    sector_count: u64,
+}
+
+impl AHCIDrive {
+    fn new(port: AHCIPort) -> Result<Self, ()> {
+        port.issue(
+            RegisterHostToDeviceFIS::new(AHCICommand::Identify, 0, 0),
+            AHCI_SECTOR_SIZE,
+            AHCIDirection::Read,
+        )?;
+
+        let mut identify_data = [0u8; AHCI_SECTOR_SIZE];
+
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                port.buffer_ptr(),
+                identify_data.as_mut_ptr(),
+                AHCI_SECTOR_SIZE,
+            );
+        }
+
+        let command_sets = u16::from_le_bytes([identify_data[166], identify_data[167]]);
+
+        // Words 100 to 103 have the size of drives that can do LBA48, which SATA drives all can,
+        // words 60 and 61 that of older ones
+        let sector_count = if command_sets & (1 << 10) != 0 {
+            u64::from_le_bytes(identify_data[200..208].try_into().unwrap())
+        } else {
+            u32::from_le_bytes(identify_data[120..124].try_into().unwrap()) as u64
+        };
+
+        return Ok(Self {
+            port: Mutex::new(port),
+            sector_count,
+        });
+    }
+
+    // Commands can take a while, so other tasks get the CPU until the one running is done
+    fn lock(&self) -> MutexGuard<'_, AHCIPort> {
+        loop {
+            if let Some(guard) = self.port.try_lock() {
+                return guard;
+            }
+
+            if scheduler::is_running() {
+                scheduler::yield_now();
+            } else {
+                #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+                crate::arch::pause();
+            }
+        }
+    }
+
+    fn read_chunk(&self, sector: u64, sector_count: usize) -> Result<Vec<u8>, ()> {
+        let length = sector_count * AHCI_SECTOR_SIZE;
+
+        let mut port = self.lock();
+        let port = port.write();
+
+        port.issue(
+            RegisterHostToDeviceFIS::new(AHCICommand::ReadDMAExt, sector, sector_count as u16),
+            length,
+            AHCIDirection::Read,
+        )?;
+
+        let mut data = Vec::with_capacity(length);
+
+        unsafe {
+            core::ptr::copy_nonoverlapping(port.buffer_ptr(), data.as_mut_ptr(), length);
+            data.set_len(length);
+        }
+
+        return Ok(data);
+    }
+
+    fn write_chunk(&self, sector: u64, data: &[u8]) -> Result<(), ()> {
+        let mut port = self.lock();
+        let port = port.write();
+
+        unsafe {
+            core::ptr::copy_nonoverlapping(data.as_ptr(), port.buffer_ptr(), data.len());
+        }
+
+        return port.issue(
+            RegisterHostToDeviceFIS::new(
+                AHCICommand::WriteDMAExt,
+                sector,
+                (data.len() / AHCI_SECTOR_SIZE) as u16,
+            ),
+            data.len(),
+            AHCIDirection::Write,
+        );
+    }
+
+    fn flush(&self) -> Result<(), ()> {
+        return self.lock().write().issue(
+            RegisterHostToDeviceFIS::new(AHCICommand::CacheFlushExt, 0, 0),
+            0,
+            AHCIDirection::Read,
+        );
+    }
+}
+
+impl BlockDevice for AHCIDrive {
+    fn read(&self, sector: u64, sector_count: usize) -> Result<Arc<[u8]>, ()> {
+        if (sector + sector_count as u64) > self.sector_count {
+            return Err(());
+        }
+
+        let mut buffer = Vec::with_capacity(sector_count * AHCI_SECTOR_SIZE);
+
+        for chunk_start in (0..sector_count).step_by(SECTORS_PER_COMMAND) {
+            let chunk_count = core::cmp::min(SECTORS_PER_COMMAND, sector_count - chunk_start);
+
+            buffer.extend_from_slice(&self.read_chunk(sector + chunk_start as u64, chunk_count)?);
+        }
+
+        return Ok(Arc::from(buffer));
+    }
+
+    fn sector_count(&self) -> u64 {
+        return self.sector_count;
+    }
+
+    fn sector_size(&self) -> usize {
+        return AHCI_SECTOR_SIZE;
+    }
+
+    fn is_read_only(&self) -> bool {
+        return false;
+    }
+
+    fn write(&self, sector: u64, data: &[u8]) -> Result<(), ()> {
+        if data.len() % AHCI_SECTOR_SIZE != 0 {
+            return Err(());
+        }
+
+        let sector_count = (data.len() / AHCI_SECTOR_SIZE) as u64;
+
+        if sector + sector_count > self.sector_count {
+            return Err(());
+        }
+
+        for (i, chunk) in data.chunks(DMA_BUFFER_SIZE).enumerate() {
+            let chunk_sector = sector + (i * SECTORS_PER_COMMAND) as u64;
+
+            self.write_chunk(chunk_sector, chunk)?;
+        }
+
+        return self.flush();
+    }
+}
@@ -2,6 +2,9 @@ use alloc::sync::Arc;
 
 pub trait BlockDevice {
     fn sector_count(&self) -> u64;
+    fn sector_size(&self) -> usize;
+    // Writes always fail on these, like CD-ROMs
+    fn is_read_only(&self) -> bool;
     fn read(&self, sector: u64, sector_count: usize) -> Result<Arc<[u8]>, ()>;
     fn write(&self, sector: u64, data: &[u8]) -> Result<(), ()>;
 }
@@ -1,19 +1,67 @@
-use core::mem::size_of;
+use core::{
+    mem::size_of,
+    sync::atomic::{AtomicBool, Ordering},
+};
 
 use alloc::{sync::Arc, vec::Vec};
 
 use crate::{
-    arch::io::{inb, insw, inw, outb},
-    drivers::{
-        fs::fat,
-        storage::drive::{GPTBlock, GPTPartitionEntry},
+    arch::{
+        interrupts::{self, InterruptIndex},
+        io::{inb, insw, inw, outb, outl, outw},
+        paging,
+    },
+    drivers::pci::{
+        driver::{PciDriver, PciMatch},
+        PciDevice,
+    },
+    libs::mutex::{Mutex, MutexGuard},
+    sys::{
+        frame_allocator::{FRAME_ALLOCATOR, FRAME_SIZE},
+        scheduler,
+        time::Deadline,
     },
-    libs::mutex::Mutex,
 };
 
 use super::drive::BlockDevice;
 
 const ATA_SECTOR_SIZE: usize = 512;
+const ATAPI_SECTOR_SIZE: usize = 2048;
+// ATAPI commands are SCSI command blocks, padded to 12 bytes
+const ATAPI_PACKET_SIZE: usize = 12;
+// A drive that had its disc changed fails the first few commands with unit attention
+const ATAPI_READY_ATTEMPTS: usize = 3;
+// A sector count of 0 means 256 to LBA28 commands, so that's the most one command can move
+const MAX_SECTORS_PER_COMMAND: usize = 256;
+// How long a drive gets to finish a command before we give up on it
+const DRIVE_TIMEOUT_MS: u64 = 5000;
+
+// Legacy ports of the two channels, used unless a channel is in native mode
+const PRIMARY_IO_BASE: u16 = 0x1F0;
+const PRIMARY_CONTROL_BASE: u16 = 0x3F6;
+const SECONDARY_IO_BASE: u16 = 0x170;
+const SECONDARY_CONTROL_BASE: u16 = 0x376;
+
+// Bus master IDE registers, relative to the channel's part of BAR4
+const BUS_MASTER_COMMAND: u16 = 0x00;
+const BUS_MASTER_STATUS: u16 = 0x02;
+const BUS_MASTER_PRDT: u16 = 0x04;
+// The secondary channel's registers come right after the primary's
+const BUS_MASTER_SECONDARY_OFFSET: u16 = 0x08;
+
+const BUS_MASTER_START: u8 = 1 << 0;
+// Set for transfers from the drive, which the controller writes to memory
+const BUS_MASTER_READ: u8 = 1 << 3;
+// Both are cleared by writing a one to them
+const BUS_MASTER_STATUS_ERROR: u8 = 1 << 1;
+const BUS_MASTER_STATUS_INTERRUPT: u8 = 1 << 2;
+
+const PRD_END_OF_TABLE: u16 = 1 << 15;
+
+// Every channel bounces DMA through a buffer this big, which is also the most one DMA command
+// moves
+const DMA_BUFFER_FRAMES: usize = 16;
+const DMA_BUFFER_SIZE: usize = DMA_BUFFER_FRAMES * FRAME_SIZE as usize;
 
 #[repr(u8)]
 #[derive(Clone, Copy, Debug)]
@@ -81,6 +129,13 @@ enum ATADriveCommand {
     Identify = 0xEC,
 }
 
+#[repr(u8)]
+enum ATAPICommand {
+    TestUnitReady = 0x00,
+    ReadCapacity = 0x25,
+    Read = 0xA8,
+}
+
 #[repr(u8)]
 enum ATADriveIdentifyResponse {
     DeviceType = 0x00,
@@ -97,6 +152,7 @@ enum ATADriveIdentifyResponse {
 }
 
 #[repr(u16)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 enum IDEDriveType {
     Pata,
     PataPi,
@@ -150,12 +206,14 @@ enum ATADriveControlRegister {
 }
 
 #[repr(u8)]
+#[derive(Clone, Copy, Debug)]
 enum ATADriveChannels {
     Primary = 0x00,
     Secondary = 0x01,
 }
 
 #[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 enum ATADriveDirection {
     Read = 0x00,
     Write = 0x01,
@@ -163,47 +221,228 @@ enum ATADriveDirection {
 
 static DRIVE_ID: Mutex<[[u16; 256]; 2]> = Mutex::new([[0u16; 256]; 2]);
 
-pub fn init() {
-    // for pci_device in super::pci::PCI_DEVICES.lock().read() {
-    //     if pci_device.class_code != 0x01 || pci_device.subclass_code != 0x01 {
-    //         continue;
-    //     }
-
-    //     let (bar0, bar1, bar2, bar3, bar4, _) =
-    //         super::pci::get_pci_bar_addresses(pci_device.bus, pci_device.device, pci_device.func);
-
-    //     crate::println!(
-    //         "bar0: {} bar1: {} bar2: {} bar3: {} bar4: {}",
-    //         bar0,
-    //         bar1,
-    //         bar2,
-    //         bar3,
-    //         bar4
-    //     );
-
-    //     ide_initialize(bar0, bar1, bar2, bar3, bar4);
-    // }
-    // crate::println!("{:?}", ata_identify_drive(0xB0));
-    ide_initialize(0x1F0, 0x3F6, 0x170, 0x376, 0x000);
+pub static PCI_DRIVER: PciDriver = PciDriver {
+    name: "ide",
+    matches: &[PciMatch::Class {
+        class_code: 0x01,
+        subclass_code: 0x01,
+        prog_if: None,
+    }],
+    probe,
+};
+
+// Set by a channel's interrupt handler, cleared before every command that waits for it
+static INTERRUPT_RECEIVED: [AtomicBool; 2] = [AtomicBool::new(false), AtomicBool::new(false)];
+
+fn probe(device: &PciDevice) -> Result<(), ()> {
+    // Compatibility mode channels have no BARs, their ports are fixed
+    let io_port = |index: usize| device.bars[index].and_then(|bar| bar.io_port());
+
+    // Bits 0 and 2 of the programming interface are set when a channel is in native mode and
+    // uses its BARs instead of the legacy ports. Native channels raise a PCI interrupt instead of
+    // IRQ 14 or 15, which we leave masked and poll the controller instead
+    let primary_native = device.prog_if & 0x01 != 0;
+    let secondary_native = device.prog_if & 0x04 != 0;
+
+    // Bit 7 is set when the controller can do bus master DMA, through the I/O ports in BAR4
+    let bus_master = match io_port(4) {
+        Some(port) if device.prog_if & 0x80 != 0 => {
+            device.enable_bus_mastering();
+            Some(port)
+        }
+        _ => None,
+    };
+
+    let primary = if primary_native {
+        ATABus::new(
+            io_port(0).ok_or(())?,
+            io_port(1).ok_or(())?,
+            ATADriveChannels::Primary,
+            false,
+            bus_master,
+        )
+    } else {
+        interrupts::set_irq_handler(InterruptIndex::PrimaryIde, primary_interrupt_handler as u64);
+
+        ATABus::new(
+            PRIMARY_IO_BASE,
+            PRIMARY_CONTROL_BASE,
+            ATADriveChannels::Primary,
+            true,
+            bus_master,
+        )
+    };
+
+    let secondary = if secondary_native {
+        ATABus::new(
+            io_port(2).ok_or(())?,
+            io_port(3).ok_or(())?,
+            ATADriveChannels::Secondary,
+            false,
+            bus_master.map(|port| port + BUS_MASTER_SECONDARY_OFFSET),
+        )
+    } else {
+        interrupts::set_irq_handler(
+            InterruptIndex::SecondaryIde,
+            secondary_interrupt_handler as u64,
+        );
+
+        ATABus::new(
+            SECONDARY_IO_BASE,
+            SECONDARY_CONTROL_BASE,
+            ATADriveChannels::Secondary,
+            true,
+            bus_master.map(|port| port + BUS_MASTER_SECONDARY_OFFSET),
+        )
+    };
+
+    ide_initialize(&[primary, secondary]);
+
+    return Ok(());
+}
+
+// Reading the status register makes the drive let go of its interrupt line
+extern "x86-interrupt" fn primary_interrupt_handler() {
+    inb(PRIMARY_IO_BASE + ATADriveDataRegister::CommandAndStatus as u16);
+    INTERRUPT_RECEIVED[ATADriveChannels::Primary as usize].store(true, Ordering::SeqCst);
+
+    interrupts::end_of_interrupt(InterruptIndex::PrimaryIde);
+}
+
+extern "x86-interrupt" fn secondary_interrupt_handler() {
+    inb(SECONDARY_IO_BASE + ATADriveDataRegister::CommandAndStatus as u16);
+    INTERRUPT_RECEIVED[ATADriveChannels::Secondary as usize].store(true, Ordering::SeqCst);
+
+    interrupts::end_of_interrupt(InterruptIndex::SecondaryIde);
+}
+
+// An entry of the physical region descriptor table, which tells the controller where in memory a
+// DMA transfer goes
+#[repr(C, packed)]
+struct PhysicalRegionDescriptor {
+    address: u32,
+    // 0 means 64 KiB
+    byte_count: u16,
+    flags: u16,
+}
+
+#[derive(Debug)]
+struct BusMaster {
+    port: u16,
+    // Physical addresses, both below 4 GiB since the controller only takes 32 bit ones
+    prdt: u64,
+    buffer: u64,
+}
+
+impl BusMaster {
+    fn new(port: u16) -> Option<Self> {
+        let mut frame_allocator = FRAME_ALLOCATOR.lock();
+        let frame_allocator = frame_allocator.write();
+
+        let prdt = frame_allocator.allocate()?;
+
+        let buffer = match frame_allocator.allocate_contiguous(DMA_BUFFER_FRAMES) {
+            Some(buffer) => buffer,
+            None => {
+                frame_allocator.free(prdt);
+                return None;
+            }
+        };
+
+        if prdt + FRAME_SIZE > 1 << 32 || buffer + DMA_BUFFER_SIZE as u64 > 1 << 32 {
+            frame_allocator.free(prdt);
+            frame_allocator.free_contiguous(buffer, DMA_BUFFER_FRAMES);
+            return None;
+        }
+
+        return Some(Self { port, prdt, buffer });
+    }
+
+    fn buffer_ptr(&self) -> *mut u8 {
+        return (self.buffer + paging::hhdm_offset()) as *mut u8;
+    }
+
+    // Describes the first `length` bytes of the buffer, no entry may cross a 64 KiB boundary
+    fn fill_prdt(&self, length: usize) {
+        let entries = (self.prdt + paging::hhdm_offset()) as *mut PhysicalRegionDescriptor;
+
+        let end = self.buffer + length as u64;
+        let mut address = self.buffer;
+        let mut i = 0;
+
+        while address < end {
+            let entry_end = core::cmp::min(end, (address | 0xFFFF) + 1);
+
+            unsafe {
+                entries.add(i).write_volatile(PhysicalRegionDescriptor {
+                    address: address as u32,
+                    byte_count: (entry_end - address) as u16,
+                    flags: if entry_end == end {
+                        PRD_END_OF_TABLE
+                    } else {
+                        0
+                    },
+                });
+            }
+
+            address = entry_end;
+            i += 1;
+        }
+    }
 }
 
 #[derive(Debug)]
 struct ATABus {
     io_bar: u16,
     control_bar: u16,
+    channel: ATADriveChannels,
+    // Whether the channel's IRQ has a handler, DMA completion is polled from the controller if not
+    has_irq: bool,
+    dma: Option<BusMaster>,
+    // Held for the whole of a command, both drives on the bus go through the same registers, PRDT
+    // and bounce buffer
+    command_lock: Mutex<()>,
 }
 
 impl ATABus {
-    fn new(io_bar: u16, control_bar: u16) -> Arc<Self> {
+    fn new(
+        io_bar: u16,
+        control_bar: u16,
+        channel: ATADriveChannels,
+        has_irq: bool,
+        bus_master: Option<u16>,
+    ) -> Arc<Self> {
         let io_bar = io_bar & 0xFFFC;
         let control_bar = control_bar & 0xFFFC;
 
+        let dma = bus_master.and_then(|port| BusMaster::new(port & 0xFFFC));
+
         return Arc::from(Self {
             io_bar,
             control_bar,
+            channel,
+            has_irq,
+            dma,
+            command_lock: Mutex::new(()),
         });
     }
 
+    // Commands can take a while, so other tasks get the CPU until the other drive's is done
+    fn lock(&self) -> MutexGuard<'_, ()> {
+        loop {
+            if let Some(guard) = self.command_lock.try_lock() {
+                return guard;
+            }
+
+            if scheduler::is_running() {
+                scheduler::yield_now();
+            } else {
+                #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+                crate::arch::pause();
+            }
+        }
+    }
+
     pub fn select(&self, drive: u8) {
         outb(
             self.io_bar + ATADriveDataRegister::DeviceSelect as u16,
@@ -228,7 +467,14 @@ impl ATABus {
     }
 
     fn wait_for_drive_ready(&self) -> Result<(), ()> {
+        let mut deadline = Deadline::after_ms(DRIVE_TIMEOUT_MS);
+
         loop {
+            if deadline.has_passed() {
+                crate::log_error!("IDE: Timed out waiting for the drive");
+                return Err(());
+            }
+
             #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
             crate::arch::pause();
 
@@ -248,14 +494,27 @@ impl ATABus {
         }
     }
 
-    pub fn await_busy(&self) {
+    pub fn await_busy(&self) -> Result<(), ()> {
+        let mut deadline = Deadline::after_ms(DRIVE_TIMEOUT_MS);
+
         while self.status() == ATADriveStatus::Busy {
+            if deadline.has_passed() {
+                crate::log_error!("IDE: Timed out waiting for the drive");
+                return Err(());
+            }
+
             #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
             crate::arch::pause();
         }
+
+        return Ok(());
     }
 
-    pub fn identify(&self, drive: ATADriveType) -> Result<Arc<[u8; ATA_SECTOR_SIZE]>, ()> {
+    /// Finds out what kind of drive is there, if any, and returns its identify data.
+    pub fn identify(
+        &self,
+        drive: ATADriveType,
+    ) -> Result<(IDEDriveType, Arc<[u8; ATA_SECTOR_SIZE]>), ()> {
         self.select(0xA0 | drive as u8);
 
         outb(self.io_bar + ATADriveDataRegister::SectorCount0 as u16, 0);
@@ -271,21 +530,28 @@ impl ATABus {
             return Err(());
         }
 
+        // Anything but an ATA drive puts its signature in the LBA registers, and may do it before
+        // it stops being busy
         while self.status() == ATADriveStatus::Busy {
             let lba_mid = inb(self.io_bar + ATADriveDataRegister::LBA1 as u16);
             let lba_high = inb(self.io_bar + ATADriveDataRegister::LBA2 as u16);
 
             if lba_mid != 0 || lba_high != 0 {
-                return Err(());
+                break;
             }
         }
 
         let lba_mid = inb(self.io_bar + ATADriveDataRegister::LBA1 as u16);
         let lba_high = inb(self.io_bar + ATADriveDataRegister::LBA2 as u16);
 
-        match IDEDriveType::from_lba(lba_mid, lba_high) {
-            Some(IDEDriveType::Pata) => {
-                // The only type we support, for now :tm:
+        let drive_type = match IDEDriveType::from_lba(lba_mid, lba_high) {
+            Some(IDEDriveType::Pata) => IDEDriveType::Pata,
+            // ATAPI drives abort the ATA identify command and need their own
+            Some(drive_type @ (IDEDriveType::PataPi | IDEDriveType::SataPi)) => {
+                self.await_busy()?;
+                self.send_command(ATADriveCommand::IdentifyPacket);
+
+                drive_type
             }
             _ => return Err(()),
         };
@@ -303,18 +569,80 @@ impl ATABus {
             };
         }
 
-        return Ok(Arc::from(buffer));
+        return Ok((drive_type, Arc::from(buffer)));
     }
 
-    pub fn read(
+    /// Sends an ATAPI command packet and reads the `length` bytes of data it returns, through PIO.
+    /// Fails quietly when the drive reports an error, like when there's no disc.
+    fn packet(
         &self,
         drive: ATADriveType,
-        sector: u64,
-        sector_count: usize,
-    ) -> Result<Arc<[u8]>, ()> {
-        self.await_busy();
+        packet: &[u8; ATAPI_PACKET_SIZE],
+        length: usize,
+    ) -> Result<Vec<u8>, ()> {
+        self.await_busy()?;
+        self.select(0xA0 | drive as u8);
+
+        // No DMA, and at most a sector every time the drive has data for us
+        outb(
+            self.io_bar + ATADriveDataRegister::ErrorAndFeatures as u16,
+            0,
+        );
+        outb(
+            self.io_bar + ATADriveDataRegister::LBA1 as u16,
+            ATAPI_SECTOR_SIZE as u8,
+        );
+        outb(
+            self.io_bar + ATADriveDataRegister::LBA2 as u16,
+            (ATAPI_SECTOR_SIZE >> 8) as u8,
+        );
+
+        self.send_command(ATADriveCommand::Packet);
+        self.wait_for_drive_ready()?;
+
+        for word in packet.chunks_exact(size_of::<u16>()) {
+            outw(
+                self.io_bar + ATADriveDataRegister::Data as u16,
+                u16::from_le_bytes([word[0], word[1]]),
+            );
+        }
+
+        let mut data = Vec::with_capacity(length);
+
+        while data.len() < length {
+            self.wait_for_drive_ready()?;
+
+            // How much the drive has for us this time
+            let byte_count = inb(self.io_bar + ATADriveDataRegister::LBA1 as u16) as usize
+                | (inb(self.io_bar + ATADriveDataRegister::LBA2 as u16) as usize) << 8;
+
+            if byte_count == 0 {
+                return Err(());
+            }
+
+            for _ in 0..byte_count.div_ceil(size_of::<u16>()) {
+                let word = inw(self.io_bar + ATADriveDataRegister::Data as u16);
+                data.extend_from_slice(&word.to_le_bytes());
+            }
+        }
+
+        self.await_busy()?;
+
+        let status = self.status();
+
+        if status == ATADriveStatus::Error || status == ATADriveStatus::WriteFault {
+            return Err(());
+        }
+
+        data.truncate(length);
+
+        return Ok(data);
+    }
 
-        let using_lba48 = sector >= (1 << 28) - 1;
+    // Selects the drive and fills in the address and sector count of a transfer, returns whether
+    // it needs the LBA48 version of the command
+    fn setup_transfer(&self, drive: ATADriveType, sector: u64, sector_count: usize) -> bool {
+        let using_lba48 = sector + sector_count as u64 >= 1 << 28;
 
         if using_lba48 {
             self.select(0x40 | (drive as u8));
@@ -336,46 +664,60 @@ impl ATABus {
                 self.io_bar + ATADriveDataRegister::LBA2 as u16,
                 (sector >> 40) as u8,
             );
-
-            // Low bytes
-            outb(
-                self.io_bar + ATADriveDataRegister::SectorCount0 as u16,
-                sector_count as u8,
-            );
-            outb(
-                self.io_bar + ATADriveDataRegister::LBA0 as u16,
-                (sector >> 16) as u8,
-            );
-            outb(
-                self.io_bar + ATADriveDataRegister::LBA1 as u16,
-                (sector >> 8) as u8,
-            );
-            outb(
-                self.io_bar + ATADriveDataRegister::LBA2 as u16,
-                sector as u8,
-            );
-
-            self.send_command(ATADriveCommand::ReadPIOExt);
         } else {
             self.select(0xE0 | (drive as u8) | ((sector >> 24) as u8 & 0x0F));
+        }
 
-            outb(
-                self.io_bar + ATADriveDataRegister::SectorCount0 as u16,
-                sector_count as u8,
-            );
-            outb(
-                self.io_bar + ATADriveDataRegister::LBA0 as u16,
-                sector as u8,
-            );
-            outb(
-                self.io_bar + ATADriveDataRegister::LBA1 as u16,
-                (sector >> 8) as u8,
-            );
-            outb(
-                self.io_bar + ATADriveDataRegister::LBA2 as u16,
-                (sector >> 16) as u8,
+        // Low bytes, and all of them for LBA28
+        outb(
+            self.io_bar + ATADriveDataRegister::SectorCount0 as u16,
+            sector_count as u8,
+        );
+        outb(
+            self.io_bar + ATADriveDataRegister::LBA0 as u16,
+            sector as u8,
+        );
+        outb(
+            self.io_bar + ATADriveDataRegister::LBA1 as u16,
+            (sector >> 8) as u8,
+        );
+        outb(
+            self.io_bar + ATADriveDataRegister::LBA2 as u16,
+            (sector >> 16) as u8,
+        );
+
+        return using_lba48;
+    }
+
+    // Logs and fails if the last command ended with an error
+    fn check_error(&self) -> Result<(), ()> {
+        let status = self.status();
+
+        if status == ATADriveStatus::Error || status == ATADriveStatus::WriteFault {
+            let error = inb(self.io_bar + ATADriveDataRegister::ErrorAndFeatures as u16);
+
+            crate::log_error!(
+                "IDE: Command failed with status {:#04X} and error {:#04X}",
+                status,
+                error
             );
+            return Err(());
+        }
+
+        return Ok(());
+    }
 
+    pub fn read(
+        &self,
+        drive: ATADriveType,
+        sector: u64,
+        sector_count: usize,
+    ) -> Result<Arc<[u8]>, ()> {
+        self.await_busy()?;
+
+        if self.setup_transfer(drive, sector, sector_count) {
+            self.send_command(ATADriveCommand::ReadPIOExt);
+        } else {
             self.send_command(ATADriveCommand::ReadPIO);
         }
 
@@ -411,10 +753,202 @@ impl ATABus {
         return Ok(arc_data);
     }
 
+    /// Writes whole sectors from `data` and makes sure they reach the disk before returning.
+    pub fn write(&self, drive: ATADriveType, sector: u64, data: &[u8]) -> Result<(), ()> {
+        let sector_count = data.len() / ATA_SECTOR_SIZE;
+
+        self.await_busy()?;
+
+        let using_lba48 = self.setup_transfer(drive, sector, sector_count);
+
+        if using_lba48 {
+            self.send_command(ATADriveCommand::WritePIOExt);
+        } else {
+            self.send_command(ATADriveCommand::WritePIO);
+        }
+
+        for sector_data in data.chunks_exact(ATA_SECTOR_SIZE) {
+            self.wait_for_drive_ready().map_err(|_| {
+                let _ = self.check_error();
+                crate::log_error!("Error writing IDE Device");
+            })?;
+
+            // Words go out one at a time, some drives can't keep up with `rep outsw`
+            for word in sector_data.chunks_exact(size_of::<u16>()) {
+                outw(
+                    self.io_bar + ATADriveDataRegister::Data as u16,
+                    u16::from_le_bytes([word[0], word[1]]),
+                );
+            }
+        }
+
+        self.await_busy()?;
+        self.check_error()?;
+
+        return self.flush(using_lba48);
+    }
+
+    // The drive may only have written data in its cache until it's flushed
+    fn flush(&self, using_lba48: bool) -> Result<(), ()> {
+        if using_lba48 {
+            self.send_command(ATADriveCommand::CacheFlushExt);
+        } else {
+            self.send_command(ATADriveCommand::CacheFlush);
+        }
+
+        self.await_busy()?;
+
+        return self.check_error();
+    }
+
+    /// Like `read`, but the controller moves the data while we wait for the drive's interrupt.
+    pub fn read_dma(
+        &self,
+        drive: ATADriveType,
+        sector: u64,
+        sector_count: usize,
+    ) -> Result<Arc<[u8]>, ()> {
+        let dma = self.dma.as_ref().ok_or(())?;
+
+        self.dma_transfer(dma, drive, sector, sector_count, ATADriveDirection::Read)?;
+
+        let buffer = unsafe {
+            core::slice::from_raw_parts(dma.buffer_ptr(), sector_count * ATA_SECTOR_SIZE)
+        };
+
+        return Ok(Arc::from(buffer));
+    }
+
+    /// Like `write`, but through DMA.
+    pub fn write_dma(&self, drive: ATADriveType, sector: u64, data: &[u8]) -> Result<(), ()> {
+        let dma = self.dma.as_ref().ok_or(())?;
+        let sector_count = data.len() / ATA_SECTOR_SIZE;
+
+        // Checked again by the transfer, but the data has to fit before it's copied in
+        if sector_count == 0 || data.len() > DMA_BUFFER_SIZE {
+            return Err(());
+        }
+
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                data.as_ptr(),
+                dma.buffer_ptr(),
+                sector_count * ATA_SECTOR_SIZE,
+            )
+        };
+
+        let using_lba48 =
+            self.dma_transfer(dma, drive, sector, sector_count, ATADriveDirection::Write)?;
+
+        return self.flush(using_lba48);
+    }
+
+    // Moves sectors between the drive and the start of the bounce buffer, returns whether the
+    // LBA48 command was used
+    fn dma_transfer(
+        &self,
+        dma: &BusMaster,
+        drive: ATADriveType,
+        sector: u64,
+        sector_count: usize,
+        direction: ATADriveDirection,
+    ) -> Result<bool, ()> {
+        if sector_count == 0 || sector_count * ATA_SECTOR_SIZE > DMA_BUFFER_SIZE {
+            return Err(());
+        }
+
+        let direction_bit = match direction {
+            ATADriveDirection::Read => BUS_MASTER_READ,
+            ATADriveDirection::Write => 0,
+        };
+
+        dma.fill_prdt(sector_count * ATA_SECTOR_SIZE);
+
+        outb(dma.port + BUS_MASTER_COMMAND, 0);
+        outl(dma.port + BUS_MASTER_PRDT, dma.prdt as u32);
+        outb(
+            dma.port + BUS_MASTER_STATUS,
+            BUS_MASTER_STATUS_ERROR | BUS_MASTER_STATUS_INTERRUPT,
+        );
+        outb(dma.port + BUS_MASTER_COMMAND, direction_bit);
+
+        self.await_busy()?;
+
+        INTERRUPT_RECEIVED[self.channel as usize].store(false, Ordering::SeqCst);
+
+        let using_lba48 = self.setup_transfer(drive, sector, sector_count);
+
+        self.send_command(match (direction, using_lba48) {
+            (ATADriveDirection::Read, false) => ATADriveCommand::ReadDMA,
+            (ATADriveDirection::Read, true) => ATADriveCommand::ReadDMAExt,
+            (ATADriveDirection::Write, false) => ATADriveCommand::WriteDMA,
+            (ATADriveDirection::Write, true) => ATADriveCommand::WriteDMAExt,
+        });
+
+        outb(
+            dma.port + BUS_MASTER_COMMAND,
+            direction_bit | BUS_MASTER_START,
+        );
+
+        let completed = self.wait_for_interrupt(dma);
+
+        outb(dma.port + BUS_MASTER_COMMAND, direction_bit);
+
+        let bus_master_status = inb(dma.port + BUS_MASTER_STATUS);
+        outb(
+            dma.port + BUS_MASTER_STATUS,
+            BUS_MASTER_STATUS_ERROR | BUS_MASTER_STATUS_INTERRUPT,
+        );
+
+        completed?;
+
+        if bus_master_status & BUS_MASTER_STATUS_ERROR != 0 {
+            crate::log_error!("IDE: The controller failed a DMA transfer");
+            return Err(());
+        }
+
+        self.check_error()?;
+
+        return Ok(using_lba48);
+    }
+
+    fn wait_for_interrupt(&self, dma: &BusMaster) -> Result<(), ()> {
+        let mut deadline = Deadline::after_ms(DRIVE_TIMEOUT_MS);
+
+        // The IRQ can't get to us with interrupts disabled, like in a syscall, but the controller
+        // sets the same bit either way
+        let use_irq = self.has_irq && crate::arch::interrupts_enabled();
+
+        loop {
+            let interrupted = if use_irq {
+                INTERRUPT_RECEIVED[self.channel as usize].load(Ordering::SeqCst)
+            } else {
+                inb(dma.port + BUS_MASTER_STATUS) & BUS_MASTER_STATUS_INTERRUPT != 0
+            };
+
+            if interrupted {
+                return Ok(());
+            }
+
+            if deadline.has_passed() {
+                crate::log_error!("IDE: Timed out waiting for a DMA transfer");
+                return Err(());
+            }
+
+            // Other tasks can have the CPU until the drive is done
+            if scheduler::is_running() {
+                scheduler::yield_now();
+            } else {
+                #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+                crate::arch::pause();
+            }
+        }
+    }
+
     fn software_reset(&self) {
         // Procedure is (1) set the SRST bit, (2) wait 5us, (3) clear the SRST bit.
         outb(
-            self.io_bar + ATADriveControlRegister::ControlAndAltStatus as u16,
+            self.control_bar + ATADriveControlRegister::ControlAndAltStatus as u16,
             0x04,
         );
         // We wait 5us by reading the status port 50 times (each read takes 100ns)
@@ -423,7 +957,7 @@ impl ATABus {
         }
 
         outb(
-            self.io_bar + ATADriveControlRegister::ControlAndAltStatus as u16,
+            self.control_bar + ATADriveControlRegister::ControlAndAltStatus as u16,
             0x00,
         );
     }
@@ -434,11 +968,20 @@ struct ATADrive {
     bus: Arc<ATABus>,
     identify_data: Arc<[u8; ATA_SECTOR_SIZE]>,
     drive_type: ATADriveType,
+    // Both the drive and the controller have to support it
+    uses_dma: bool,
 }
 
 impl ATADrive {
     pub fn new(bus: Arc<ATABus>, drive: ATADriveType) -> Result<Self, ()> {
-        let identify_data = bus.identify(drive)?;
+        let (drive_type, identify_data) = {
+            let _command = bus.lock();
+            bus.identify(drive)?
+        };
+
+        if drive_type != IDEDriveType::Pata {
+            return Err(());
+        }
 
         let capabilities_bytes = &identify_data[98..100];
 
@@ -452,12 +995,56 @@ impl ATADrive {
             return Err(());
         }
 
+        let uses_dma = capabilities & 0x100 != 0 && bus.dma.is_some();
+
         return Ok(Self {
             bus,
             identify_data,
             drive_type: drive,
+            uses_dma,
         });
     }
+
+    fn sectors_per_command(&self) -> usize {
+        if self.uses_dma {
+            return DMA_BUFFER_SIZE / ATA_SECTOR_SIZE;
+        }
+
+        return MAX_SECTORS_PER_COMMAND;
+    }
+
+    fn read_chunk(&self, sector: u64, sector_count: usize) -> Result<Arc<[u8]>, ()> {
+        // Until the data is out of the bounce buffer, and through the PIO retry
+        let _command = self.bus.lock();
+
+        if self.uses_dma {
+            match self.bus.read_dma(self.drive_type, sector, sector_count) {
+                Ok(data) => return Ok(data),
+                Err(()) => {
+                    crate::log_error!("IDE: DMA read failed, retrying with PIO");
+                    self.bus.software_reset();
+                }
+            }
+        }
+
+        return self.bus.read(self.drive_type, sector, sector_count);
+    }
+
+    fn write_chunk(&self, sector: u64, data: &[u8]) -> Result<(), ()> {
+        let _command = self.bus.lock();
+
+        if self.uses_dma {
+            match self.bus.write_dma(self.drive_type, sector, data) {
+                Ok(()) => return Ok(()),
+                Err(()) => {
+                    crate::log_error!("IDE: DMA write failed, retrying with PIO");
+                    self.bus.software_reset();
+                }
+            }
+        }
+
+        return self.bus.write(self.drive_type, sector, data);
+    }
 }
 
 impl BlockDevice for ATADrive {
@@ -466,148 +1053,226 @@ impl BlockDevice for ATADrive {
             return Err(());
         }
 
-        self.bus.software_reset();
+        // The drive would take a count of 0 to mean as many sectors as a command can move
+        if sector_count == 0 {
+            return Ok(Arc::from(Vec::new()));
+        }
 
-        return self.bus.read(self.drive_type, sector, sector_count);
+        let sectors_per_command = self.sectors_per_command();
+
+        if sector_count <= sectors_per_command {
+            return self.read_chunk(sector, sector_count);
+        }
+
+        let mut buffer = Vec::with_capacity(sector_count * ATA_SECTOR_SIZE);
+
+        for chunk_start in (0..sector_count).step_by(sectors_per_command) {
+            let chunk_count = core::cmp::min(sectors_per_command, sector_count - chunk_start);
+
+            buffer.extend_from_slice(&self.read_chunk(sector + chunk_start as u64, chunk_count)?);
+        }
+
+        return Ok(Arc::from(buffer));
     }
 
     fn sector_count(&self) -> u64 {
-        let sectors = self.identify_data[120..].as_ptr();
+        let command_sets = u16::from_le_bytes([self.identify_data[166], self.identify_data[167]]);
+
+        // Words 100 to 103 have the size of drives that can do LBA48, words 60 and 61 that of
+        // older ones, which stop at 2^28 sectors
+        if command_sets & (1 << 10) != 0 {
+            return u64::from_le_bytes(self.identify_data[200..208].try_into().unwrap());
+        }
 
-        return unsafe { *(sectors as *const u32) } as u64;
+        return u32::from_le_bytes(self.identify_data[120..124].try_into().unwrap()) as u64;
     }
 
-    fn write(&self, sector: u64, data: &[u8]) -> Result<(), ()> {
-        panic!("TODO: ATA Drive writes");
+    fn sector_size(&self) -> usize {
+        return ATA_SECTOR_SIZE;
     }
-}
 
-// TODO: This code is pretty much just the C from @Moldytzu's mOS
-// This code could probably be made better and more device agnostic
-// But that's TODO obviously
-fn ide_initialize(bar0: u32, bar1: u32, _bar2: u32, _bar3: u32, _bar4: u32) {
-    let io_port_base = bar0 as u16;
-    let control_port_base = bar1 as u16;
+    fn is_read_only(&self) -> bool {
+        return false;
+    }
 
-    let bus = ATABus::new(io_port_base, control_port_base);
+    fn write(&self, sector: u64, data: &[u8]) -> Result<(), ()> {
+        if data.len() % ATA_SECTOR_SIZE != 0 {
+            return Err(());
+        }
 
-    let mut drives = Vec::new();
+        let sector_count = (data.len() / ATA_SECTOR_SIZE) as u64;
 
-    for i in 0..2 {
-        let drive_type = if i == 0 {
-            ATADriveType::Parent
-        } else {
-            ATADriveType::Child
-        };
+        if sector + sector_count > self.sector_count() {
+            return Err(());
+        }
 
-        let drive = ATADrive::new(bus.clone(), drive_type);
+        let sectors_per_command = self.sectors_per_command();
 
-        if drive.is_ok() {
-            drives.push(drive);
+        for (i, chunk) in data
+            .chunks(sectors_per_command * ATA_SECTOR_SIZE)
+            .enumerate()
+        {
+            let chunk_sector = sector + (i * sectors_per_command) as u64;
+
+            self.write_chunk(chunk_sector, chunk)?;
         }
+
+        return Ok(());
     }
+}
 
-    crate::log_info!(
-        "ATA: Detected {} drive{}",
-        drives.len(),
-        match drives.len() {
-            1 => "",
-            _ => "s",
+/// CD and DVD drives. They only take SCSI commands sent through the packet command, and we only
+/// ever read from them.
+#[derive(Debug)]
+struct ATAPIDrive {
+    bus: Arc<ATABus>,
+    drive_type: ATADriveType,
+    sector_count: u64,
+}
+
+impl ATAPIDrive {
+    pub fn new(bus: Arc<ATABus>, drive: ATADriveType) -> Result<Self, ()> {
+        let (drive_type, _) = {
+            let _command = bus.lock();
+            bus.identify(drive)?
+        };
+
+        if drive_type != IDEDriveType::PataPi && drive_type != IDEDriveType::SataPi {
+            return Err(());
         }
-    );
 
-    for drive in drives.iter() {
-        if drive.is_err() {
-            continue;
+        let mut atapi_drive = Self {
+            bus,
+            drive_type: drive,
+            sector_count: 0,
+        };
+
+        // An empty drive is still a drive, it just has nothing to read
+        if atapi_drive.test_unit_ready() {
+            atapi_drive.sector_count = atapi_drive.read_capacity().unwrap_or(0);
         }
 
-        let drive = drive.as_ref().unwrap();
-        let sectors = drive.sector_count();
+        return Ok(atapi_drive);
+    }
 
-        crate::log_info!(
-            "ATA: Drive 0 has {} sectors ({} MB)",
-            sectors,
-            (sectors as u64 * ATA_SECTOR_SIZE as u64) / 1024 / 1024
-        );
+    fn test_unit_ready(&self) -> bool {
+        let mut packet = [0u8; ATAPI_PACKET_SIZE];
+        packet[0] = ATAPICommand::TestUnitReady as u8;
 
-        let mbr_sector = drive.read(0, 1).expect("Failed to read first sector");
+        return (0..ATAPI_READY_ATTEMPTS).any(|_| {
+            let _command = self.bus.lock();
+            self.bus.packet(self.drive_type, &packet, 0).is_ok()
+        });
+    }
 
-        let signature: [u8; 2] = mbr_sector[510..].try_into().unwrap();
+    fn read_capacity(&self) -> Result<u64, ()> {
+        let mut packet = [0u8; ATAPI_PACKET_SIZE];
+        packet[0] = ATAPICommand::ReadCapacity as u8;
 
-        if u16::from_le_bytes(signature[0..2].try_into().unwrap()) != 0xAA55 {
-            panic!("First sector is not MBR");
-        }
+        let capacity = {
+            let _command = self.bus.lock();
+            self.bus.packet(self.drive_type, &packet, 8)?
+        };
 
-        let gpt_sector = drive.read(1, 1).expect("Failed to read sector 2");
+        // Both are big endian, and it's the address of the last block rather than a count
+        let last_block = u32::from_be_bytes(capacity[0..4].try_into().unwrap());
+        let block_size = u32::from_be_bytes(capacity[4..8].try_into().unwrap());
 
-        let mut array = [0u8; 512];
-        array.copy_from_slice(&gpt_sector[..512]);
+        if block_size as usize != ATAPI_SECTOR_SIZE {
+            crate::log_error!("ATAPI: Unsupported block size {}", block_size);
+            return Err(());
+        }
 
-        let gpt = GPTBlock::new(&array);
+        return Ok(last_block as u64 + 1);
+    }
+}
 
-        let mut partitions: Vec<GPTPartitionEntry> =
-            Vec::with_capacity(gpt.partition_entry_count as usize);
+impl BlockDevice for ATAPIDrive {
+    fn read(&self, sector: u64, sector_count: usize) -> Result<Arc<[u8]>, ()> {
+        if (sector + sector_count as u64) > self.sector_count {
+            return Err(());
+        }
 
-        let partition_sector = drive
-            .read(
-                2,
-                (gpt.partition_entry_count * gpt.partition_entry_size) as usize / ATA_SECTOR_SIZE,
-            )
-            .expect("Failed to read partition table");
+        let mut packet = [0u8; ATAPI_PACKET_SIZE];
+        packet[0] = ATAPICommand::Read as u8;
+        packet[2..6].copy_from_slice(&(sector as u32).to_be_bytes());
+        packet[6..10].copy_from_slice(&(sector_count as u32).to_be_bytes());
 
-        for i in 0..gpt.partition_entry_count {
-            let entry_offset = (i * gpt.partition_entry_size) as usize;
+        let _command = self.bus.lock();
 
-            let partition_type_guid: [u8; 16] = partition_sector[entry_offset..entry_offset + 16]
-                .try_into()
-                .unwrap();
+        let data = self
+            .bus
+            .packet(self.drive_type, &packet, sector_count * ATAPI_SECTOR_SIZE)?;
 
-            let mut is_zero = true;
+        return Ok(Arc::from(data));
+    }
 
-            for &j in partition_type_guid.iter() {
-                if j != 0 {
-                    is_zero = false;
-                }
-            }
+    fn sector_count(&self) -> u64 {
+        return self.sector_count;
+    }
 
-            if is_zero {
-                continue;
-            }
+    fn sector_size(&self) -> usize {
+        return ATAPI_SECTOR_SIZE;
+    }
 
-            let start_sector = u64::from_le_bytes(
-                partition_sector[entry_offset + 32..entry_offset + 40]
-                    .try_into()
-                    .unwrap(),
-            );
-            let end_sector = u64::from_le_bytes(
-                partition_sector[entry_offset + 40..entry_offset + 48]
-                    .try_into()
-                    .unwrap(),
-            );
+    fn is_read_only(&self) -> bool {
+        return true;
+    }
 
-            // Store the parsed information in the partition_entries array
-            partitions.push(GPTPartitionEntry {
-                partition_type_guid,
-                start_sector,
-                end_sector,
-            });
-        }
+    fn write(&self, _sector: u64, _data: &[u8]) -> Result<(), ()> {
+        return Err(());
+    }
+}
+
+// TODO: This code is pretty much just the C from @Moldytzu's mOS
+// This code could probably be made better and more device agnostic
+// But that's TODO obviously
+fn ide_initialize(buses: &[Arc<ATABus>]) {
+    let mut drives = Vec::new();
 
-        for &partition in partitions.iter() {
-            let fat_fs = fat::FATFS::new(drive, partition);
+    // Drives are registered as they're found, so they're numbered by where they are on the buses
+    // whatever kind they are
+    for bus in buses {
+        for drive_type in [ATADriveType::Parent, ATADriveType::Child] {
+            if let Ok(drive) = ATADrive::new(bus.clone(), drive_type) {
+                let drive = Arc::new(drive);
+                let sectors = drive.sector_count();
+
+                crate::log_info!(
+                    "ATA: Drive {} has {} sectors ({} MB), using {}",
+                    drives.len(),
+                    sectors,
+                    (sectors as u64 * ATA_SECTOR_SIZE as u64) / 1024 / 1024,
+                    if drive.uses_dma { "DMA" } else { "PIO" }
+                );
 
-            if fat_fs.is_err() {
+                super::register_block_device(drive.clone());
+                drives.push(drive);
                 continue;
             }
 
-            let file_data = fat_fs
-                .unwrap()
-                .read("/boot/limine/limine.cfg")
-                .expect("Failed to read file");
+            if let Ok(drive) = ATAPIDrive::new(bus.clone(), drive_type) {
+                crate::log_info!(
+                    "ATAPI: Detected drive with {} sectors ({} MB)",
+                    drive.sector_count,
+                    (drive.sector_count * ATAPI_SECTOR_SIZE as u64) / 1024 / 1024
+                );
 
-            crate::println!("{:?}", file_data);
+                super::register_block_device(Arc::new(drive));
+            }
         }
+    }
+
+    crate::log_info!(
+        "ATA: Detected {} drive{}",
+        drives.len(),
+        match drives.len() {
+            1 => "",
+            _ => "s",
+        }
+    );
 
-        crate::println!("{:?}", partitions);
+    for drive in drives {
+        super::mount_partitions(drive);
     }
 }
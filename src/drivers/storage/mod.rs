@@ -1,2 +1,189 @@
+pub mod ahci;
 pub mod drive;
 pub mod ide;
+
+use core::fmt;
+
+use alloc::{boxed::Box, sync::Arc, vec::Vec};
+
+use crate::{
+    drivers::fs::{
+        fat,
+        vfs::{self, VfsFileSystem},
+    },
+    libs::mutex::Mutex,
+};
+
+use self::drive::{BlockDevice, GPTBlock, GPTPartitionEntry};
+
+// Every disk a storage driver found, in the order they were found
+static BLOCK_DEVICES: Mutex<Vec<Arc<dyn BlockDevice>>> = Mutex::new(Vec::new());
+
+pub fn register_block_device(device: Arc<dyn BlockDevice>) {
+    BLOCK_DEVICES.lock().write().push(device);
+}
+
+pub fn block_devices() -> Vec<Arc<dyn BlockDevice>> {
+    return BLOCK_DEVICES.lock().read().clone();
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RoundTripError {
+    OutOfRange,
+    ReadFailed,
+    WriteFailed,
+    ReadBackFailed,
+    RestoreFailed,
+    Mismatch,
+}
+
+impl fmt::Display for RoundTripError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            RoundTripError::OutOfRange => write!(f, "the sectors are past the end of the drive"),
+            RoundTripError::ReadFailed => write!(f, "failed to read the sectors"),
+            RoundTripError::WriteFailed => write!(f, "failed to write the pattern"),
+            RoundTripError::ReadBackFailed => write!(f, "failed to read the pattern back"),
+            RoundTripError::RestoreFailed => write!(f, "failed to restore the old data"),
+            RoundTripError::Mismatch => {
+                write!(f, "the data read back doesn't match what was written")
+            }
+        };
+    }
+}
+
+/// Writes a pattern to the sectors, reads it back and puts the old data back.
+pub fn round_trip(device: &dyn BlockDevice, sector: u64, count: u64) -> Result<(), RoundTripError> {
+    match sector.checked_add(count) {
+        Some(end) if count > 0 && end <= device.sector_count() => {}
+        _ => return Err(RoundTripError::OutOfRange),
+    }
+
+    let original = device
+        .read(sector, count as usize)
+        .map_err(|_| RoundTripError::ReadFailed)?;
+
+    // Different for every run and every byte, so stale data can't pass
+    let seed = crate::sys::time::uptime_ms();
+    let pattern: Vec<u8> = (0..original.len() as u64)
+        .map(|i| (i.wrapping_mul(31) ^ seed ^ (i >> 9)) as u8)
+        .collect();
+
+    device
+        .write(sector, &pattern)
+        .map_err(|_| RoundTripError::WriteFailed)?;
+
+    let matches = match device.read(sector, count as usize) {
+        Ok(data) => *data == *pattern,
+        Err(()) => return Err(RoundTripError::ReadBackFailed),
+    };
+
+    device
+        .write(sector, &original)
+        .map_err(|_| RoundTripError::RestoreFailed)?;
+
+    if !matches {
+        return Err(RoundTripError::Mismatch);
+    }
+
+    return Ok(());
+}
+
+/// Looks for a GPT on the drive and mounts the first FAT partition on it at /boot.
+pub fn mount_partitions(drive: Arc<dyn BlockDevice>) {
+    let mbr_sector = match drive.read(0, 1) {
+        Ok(sector) => sector,
+        Err(()) => {
+            crate::log_error!("Storage: Failed to read the first sector of a drive");
+            return;
+        }
+    };
+
+    let signature: [u8; 2] = mbr_sector[510..].try_into().unwrap();
+
+    // Blank and scratch disks are fine, there's just nothing to mount on them
+    if u16::from_le_bytes(signature[0..2].try_into().unwrap()) != 0xAA55 {
+        crate::log_info!("Storage: Drive has no partition table");
+        return;
+    }
+
+    let gpt_sector = drive.read(1, 1).expect("Failed to read sector 2");
+
+    let mut array = [0u8; 512];
+    array.copy_from_slice(&gpt_sector[..512]);
+
+    let gpt = GPTBlock::new(&array);
+
+    let mut partitions: Vec<GPTPartitionEntry> =
+        Vec::with_capacity(gpt.partition_entry_count as usize);
+
+    let partition_sector = drive
+        .read(
+            2,
+            (gpt.partition_entry_count * gpt.partition_entry_size) as usize / drive.sector_size(),
+        )
+        .expect("Failed to read partition table");
+
+    for i in 0..gpt.partition_entry_count {
+        let entry_offset = (i * gpt.partition_entry_size) as usize;
+
+        let partition_type_guid: [u8; 16] = partition_sector[entry_offset..entry_offset + 16]
+            .try_into()
+            .unwrap();
+
+        let mut is_zero = true;
+
+        for &j in partition_type_guid.iter() {
+            if j != 0 {
+                is_zero = false;
+            }
+        }
+
+        if is_zero {
+            continue;
+        }
+
+        let start_sector = u64::from_le_bytes(
+            partition_sector[entry_offset + 32..entry_offset + 40]
+                .try_into()
+                .unwrap(),
+        );
+        let end_sector = u64::from_le_bytes(
+            partition_sector[entry_offset + 40..entry_offset + 48]
+                .try_into()
+                .unwrap(),
+        );
+
+        // Store the parsed information in the partition_entries array
+        partitions.push(GPTPartitionEntry {
+            partition_type_guid,
+            start_sector,
+            end_sector,
+        });
+    }
+
+    for &partition in partitions.iter() {
+        let fat_fs = match fat::FATFS::new(drive.clone(), partition) {
+            Ok(fat_fs) => fat_fs,
+            Err(()) => continue,
+        };
+
+        // The first FAT partition is the one we booted from. The kernel and limine are in its own
+        // /boot, which is what goes at ours, rather than showing up at /boot/boot
+        let has_boot_directory = fat_fs
+            .stat("/boot")
+            .is_ok_and(|stat| stat.node_type == vfs::VfsNodeType::Directory);
+
+        let mounted = if has_boot_directory {
+            vfs::mount_subtree("/boot", Box::new(fat_fs), "/boot")
+        } else {
+            vfs::mount("/boot", Box::new(fat_fs))
+        };
+
+        if mounted.is_err() {
+            crate::log_info!("Storage: /boot is already mounted, skipping FAT partition");
+        }
+    }
+
+    crate::println!("{:?}", partitions);
+}
@@ -2,3 +2,5 @@ pub mod bit_manipulator;
 pub mod logging;
 pub mod mutex;
 pub mod util;
+
+pub use gzip;
@@ -1,5 +1,6 @@
 use core::{
     cell::UnsafeCell,
+    fmt,
     sync::atomic::{AtomicBool, Ordering},
 };
 
@@ -25,6 +26,25 @@ impl<T> Mutex<T> {
         }
         return MutexGuard { mutex: self };
     }
+
+    /// Like `lock`, but gives up instead of spinning when it's already locked.
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        if self.locked.swap(true, Ordering::Acquire) {
+            return None;
+        }
+
+        return Some(MutexGuard { mutex: self });
+    }
+}
+
+// The data can't be looked at without locking it
+impl<T: ?Sized> fmt::Debug for Mutex<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return f
+            .debug_struct("Mutex")
+            .field("locked", &self.locked.load(Ordering::Relaxed))
+            .finish_non_exhaustive();
+    }
 }
 
 pub struct MutexGuard<'a, T: ?Sized> {
@@ -13,52 +13,65 @@ mod usr;
 
 use drivers::serial;
 use libs::util::hcf;
-use limine::ModuleRequest;
-
-pub static MODULE_REQUEST: ModuleRequest = ModuleRequest::new(0);
 
 #[no_mangle]
 pub extern "C" fn _start() -> ! {
+    // Limine's stack gets reclaimed once we're done booting, so get off of it first
+    #[cfg(target_arch = "x86_64")]
+    arch::gdt::switch_to_boot_stack(kmain);
+
+    #[cfg(not(target_arch = "x86_64"))]
+    kmain();
+}
+
+extern "C" fn kmain() -> ! {
+    #[cfg(target_arch = "x86_64")]
+    {
+        let tss = arch::gdt::init();
+        arch::syscall::init(tss);
+    }
+
     #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
     arch::interrupts::init();
 
+    sys::time::init();
+
+    #[cfg(target_arch = "x86_64")]
+    arch::paging::init();
+
     sys::mem::init();
 
     serial::init_serial();
 
-    // drivers::acpi::init_acpi();
+    // Has to happen before the bootloader memory the RSDP response is in gets reclaimed
+    drivers::acpi::init_acpi();
 
-    drivers::pci::enumerate_pci_bus();
+    drivers::rtc::init();
 
-    drivers::storage::ide::init();
-
-    if let Some(module_response) = MODULE_REQUEST.get_response().get() {
-        let module_name = "initramfs.gz";
-
-        for module in module_response.modules() {
-            let c_path = module.path.to_str();
-            if c_path.is_none() {
-                continue;
-            }
-
-            if c_path.unwrap().to_str().unwrap().contains(module_name) {
-                let initramfs = module;
-
-                crate::println!("Initramfs is located at: {:#018X?}", unsafe {
-                    initramfs.base.as_ptr().unwrap()
-                        ..initramfs
-                            .base
-                            .as_ptr()
-                            .unwrap()
-                            .add(initramfs.length as usize)
-                });
-            }
-        }
+    #[cfg(target_arch = "x86_64")]
+    {
+        arch::apic::init();
+        arch::smp::init();
     }
 
+    drivers::pci::enumerate_pci_bus();
+
+    drivers::fs::initramfs::init();
+
+    drivers::pci::driver::bind_drivers();
+
+    // Anything still needed from bootloader memory has to be copied out before this
+    sys::mem::reclaim_memory();
+
+    sys::scheduler::init();
+
+    #[cfg(feature = "disk-test")]
+    usr::disk_test::run();
+
     usr::shell::init_shell();
 
-    hcf();
+    // Whatever is left of the boot context becomes the idle task
+    sys::scheduler::idle();
 }
 
 #[panic_handler]
@@ -192,6 +192,12 @@ impl BuddyAllocator {
         return self.heap_size.load(SeqCst);
     }
 
+    pub fn contains(&self, ptr: *mut u8) -> bool {
+        let heap_start = self.heap_start.load(SeqCst);
+
+        return ptr >= heap_start && (ptr as usize) < heap_start as usize + self.get_total_mem();
+    }
+
     pub fn get_free_mem(&self) -> usize {
         let mut free_mem = 0;
 
@@ -250,3 +256,114 @@ unsafe impl GlobalAlloc for BuddyAllocator {
         }
     }
 }
+
+const MAX_HEAP_ARENAS: usize = 64;
+const MIN_ARENA_SIZE: usize = 0x40_0000;
+
+/// Kernel heap that grows on demand. It is made of buddy allocated arenas placed one after the
+/// other in a virtual address range, `map_memory` is called to back a new arena with memory.
+pub struct KernelHeap {
+    arenas: Mutex<[Option<BuddyAllocator>; MAX_HEAP_ARENAS]>,
+    next_address: AtomicUsize,
+    end_address: usize,
+    map_memory: fn(address: usize, size: usize) -> Result<(), ()>,
+}
+
+impl KernelHeap {
+    pub const fn new(
+        start_address: usize,
+        end_address: usize,
+        map_memory: fn(address: usize, size: usize) -> Result<(), ()>,
+    ) -> Self {
+        const NO_ARENA: Option<BuddyAllocator> = None;
+
+        return Self {
+            arenas: Mutex::new([NO_ARENA; MAX_HEAP_ARENAS]),
+            next_address: AtomicUsize::new(start_address),
+            end_address,
+            map_memory,
+        };
+    }
+
+    /// Adds a new arena big enough for at least `size` bytes.
+    pub fn grow(&self, size: usize) -> Result<(), ()> {
+        let arena_size = max(size.next_power_of_two(), MIN_ARENA_SIZE);
+        let address = self.next_address.load(SeqCst);
+
+        if address + arena_size > self.end_address {
+            return Err(());
+        }
+
+        let arenas = self.arenas.lock().write();
+        let slot = arenas.iter_mut().find(|arena| arena.is_none()).ok_or(())?;
+
+        (self.map_memory)(address, arena_size)?;
+
+        *slot = Some(BuddyAllocator::new_unchecked(
+            address as *mut u8,
+            arena_size,
+        ));
+        self.next_address.store(address + arena_size, SeqCst);
+
+        return Ok(());
+    }
+
+    fn arenas(&self) -> impl Iterator<Item = &BuddyAllocator> {
+        return self.arenas.lock().read().iter().flatten();
+    }
+
+    pub fn arena_count(&self) -> usize {
+        return self.arenas().count();
+    }
+
+    pub fn get_total_mem(&self) -> usize {
+        return self.arenas().map(|arena| arena.get_total_mem()).sum();
+    }
+
+    pub fn get_free_mem(&self) -> usize {
+        return self.arenas().map(|arena| arena.get_free_mem()).sum();
+    }
+
+    pub fn get_used_mem(&self) -> usize {
+        return self.get_total_mem() - self.get_free_mem();
+    }
+}
+
+// Tasks can be preempted at any point, and the locks don't actually keep anyone out, so the
+// heap is only ever touched with interrupts disabled
+unsafe impl GlobalAlloc for KernelHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        return crate::arch::without_interrupts(|| {
+            for arena in self.arenas() {
+                let block = arena.alloc(layout);
+
+                if !block.is_null() {
+                    return block;
+                }
+            }
+
+            // Growing won't help with an alignment no arena can give out
+            if layout.align() > MIN_HEAP_ALIGN
+                || self.grow(max(layout.size(), layout.align())).is_err()
+            {
+                return ptr::null_mut();
+            }
+
+            // The newest arena is the last one
+            return match self.arenas().last() {
+                Some(arena) => arena.alloc(layout),
+                None => ptr::null_mut(),
+            };
+        });
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        crate::arch::without_interrupts(|| {
+            let arena = self.arenas().find(|arena| arena.contains(ptr));
+
+            if let Some(arena) = arena {
+                arena.dealloc(ptr, layout);
+            }
+        });
+    }
+}
@@ -0,0 +1,366 @@
+use core::alloc::Layout;
+
+use alloc::{
+    alloc::{alloc_zeroed, dealloc},
+    vec::Vec,
+};
+
+const ELF_MAGIC: [u8; 4] = [0x7F, b'E', b'L', b'F'];
+const ELF_CLASS_64: u8 = 2;
+const ELF_DATA_LITTLE_ENDIAN: u8 = 1;
+const ELF_MACHINE_X86_64: u16 = 0x3E;
+
+const PAGE_SIZE: u64 = 0x1000;
+
+#[repr(u16)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ElfType {
+    Executable = 2,
+    SharedObject = 3,
+}
+
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ProgramHeaderType {
+    Load = 1,
+    Dynamic = 2,
+}
+
+// d_tag values we care about in the dynamic section
+const DT_NULL: u64 = 0;
+const DT_RELA: u64 = 7;
+const DT_RELASZ: u64 = 8;
+const DT_RELAENT: u64 = 9;
+
+const R_X86_64_NONE: u32 = 0;
+const R_X86_64_RELATIVE: u32 = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ElfError {
+    InvalidMagic,
+    UnsupportedClass,
+    UnsupportedMachine,
+    UnsupportedType,
+    // The executable wants to be loaded at a fixed address
+    NotRelocatable,
+    UnsupportedRelocation(u32),
+    Truncated,
+    // A segment holds more of the file than it has memory, or ends past the address space
+    InvalidSegment,
+    NoLoadableSegments,
+    OutOfMemory,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ElfHeader {
+    pub elf_type: ElfType,
+    pub machine: u16,
+    pub entry: u64,
+    pub program_header_offset: u64,
+    pub program_header_size: u16,
+    pub program_header_count: u16,
+}
+
+impl ElfHeader {
+    pub fn from_bytes(data: &[u8]) -> Result<Self, ElfError> {
+        if data.len() < 64 {
+            return Err(ElfError::Truncated);
+        }
+
+        if data[0..4] != ELF_MAGIC {
+            return Err(ElfError::InvalidMagic);
+        }
+
+        if data[4] != ELF_CLASS_64 || data[5] != ELF_DATA_LITTLE_ENDIAN {
+            return Err(ElfError::UnsupportedClass);
+        }
+
+        let elf_type = match u16::from_le_bytes(data[0x10..0x12].try_into().unwrap()) {
+            2 => ElfType::Executable,
+            3 => ElfType::SharedObject,
+            _ => return Err(ElfError::UnsupportedType),
+        };
+        let machine = u16::from_le_bytes(data[0x12..0x14].try_into().unwrap());
+
+        if machine != ELF_MACHINE_X86_64 {
+            return Err(ElfError::UnsupportedMachine);
+        }
+
+        let entry = u64::from_le_bytes(data[0x18..0x20].try_into().unwrap());
+        let program_header_offset = u64::from_le_bytes(data[0x20..0x28].try_into().unwrap());
+        let program_header_size = u16::from_le_bytes(data[0x36..0x38].try_into().unwrap());
+        let program_header_count = u16::from_le_bytes(data[0x38..0x3A].try_into().unwrap());
+
+        return Ok(Self {
+            elf_type,
+            machine,
+            entry,
+            program_header_offset,
+            program_header_size,
+            program_header_count,
+        });
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ProgramHeader {
+    pub segment_type: u32,
+    pub flags: u32,
+    pub offset: u64,
+    pub virtual_address: u64,
+    pub file_size: u64,
+    pub memory_size: u64,
+    pub align: u64,
+}
+
+impl ProgramHeader {
+    fn from_bytes(data: &[u8]) -> Result<Self, ElfError> {
+        if data.len() < 56 {
+            return Err(ElfError::Truncated);
+        }
+
+        return Ok(Self {
+            segment_type: u32::from_le_bytes(data[0x00..0x04].try_into().unwrap()),
+            flags: u32::from_le_bytes(data[0x04..0x08].try_into().unwrap()),
+            offset: u64::from_le_bytes(data[0x08..0x10].try_into().unwrap()),
+            virtual_address: u64::from_le_bytes(data[0x10..0x18].try_into().unwrap()),
+            file_size: u64::from_le_bytes(data[0x20..0x28].try_into().unwrap()),
+            memory_size: u64::from_le_bytes(data[0x28..0x30].try_into().unwrap()),
+            align: u64::from_le_bytes(data[0x30..0x38].try_into().unwrap()),
+        });
+    }
+}
+
+pub fn program_headers(data: &[u8], header: &ElfHeader) -> Result<Vec<ProgramHeader>, ElfError> {
+    let mut program_headers = Vec::with_capacity(header.program_header_count as usize);
+
+    for i in 0..header.program_header_count as usize {
+        let start = header.program_header_offset as usize + i * header.program_header_size as usize;
+        let bytes = data
+            .get(start..start + header.program_header_size as usize)
+            .ok_or(ElfError::Truncated)?;
+
+        program_headers.push(ProgramHeader::from_bytes(bytes)?);
+    }
+
+    return Ok(program_headers);
+}
+
+// p_flags bits
+const PF_X: u32 = 1 << 0;
+const PF_W: u32 = 1 << 1;
+
+#[derive(Clone, Copy, Debug)]
+pub struct LoadedSegment {
+    // Where the segment is in the program's address space
+    pub address: u64,
+    pub size: u64,
+    pub writable: bool,
+    pub executable: bool,
+}
+
+/// An executable that has been copied into memory and relocated to run at `address`.
+pub struct LoadedImage {
+    base: *mut u8,
+    layout: Layout,
+    address: u64,
+    pub entry: u64,
+    pub segments: Vec<LoadedSegment>,
+}
+
+impl LoadedImage {
+    /// Where the image is in kernel memory.
+    pub fn base(&self) -> u64 {
+        return self.base as u64;
+    }
+
+    /// Where the image was relocated to run at.
+    pub fn address(&self) -> u64 {
+        return self.address;
+    }
+
+    pub fn size(&self) -> usize {
+        return self.layout.size();
+    }
+}
+
+impl Drop for LoadedImage {
+    fn drop(&mut self) {
+        unsafe { dealloc(self.base, self.layout) };
+    }
+}
+
+/// Copies every `PT_LOAD` segment of `data` into a freshly allocated block of memory and applies
+/// the relocations found in the `PT_DYNAMIC` segment, so that it can be mapped at `load_address`.
+pub fn load(data: &[u8], load_address: u64) -> Result<LoadedImage, ElfError> {
+    let header = ElfHeader::from_bytes(data)?;
+
+    if header.elf_type != ElfType::SharedObject {
+        // Everything is loaded at load_address, which isn't where these want to be
+        return Err(ElfError::NotRelocatable);
+    }
+
+    let program_headers = program_headers(data, &header)?;
+
+    let mut lowest_address = u64::MAX;
+    let mut highest_address = 0;
+
+    for program_header in program_headers.iter() {
+        if program_header.segment_type != ProgramHeaderType::Load as u32 {
+            continue;
+        }
+
+        if program_header.file_size > program_header.memory_size {
+            return Err(ElfError::InvalidSegment);
+        }
+
+        let segment_end = program_header
+            .virtual_address
+            .checked_add(program_header.memory_size)
+            .ok_or(ElfError::InvalidSegment)?;
+
+        lowest_address = lowest_address.min(program_header.virtual_address & !(PAGE_SIZE - 1));
+        highest_address = highest_address.max(segment_end);
+    }
+
+    if lowest_address > highest_address {
+        return Err(ElfError::NoLoadableSegments);
+    }
+
+    let image_size = ((highest_address - lowest_address)
+        .checked_add(PAGE_SIZE - 1)
+        .ok_or(ElfError::InvalidSegment)?
+        & !(PAGE_SIZE - 1)) as usize;
+    let layout = Layout::from_size_align(image_size, PAGE_SIZE as usize)
+        .map_err(|_| ElfError::OutOfMemory)?;
+
+    let base = unsafe { alloc_zeroed(layout) };
+
+    if base.is_null() {
+        return Err(ElfError::OutOfMemory);
+    }
+
+    let mut image = LoadedImage {
+        base,
+        layout,
+        address: load_address,
+        entry: 0,
+        segments: Vec::new(),
+    };
+
+    // Difference between where the segments want to be and where they will run
+    let load_bias = load_address.wrapping_sub(lowest_address);
+    // And the same for where we are writing them to right now
+    let kernel_bias = (base as u64).wrapping_sub(lowest_address);
+
+    for program_header in program_headers.iter() {
+        if program_header.segment_type != ProgramHeaderType::Load as u32 {
+            continue;
+        }
+
+        let segment_end = program_header
+            .offset
+            .checked_add(program_header.file_size)
+            .ok_or(ElfError::InvalidSegment)?;
+
+        let segment_data = data
+            .get(program_header.offset as usize..segment_end as usize)
+            .ok_or(ElfError::Truncated)?;
+
+        // Everything past file_size is .bss, which alloc_zeroed already took care of
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                segment_data.as_ptr(),
+                (program_header.virtual_address.wrapping_add(kernel_bias)) as *mut u8,
+                segment_data.len(),
+            );
+        }
+
+        image.segments.push(LoadedSegment {
+            address: program_header.virtual_address.wrapping_add(load_bias),
+            size: program_header.memory_size,
+            writable: program_header.flags & PF_W != 0,
+            executable: program_header.flags & PF_X != 0,
+        });
+    }
+
+    for program_header in program_headers.iter() {
+        if program_header.segment_type == ProgramHeaderType::Dynamic as u32 {
+            apply_relocations(&image, program_header, kernel_bias, load_bias)?;
+        }
+    }
+
+    image.entry = header.entry.wrapping_add(load_bias);
+
+    return Ok(image);
+}
+
+fn apply_relocations(
+    image: &LoadedImage,
+    dynamic_header: &ProgramHeader,
+    kernel_bias: u64,
+    load_bias: u64,
+) -> Result<(), ElfError> {
+    let image_range = image.base()..image.base() + image.size() as u64;
+
+    let read_u64 = |address: u64| -> Result<u64, ElfError> {
+        let last_byte = address.checked_add(7).ok_or(ElfError::Truncated)?;
+
+        if !image_range.contains(&address) || !image_range.contains(&last_byte) {
+            return Err(ElfError::Truncated);
+        }
+
+        return Ok(unsafe { core::ptr::read_unaligned(address as *const u64) });
+    };
+
+    let mut rela_address = 0;
+    let mut rela_size = 0;
+    let mut rela_entry_size = 24;
+
+    let mut entry_address = dynamic_header.virtual_address.wrapping_add(kernel_bias);
+
+    loop {
+        let tag = read_u64(entry_address)?;
+        let value = read_u64(entry_address + 8)?;
+
+        match tag {
+            DT_NULL => break,
+            DT_RELA => rela_address = value.wrapping_add(kernel_bias),
+            DT_RELASZ => rela_size = value,
+            DT_RELAENT => rela_entry_size = value,
+            _ => {}
+        }
+
+        entry_address += 16;
+    }
+
+    if rela_size == 0 || rela_entry_size == 0 {
+        return Ok(());
+    }
+
+    for i in 0..(rela_size / rela_entry_size) {
+        // Both come from the file, read_u64 turns away anything outside of the image
+        let relocation = rela_address.wrapping_add(i.wrapping_mul(rela_entry_size));
+
+        let offset = read_u64(relocation)?;
+        let info = read_u64(relocation + 8)?;
+        let addend = read_u64(relocation + 16)?;
+
+        match info as u32 {
+            R_X86_64_NONE => {}
+            R_X86_64_RELATIVE => {
+                let target = offset.wrapping_add(kernel_bias);
+
+                // Make sure the target is inside of the image as well
+                read_u64(target)?;
+
+                unsafe {
+                    core::ptr::write_unaligned(target as *mut u64, addend.wrapping_add(load_bias))
+                };
+            }
+            relocation_type => return Err(ElfError::UnsupportedRelocation(relocation_type)),
+        }
+    }
+
+    return Ok(());
+}
@@ -0,0 +1,154 @@
+use alloc::{format, string::String, vec::Vec};
+
+use crate::{
+    arch::gdt,
+    drivers::fs::vfs::{self, VfsError},
+};
+
+use super::{
+    elf::{self, ElfError},
+    process::{self, Process},
+    scheduler,
+};
+
+const PROGRAM_STACK_SIZE: usize = 0x10000;
+// Upper limit for how far brk can move the program break
+const PROGRAM_HEAP_SIZE: usize = 0x100000;
+
+#[derive(Debug)]
+pub enum ExecError {
+    NotFound,
+    Vfs(VfsError),
+    InvalidExecutable(ElfError),
+    // Not enough memory for the program or its address space
+    OutOfMemory,
+}
+
+/// Lays out the initial program stack following the System V ABI, `rsp` points at argc,
+/// followed by the argv pointers, a NULL, an empty envp and an empty auxiliary vector.
+/// `stack_base` is the address of the stack in the program's address space.
+fn build_stack(stack: &mut [u8], stack_base: u64, args: &[&str]) -> u64 {
+    let mut top = stack.len();
+
+    let mut arg_pointers: Vec<u64> = Vec::with_capacity(args.len());
+
+    for arg in args {
+        top -= arg.len() + 1;
+        stack[top..top + arg.len()].copy_from_slice(arg.as_bytes());
+        stack[top + arg.len()] = 0;
+
+        arg_pointers.push(stack_base + top as u64);
+    }
+
+    // argc, argv, NULL, envp NULL, AT_NULL auxv pair
+    let word_count = 1 + args.len() + 1 + 1 + 2;
+
+    top &= !0xF;
+    top -= word_count * 8;
+
+    // Keep rsp 16 byte aligned at the entry point
+    top &= !0xF;
+
+    let mut words: Vec<u64> = Vec::with_capacity(word_count);
+    words.push(args.len() as u64);
+    words.extend_from_slice(&arg_pointers);
+    words.extend_from_slice(&[0, 0, 0, 0]);
+
+    for (i, word) in words.iter().enumerate() {
+        let offset = top + i * 8;
+        stack[offset..offset + 8].copy_from_slice(&word.to_le_bytes());
+    }
+
+    return stack_base + top as u64;
+}
+
+/// Drops to ring 3 at `entry` with `stack` as the stack pointer, the kernel stack is left to
+/// whatever comes in from ring 3 next.
+#[naked]
+unsafe extern "C" fn enter_program(entry: u64, stack: u64) -> ! {
+    core::arch::asm!(
+        // Interrupt frame for iretq: ss, rsp, rflags (with interrupts on), cs and rip
+        "push {user_data}",
+        "push rsi",
+        "push 0x202",
+        "push {user_code}",
+        "push rdi",
+        // argc and argv, for programs that want them in registers instead
+        "mov rdi, [rsi]",
+        "lea rsi, [rsi + 8]",
+        // Don't leak kernel values to the program
+        "xor eax, eax",
+        "xor ebx, ebx",
+        "xor ecx, ecx",
+        "xor edx, edx",
+        "xor ebp, ebp",
+        "xor r8d, r8d",
+        "xor r9d, r9d",
+        "xor r10d, r10d",
+        "xor r11d, r11d",
+        "xor r12d, r12d",
+        "xor r13d, r13d",
+        "xor r14d, r14d",
+        "xor r15d, r15d",
+        // Hand the program its own GS, see arch::syscall
+        "swapgs",
+        "iretq",
+        user_data = const gdt::USER_DATA_SELECTOR,
+        user_code = const gdt::USER_CODE_SELECTOR,
+        options(noreturn)
+    );
+}
+
+// Entry point of the task a program runs in, the scheduler has already switched to the
+// program's address space
+fn run_program() {
+    let (entry, stack_pointer) = match scheduler::current_process() {
+        Some(process) => process.start(),
+        None => return,
+    };
+
+    unsafe { enter_program(entry, stack_pointer) };
+}
+
+pub fn exec(path: &str, args: &[&str]) -> Result<usize, ExecError> {
+    let data = vfs::read(path).map_err(|err| match err {
+        VfsError::NotFound => ExecError::NotFound,
+        err => ExecError::Vfs(err),
+    })?;
+
+    let image = elf::load(&data, process::IMAGE_ADDRESS)
+        .map_err(|err| ExecError::InvalidExecutable(err))?;
+
+    crate::log_info!(
+        "Exec: Loaded {} at {:#X} (entry {:#X})",
+        path,
+        image.address(),
+        image.entry
+    );
+
+    let entry = image.entry;
+
+    let mut process = Process::new(image, PROGRAM_STACK_SIZE, PROGRAM_HEAP_SIZE)
+        .map_err(|_| ExecError::OutOfMemory)?;
+
+    let stack = process.stack_mut();
+    let stack_base = stack.address();
+    let initial_stack_pointer = build_stack(stack.as_mut_slice(), stack_base, args);
+
+    process.set_start(entry, initial_stack_pointer);
+
+    let name = path.rsplit('/').next().unwrap_or(path);
+    let id = scheduler::spawn_process(name, process, run_program);
+
+    // Program tasks are joinable, nothing else can get rid of it before we do
+    return Ok(scheduler::wait(id).unwrap());
+}
+
+/// Resolves a shell command to a path, bare names are looked up in /bin.
+pub fn resolve_command(command: &str) -> String {
+    if command.contains('/') {
+        return command.into();
+    }
+
+    return format!("/bin/{}", command);
+}
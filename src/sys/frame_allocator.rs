@@ -0,0 +1,195 @@
+// Physical memory manager, hands out 4KiB frames from every usable region of the memory map.
+// Every frame is tracked by one bit in a bitmap (set means in use), which lives in the first
+// usable region big enough to hold it.
+
+use limine::{MemmapEntry, MemoryMapEntryType, NonNullPtr};
+
+use crate::libs::mutex::Mutex;
+
+pub const FRAME_SIZE: u64 = 0x1000;
+
+pub static FRAME_ALLOCATOR: Mutex<FrameAllocator> = Mutex::new(FrameAllocator::new());
+
+pub struct FrameAllocator {
+    bitmap: *mut u64,
+    // Number of frames the bitmap covers, starting at physical address 0
+    frame_count: usize,
+    free_frames: usize,
+    total_frames: usize,
+    // Where to start looking for a free frame
+    next_frame: usize,
+}
+
+// Regions that are, or will eventually be, usable as normal memory
+fn is_tracked(entry: &MemmapEntry) -> bool {
+    return matches!(
+        entry.typ,
+        MemoryMapEntryType::Usable
+            | MemoryMapEntryType::BootloaderReclaimable
+            | MemoryMapEntryType::AcpiReclaimable
+    );
+}
+
+impl FrameAllocator {
+    const fn new() -> Self {
+        return Self {
+            bitmap: core::ptr::null_mut(),
+            frame_count: 0,
+            free_frames: 0,
+            total_frames: 0,
+            next_frame: 0,
+        };
+    }
+
+    pub fn init(&mut self, memmap: &[NonNullPtr<MemmapEntry>], hhdm_offset: u64) -> Result<(), ()> {
+        let highest_address = memmap
+            .iter()
+            .filter(|entry| is_tracked(entry))
+            .map(|entry| entry.base + entry.len)
+            .max()
+            .ok_or(())?;
+
+        let frame_count = (highest_address / FRAME_SIZE) as usize;
+        let bitmap_size = (frame_count.div_ceil(64) * 8) as u64;
+
+        let bitmap_region = memmap
+            .iter()
+            .find(|entry| entry.typ == MemoryMapEntryType::Usable && entry.len >= bitmap_size)
+            .ok_or(())?;
+
+        self.bitmap = (bitmap_region.base + hhdm_offset) as *mut u64;
+        self.frame_count = frame_count;
+
+        // Everything starts out as used, then the usable regions are freed
+        unsafe { core::ptr::write_bytes(self.bitmap, 0xFF, frame_count.div_ceil(64)) };
+
+        for entry in memmap.iter() {
+            if entry.typ == MemoryMapEntryType::Usable {
+                self.add_region(entry.base, entry.len);
+            }
+        }
+
+        let bitmap_frames = bitmap_size.div_ceil(FRAME_SIZE) as usize;
+        for i in 0..bitmap_frames {
+            self.mark_used(bitmap_region.base / FRAME_SIZE + i as u64);
+        }
+        self.total_frames -= bitmap_frames;
+
+        // Nobody should ever get back a null pointer
+        if !self.is_used(0) {
+            self.mark_used(0);
+            self.total_frames -= 1;
+        }
+
+        return Ok(());
+    }
+
+    fn is_used(&self, frame: usize) -> bool {
+        return unsafe { *self.bitmap.add(frame / 64) } & (1 << (frame % 64)) != 0;
+    }
+
+    fn mark_used(&mut self, frame: u64) {
+        let frame = frame as usize;
+
+        if !self.is_used(frame) {
+            unsafe { *self.bitmap.add(frame / 64) |= 1 << (frame % 64) };
+            self.free_frames -= 1;
+        }
+    }
+
+    fn mark_free(&mut self, frame: u64) {
+        let frame = frame as usize;
+
+        if self.is_used(frame) {
+            unsafe { *self.bitmap.add(frame / 64) &= !(1 << (frame % 64)) };
+            self.free_frames += 1;
+        }
+    }
+
+    /// Hands the frames fully inside of the given range over to the allocator.
+    pub fn add_region(&mut self, base: u64, length: u64) -> usize {
+        let first_frame = base.div_ceil(FRAME_SIZE);
+        let end_frame = ((base + length) / FRAME_SIZE).min(self.frame_count as u64);
+
+        let mut added = 0;
+
+        for frame in first_frame..end_frame {
+            if self.is_used(frame as usize) {
+                self.mark_free(frame);
+                added += 1;
+            }
+        }
+
+        self.total_frames += added;
+
+        return added;
+    }
+
+    /// Returns the physical address of a free frame, the contents are not zeroed.
+    pub fn allocate(&mut self) -> Option<u64> {
+        return self.allocate_contiguous(1);
+    }
+
+    /// Finds `count` free frames in a row and returns the physical address of the first one.
+    pub fn allocate_contiguous(&mut self, count: usize) -> Option<u64> {
+        if count == 0 || count > self.free_frames {
+            return None;
+        }
+
+        let mut run_start = 0;
+        let mut run_length = 0;
+
+        // Go around once, starting where the last search left off
+        for i in 0..self.frame_count {
+            let frame = (self.next_frame + i) % self.frame_count;
+
+            // Runs can't wrap around the end of memory
+            if frame == 0 {
+                run_length = 0;
+            }
+
+            if self.is_used(frame) {
+                run_length = 0;
+                continue;
+            }
+
+            if run_length == 0 {
+                run_start = frame;
+            }
+
+            run_length += 1;
+
+            if run_length == count {
+                for frame in run_start..run_start + count {
+                    self.mark_used(frame as u64);
+                }
+
+                self.next_frame = run_start + count;
+
+                return Some(run_start as u64 * FRAME_SIZE);
+            }
+        }
+
+        return None;
+    }
+
+    pub fn free(&mut self, address: u64) {
+        self.free_contiguous(address, 1);
+    }
+
+    pub fn free_contiguous(&mut self, address: u64, count: usize) {
+        let first_frame = address / FRAME_SIZE;
+
+        for frame in first_frame..first_frame + count as u64 {
+            self.mark_free(frame);
+        }
+    }
+
+    pub fn free_memory(&self) -> usize {
+        return self.free_frames * FRAME_SIZE as usize;
+    }
+
+    pub fn total_memory(&self) -> usize {
+        return self.total_frames * FRAME_SIZE as usize;
+    }
+}
@@ -1,12 +1,39 @@
-use limine::{MemmapEntry, MemoryMapEntryType, NonNullPtr};
+use core::sync::atomic::{AtomicUsize, Ordering};
 
-use super::allocator::BuddyAllocator;
+use alloc::vec::Vec;
+use limine::MemoryMapEntryType;
+
+use crate::{
+    arch::paging::{self, AddressSpace, PageFlags, PageSize},
+    libs::mutex::Mutex,
+};
+
+use super::{
+    allocator::KernelHeap,
+    frame_allocator::{FRAME_ALLOCATOR, FRAME_SIZE},
+};
 
 static MEMMAP_REQUEST: limine::MemmapRequest = limine::MemmapRequest::new(0);
 
+// The heap has a PML4 entry of its own, well past the end of the HHDM
+const HEAP_START: usize = 0xFFFF_A000_0000_0000;
+const HEAP_END: usize = HEAP_START + 0x80_0000_0000;
+
 #[global_allocator]
-pub static ALLOCATOR: BuddyAllocator =
-    BuddyAllocator::new_unchecked(0x1000 as *mut u8, 0x0008_0000);
+pub static ALLOCATOR: KernelHeap = KernelHeap::new(HEAP_START, HEAP_END, map_heap_memory);
+
+/// An entry of the memory map Limine gave us.
+#[derive(Clone, Copy, Debug)]
+pub struct MemoryRegion {
+    pub base: u64,
+    pub length: u64,
+    pub typ: MemoryMapEntryType,
+}
+
+// Limine's copy of the memory map is in bootloader reclaimable memory, so we keep our own
+pub static MEMORY_MAP: Mutex<Vec<MemoryRegion>> = Mutex::new(Vec::new());
+
+static RECLAIMED_MEMORY: AtomicUsize = AtomicUsize::new(0);
 
 // fn stitch_memory_map(memmap: &mut [NonNullPtr<MemmapEntry>]) -> &mut [NonNullPtr<MemmapEntry>] {
 //     let mut null_index_ptr = 0;
@@ -63,65 +90,53 @@ pub static ALLOCATOR: BuddyAllocator =
 //     return &mut memmap[0..null_index_ptr];
 // }
 
-fn find_largest_memory_regions(
-    memmap: &[NonNullPtr<MemmapEntry>],
-    min_heap_size: usize,
-) -> (
-    Option<&NonNullPtr<MemmapEntry>>,
-    Option<&NonNullPtr<MemmapEntry>>,
-) {
-    let mut largest_region: Option<&NonNullPtr<MemmapEntry>> = None;
-    let mut second_largest_region: Option<&NonNullPtr<MemmapEntry>> = None;
-    let mut framebuffer_region: Option<&NonNullPtr<MemmapEntry>> = None;
-
-    for region in memmap.iter() {
-        if region.typ == limine::MemoryMapEntryType::Framebuffer {
-            framebuffer_region = Some(region);
-            continue;
-        }
-
-        if !memory_section_is_usable(region) {
-            continue;
-        }
+// Backs `size` bytes of the heap at `address` with freshly allocated frames
+fn map_heap_memory(address: usize, size: usize) -> Result<(), ()> {
+    let mut kernel_space = AddressSpace::kernel();
+
+    for offset in (0..size).step_by(FRAME_SIZE as usize) {
+        let frame = match FRAME_ALLOCATOR.lock().write().allocate() {
+            Some(frame) => frame,
+            None => {
+                unmap_heap_memory(address, offset);
+                return Err(());
+            }
+        };
+
+        let mapped = kernel_space.map(
+            (address + offset) as u64,
+            frame,
+            PageSize::Small,
+            PageFlags::WRITABLE | PageFlags::NO_EXECUTE,
+        );
 
-        if largest_region.is_none() || region.len > largest_region.unwrap().len {
-            second_largest_region = largest_region;
-            largest_region = Some(region);
-        } else if second_largest_region.is_none() || region.len > second_largest_region.unwrap().len
-        {
-            second_largest_region = Some(region);
+        if mapped.is_err() {
+            FRAME_ALLOCATOR.lock().write().free(frame);
+            unmap_heap_memory(address, offset);
+            return Err(());
         }
     }
 
-    if framebuffer_region.is_none() || second_largest_region.is_none() {
-        return (largest_region, None);
-    }
-
-    let framebuffer_size = framebuffer_region.unwrap().len;
-
-    if second_largest_region.unwrap().len >= framebuffer_size {
-        return (largest_region, second_largest_region);
-    }
+    return Ok(());
+}
 
-    let shrunk_heap = largest_region.unwrap().len - framebuffer_size;
+fn unmap_heap_memory(address: usize, size: usize) {
+    let mut kernel_space = AddressSpace::kernel();
 
-    if shrunk_heap < min_heap_size as u64 {
-        return (largest_region, None);
+    for offset in (0..size).step_by(FRAME_SIZE as usize) {
+        if let Ok(frame) = kernel_space.unmap((address + offset) as u64, PageSize::Small) {
+            FRAME_ALLOCATOR.lock().write().free(frame);
+        }
     }
+}
 
-    unsafe {
-        (*second_largest_region.unwrap().as_ptr()).base = largest_region.unwrap().base;
-
-        (*largest_region.unwrap().as_ptr()).len = shrunk_heap;
-        (*second_largest_region.unwrap().as_ptr()).base += shrunk_heap;
-    }
+// Grabs physically contiguous memory for the console to draw into before copying to the screen
+fn allocate_back_buffer() -> Option<u64> {
+    let framebuffer = crate::drivers::video::get_framebuffer()?;
 
-    return (largest_region, second_largest_region);
-}
+    let frames = (framebuffer.pitch * framebuffer.height).div_ceil(FRAME_SIZE as usize);
 
-#[inline]
-fn memory_section_is_usable(entry: &MemmapEntry) -> bool {
-    return entry.typ == MemoryMapEntryType::Usable;
+    return FRAME_ALLOCATOR.lock().write().allocate_contiguous(frames);
 }
 
 pub fn init() {
@@ -134,40 +149,55 @@ pub fn init() {
 
     // let memmap = stitch_memory_map(memmap.memmap_mut());
 
-    let (largest_region, second_largest_region) = find_largest_memory_regions(&memmap, 0x0008_0000);
-
-    crate::usr::tty::CONSOLE.reinit(second_largest_region);
-
-    if largest_region.is_none() {
+    if FRAME_ALLOCATOR
+        .lock()
+        .write()
+        .init(memmap, paging::hhdm_offset())
+        .is_err()
+    {
         panic!("Suitable memory regions not found!");
     }
 
-    ALLOCATOR.set_heap(
-        largest_region.unwrap().base as *mut u8,
-        largest_region.unwrap().len as usize,
-    );
+    // The first arena is made right away, so its page tables exist before any address space
+    // copies the kernel's half
+    if ALLOCATOR.grow(0).is_err() {
+        panic!("Failed to set up the kernel heap!");
+    }
+
+    let back_buffer = allocate_back_buffer();
+
+    crate::usr::tty::CONSOLE.reinit(back_buffer);
+
+    let (free_mem, free_mem_label) = label_units(FRAME_ALLOCATOR.lock().read().free_memory());
 
     crate::log_ok!(
-        "Using largest section with: {} bytes of memory for heap at {:#X}",
-        largest_region.unwrap().len,
-        largest_region.unwrap().base
+        "Physical memory manager initialized with {free_mem} {free_mem_label} free, heap at {:#X}",
+        HEAP_START
     );
 
-    if second_largest_region.is_some() {
+    if let Some(back_buffer) = back_buffer {
         crate::log_ok!(
-            "Using second largest section with: {} bytes of memory for framebuffer mirroring at {:#X}",
-            second_largest_region.unwrap().len,
-            second_largest_region.unwrap().base
+            "Using physical memory at {:#X} for framebuffer mirroring",
+            back_buffer
         );
     }
 
+    *MEMORY_MAP.lock().write() = memmap
+        .iter()
+        .map(|entry| MemoryRegion {
+            base: entry.base,
+            length: entry.len,
+            typ: entry.typ,
+        })
+        .collect();
+
     crate::println!("====== MEMORY MAP ======");
-    for entry in memmap.iter() {
-        let label = label_units(entry.len as usize);
+    for entry in MEMORY_MAP.lock().read().iter() {
+        let label = label_units(entry.length as usize);
 
         crate::println!(
             "[ {:#018X?} ] Type: \033[{};m{:?}\033[0;m Size: {} {}",
-            entry.base..entry.base + entry.len,
+            entry.base..entry.base + entry.length,
             match entry.typ {
                 limine::MemoryMapEntryType::Usable => 32,
                 _ => 31,
@@ -177,6 +207,64 @@ pub fn init() {
             label.1
         )
     }
+
+    // Limine's page tables are in bootloader reclaimable memory. They are moved out right away,
+    // so nothing started later on, like the other CPUs, ends up using them
+    let relocated = paging::relocate_kernel_tables(|table| {
+        is_in_region(table, MemoryMapEntryType::BootloaderReclaimable)
+    });
+
+    if relocated.is_err() {
+        panic!("Failed to move the page tables out of bootloader memory!");
+    }
+}
+
+fn is_in_region(address: u64, typ: MemoryMapEntryType) -> bool {
+    return MEMORY_MAP.lock().read().iter().any(|region| {
+        region.typ == typ && (region.base..region.base + region.length).contains(&address)
+    });
+}
+
+// Gives every region of the given type to the frame allocator
+fn reclaim_regions(typ: MemoryMapEntryType) -> usize {
+    let mut reclaimed = 0;
+
+    for region in MEMORY_MAP.lock().read().iter() {
+        if region.typ == typ {
+            reclaimed += FRAME_ALLOCATOR
+                .lock()
+                .write()
+                .add_region(region.base, region.length)
+                * FRAME_SIZE as usize;
+        }
+    }
+
+    RECLAIMED_MEMORY.fetch_add(reclaimed, Ordering::SeqCst);
+
+    return reclaimed;
+}
+
+/// Hands the bootloader reclaimable memory to the frame allocator. Nothing Limine gave us can be
+/// used after this, besides the kernel, modules and framebuffer, which live in memory of their
+/// own.
+pub fn reclaim_memory() {
+    let (reclaimed, reclaimed_label) =
+        label_units(reclaim_regions(MemoryMapEntryType::BootloaderReclaimable));
+
+    crate::log_ok!("Reclaimed {reclaimed} {reclaimed_label} of bootloader memory");
+}
+
+/// Hands the ACPI reclaimable memory to the frame allocator, only to be called once every ACPI
+/// table we care about has been copied out.
+pub fn reclaim_acpi_memory() {
+    let (reclaimed, reclaimed_label) =
+        label_units(reclaim_regions(MemoryMapEntryType::AcpiReclaimable));
+
+    crate::log_ok!("Reclaimed {reclaimed} {reclaimed_label} of ACPI memory");
+}
+
+pub fn reclaimed_memory() -> usize {
+    return RECLAIMED_MEMORY.load(Ordering::SeqCst);
 }
 
 pub fn label_units(bytes: usize) -> (usize, &'static str) {
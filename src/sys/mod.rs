@@ -1,2 +1,9 @@
 pub mod allocator;
+pub mod elf;
+pub mod exec;
+pub mod frame_allocator;
 pub mod mem;
+pub mod process;
+pub mod scheduler;
+pub mod syscall;
+pub mod time;
@@ -0,0 +1,333 @@
+// State of a running program, owned by the task it runs in.
+// Every program gets its own address space, the memory backing it still comes from the kernel
+// heap and is mapped a second time into the user half. User pointers are validated against the
+// address space's page tables.
+
+use core::alloc::Layout;
+
+use alloc::{
+    alloc::{alloc_zeroed, dealloc},
+    sync::Arc,
+    vec,
+    vec::Vec,
+};
+
+use crate::arch::paging::{self, AddressSpace, PageFlags, PageSize, PagingError};
+
+use super::elf::LoadedImage;
+
+const PAGE_SIZE: usize = paging::PAGE_SIZE as usize;
+
+// Layout of a program's address space
+pub const IMAGE_ADDRESS: u64 = paging::USER_SPACE_START;
+const HEAP_ADDRESS: u64 = 0x0000_0100_0000_0000;
+const MMAP_ADDRESS: u64 = 0x0000_0200_0000_0000;
+pub const STACK_TOP: u64 = 0x0000_7FFF_FFFF_F000;
+
+// Regions come out of the kernel heap, nothing near this big could ever be backed
+const MAX_REGION_SIZE: usize = 1 << 30;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RegionError {
+    // Zero, or more than a region can be
+    InvalidSize,
+    OutOfMemory,
+}
+
+/// A zeroed, page aligned block of kernel memory that gets mapped into a process at `address`.
+pub struct UserRegion {
+    base: *mut u8,
+    layout: Layout,
+    address: u64,
+}
+
+impl UserRegion {
+    pub fn new(address: u64, size: usize) -> Result<Self, RegionError> {
+        // The size can come straight from a program
+        let size = size
+            .checked_add(PAGE_SIZE - 1)
+            .ok_or(RegionError::InvalidSize)?
+            & !(PAGE_SIZE - 1);
+
+        if size == 0 || size > MAX_REGION_SIZE {
+            return Err(RegionError::InvalidSize);
+        }
+
+        let layout =
+            Layout::from_size_align(size, PAGE_SIZE).map_err(|_| RegionError::InvalidSize)?;
+
+        let base = unsafe { alloc_zeroed(layout) };
+
+        if base.is_null() {
+            return Err(RegionError::OutOfMemory);
+        }
+
+        return Ok(Self {
+            base,
+            layout,
+            address,
+        });
+    }
+
+    /// Where the region is in the process's address space.
+    pub fn address(&self) -> u64 {
+        return self.address;
+    }
+
+    pub fn size(&self) -> usize {
+        return self.layout.size();
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        return unsafe { core::slice::from_raw_parts_mut(self.base, self.size()) };
+    }
+
+    fn map(&self, address_space: &mut AddressSpace, flags: PageFlags) -> Result<(), PagingError> {
+        return map_pages(
+            address_space,
+            self.address,
+            self.base as u64,
+            self.size(),
+            |_| flags,
+        );
+    }
+
+    fn unmap(&self, address_space: &mut AddressSpace) {
+        for offset in (0..self.size()).step_by(PAGE_SIZE) {
+            let _ = address_space.unmap(self.address + offset as u64, PageSize::Small);
+        }
+    }
+}
+
+impl Drop for UserRegion {
+    fn drop(&mut self) {
+        unsafe { dealloc(self.base, self.layout) };
+    }
+}
+
+// Maps the kernel memory at `kernel_address` to `address`. The heap is only virtually
+// contiguous, so every page is looked up on its own
+fn map_pages(
+    address_space: &mut AddressSpace,
+    address: u64,
+    kernel_address: u64,
+    size: usize,
+    flags: impl Fn(u64) -> PageFlags,
+) -> Result<(), PagingError> {
+    let kernel_space = AddressSpace::kernel();
+
+    for offset in (0..size as u64).step_by(PAGE_SIZE) {
+        let (physical_address, _) = kernel_space
+            .translate(kernel_address + offset)
+            .ok_or(PagingError::NotMapped)?;
+
+        address_space.map(
+            address + offset,
+            physical_address,
+            PageSize::Small,
+            flags(address + offset),
+        )?;
+    }
+
+    return Ok(());
+}
+
+pub enum FileDescriptor {
+    Console,
+    File { data: Arc<[u8]>, offset: usize },
+}
+
+pub struct Process {
+    pub address_space: AddressSpace,
+    // Only kept around so it is freed together with the process
+    _image: LoadedImage,
+    stack: UserRegion,
+    // Memory handed out with brk, the break starts at the beginning of the region
+    heap: UserRegion,
+    program_break: u64,
+    mappings: Vec<UserRegion>,
+    next_mapping_address: u64,
+    files: Vec<Option<FileDescriptor>>,
+    // Where the program starts running, and its stack pointer at that point
+    entry: u64,
+    initial_stack_pointer: u64,
+}
+
+impl Process {
+    /// Sets up a new address space with the image, a stack and a heap mapped into it.
+    pub fn new(image: LoadedImage, stack_size: usize, heap_size: usize) -> Result<Self, ()> {
+        let mut address_space = AddressSpace::new_user().map_err(|_| ())?;
+
+        let stack = UserRegion::new(STACK_TOP - stack_size as u64, stack_size).map_err(|_| ())?;
+        let heap = UserRegion::new(HEAP_ADDRESS, heap_size).map_err(|_| ())?;
+
+        let data_flags = PageFlags::USER | PageFlags::WRITABLE | PageFlags::NO_EXECUTE;
+
+        stack.map(&mut address_space, data_flags).map_err(|_| ())?;
+        heap.map(&mut address_space, data_flags).map_err(|_| ())?;
+
+        // Segments can share a page, so a page gets whatever every segment in it needs
+        map_pages(
+            &mut address_space,
+            image.address(),
+            image.base(),
+            image.size(),
+            |page| {
+                let mut flags = PageFlags::USER | PageFlags::NO_EXECUTE;
+
+                for segment in image.segments.iter() {
+                    let segment_start = segment.address & !(PAGE_SIZE as u64 - 1);
+
+                    if page < segment_start || page >= segment.address + segment.size {
+                        continue;
+                    }
+
+                    if segment.writable {
+                        flags = flags | PageFlags::WRITABLE;
+                    }
+
+                    if segment.executable {
+                        flags = flags.without(PageFlags::NO_EXECUTE);
+                    }
+                }
+
+                return flags;
+            },
+        )
+        .map_err(|_| ())?;
+
+        let program_break = heap.address();
+
+        return Ok(Self {
+            address_space,
+            _image: image,
+            stack,
+            heap,
+            program_break,
+            mappings: Vec::new(),
+            next_mapping_address: MMAP_ADDRESS,
+            // stdin, stdout and stderr
+            files: vec![
+                Some(FileDescriptor::Console),
+                Some(FileDescriptor::Console),
+                Some(FileDescriptor::Console),
+            ],
+            entry: 0,
+            initial_stack_pointer: 0,
+        });
+    }
+
+    pub fn set_start(&mut self, entry: u64, stack_pointer: u64) {
+        self.entry = entry;
+        self.initial_stack_pointer = stack_pointer;
+    }
+
+    /// The entry point and stack pointer the program starts with.
+    pub fn start(&self) -> (u64, u64) {
+        return (self.entry, self.initial_stack_pointer);
+    }
+
+    pub fn stack_mut(&mut self) -> &mut UserRegion {
+        return &mut self.stack;
+    }
+
+    /// Whether the process is allowed to access `length` bytes at `address`, going by its page
+    /// tables.
+    pub fn can_access(&self, address: u64, length: usize, write: bool) -> bool {
+        let end = match address.checked_add(length as u64) {
+            Some(end) => end,
+            None => return false,
+        };
+
+        if address < paging::USER_SPACE_START || end > paging::USER_SPACE_END {
+            return false;
+        }
+
+        let mut page = address & !(PAGE_SIZE as u64 - 1);
+
+        while page < end {
+            match self.address_space.translate(page) {
+                Some((_, flags))
+                    if flags.contains(PageFlags::USER)
+                        && (!write || flags.contains(PageFlags::WRITABLE)) => {}
+                _ => return false,
+            }
+
+            page += PAGE_SIZE as u64;
+        }
+
+        return true;
+    }
+
+    /// Moves the program break, returns the new break, or the old one if it couldn't be moved.
+    pub fn set_program_break(&mut self, program_break: u64) -> u64 {
+        if program_break >= self.heap.address()
+            && program_break <= self.heap.address() + self.heap.size() as u64
+        {
+            self.program_break = program_break;
+        }
+
+        return self.program_break;
+    }
+
+    pub fn map(&mut self, length: usize) -> Result<u64, RegionError> {
+        let region = UserRegion::new(self.next_mapping_address, length)?;
+
+        // Mappings go up from MMAP_ADDRESS and must not run into the stack
+        if region.address() + region.size() as u64 > self.stack.address() {
+            return Err(RegionError::OutOfMemory);
+        }
+
+        let flags = PageFlags::USER | PageFlags::WRITABLE | PageFlags::NO_EXECUTE;
+
+        if region.map(&mut self.address_space, flags).is_err() {
+            region.unmap(&mut self.address_space);
+            return Err(RegionError::OutOfMemory);
+        }
+
+        // Leave an unmapped page between mappings to catch overruns
+        self.next_mapping_address += (region.size() + PAGE_SIZE) as u64;
+
+        let address = region.address();
+        self.mappings.push(region);
+
+        return Ok(address);
+    }
+
+    pub fn unmap(&mut self, address: u64) -> Result<(), ()> {
+        let index = self
+            .mappings
+            .iter()
+            .position(|mapping| mapping.address() == address)
+            .ok_or(())?;
+
+        let region = self.mappings.remove(index);
+        region.unmap(&mut self.address_space);
+
+        return Ok(());
+    }
+
+    pub fn add_file(&mut self, file: FileDescriptor) -> usize {
+        if let Some(fd) = self.files.iter().position(|file| file.is_none()) {
+            self.files[fd] = Some(file);
+            return fd;
+        }
+
+        self.files.push(Some(file));
+        return self.files.len() - 1;
+    }
+
+    pub fn file(&mut self, fd: usize) -> Option<&mut FileDescriptor> {
+        return self.files.get_mut(fd)?.as_mut();
+    }
+
+    pub fn close_file(&mut self, fd: usize) -> Result<(), ()> {
+        match self.files.get_mut(fd) {
+            Some(file) if file.is_some() => {
+                *file = None;
+                return Ok(());
+            }
+            _ => return Err(()),
+        }
+    }
+}
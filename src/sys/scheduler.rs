@@ -0,0 +1,355 @@
+// Round robin scheduler, switching tasks on every timer tick or whenever a task gives up the CPU.
+// Every task has a kernel stack of its own, tasks running a program also own its process.
+// Task 0 is the context the kernel booted in, it only runs when nothing else can.
+
+use core::fmt;
+
+use alloc::{boxed::Box, string::String, vec, vec::Vec};
+
+use crate::{
+    arch::{self, context, gdt, paging::AddressSpace},
+    libs::mutex::Mutex,
+};
+
+use super::{process::Process, time};
+
+const KERNEL_STACK_SIZE: usize = 0x10000;
+const IDLE_TASK_ID: usize = 0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TaskState {
+    Ready,
+    Running,
+    // Until the tick count reaches the given value
+    Sleeping(u64),
+    Exited(usize),
+}
+
+impl fmt::Display for TaskState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            TaskState::Ready => write!(f, "ready"),
+            TaskState::Running => write!(f, "running"),
+            TaskState::Sleeping(_) => write!(f, "sleeping"),
+            TaskState::Exited(exit_code) => write!(f, "exited ({exit_code})"),
+        };
+    }
+}
+
+struct Task {
+    id: usize,
+    name: String,
+    state: TaskState,
+    // Where the task's context was saved the last time it was switched away from
+    stack_pointer: u64,
+    // The idle task runs on the boot stack instead
+    kernel_stack: Option<Box<[u8]>>,
+    entry: Option<fn()>,
+    process: Option<Process>,
+    // Joinable tasks stick around after exiting until someone waits on them
+    joinable: bool,
+}
+
+impl Task {
+    fn kernel_stack_top(&self) -> Option<u64> {
+        return self
+            .kernel_stack
+            .as_ref()
+            .map(|stack| stack.as_ptr() as u64 + stack.len() as u64);
+    }
+}
+
+/// A snapshot of a task, for listing them.
+pub struct TaskInfo {
+    pub id: usize,
+    pub name: String,
+    pub state: TaskState,
+}
+
+struct Scheduler {
+    tasks: Vec<Box<Task>>,
+    // ID of the running task
+    current: usize,
+    next_id: usize,
+}
+
+impl Scheduler {
+    fn index_of(&self, id: usize) -> Option<usize> {
+        return self.tasks.iter().position(|task| task.id == id);
+    }
+
+    fn current_task(&mut self) -> Option<&mut Task> {
+        let index = self.index_of(self.current)?;
+        return Some(&mut self.tasks[index]);
+    }
+}
+
+static SCHEDULER: Mutex<Scheduler> = Mutex::new(Scheduler {
+    tasks: Vec::new(),
+    current: IDLE_TASK_ID,
+    next_id: IDLE_TASK_ID + 1,
+});
+
+// Picks the next task to run and switches to it, interrupts have to be disabled
+fn schedule() {
+    let scheduler = SCHEDULER.lock().write();
+
+    let current_index = match scheduler.index_of(scheduler.current) {
+        Some(index) => index,
+        None => return,
+    };
+
+    let task_count = scheduler.tasks.len();
+
+    let next_index = (1..=task_count)
+        .map(|offset| (current_index + offset) % task_count)
+        .find(|&index| {
+            let task = &scheduler.tasks[index];
+            task.id != IDLE_TASK_ID && task.state == TaskState::Ready
+        });
+
+    let next_index = match next_index {
+        Some(index) => index,
+        None if scheduler.tasks[current_index].state == TaskState::Running => return,
+        None => match scheduler.index_of(IDLE_TASK_ID) {
+            Some(index) => index,
+            None => return,
+        },
+    };
+
+    if next_index == current_index {
+        return;
+    }
+
+    let current_task = &mut scheduler.tasks[current_index];
+
+    if current_task.state == TaskState::Running {
+        current_task.state = TaskState::Ready;
+    }
+
+    let old_stack_pointer = core::ptr::addr_of_mut!(current_task.stack_pointer);
+
+    let next_task = &mut scheduler.tasks[next_index];
+    next_task.state = TaskState::Running;
+
+    if let Some(stack_top) = next_task.kernel_stack_top() {
+        gdt::set_kernel_stack(stack_top);
+    }
+
+    match &next_task.process {
+        Some(process) if !process.address_space.is_active() => process.address_space.activate(),
+        Some(_) => {}
+        None => {
+            let kernel_space = AddressSpace::kernel();
+
+            if !kernel_space.is_active() {
+                kernel_space.activate();
+            }
+        }
+    }
+
+    let new_stack_pointer = next_task.stack_pointer;
+    scheduler.current = next_task.id;
+
+    unsafe { context::switch_context(old_stack_pointer, new_stack_pointer) };
+}
+
+// Every new task starts here, with interrupts still disabled from the switch
+extern "C" fn task_start() -> ! {
+    let entry = SCHEDULER
+        .lock()
+        .write()
+        .current_task()
+        .and_then(|task| task.entry.take());
+
+    arch::enable_interrupts();
+
+    if let Some(entry) = entry {
+        entry();
+    }
+
+    exit(0);
+}
+
+fn add_task(name: &str, entry: fn(), process: Option<Process>, joinable: bool) -> usize {
+    let kernel_stack = vec![0u8; KERNEL_STACK_SIZE].into_boxed_slice();
+    let stack_top = kernel_stack.as_ptr() as u64 + KERNEL_STACK_SIZE as u64;
+
+    let mut task = Box::new(Task {
+        id: 0,
+        name: name.into(),
+        state: TaskState::Ready,
+        stack_pointer: context::initial_stack_pointer(stack_top, task_start),
+        kernel_stack: Some(kernel_stack),
+        entry: Some(entry),
+        process,
+        joinable,
+    });
+
+    return arch::without_interrupts(|| {
+        let scheduler = SCHEDULER.lock().write();
+
+        task.id = scheduler.next_id;
+        scheduler.next_id += 1;
+
+        let id = task.id;
+        scheduler.tasks.push(task);
+
+        return id;
+    });
+}
+
+/// Starts a kernel task running `entry`, it is cleaned up on its own once it exits.
+pub fn spawn(name: &str, entry: fn()) -> usize {
+    return add_task(name, entry, None, false);
+}
+
+/// Starts a task that runs `entry` in the address space of `process`. The task has to be
+/// waited on.
+pub fn spawn_process(name: &str, process: Process, entry: fn()) -> usize {
+    return add_task(name, entry, Some(process), true);
+}
+
+/// Gives the CPU to the next task that is ready to run, if there is one.
+pub fn yield_now() {
+    arch::without_interrupts(schedule);
+}
+
+/// Puts the running task to sleep for at least `ticks` timer ticks.
+pub fn sleep(ticks: u64) {
+    arch::without_interrupts(|| {
+        let wake_at = time::ticks() + ticks;
+
+        if let Some(task) = SCHEDULER.lock().write().current_task() {
+            task.state = TaskState::Sleeping(wake_at);
+        }
+
+        schedule();
+    });
+}
+
+/// Ends the running task.
+pub fn exit(exit_code: usize) -> ! {
+    arch::disable_interrupts();
+
+    if let Some(task) = SCHEDULER.lock().write().current_task() {
+        task.state = TaskState::Exited(exit_code);
+    }
+
+    schedule();
+
+    panic!("Exited task was scheduled again!");
+}
+
+/// Blocks until the task with `id` exits and returns its exit code. Returns None if there is no
+/// such task.
+pub fn wait(id: usize) -> Option<usize> {
+    loop {
+        let state = arch::without_interrupts(|| {
+            let scheduler = SCHEDULER.lock().write();
+            let index = scheduler.index_of(id)?;
+            let state = scheduler.tasks[index].state;
+
+            if let TaskState::Exited(_) = state {
+                // Frees the task's stack and process
+                scheduler.tasks.remove(index);
+            }
+
+            return Some(state);
+        });
+
+        match state {
+            Some(TaskState::Exited(exit_code)) => return Some(exit_code),
+            Some(_) => sleep(1),
+            None => return None,
+        }
+    }
+}
+
+/// Called on every timer interrupt, wakes up sleeping tasks and preempts the running one.
+pub fn tick() {
+    let ticks = time::ticks();
+
+    for task in SCHEDULER.lock().write().tasks.iter_mut() {
+        if let TaskState::Sleeping(wake_at) = task.state {
+            if wake_at <= ticks {
+                task.state = TaskState::Ready;
+            }
+        }
+    }
+
+    schedule();
+}
+
+/// Whether `init` has been called, before that there is nothing to switch between.
+pub fn is_running() -> bool {
+    return !SCHEDULER.lock().read().tasks.is_empty();
+}
+
+pub fn current_id() -> usize {
+    return SCHEDULER.lock().read().current;
+}
+
+/// The process of the running task, None for kernel tasks.
+pub fn current_process() -> Option<&'static mut Process> {
+    return SCHEDULER.lock().write().current_task()?.process.as_mut();
+}
+
+pub fn tasks() -> Vec<TaskInfo> {
+    return arch::without_interrupts(|| {
+        return SCHEDULER
+            .lock()
+            .read()
+            .tasks
+            .iter()
+            .map(|task| TaskInfo {
+                id: task.id,
+                name: task.name.clone(),
+                state: task.state,
+            })
+            .collect();
+    });
+}
+
+/// Turns the running context into the idle task, has to be called before anything is spawned.
+pub fn init() {
+    SCHEDULER.lock().write().tasks.push(Box::new(Task {
+        id: IDLE_TASK_ID,
+        name: "idle".into(),
+        state: TaskState::Running,
+        stack_pointer: 0,
+        kernel_stack: None,
+        entry: None,
+        process: None,
+        joinable: false,
+    }));
+
+    crate::log_ok!("Scheduler initialized");
+}
+
+/// What the idle task does forever, cleans up after exited tasks while waiting for interrupts.
+pub fn idle() -> ! {
+    loop {
+        arch::without_interrupts(|| {
+            let scheduler = SCHEDULER.lock().write();
+            let current = scheduler.current;
+
+            scheduler.tasks.retain(|task| {
+                task.id == current || task.joinable || !matches!(task.state, TaskState::Exited(_))
+            });
+        });
+
+        arch::halt();
+    }
+}
+
+/// Where the application processors go once they are up. Tasks are only scheduled on the
+/// bootstrap processor for now, so they wait for interrupts like its idle task does, without the
+/// cleanup, which would touch the heap.
+pub fn ap_idle() -> ! {
+    arch::enable_interrupts();
+
+    loop {
+        arch::halt();
+    }
+}
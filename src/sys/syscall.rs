@@ -0,0 +1,262 @@
+// System call numbers and dispatching, the numbers match Linux on x86_64.
+// The number goes in rax, the arguments in rdi, rsi, rdx, r10 and r8. The result comes back in
+// rax, where a value between -4095 and -1 is a negated errno.
+
+use alloc::{string::String, sync::Arc};
+
+use crate::drivers::fs::vfs::{self, VfsError, VfsNodeType};
+
+use super::{
+    process::{FileDescriptor, Process, RegionError},
+    scheduler,
+};
+
+pub const SYS_READ: u64 = 0;
+pub const SYS_WRITE: u64 = 1;
+pub const SYS_OPEN: u64 = 2;
+pub const SYS_CLOSE: u64 = 3;
+pub const SYS_MMAP: u64 = 9;
+pub const SYS_MUNMAP: u64 = 11;
+pub const SYS_BRK: u64 = 12;
+pub const SYS_YIELD: u64 = 24;
+pub const SYS_GETPID: u64 = 39;
+pub const SYS_EXIT: u64 = 60;
+
+// open flags
+const O_ACCMODE: u64 = 0o3;
+const O_CREAT: u64 = 0o100;
+
+// mmap flags
+const MAP_FIXED: u64 = 0x10;
+const MAP_ANONYMOUS: u64 = 0x20;
+
+const PATH_MAX: usize = 4096;
+
+#[repr(i64)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Errno {
+    NotPermitted = 1,        // EPERM
+    NoSuchFile = 2,          // ENOENT
+    IoError = 5,             // EIO
+    BadFileDescriptor = 9,   // EBADF
+    OutOfMemory = 12,        // ENOMEM
+    BadAddress = 14,         // EFAULT
+    NotADirectory = 20,      // ENOTDIR
+    IsADirectory = 21,       // EISDIR
+    InvalidArgument = 22,    // EINVAL
+    ReadOnlyFileSystem = 30, // EROFS
+    NameTooLong = 36,        // ENAMETOOLONG
+    NotImplemented = 38,     // ENOSYS
+}
+
+impl From<VfsError> for Errno {
+    fn from(err: VfsError) -> Self {
+        return match err {
+            VfsError::NotFound => Errno::NoSuchFile,
+            VfsError::FileExists => Errno::InvalidArgument,
+            VfsError::NotADirectory => Errno::NotADirectory,
+            VfsError::IsADirectory => Errno::IsADirectory,
+            VfsError::ReadOnly => Errno::ReadOnlyFileSystem,
+            VfsError::InvalidPath => Errno::NoSuchFile,
+            VfsError::IoError => Errno::IoError,
+        };
+    }
+}
+
+/// Checks that the current process can access the memory it passed us. When there is no process
+/// the call came from the kernel itself, which is trusted.
+fn check_user_buffer(
+    process: &Option<&mut Process>,
+    address: u64,
+    length: usize,
+    write: bool,
+) -> Result<(), Errno> {
+    // Nothing is accessed through an empty buffer, so any address will do, NULL included
+    if length == 0 {
+        return Ok(());
+    }
+
+    if let Some(process) = process {
+        if address == 0 || !process.can_access(address, length, write) {
+            return Err(Errno::BadAddress);
+        }
+    }
+
+    return Ok(());
+}
+
+fn user_slice<'a>(
+    process: &Option<&mut Process>,
+    address: u64,
+    length: usize,
+) -> Result<&'a [u8], Errno> {
+    check_user_buffer(process, address, length, false)?;
+
+    // Slices can't be made from NULL, not even empty ones
+    if length == 0 {
+        return Ok(&[]);
+    }
+
+    return Ok(unsafe { core::slice::from_raw_parts(address as *const u8, length) });
+}
+
+fn user_slice_mut<'a>(
+    process: &Option<&mut Process>,
+    address: u64,
+    length: usize,
+) -> Result<&'a mut [u8], Errno> {
+    check_user_buffer(process, address, length, true)?;
+
+    // Slices can't be made from NULL, not even empty ones
+    if length == 0 {
+        return Ok(&mut []);
+    }
+
+    return Ok(unsafe { core::slice::from_raw_parts_mut(address as *mut u8, length) });
+}
+
+/// Reads a NUL terminated string out of user memory.
+fn user_string(process: &Option<&mut Process>, address: u64) -> Result<String, Errno> {
+    let mut bytes = alloc::vec::Vec::new();
+
+    loop {
+        if bytes.len() >= PATH_MAX {
+            return Err(Errno::NameTooLong);
+        }
+
+        let byte = user_slice(process, address + bytes.len() as u64, 1)?[0];
+
+        if byte == 0 {
+            break;
+        }
+
+        bytes.push(byte);
+    }
+
+    return String::from_utf8(bytes).map_err(|_| Errno::InvalidArgument);
+}
+
+fn sys_read(process: Option<&mut Process>, fd: u64, buf: u64, count: u64) -> Result<u64, Errno> {
+    let buffer = user_slice_mut(&process, buf, count as usize)?;
+    let process = process.ok_or(Errno::BadFileDescriptor)?;
+
+    match process.file(fd as usize) {
+        // The shell owns the keyboard while a program runs, so there is never any input
+        Some(FileDescriptor::Console) => return Ok(0),
+        Some(FileDescriptor::File { data, offset }) => {
+            let remaining = data.get(*offset..).unwrap_or(&[]);
+            let length = remaining.len().min(buffer.len());
+
+            buffer[..length].copy_from_slice(&remaining[..length]);
+            *offset += length;
+
+            return Ok(length as u64);
+        }
+        None => return Err(Errno::BadFileDescriptor),
+    }
+}
+
+fn sys_write(process: Option<&mut Process>, fd: u64, buf: u64, count: u64) -> Result<u64, Errno> {
+    let buffer = user_slice(&process, buf, count as usize)?;
+
+    let is_console = match process {
+        Some(process) => match process.file(fd as usize) {
+            Some(FileDescriptor::Console) => true,
+            // Nothing we can open is writable yet
+            Some(FileDescriptor::File { .. }) => return Err(Errno::BadFileDescriptor),
+            None => return Err(Errno::BadFileDescriptor),
+        },
+        None => fd == 1 || fd == 2,
+    };
+
+    if !is_console {
+        return Err(Errno::BadFileDescriptor);
+    }
+
+    crate::print!("{}", String::from_utf8_lossy(buffer));
+
+    return Ok(count);
+}
+
+fn sys_open(process: Option<&mut Process>, path: u64, flags: u64) -> Result<u64, Errno> {
+    let path = user_string(&process, path)?;
+    let process = process.ok_or(Errno::NotPermitted)?;
+
+    if flags & O_ACCMODE != 0 || flags & O_CREAT != 0 {
+        return Err(Errno::ReadOnlyFileSystem);
+    }
+
+    if vfs::stat(&path)?.node_type == VfsNodeType::Directory {
+        return Err(Errno::IsADirectory);
+    }
+
+    let data: Arc<[u8]> = vfs::read(&path)?;
+
+    return Ok(process.add_file(FileDescriptor::File { data, offset: 0 }) as u64);
+}
+
+fn sys_close(process: Option<&mut Process>, fd: u64) -> Result<u64, Errno> {
+    let process = process.ok_or(Errno::BadFileDescriptor)?;
+
+    process
+        .close_file(fd as usize)
+        .map_err(|_| Errno::BadFileDescriptor)?;
+
+    return Ok(0);
+}
+
+fn sys_mmap(process: Option<&mut Process>, length: u64, flags: u64) -> Result<u64, Errno> {
+    let process = process.ok_or(Errno::NotPermitted)?;
+
+    // Only anonymous memory for now, and we pick the address
+    if length == 0 || flags & MAP_ANONYMOUS == 0 || flags & MAP_FIXED != 0 {
+        return Err(Errno::InvalidArgument);
+    }
+
+    return process.map(length as usize).map_err(|err| match err {
+        RegionError::InvalidSize => Errno::InvalidArgument,
+        RegionError::OutOfMemory => Errno::OutOfMemory,
+    });
+}
+
+fn sys_munmap(process: Option<&mut Process>, address: u64) -> Result<u64, Errno> {
+    let process = process.ok_or(Errno::NotPermitted)?;
+
+    process.unmap(address).map_err(|_| Errno::InvalidArgument)?;
+
+    return Ok(0);
+}
+
+pub fn dispatch(number: u64, arg0: u64, arg1: u64, arg2: u64, arg3: u64, _arg4: u64) -> i64 {
+    if number == SYS_EXIT {
+        scheduler::exit(arg0 as usize);
+    }
+
+    let process = scheduler::current_process();
+
+    let result = match number {
+        SYS_READ => sys_read(process, arg0, arg1, arg2),
+        SYS_WRITE => sys_write(process, arg0, arg1, arg2),
+        SYS_OPEN => sys_open(process, arg0, arg1),
+        SYS_CLOSE => sys_close(process, arg0),
+        // The address hint, protection and file descriptor are ignored
+        SYS_MMAP => sys_mmap(process, arg1, arg3),
+        SYS_MUNMAP => sys_munmap(process, arg0),
+        SYS_BRK => match process {
+            Some(process) => Ok(process.set_program_break(arg0)),
+            None => Err(Errno::NotPermitted),
+        },
+        SYS_YIELD => {
+            scheduler::yield_now();
+            Ok(0)
+        }
+        // A program's PID is the ID of the task it runs in, the kernel is 0
+        SYS_GETPID => Ok(process.map_or(0, |_| scheduler::current_id() as u64)),
+        _ => Err(Errno::NotImplemented),
+    };
+
+    return match result {
+        Ok(value) => value as i64,
+        Err(errno) => -(errno as i64),
+    };
+}
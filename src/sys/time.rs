@@ -0,0 +1,165 @@
+// Monotonic time since boot, counted in ticks of the system timer. The PIT drives the ticks for
+// now, anything else that calls `tick` at `frequency()` Hz would do just as well.
+
+use core::{
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use alloc::vec::Vec;
+
+use crate::{arch, libs::mutex::Mutex};
+
+use super::scheduler;
+
+// What we ask the timer for, the real frequency is whatever it could get closest to
+const TARGET_FREQUENCY: u32 = 1000;
+
+static TICKS: AtomicU64 = AtomicU64::new(0);
+static FREQUENCY: AtomicU64 = AtomicU64::new(0);
+
+struct Timer {
+    id: usize,
+    // Tick at which the callback runs
+    deadline: u64,
+    callback: fn(),
+}
+
+static TIMERS: Mutex<Vec<Timer>> = Mutex::new(Vec::new());
+static NEXT_TIMER_ID: AtomicUsize = AtomicUsize::new(1);
+
+pub fn init() {
+    let frequency = arch::pit::set_frequency(TARGET_FREQUENCY);
+    FREQUENCY.store(frequency as u64, Ordering::SeqCst);
+
+    crate::log_ok!("System timer running at {} Hz", frequency);
+}
+
+/// Called by the timer interrupt, runs any timer callbacks that are due.
+pub fn tick() {
+    let ticks = TICKS.fetch_add(1, Ordering::SeqCst) + 1;
+
+    let timers = TIMERS.lock().write();
+
+    while let Some(index) = timers.iter().position(|timer| timer.deadline <= ticks) {
+        let timer = timers.swap_remove(index);
+        (timer.callback)();
+    }
+}
+
+pub fn ticks() -> u64 {
+    return TICKS.load(Ordering::SeqCst);
+}
+
+/// How many ticks there are in a second.
+pub fn frequency() -> u64 {
+    return FREQUENCY.load(Ordering::SeqCst);
+}
+
+/// Converts milliseconds into ticks, rounding up so waits are never cut short.
+pub fn ms_to_ticks(ms: u64) -> u64 {
+    return (ms * frequency()).div_ceil(1000);
+}
+
+pub fn uptime() -> Duration {
+    let frequency = frequency();
+
+    if frequency == 0 {
+        return Duration::ZERO;
+    }
+
+    let ticks = ticks();
+
+    return Duration::from_secs(ticks / frequency)
+        + Duration::from_nanos((ticks % frequency) * 1_000_000_000 / frequency);
+}
+
+pub fn uptime_ms() -> u64 {
+    return uptime().as_millis() as u64;
+}
+
+/// When a wait has to give up. The clock only moves while interrupts are enabled, so the PIT's
+/// counter is read as well every time the deadline is checked, and whichever says more time went
+/// by wins. Waits that check their deadline less than once a timer period, with interrupts
+/// disabled, take longer than asked for.
+pub struct Deadline {
+    ms: u64,
+    start_ms: u64,
+    // PIT clocks seen go by while checking, and the count they were last read at
+    pit_clocks: u64,
+    pit_count: u16,
+}
+
+impl Deadline {
+    pub fn after_ms(ms: u64) -> Self {
+        return Self {
+            ms,
+            start_ms: uptime_ms(),
+            pit_clocks: 0,
+            pit_count: arch::pit::count(),
+        };
+    }
+
+    pub fn has_passed(&mut self) -> bool {
+        let count = arch::pit::count();
+        self.pit_clocks += arch::pit::clocks_between(self.pit_count, count) as u64;
+        self.pit_count = count;
+
+        let pit_ms = self.pit_clocks * 1000 / arch::pit::BASE_FREQUENCY as u64;
+        let clock_ms = uptime_ms() - self.start_ms;
+
+        return pit_ms.max(clock_ms) > self.ms;
+    }
+}
+
+/// Spins for `ms` milliseconds, with or without interrupts.
+pub fn busy_sleep_ms(ms: u64) {
+    let mut deadline = Deadline::after_ms(ms);
+
+    while !deadline.has_passed() {
+        arch::pause();
+    }
+}
+
+/// Puts the running task to sleep for `ms` milliseconds, spins instead if there is no scheduler
+/// to give the CPU to yet.
+pub fn sleep_ms(ms: u64) {
+    if !scheduler::is_running() {
+        busy_sleep_ms(ms);
+        return;
+    }
+
+    scheduler::sleep(ms_to_ticks(ms));
+}
+
+/// Calls `callback` once, `delay_ms` milliseconds from now. The callback runs inside of the
+/// timer interrupt, so it has to be quick and can't sleep. Returns an ID to cancel it with.
+pub fn add_timer(delay_ms: u64, callback: fn()) -> usize {
+    let id = NEXT_TIMER_ID.fetch_add(1, Ordering::SeqCst);
+    let deadline = ticks() + ms_to_ticks(delay_ms).max(1);
+
+    arch::without_interrupts(|| {
+        TIMERS.lock().write().push(Timer {
+            id,
+            deadline,
+            callback,
+        });
+    });
+
+    return id;
+}
+
+/// Stops a timer from going off, returns false if it already has.
+pub fn cancel_timer(id: usize) -> bool {
+    return arch::without_interrupts(|| {
+        let timers = TIMERS.lock().write();
+
+        match timers.iter().position(|timer| timer.id == id) {
+            Some(index) => {
+                timers.swap_remove(index);
+                return true;
+            }
+            None => return false,
+        }
+    });
+}
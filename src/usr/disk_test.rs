@@ -0,0 +1,83 @@
+// Built in with the disk-test feature, for `make run-disk-test` and `make run-ahci-test`.
+// Runs disktest's round trip against the scratch disk at boot, then reports the result through
+// QEMU's isa-debug-exit device so make can check it. Without the device boot just carries on.
+
+use alloc::{sync::Arc, vec::Vec};
+
+use crate::{
+    arch::io::outb,
+    drivers::storage::{self, drive::BlockDevice},
+};
+
+// Where the Makefile puts the isa-debug-exit device
+const DEBUG_EXIT_PORT: u16 = 0xF4;
+
+// QEMU exits with (value << 1) | 1, so 33 and 35
+const DEBUG_EXIT_PASSED: u8 = 0x10;
+const DEBUG_EXIT_FAILED: u8 = 0x11;
+
+// The blank image the Makefile makes. Drives are picked by what's on them rather than by the
+// order they were found in, so the one we booted from can never be written to
+const TEST_DISK_SIZE: u64 = 16 * 1024 * 1024;
+// At the end of the first sector of anything with an MBR, protective ones included
+const BOOT_SIGNATURE: [u8; 2] = [0x55, 0xAA];
+
+const TEST_SECTOR: u64 = 0;
+const TEST_SECTOR_COUNT: u64 = 16;
+
+fn is_scratch_disk(device: &dyn BlockDevice) -> bool {
+    if device.is_read_only()
+        || device.sector_count() * device.sector_size() as u64 != TEST_DISK_SIZE
+    {
+        return false;
+    }
+
+    return match device.read(0, 1) {
+        Ok(sector) => sector[510..512] != BOOT_SIGNATURE,
+        Err(()) => false,
+    };
+}
+
+// Anything but exactly one candidate means the setup isn't what we expect, so nothing is written
+fn find_scratch_disk() -> Option<Arc<dyn BlockDevice>> {
+    let mut candidates: Vec<_> = storage::block_devices()
+        .into_iter()
+        .filter(|device| is_scratch_disk(device.as_ref()))
+        .collect();
+
+    if candidates.len() != 1 {
+        crate::log_error!(
+            "Disk test: expected one blank {} MiB drive, found {}",
+            TEST_DISK_SIZE / 1024 / 1024,
+            candidates.len()
+        );
+        return None;
+    }
+
+    return candidates.pop();
+}
+
+pub fn run() {
+    let passed = match find_scratch_disk() {
+        Some(device) => {
+            match storage::round_trip(device.as_ref(), TEST_SECTOR, TEST_SECTOR_COUNT) {
+                Ok(()) => true,
+                Err(err) => {
+                    crate::log_error!("Disk test: {}", err);
+                    false
+                }
+            }
+        }
+        None => false,
+    };
+
+    if passed {
+        crate::log_ok!(
+            "Disk test: {} sectors written and read back",
+            TEST_SECTOR_COUNT
+        );
+        outb(DEBUG_EXIT_PORT, DEBUG_EXIT_PASSED);
+    } else {
+        outb(DEBUG_EXIT_PORT, DEBUG_EXIT_FAILED);
+    }
+}
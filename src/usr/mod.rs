@@ -1,2 +1,4 @@
+#[cfg(feature = "disk-test")]
+pub mod disk_test;
 pub mod shell;
 pub mod tty;
@@ -108,6 +108,10 @@ impl ModStatusBits {
 static MOD_STATUS: ModStatusBits = ModStatusBits::new();
 
 pub fn init_shell() {
+    crate::sys::scheduler::spawn("shell", run_shell);
+}
+
+fn run_shell() {
     prompt();
 
     #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
@@ -119,6 +123,15 @@ pub fn init_shell() {
 
     // #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
     // crate::drivers::keyboard::consume_scancode();
+
+    loop {
+        while let Some(key) = crate::drivers::keyboard::read_key() {
+            handle_key(key);
+        }
+
+        // Nothing to do until the next key comes in
+        crate::sys::time::sleep_ms(10);
+    }
 }
 
 pub fn handle_key(mut key: Key) {
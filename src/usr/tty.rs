@@ -6,7 +6,6 @@ use alloc::{
     string::String,
     vec::Vec,
 };
-use limine::{MemmapEntry, NonNullPtr};
 
 use crate::libs::{bit_manipulator::BitManipulator, mutex::Mutex};
 
@@ -44,7 +43,9 @@ impl Console {
         }
     }
 
-    pub fn reinit(&self, back_buffer_region: Option<&NonNullPtr<MemmapEntry>>) {
+    /// `back_buffer_address` is the physical address of memory big enough to mirror the
+    /// framebuffer in.
+    pub fn reinit(&self, back_buffer_address: Option<u64>) {
         let framebuffer = crate::drivers::video::get_framebuffer();
 
         // Enable serial if it initialized correctly
@@ -59,11 +60,12 @@ impl Console {
             return;
         }
 
-        if back_buffer_region.is_some() {
+        if back_buffer_address.is_some() {
             self.feature_bits.lock().write().set_bit(2);
             let mut back_buffer = crate::drivers::video::get_framebuffer().unwrap();
 
-            back_buffer.pointer = back_buffer_region.unwrap().base as *mut u8;
+            back_buffer.pointer =
+                (back_buffer_address.unwrap() + crate::arch::paging::hhdm_offset()) as *mut u8;
 
             let row_size = back_buffer.pitch / (back_buffer.bpp / 8);
 
@@ -71,7 +73,7 @@ impl Console {
 
             unsafe {
                 crate::arch::set_mtrr(
-                    back_buffer_region.unwrap().base as u64,
+                    back_buffer_address.unwrap(),
                     screen_size as u64,
                     crate::arch::MTRRMode::WriteCombining,
                 );
@@ -306,17 +308,10 @@ impl Cursor {
     }
 
     fn move_right(&self) {
-        let framebuffer_response = crate::drivers::video::FRAMEBUFFER_REQUEST
-            .get_response()
-            .get();
-
-        if framebuffer_response.is_none() {
-            return;
-        }
-
-        // eww, variable redeclaration
-        let framebuffer_response = framebuffer_response.unwrap();
-        let framebuffer = &framebuffer_response.framebuffers()[0];
+        let framebuffer = match crate::drivers::video::get_framebuffer() {
+            Some(framebuffer) => framebuffer,
+            None => return,
+        };
 
         if self.cx.load(Ordering::SeqCst) == (framebuffer.width / 8) as u16 - 1 {
             if self.cy.load(Ordering::SeqCst) == (framebuffer.height / 16) as u16 - 1 {
@@ -335,17 +330,10 @@ impl Cursor {
     }
 
     fn move_left(&self) {
-        let framebuffer_response = crate::drivers::video::FRAMEBUFFER_REQUEST
-            .get_response()
-            .get();
-
-        if framebuffer_response.is_none() {
-            return;
-        }
-
-        // eww, variable redeclaration
-        let framebuffer_response = framebuffer_response.unwrap();
-        let framebuffer = &framebuffer_response.framebuffers()[0];
+        let framebuffer = match crate::drivers::video::get_framebuffer() {
+            Some(framebuffer) => framebuffer,
+            None => return,
+        };
 
         if self.cx.load(Ordering::SeqCst) == 0 {
             self.cx
@@ -502,9 +490,24 @@ pub fn exec(command: &str) {
         let (free_mem, free_mem_label) = crate::sys::mem::label_units(allocator.get_free_mem());
         let (total_mem, total_mem_label) = crate::sys::mem::label_units(allocator.get_total_mem());
 
+        let frame_allocator = crate::sys::frame_allocator::FRAME_ALLOCATOR.lock().read();
+
+        let (free_frames, free_frames_label) =
+            crate::sys::mem::label_units(frame_allocator.free_memory());
+        let (total_frames, total_frames_label) =
+            crate::sys::mem::label_units(frame_allocator.total_memory());
+
         println!(
             "Allocated so far: {used_mem} {used_mem_label}\nFree memory: {free_mem} {free_mem_label}\nTotal Memory: {total_mem} {total_mem_label}",
         );
+        println!(
+            "Heap arenas: {}\nFree physical memory: {free_frames} {free_frames_label}\nTotal physical memory: {total_frames} {total_frames_label}",
+            allocator.arena_count()
+        );
+
+        let (reclaimed, reclaimed_label) =
+            crate::sys::mem::label_units(crate::sys::mem::reclaimed_memory());
+        println!("Reclaimed from the bootloader and ACPI: {reclaimed} {reclaimed_label}");
         return;
     }
 
@@ -659,24 +662,308 @@ pub fn exec(command: &str) {
         return;
     }
 
+    if command == "ls" {
+        let mut path = "/";
+
+        if args.len() != 0 {
+            path = args[0].as_str();
+        }
+
+        match crate::drivers::fs::vfs::list_directory(path) {
+            Ok(entries) => {
+                for entry in entries {
+                    let modified = match entry.stat.modified {
+                        Some(timestamp) => {
+                            format!(
+                                "{}  ",
+                                crate::drivers::rtc::DateTime::from_unix_timestamp(timestamp)
+                            )
+                        }
+                        None => String::new(),
+                    };
+
+                    if entry.stat.node_type == crate::drivers::fs::vfs::VfsNodeType::Directory {
+                        println!("{}\033[94m{}/\033[0m", modified, entry.name);
+                    } else {
+                        println!("{}{} ({} bytes)", modified, entry.name, entry.stat.size);
+                    }
+                }
+            }
+            Err(err) => println!("ls: {}: {:?}", path, err),
+        }
+
+        return;
+    }
+
+    if command == "cat" {
+        if args.len() == 0 {
+            println!("cat: usage error: file path required!");
+            return;
+        }
+
+        match crate::drivers::fs::vfs::read(args[0].as_str()) {
+            Ok(data) => CONSOLE.puts(&String::from_utf8_lossy(&data)),
+            Err(err) => println!("cat: {}: {:?}", args[0], err),
+        }
+
+        return;
+    }
+
+    if command == "uptime" {
+        let uptime = crate::sys::time::uptime();
+
+        println!(
+            "Up for {}.{:03} seconds",
+            uptime.as_secs(),
+            uptime.subsec_millis()
+        );
+        return;
+    }
+
+    if command == "date" {
+        let now = crate::drivers::rtc::now();
+
+        println!(
+            "{}",
+            crate::drivers::rtc::DateTime::from_unix_timestamp(now)
+        );
+        return;
+    }
+
+    if command == "ps" {
+        println!("{:>5}  {:<14}  NAME", "PID", "STATE");
+
+        for task in crate::sys::scheduler::tasks() {
+            println!(
+                "{:>5}  {:<14}  {}",
+                task.id,
+                format!("{}", task.state),
+                task.name
+            );
+        }
+
+        return;
+    }
+
+    if command == "acpi" {
+        use crate::drivers::acpi::{aml, signature_str};
+
+        if args.first().map(|arg| arg.as_str()) == Some("namespace") {
+            for (name, object) in aml::namespace() {
+                let segment = match name.last() {
+                    Some(segment) => segment,
+                    None => continue,
+                };
+
+                println!(
+                    "{:indent$}{}  {}",
+                    "",
+                    aml::name::segment_str(&segment),
+                    object,
+                    indent = (name.depth() - 1) * 2
+                );
+            }
+
+            return;
+        }
+
+        if args.first().map(|arg| arg.as_str()) == Some("eval") {
+            if args.len() < 2 {
+                println!("acpi: usage error: object path required!");
+                return;
+            }
+
+            match aml::evaluate_path(args[1].as_str()) {
+                Ok(aml::AmlValue::Package(elements)) => {
+                    println!("Package ({} elements)", elements.len());
+
+                    for element in elements {
+                        println!("  {}", element);
+                    }
+                }
+                Ok(aml::AmlValue::Buffer(bytes)) => {
+                    println!("Buffer ({} bytes)", bytes.len());
+
+                    for line in bytes.chunks(16) {
+                        let hex: Vec<String> =
+                            line.iter().map(|byte| format!("{:02X}", byte)).collect();
+                        println!("  {}", hex.join(" "));
+                    }
+                }
+                Ok(value) => println!("{}", value),
+                Err(err) => println!("acpi: {}: {:?}", args[1], err),
+            }
+
+            return;
+        }
+
+        println!(
+            "{:<4}  {:<6}  {:<8}  {:>3}  {:>6}  ADDRESS",
+            "SIG", "OEM", "OEM TAB", "REV", "LENGTH"
+        );
+
+        for table in crate::drivers::acpi::tables() {
+            println!(
+                "{:<4}  {:<6}  {:<8}  {:>3}  {:>6}  {:#X}",
+                signature_str(&table.signature),
+                signature_str(&table.oem_id),
+                signature_str(&table.oem_table_id),
+                table.revision,
+                table.length,
+                table.physical_address
+            );
+        }
+
+        return;
+    }
+
+    if command == "cpus" {
+        println!("{:>3}  {:>7}  STATE", "CPU", "APIC ID");
+
+        for cpu in crate::arch::smp::cpus() {
+            println!(
+                "{:>3}  {:>7}  {}{}",
+                cpu.id,
+                cpu.apic_id,
+                cpu.state,
+                if cpu.bootstrap { " (bootstrap)" } else { "" }
+            );
+        }
+
+        return;
+    }
+
+    if command == "lspci" {
+        let verbose = args.first().map(|arg| arg.as_str()) == Some("-v");
+
+        println!(
+            "{:<7}  {:<9}  {:<8}  {:<24}  DRIVER",
+            "SLOT", "ID", "CLASS", "DESCRIPTION"
+        );
+
+        for device in crate::drivers::pci::devices() {
+            println!(
+                "{:02X}:{:02X}.{}  {:04X}:{:04X}  {:02X}.{:02X}.{:02X}  {:<24}  {}",
+                device.bus,
+                device.device,
+                device.func,
+                device.vendor_id,
+                device.device_id,
+                device.class_code,
+                device.subclass_code,
+                device.prog_if,
+                device.class_name(),
+                device.driver.unwrap_or("-")
+            );
+
+            if !verbose {
+                continue;
+            }
+
+            for (i, bar) in device.bars.iter().enumerate() {
+                if let Some(bar) = bar {
+                    println!("         BAR{}: {}", i, bar);
+                }
+            }
+
+            if !device.capabilities.is_empty() {
+                let capabilities: Vec<String> = device
+                    .capabilities
+                    .iter()
+                    .map(|capability| format!("{}", capability))
+                    .collect();
+
+                println!("         Capabilities: {}", capabilities.join(", "));
+            }
+        }
+
+        return;
+    }
+
+    if command == "disktest" {
+        let devices = crate::drivers::storage::block_devices();
+
+        if args.len() < 2 {
+            println!("disktest DRIVE SECTOR [COUNT]\nWrites a pattern to the sectors, reads it back and puts the old data back.");
+
+            for (i, device) in devices.iter().enumerate() {
+                println!(
+                    "Drive {}: {} sectors of {} bytes",
+                    i,
+                    device.sector_count(),
+                    device.sector_size()
+                );
+            }
+
+            return;
+        }
+
+        let numbers: Vec<Option<u64>> = args.iter().map(|arg| arg.parse().ok()).collect();
+
+        let (drive, sector, count) = match (numbers[0], numbers[1], numbers.get(2)) {
+            (Some(drive), Some(sector), None) => (drive, sector, 1),
+            (Some(drive), Some(sector), Some(&Some(count))) => (drive, sector, count),
+            _ => {
+                println!("disktest: arguments must be numbers");
+                return;
+            }
+        };
+
+        let device = match devices.get(drive as usize) {
+            Some(device) => device,
+            None => {
+                println!("disktest: no drive {}", drive);
+                return;
+            }
+        };
+
+        match crate::drivers::storage::round_trip(device.as_ref(), sector, count) {
+            Ok(()) => println!("disktest: {} sectors written and read back", count),
+            Err(err) => println!("disktest: {}", err),
+        }
+
+        return;
+    }
+
+    if command == "shutdown" {
+        println!("Powering off...");
+
+        let err = crate::drivers::acpi::power::shutdown();
+        println!("shutdown: failed to power off: {:?}", err);
+        return;
+    }
+
+    if command == "reboot" {
+        println!("Rebooting...");
+        crate::drivers::acpi::power::reboot();
+    }
+
     if command == "test" {
         let message = "Hello from syscall!\n";
         unsafe {
             core::arch::asm!(
-                "mov rdi, 0x01", // write syscall
-                "mov rsi, 0x01", // stdio (but it doesnt matter)
-                "mov rdx, {0:r}", // pointer
-                "mov rcx, {1:r}", // count
                 "int 0x80",
-                in(reg) message.as_ptr(),
-                in(reg) message.len()
+                inout("rax") crate::sys::syscall::SYS_WRITE => _,
+                in("rdi") 1, // stdout
+                in("rsi") message.as_ptr(),
+                in("rdx") message.len(),
             );
         }
 
         return;
     }
 
-    println!("{:?} {:?}", command, args);
+    let path = crate::sys::exec::resolve_command(command.as_str());
+
+    let mut argv: Vec<&str> = Vec::with_capacity(args.len() + 1);
+    argv.push(command.as_str());
+    argv.extend(args.iter().map(|arg| arg.as_str()));
+
+    match crate::sys::exec::exec(&path, &argv) {
+        Ok(_) => {}
+        Err(crate::sys::exec::ExecError::NotFound) => println!("{}: command not found", command),
+        Err(err) => println!("{}: failed to execute: {:?}", command, err),
+    }
 }
 
 fn parse_input(input: &str) -> (String, Vec<String>) {